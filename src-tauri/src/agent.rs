@@ -0,0 +1,824 @@
+use futures_util::stream::SplitSink;
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use log::{info, warn, error};
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+
+use crate::api::hash_token;
+use crate::metrics::MetricsSampler;
+use crate::migration;
+use crate::protocol::{
+    self, AgentAuth, AgentMessage, ControllerMessage, ErrorCode, FileChunk, NodeEvent, NodeRequest, ProtocolError,
+};
+use crate::server::{self, ServerInfo, ServerManager};
+
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+const EVENT_INTERVAL: Duration = Duration::from_millis(500);
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A connection that stayed up this long resets the backoff.
+const STABLE_CONNECTION: Duration = Duration::from_secs(30);
+
+type Sink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
+
+/// The `[agent]` section of `agent-config.toml`. servermintd runs the agent
+/// when its config has this section.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AgentConfig {
+    pub name: String,
+    /// One-time pairing code from the controller. It is exchanged for a
+    /// credential on first connect; putting a new code here re-pairs.
+    pub token: String,
+    pub ws_url: String,
+    /// Where the issued credential is kept. Defaults to
+    /// `ServerMint/agent-credential.json` in the app data directory.
+    #[serde(default)]
+    pub credential_file: Option<String>,
+}
+
+/// The credential the controller issued when this agent paired.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+struct StoredCredential {
+    ws_url: String,
+    node_id: String,
+    credential: String,
+    /// Hash of the pairing code it was issued for, so a new code in the
+    /// config is noticed.
+    pairing_code_hash: String,
+}
+
+/// What was last reported for a server, used to turn polled status and
+/// output into events.
+struct ServerWatch {
+    status: String,
+    sent_lines: usize,
+}
+
+/// An upload from the controller that is still receiving chunks.
+struct Upload {
+    path: PathBuf,
+    /// Where a file upload is written until it is complete. Archive uploads
+    /// append to `path` directly so an interrupted transfer can resume.
+    temp_path: Option<PathBuf>,
+    file: tokio::fs::File,
+    /// Size of the file before this upload started appending to it.
+    base: u64,
+    expected: u64,
+    received: u64,
+}
+
+impl Upload {
+    /// Returns the resulting file size.
+    async fn finish(mut self) -> Result<u64, String> {
+        self.file.flush().await
+            .map_err(|e| format!("Failed to write upload: {}", e))?;
+        drop(self.file);
+
+        if self.received != self.expected {
+            if let Some(temp_path) = &self.temp_path {
+                let _ = tokio::fs::remove_file(temp_path).await;
+            }
+            return Err(format!("Expected {} bytes, received {}", self.expected, self.received));
+        }
+        if let Some(temp_path) = &self.temp_path {
+            tokio::fs::rename(temp_path, &self.path).await
+                .map_err(|e| format!("Failed to move upload into place: {}", e))?;
+        }
+        Ok(self.base + self.received)
+    }
+}
+
+/// Connects to the controller and serves it until the process exits,
+/// reconnecting with exponential backoff.
+pub struct Agent {
+    config: AgentConfig,
+    server_manager: Arc<Mutex<ServerManager>>,
+    /// Where servers installed by the controller are created.
+    servers_dir: String,
+}
+
+impl Agent {
+    pub fn new(config: AgentConfig, server_manager: Arc<Mutex<ServerManager>>, servers_dir: String) -> Self {
+        Agent { config, server_manager, servers_dir }
+    }
+
+    pub async fn run(self) {
+        let mut backoff = MIN_BACKOFF;
+
+        loop {
+            let connected_at = Instant::now();
+            match self.run_connection().await {
+                Ok(()) => info!("Controller closed the connection"),
+                Err(e) => warn!("Agent connection failed: {}", e),
+            }
+
+            if connected_at.elapsed() >= STABLE_CONNECTION {
+                backoff = MIN_BACKOFF;
+            }
+
+            // Up to 25% jitter so a fleet of agents doesn't reconnect in lockstep.
+            let jitter = backoff.mul_f64(uuid::Uuid::new_v4().as_bytes()[0] as f64 / 255.0 * 0.25);
+            info!("Reconnecting in {:.1}s", (backoff + jitter).as_secs_f64());
+            tokio::time::sleep(backoff + jitter).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    fn credential_path(&self) -> PathBuf {
+        match &self.config.credential_file {
+            Some(path) => PathBuf::from(path),
+            None => {
+                let app_data_dir = std::env::var("APPDATA")
+                    .unwrap_or_else(|_| std::env::var("HOME").unwrap_or_else(|_| ".".to_string()));
+                PathBuf::from(format!("{}/ServerMint/agent-credential.json", app_data_dir))
+            }
+        }
+    }
+
+    /// The stored credential, unless it belongs to another controller or the
+    /// config now holds a different pairing code.
+    fn load_credential(&self) -> Option<StoredCredential> {
+        let contents = std::fs::read_to_string(self.credential_path()).ok()?;
+        let stored: StoredCredential = serde_json::from_str(&contents).ok()?;
+        let current = stored.ws_url == self.config.ws_url
+            && stored.pairing_code_hash == hash_token(&self.config.token);
+        current.then_some(stored)
+    }
+
+    fn save_credential(&self, node_id: &str, credential: &str) -> Result<(), String> {
+        let stored = StoredCredential {
+            ws_url: self.config.ws_url.clone(),
+            node_id: node_id.to_string(),
+            credential: credential.to_string(),
+            pairing_code_hash: hash_token(&self.config.token),
+        };
+        let json = serde_json::to_string_pretty(&stored)
+            .map_err(|e| format!("Failed to serialize credential: {}", e))?;
+
+        let path = self.credential_path();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        let tmp_path = path.with_extension("json.tmp");
+        std::fs::write(&tmp_path, json)
+            .map_err(|e| format!("Failed to write credential: {}", e))?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(&tmp_path, std::fs::Permissions::from_mode(0o600))
+                .map_err(|e| format!("Failed to restrict credential permissions: {}", e))?;
+        }
+        std::fs::rename(&tmp_path, &path)
+            .map_err(|e| format!("Failed to replace credential: {}", e))
+    }
+
+    async fn run_connection(&self) -> Result<(), String> {
+        info!("Connecting to {}", self.config.ws_url);
+        let (socket, _) = tokio_tungstenite::connect_async(&self.config.ws_url).await
+            .map_err(|e| format!("Failed to connect to {}: {}", self.config.ws_url, e))?;
+        let (mut sink, mut stream) = socket.split();
+
+        let auth = match self.load_credential() {
+            Some(stored) => AgentAuth::Credential { node_id: stored.node_id, credential: stored.credential },
+            None => AgentAuth::PairingCode { code: self.config.token.clone() },
+        };
+        let hello = AgentMessage::Hello {
+            auth,
+            name: self.config.name.clone(),
+            hostname: hostname(),
+            agent_version: env!("CARGO_PKG_VERSION").to_string(),
+            protocol_versions: protocol::SUPPORTED_VERSIONS.to_vec(),
+        };
+        send(&mut sink, &hello).await?;
+
+        let mut sampler = MetricsSampler::new();
+        let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+        let mut event_ticker = tokio::time::interval(EVENT_INTERVAL);
+        let mut watches: HashMap<String, ServerWatch> = HashMap::new();
+        let mut uploads: HashMap<String, Upload> = HashMap::new();
+        let mut current_node: Option<String> = None;
+        // Replies from requests that run in the background, such as installs.
+        let (finished_tx, mut finished) = mpsc::unbounded_channel::<AgentMessage>();
+
+        loop {
+            tokio::select! {
+                Some(reply) = finished.recv() => {
+                    send(&mut sink, &reply).await?;
+                },
+                _ = heartbeat.tick(), if current_node.is_some() => {
+                    let servers = self.with_manager(|m| Ok(m.list_servers())).await.unwrap_or_default();
+                    let event = NodeEvent::Metrics { metrics: sampler.sample(), servers };
+                    send(&mut sink, &AgentMessage::Event { event }).await?;
+                },
+                _ = event_ticker.tick(), if current_node.is_some() => {
+                    for event in self.collect_events(&mut watches).await {
+                        send(&mut sink, &AgentMessage::Event { event }).await?;
+                    }
+                },
+                incoming = stream.next() => {
+                    let text = match incoming {
+                        None => return Ok(()),
+                        Some(Err(e)) => return Err(format!("WebSocket error: {}", e)),
+                        Some(Ok(Message::Text(text))) => text,
+                        Some(Ok(Message::Ping(payload))) => {
+                            let _ = sink.send(Message::Pong(payload)).await;
+                            continue;
+                        },
+                        Some(Ok(Message::Close(_))) => return Ok(()),
+                        Some(Ok(_)) => continue,
+                    };
+
+                    match serde_json::from_str::<ControllerMessage>(&text) {
+                        Ok(ControllerMessage::Welcome { node_id, protocol_version, credential }) => {
+                            info!("Authenticated with controller as node {} (protocol v{})", node_id, protocol_version);
+                            if let Some(credential) = credential {
+                                self.save_credential(&node_id, &credential)?;
+                                info!("Paired with controller, credential stored");
+                            }
+                            current_node = Some(node_id);
+                        },
+                        Ok(ControllerMessage::Rejected { error }) => {
+                            return Err(format!("Controller rejected the agent: {}", error));
+                        },
+                        Ok(ControllerMessage::Request { request_id, request }) => {
+                            let Some(node_id) = current_node.as_deref() else {
+                                warn!("Ignoring request {} before authentication", request_id);
+                                continue;
+                            };
+                            if matches!(request, NodeRequest::InstallServer { .. } | NodeRequest::PackageServer { .. } | NodeRequest::ImportServer { .. }) {
+                                self.spawn_background(request_id, request, finished_tx.clone());
+                                continue;
+                            }
+                            if let NodeRequest::RotateCredential { credential } = request {
+                                let reply = match self.save_credential(node_id, &credential) {
+                                    Ok(()) => AgentMessage::Response { request_id, data: serde_json::json!({ "success": true }) },
+                                    Err(e) => AgentMessage::Error { request_id, error: ProtocolError::new(ErrorCode::Failed, e) },
+                                };
+                                send(&mut sink, &reply).await?;
+                                continue;
+                            }
+                            self.handle_request(&mut sink, &mut uploads, request_id, request).await?;
+                        },
+                        Ok(ControllerMessage::FileChunk(chunk)) => {
+                            self.handle_chunk(&mut sink, &mut uploads, chunk).await?;
+                        },
+                        Err(e) => error!("Invalid controller message: {}", e),
+                    }
+                },
+            }
+        }
+    }
+
+    /// Installs and archive packaging run for minutes, so they are answered
+    /// from a separate task while the connection keeps sending heartbeats.
+    fn spawn_background(&self, request_id: String, request: NodeRequest, finished: mpsc::UnboundedSender<AgentMessage>) {
+        let server_manager = self.server_manager.clone();
+        let servers_dir = self.servers_dir.clone();
+
+        tokio::spawn(async move {
+            let result = match request {
+                NodeRequest::InstallServer { server_id, server_type, version, download_url, name } => {
+                    let server_path = format!("{}/{}", servers_dir, server_id);
+                    server::install_server(&server_manager, server_id.clone(), server_path, server_type, version, download_url, name).await
+                        .and_then(|_| {
+                            let manager = server_manager.lock().map_err(|_| "Failed to lock server manager".to_string())?;
+                            manager.get_server_info(&server_id)
+                        })
+                        .and_then(|info| serde_json::to_value(info).map_err(|e| e.to_string()))
+                },
+                NodeRequest::PackageServer { server_id } => {
+                    tokio::task::spawn_blocking(move || migration::package_server(&server_manager, &server_id))
+                        .await
+                        .map_err(|e| format!("Task join error: {}", e))
+                        .and_then(|packaged| packaged)
+                        .and_then(|archive| serde_json::to_value(archive).map_err(|e| e.to_string()))
+                },
+                NodeRequest::ImportServer { server_id, config, archive, sha256, port } => {
+                    tokio::task::spawn_blocking(move || {
+                        migration::import_server(&server_manager, &servers_dir, &server_id, config, &archive, &sha256, port)
+                    })
+                        .await
+                        .map_err(|e| format!("Task join error: {}", e))
+                        .and_then(|imported| imported)
+                        .and_then(|info| serde_json::to_value(info).map_err(|e| e.to_string()))
+                },
+                _ => Err("This request is handled by the connection".to_string()),
+            };
+
+            let reply = match result {
+                Ok(data) => AgentMessage::Response { request_id, data },
+                Err(e) => {
+                    error!("Request {} failed: {}", request_id, e);
+                    AgentMessage::Error { request_id, error: ProtocolError::new(ErrorCode::Failed, e) }
+                },
+            };
+            let _ = finished.send(reply);
+        });
+    }
+
+    async fn with_manager<T, F>(&self, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut ServerManager) -> Result<T, String> + Send + 'static,
+    {
+        let server_manager = self.server_manager.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut manager = server_manager.lock().map_err(|_| "Failed to lock server manager".to_string())?;
+            f(&mut manager)
+        })
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+    }
+
+    /// Diffs each server's status and console output against what was last
+    /// reported.
+    async fn collect_events(&self, watches: &mut HashMap<String, ServerWatch>) -> Vec<NodeEvent> {
+        let snapshot = self.with_manager(|m| {
+            Ok(m.list_servers()
+                .into_iter()
+                .map(|s| {
+                    let output = m.get_server_output(&s.id).unwrap_or_default();
+                    (s.id, s.status, output)
+                })
+                .collect::<Vec<_>>())
+        }).await.unwrap_or_default();
+
+        let mut events = Vec::new();
+        watches.retain(|id, _| snapshot.iter().any(|(s, _, _)| s == id));
+
+        for (server_id, status, output) in snapshot {
+            let watch = watches.entry(server_id.clone()).or_insert_with(|| ServerWatch {
+                status: status.clone(),
+                sent_lines: output.len(),
+            });
+
+            if watch.status != status {
+                watch.status = status.clone();
+                events.push(NodeEvent::StatusChanged { server_id: server_id.clone(), status });
+            }
+
+            if output.len() < watch.sent_lines {
+                watch.sent_lines = 0;
+            }
+            for line in &output[watch.sent_lines..] {
+                events.push(NodeEvent::ConsoleLine { server_id: server_id.clone(), line: line.clone() });
+            }
+            watch.sent_lines = output.len();
+        }
+
+        events
+    }
+
+    async fn server_file(&self, server_id: String, path: &str) -> Result<PathBuf, ProtocolError> {
+        let root = self.with_manager(move |m| m.get_server_info(&server_id).map(|s| s.config.path)).await
+            .map_err(|e| ProtocolError::new(ErrorCode::NotFound, e))?;
+        server::resolve_server_path(&root, path)
+            .map_err(|e| ProtocolError::new(ErrorCode::InvalidRequest, e))
+    }
+
+    async fn handle_request(
+        &self,
+        sink: &mut Sink,
+        uploads: &mut HashMap<String, Upload>,
+        request_id: String,
+        request: NodeRequest,
+    ) -> Result<(), String> {
+        info!("Handling request {}: {:?}", request_id, request);
+
+        let result = match request {
+            NodeRequest::ReadFile { server_id, path } => {
+                self.read_file(sink, &request_id, server_id, &path).await
+                    .map(|size| serde_json::json!({ "size": size }))
+            },
+            NodeRequest::WriteFile { server_id, path, size } => {
+                match self.begin_upload(server_id, &path, size).await {
+                    // Answered once the last chunk has been written.
+                    Ok(upload) => {
+                        uploads.insert(request_id, upload);
+                        return Ok(());
+                    },
+                    Err(e) => Err(e),
+                }
+            },
+            NodeRequest::ReadArchive { archive, offset, length } => {
+                self.read_archive(sink, &request_id, archive, offset, length).await
+                    .map(|size| serde_json::json!({ "size": size }))
+            },
+            NodeRequest::WriteArchive { archive, offset, size } => {
+                match self.begin_archive_upload(archive, offset, size).await {
+                    Ok(upload) => {
+                        uploads.insert(request_id, upload);
+                        return Ok(());
+                    },
+                    Err(e) => Err(e),
+                }
+            },
+            other => self.execute(other).await
+                .map_err(|e| ProtocolError::new(ErrorCode::Failed, e)),
+        };
+
+        let reply = match result {
+            Ok(data) => AgentMessage::Response { request_id, data },
+            Err(error) => AgentMessage::Error { request_id, error },
+        };
+        send(sink, &reply).await
+    }
+
+    async fn execute(&self, request: NodeRequest) -> Result<serde_json::Value, String> {
+        let to_value = |v: ServerInfo| serde_json::to_value(v).map_err(|e| e.to_string());
+
+        match request {
+            NodeRequest::ListServers => {
+                let servers = self.with_manager(|m| Ok(m.list_servers())).await?;
+                serde_json::to_value(servers).map_err(|e| e.to_string())
+            },
+            NodeRequest::GetServer { server_id } => {
+                to_value(self.with_manager(move |m| m.get_server_info(&server_id)).await?)
+            },
+            NodeRequest::StartServer { server_id } => to_value(self.with_manager(move |m| {
+                m.start_server(&server_id)?;
+                m.get_server_info(&server_id)
+            }).await?),
+            NodeRequest::StopServer { server_id } => to_value(self.with_manager(move |m| {
+                m.stop_server(&server_id)?;
+                m.get_server_info(&server_id)
+            }).await?),
+            NodeRequest::RestartServer { server_id } => to_value(self.with_manager(move |m| {
+                m.restart_server(&server_id)?;
+                m.get_server_info(&server_id)
+            }).await?),
+            NodeRequest::SendCommand { server_id, command } => {
+                self.with_manager(move |m| m.send_server_command(&server_id, &command)).await?;
+                Ok(serde_json::json!({ "success": true }))
+            },
+            NodeRequest::GetOutput { server_id } => {
+                let output = self.with_manager(move |m| m.get_server_output(&server_id)).await?;
+                serde_json::to_value(output).map_err(|e| e.to_string())
+            },
+            NodeRequest::ArchiveStatus { archive } => {
+                let size = tokio::task::spawn_blocking(move || migration::archive_size(&archive)).await
+                    .map_err(|e| format!("Task join error: {}", e))??;
+                Ok(serde_json::json!({ "size": size }))
+            },
+            NodeRequest::DeleteArchive { archive } => {
+                tokio::task::spawn_blocking(move || migration::delete_archive(&archive)).await
+                    .map_err(|e| format!("Task join error: {}", e))??;
+                Ok(serde_json::json!({ "success": true }))
+            },
+            NodeRequest::RemoveServer { server_id, delete_files } => {
+                let server_manager = self.server_manager.clone();
+                tokio::task::spawn_blocking(move || migration::remove_server(&server_manager, &server_id, delete_files)).await
+                    .map_err(|e| format!("Task join error: {}", e))??;
+                Ok(serde_json::json!({ "success": true }))
+            },
+            NodeRequest::ReadFile { .. }
+            | NodeRequest::WriteFile { .. }
+            | NodeRequest::ReadArchive { .. }
+            | NodeRequest::WriteArchive { .. }
+            | NodeRequest::InstallServer { .. }
+            | NodeRequest::PackageServer { .. }
+            | NodeRequest::ImportServer { .. }
+            | NodeRequest::RotateCredential { .. } => {
+                Err("This request is handled by the connection".to_string())
+            },
+        }
+    }
+
+    async fn read_file(
+        &self,
+        sink: &mut Sink,
+        request_id: &str,
+        server_id: String,
+        path: &str,
+    ) -> Result<u64, ProtocolError> {
+        let file_path = self.server_file(server_id, path).await?;
+        let bytes = tokio::fs::read(&file_path).await
+            .map_err(|e| ProtocolError::new(ErrorCode::NotFound, format!("Failed to read {}: {}", path, e)))?;
+
+        for chunk in FileChunk::split(request_id, &bytes) {
+            send(sink, &AgentMessage::FileChunk(chunk)).await
+                .map_err(|e| ProtocolError::new(ErrorCode::Failed, e))?;
+        }
+        Ok(bytes.len() as u64)
+    }
+
+    async fn read_archive(
+        &self,
+        sink: &mut Sink,
+        request_id: &str,
+        archive: String,
+        offset: u64,
+        length: u64,
+    ) -> Result<u64, ProtocolError> {
+        let length = length.min(migration::TRANSFER_CHUNK_SIZE);
+        let bytes = tokio::task::spawn_blocking(move || migration::read_archive(&archive, offset, length)).await
+            .map_err(|e| ProtocolError::new(ErrorCode::Failed, format!("Task join error: {}", e)))?
+            .map_err(|e| ProtocolError::new(ErrorCode::NotFound, e))?;
+
+        for chunk in FileChunk::split(request_id, &bytes) {
+            send(sink, &AgentMessage::FileChunk(chunk)).await
+                .map_err(|e| ProtocolError::new(ErrorCode::Failed, e))?;
+        }
+        Ok(bytes.len() as u64)
+    }
+
+    async fn begin_upload(&self, server_id: String, path: &str, size: u64) -> Result<Upload, ProtocolError> {
+        let file_path = self.server_file(server_id, path).await?;
+        let failed = |e: std::io::Error| ProtocolError::new(ErrorCode::Failed, format!("Failed to write {}: {}", path, e));
+
+        if let Some(parent) = file_path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(failed)?;
+        }
+        let temp_path = upload_temp_path(&file_path);
+        let file = tokio::fs::File::create(&temp_path).await.map_err(failed)?;
+
+        Ok(Upload { path: file_path, temp_path: Some(temp_path), file, base: 0, expected: size, received: 0 })
+    }
+
+    async fn begin_archive_upload(&self, archive: String, offset: u64, size: u64) -> Result<Upload, ProtocolError> {
+        let path = migration::archive_path(&archive)
+            .map_err(|e| ProtocolError::new(ErrorCode::InvalidRequest, e))?;
+        let file = tokio::task::spawn_blocking(move || migration::open_archive_for_append(&archive, offset)).await
+            .map_err(|e| ProtocolError::new(ErrorCode::Failed, format!("Task join error: {}", e)))?
+            .map_err(|e| ProtocolError::new(ErrorCode::InvalidRequest, e))?;
+
+        Ok(Upload { path, temp_path: None, file: tokio::fs::File::from_std(file), base: offset, expected: size, received: 0 })
+    }
+
+    async fn handle_chunk(
+        &self,
+        sink: &mut Sink,
+        uploads: &mut HashMap<String, Upload>,
+        chunk: FileChunk,
+    ) -> Result<(), String> {
+        let request_id = chunk.transfer_id.clone();
+        let Some(upload) = uploads.get_mut(&request_id) else {
+            warn!("Dropping chunk for unknown transfer {}", request_id);
+            return Ok(());
+        };
+
+        let written = if chunk.offset != upload.received {
+            Err(format!("Expected offset {}, got {}", upload.received, chunk.offset))
+        } else {
+            match chunk.bytes() {
+                Ok(bytes) => upload.file.write_all(&bytes).await
+                    .map(|_| upload.received += bytes.len() as u64)
+                    .map_err(|e| format!("Failed to write upload: {}", e)),
+                Err(e) => Err(e),
+            }
+        };
+
+        if written.is_ok() && !chunk.eof {
+            return Ok(());
+        }
+
+        let upload = uploads.remove(&request_id).expect("upload exists");
+        let result = match written {
+            Ok(()) => upload.finish().await,
+            Err(e) => {
+                drop(upload.file);
+                if let Some(temp_path) = &upload.temp_path {
+                    let _ = tokio::fs::remove_file(temp_path).await;
+                }
+                Err(e)
+            },
+        };
+
+        let reply = match result {
+            Ok(size) => AgentMessage::Response { request_id, data: serde_json::json!({ "size": size }) },
+            Err(e) => AgentMessage::Error { request_id, error: ProtocolError::new(ErrorCode::Failed, e) },
+        };
+        send(sink, &reply).await
+    }
+}
+
+async fn send(sink: &mut Sink, message: &AgentMessage) -> Result<(), String> {
+    let text = serde_json::to_string(message)
+        .map_err(|e| format!("Failed to serialize message: {}", e))?;
+    sink.send(Message::Text(text)).await
+        .map_err(|e| format!("Failed to send message: {}", e))
+}
+
+/// Where an upload to `path` is written until it is complete. Unique per
+/// upload, so neither `a.jar` and `a.zip` nor two uploads of the same file
+/// share one.
+fn upload_temp_path(path: &Path) -> PathBuf {
+    let file_name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.{}.upload", file_name, uuid::Uuid::new_v4().simple()))
+}
+
+fn hostname() -> Option<String> {
+    std::env::var("HOSTNAME").ok()
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .or_else(|| std::fs::read_to_string("/etc/hostname").ok().map(|h| h.trim().to_string()))
+        .filter(|h| !h.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::ServerConfig;
+    use crate::test_support::scratch_dir;
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    const TIMEOUT: Duration = Duration::from_secs(10);
+
+    type ControllerSocket = WebSocketStream<TcpStream>;
+
+    /// Starts an agent against a controller stand-in listening on a free port.
+    async fn start_agent(dir: &std::path::Path, manager: Arc<Mutex<ServerManager>>) -> (TcpListener, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let config = AgentConfig {
+            name: "test-node".to_string(),
+            token: "pairing-code".to_string(),
+            ws_url: format!("ws://{}", listener.local_addr().unwrap()),
+            credential_file: Some(dir.join("credential.json").display().to_string()),
+        };
+        let agent = Agent::new(config, manager, dir.join("servers").display().to_string());
+        (listener, tokio::spawn(agent.run()))
+    }
+
+    async fn accept(listener: &TcpListener) -> ControllerSocket {
+        let (stream, _) = tokio::time::timeout(TIMEOUT, listener.accept()).await
+            .expect("agent did not connect")
+            .unwrap();
+        tokio_tungstenite::accept_async(stream).await.unwrap()
+    }
+
+    /// The agent's next message other than an event.
+    async fn receive(socket: &mut ControllerSocket) -> AgentMessage {
+        loop {
+            let message = tokio::time::timeout(TIMEOUT, socket.next()).await
+                .expect("agent sent nothing")
+                .expect("agent closed the connection")
+                .unwrap();
+            if let Message::Text(text) = message {
+                match serde_json::from_str(&text).unwrap() {
+                    AgentMessage::Event { .. } => continue,
+                    other => return other,
+                }
+            }
+        }
+    }
+
+    async fn send_to_agent(socket: &mut ControllerSocket, message: &ControllerMessage) {
+        socket.send(Message::Text(serde_json::to_string(message).unwrap())).await.unwrap();
+    }
+
+    async fn request(socket: &mut ControllerSocket, request_id: &str, request: NodeRequest) {
+        send_to_agent(socket, &ControllerMessage::Request { request_id: request_id.to_string(), request }).await;
+    }
+
+    /// Accepts a connection and completes the handshake, returning the auth
+    /// the agent presented.
+    async fn handshake(listener: &TcpListener, credential: Option<&str>) -> (ControllerSocket, AgentAuth) {
+        let mut socket = accept(listener).await;
+        let AgentMessage::Hello { auth, name, protocol_versions, .. } = receive(&mut socket).await else {
+            panic!("expected hello");
+        };
+        assert_eq!(name, "test-node");
+        assert_eq!(protocol_versions, protocol::SUPPORTED_VERSIONS.to_vec());
+
+        let welcome = ControllerMessage::Welcome {
+            node_id: "node-1".to_string(),
+            protocol_version: protocol::PROTOCOL_VERSION,
+            credential: credential.map(str::to_string),
+        };
+        send_to_agent(&mut socket, &welcome).await;
+        (socket, auth)
+    }
+
+    #[test]
+    fn uploads_get_their_own_temp_files() {
+        let dir = Path::new("/srv/survival/plugins");
+        let jar = upload_temp_path(&dir.join("a.jar"));
+        let zip = upload_temp_path(&dir.join("a.zip"));
+        assert_ne!(jar, zip);
+        assert_ne!(jar, upload_temp_path(&dir.join("a.jar")));
+        assert_eq!(jar.parent(), Some(dir));
+        let name = jar.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with(".a.jar.") && name.ends_with(".upload"), "{}", name);
+    }
+
+    #[tokio::test]
+    async fn pairs_then_reconnects_with_the_issued_credential() {
+        let dir = scratch_dir("agent-pairing");
+        let (listener, agent) = start_agent(&dir, Arc::new(Mutex::new(ServerManager::new()))).await;
+
+        let (mut socket, auth) = handshake(&listener, Some("credential-1")).await;
+        assert!(matches!(auth, AgentAuth::PairingCode { ref code } if code == "pairing-code"));
+
+        // Requests are handled in order, so once this is answered the
+        // credential from the welcome has been stored.
+        request(&mut socket, "r1", NodeRequest::ListServers).await;
+        assert!(matches!(receive(&mut socket).await, AgentMessage::Response { ref request_id, .. } if request_id == "r1"));
+        drop(socket);
+
+        let (_socket, auth) = handshake(&listener, None).await;
+        match auth {
+            AgentAuth::Credential { node_id, credential } => {
+                assert_eq!(node_id, "node-1");
+                assert_eq!(credential, "credential-1");
+            },
+            other => panic!("expected the stored credential, got {:?}", other),
+        }
+        agent.abort();
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_the_controller_rejects_it() {
+        let dir = scratch_dir("agent-rejected");
+        let (listener, agent) = start_agent(&dir, Arc::new(Mutex::new(ServerManager::new()))).await;
+
+        let mut socket = accept(&listener).await;
+        assert!(matches!(receive(&mut socket).await, AgentMessage::Hello { .. }));
+        let error = ProtocolError::new(ErrorCode::Unauthorized, "Unknown pairing code");
+        send_to_agent(&mut socket, &ControllerMessage::Rejected { error }).await;
+
+        let (_socket, auth) = handshake(&listener, None).await;
+        assert!(matches!(auth, AgentAuth::PairingCode { .. }));
+        assert!(!dir.join("credential.json").exists());
+        agent.abort();
+    }
+
+    #[tokio::test]
+    async fn relays_requests_and_file_transfers() {
+        let dir = scratch_dir("agent-relay");
+        let server_dir = dir.join("server");
+        std::fs::create_dir_all(&server_dir).unwrap();
+        let manager = Arc::new(Mutex::new(ServerManager::new()));
+        let server_id = format!("relay-{}", uuid::Uuid::new_v4().simple());
+        let config = ServerConfig {
+            name: "Relay".to_string(),
+            path: server_dir.display().to_string(),
+            version: "1.20.4".to_string(),
+            server_type: "paper".to_string(),
+            java_path: None,
+            min_memory: 512,
+            max_memory: 1024,
+            jvm_args: None,
+            port: 25565,
+        };
+        manager.lock().unwrap().add_server(server_id.clone(), config).unwrap();
+
+        let (listener, agent) = start_agent(&dir, manager).await;
+        let (mut socket, _) = handshake(&listener, None).await;
+
+        request(&mut socket, "get", NodeRequest::GetServer { server_id: server_id.clone() }).await;
+        match receive(&mut socket).await {
+            AgentMessage::Response { request_id, data } => {
+                assert_eq!(request_id, "get");
+                assert_eq!(data["config"]["name"], "Relay");
+            },
+            other => panic!("expected a response, got {:?}", other),
+        }
+
+        request(&mut socket, "cmd", NodeRequest::SendCommand { server_id: server_id.clone(), command: "say hi".to_string() }).await;
+        assert!(matches!(receive(&mut socket).await, AgentMessage::Error { ref request_id, .. } if request_id == "cmd"));
+
+        request(&mut socket, "missing", NodeRequest::GetServer { server_id: "missing".to_string() }).await;
+        assert!(matches!(receive(&mut socket).await, AgentMessage::Error { ref request_id, .. } if request_id == "missing"));
+
+        let contents: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let write = NodeRequest::WriteFile { server_id: server_id.clone(), path: "plugins/data.bin".to_string(), size: contents.len() as u64 };
+        request(&mut socket, "write", write).await;
+        for chunk in FileChunk::split("write", &contents) {
+            send_to_agent(&mut socket, &ControllerMessage::FileChunk(chunk)).await;
+        }
+        assert!(matches!(receive(&mut socket).await, AgentMessage::Response { ref request_id, .. } if request_id == "write"));
+        assert_eq!(std::fs::read(server_dir.join("plugins/data.bin")).unwrap(), contents);
+
+        request(&mut socket, "read", NodeRequest::ReadFile { server_id: server_id.clone(), path: "plugins/data.bin".to_string() }).await;
+        let mut read = Vec::new();
+        loop {
+            match receive(&mut socket).await {
+                AgentMessage::FileChunk(chunk) => {
+                    assert_eq!(chunk.transfer_id, "read");
+                    assert_eq!(chunk.offset, read.len() as u64);
+                    read.extend(chunk.bytes().unwrap());
+                },
+                AgentMessage::Response { request_id, data } => {
+                    assert_eq!(request_id, "read");
+                    assert_eq!(data["size"], contents.len() as u64);
+                    break;
+                },
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        assert_eq!(read, contents);
+
+        request(&mut socket, "escape", NodeRequest::ReadFile { server_id, path: "../outside".to_string() }).await;
+        assert!(matches!(receive(&mut socket).await, AgentMessage::Error { ref request_id, .. } if request_id == "escape"));
+        agent.abort();
+    }
+}
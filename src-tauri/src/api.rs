@@ -0,0 +1,680 @@
+use axum::body::Bytes;
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path as UrlPath, Query, State as AxumState};
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::State;
+use uuid::Uuid;
+
+use crate::backup::{self, BackupInfo};
+use crate::chunk_store::GcReport;
+use crate::destination::DestinationStore;
+use crate::retention::RetentionStore;
+use crate::server::{self, ServerInfo, ServerManager};
+
+pub const API_VERSION: u32 = 1;
+const DEFAULT_BIND: &str = "127.0.0.1:8765";
+/// `last_used_at` is only written back when it moves by more than this, so
+/// busy clients don't rewrite the token file on every request.
+const LAST_USED_RESOLUTION_SECS: i64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ApiScope {
+    #[serde(rename = "servers:read")]
+    ServersRead,
+    #[serde(rename = "servers:control")]
+    ServersControl,
+    #[serde(rename = "console")]
+    Console,
+    #[serde(rename = "backups")]
+    Backups,
+    #[serde(rename = "files:read")]
+    FilesRead,
+    #[serde(rename = "files:write")]
+    FilesWrite,
+    /// Grants every other scope.
+    #[serde(rename = "admin")]
+    Admin,
+}
+
+impl std::fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match serde_json::to_value(self) {
+            Ok(serde_json::Value::String(name)) => write!(f, "{}", name),
+            _ => write!(f, "{:?}", self),
+        }
+    }
+}
+
+impl std::str::FromStr for ApiScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|_| format!("Unknown API scope: {}", s))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiTokenRecord {
+    pub id: String,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl ApiTokenRecord {
+    pub fn allows(&self, scope: ApiScope) -> bool {
+        self.scopes.contains(&ApiScope::Admin) || self.scopes.contains(&scope)
+    }
+}
+
+/// Returned once when a token is created; only the hash is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatedApiToken {
+    pub id: String,
+    pub name: String,
+    pub token: String,
+    pub scopes: Vec<ApiScope>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ApiSettings {
+    pub enabled: bool,
+    pub bind: String,
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        ApiSettings {
+            enabled: false,
+            bind: DEFAULT_BIND.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ApiStoreFile {
+    #[serde(default)]
+    settings: ApiSettings,
+    #[serde(default)]
+    tokens: Vec<ApiTokenRecord>,
+}
+
+#[derive(Debug)]
+pub struct ApiTokenStore {
+    path: PathBuf,
+    pub settings: ApiSettings,
+    tokens: Vec<ApiTokenRecord>,
+}
+
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+impl ApiTokenStore {
+    pub fn new() -> Self {
+        let app_data_dir = std::env::var("APPDATA")
+            .unwrap_or_else(|_| std::env::var("HOME").unwrap_or_else(|_| ".".to_string()));
+        let path = PathBuf::from(format!("{}/ServerMint/api.json", app_data_dir));
+
+        let mut store = ApiTokenStore {
+            path,
+            settings: ApiSettings::default(),
+            tokens: Vec::new(),
+        };
+
+        if let Err(e) = store.load() {
+            println!("Warning: Failed to load API tokens: {}", e);
+        }
+
+        store
+    }
+
+    fn load(&mut self) -> Result<(), String> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+
+        let contents = fs::read_to_string(&self.path)
+            .map_err(|e| format!("Failed to read {}: {}", self.path.display(), e))?;
+        let file: ApiStoreFile = serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse {}: {}", self.path.display(), e))?;
+
+        self.settings = file.settings;
+        self.tokens = file.tokens;
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+
+        let file = ApiStoreFile {
+            settings: self.settings.clone(),
+            tokens: self.tokens.clone(),
+        };
+        let json = serde_json::to_string_pretty(&file)
+            .map_err(|e| format!("Failed to serialize API tokens: {}", e))?;
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)
+            .map_err(|e| format!("Failed to write API tokens: {}", e))?;
+        fs::rename(&tmp_path, &self.path)
+            .map_err(|e| format!("Failed to replace API tokens: {}", e))
+    }
+
+    pub fn list_tokens(&self) -> Vec<ApiTokenRecord> {
+        self.tokens.clone()
+    }
+
+    pub fn create_token(&mut self, name: String, scopes: Vec<ApiScope>) -> Result<CreatedApiToken, String> {
+        if scopes.is_empty() {
+            return Err("A token needs at least one scope".to_string());
+        }
+
+        let token = format!("smapi_{}", Uuid::new_v4().simple());
+        let record = ApiTokenRecord {
+            id: Uuid::new_v4().to_string(),
+            name: name.clone(),
+            token_hash: hash_token(&token),
+            scopes: scopes.clone(),
+            created_at: Utc::now(),
+            last_used_at: None,
+        };
+        let id = record.id.clone();
+
+        self.tokens.push(record);
+        self.save()?;
+
+        Ok(CreatedApiToken { id, name, token, scopes })
+    }
+
+    pub fn revoke_token(&mut self, id: &str) -> Result<(), String> {
+        let before = self.tokens.len();
+        self.tokens.retain(|t| t.id != id);
+        if self.tokens.len() == before {
+            return Err(format!("API token {} not found", id));
+        }
+        self.save()
+    }
+
+    pub fn authenticate(&mut self, token: &str) -> Option<ApiTokenRecord> {
+        let hash = hash_token(token);
+        let now = Utc::now();
+        let record = self.tokens.iter_mut().find(|t| t.token_hash == hash)?;
+        let stale = record.last_used_at
+            .map_or(true, |at| (now - at).num_seconds() >= LAST_USED_RESOLUTION_SECS);
+        record.last_used_at = Some(now);
+        let record = record.clone();
+
+        if stale {
+            if let Err(e) = self.save() {
+                println!("Warning: Failed to record API token use: {}", e);
+            }
+        }
+        Some(record)
+    }
+}
+
+impl Default for ApiTokenStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Clone)]
+pub struct ApiContext {
+    pub server_manager: Arc<Mutex<ServerManager>>,
+    pub tokens: Arc<Mutex<ApiTokenStore>>,
+    pub backup_retention: Arc<Mutex<RetentionStore>>,
+    pub backup_destinations: Arc<Mutex<DestinationStore>>,
+}
+
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    message: String,
+}
+
+impl ApiError {
+    fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        ApiError { status, message: message.into() }
+    }
+
+    fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, message)
+    }
+
+    fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, message)
+    }
+}
+
+impl From<String> for ApiError {
+    fn from(message: String) -> Self {
+        if message.contains("not found") {
+            ApiError::new(StatusCode::NOT_FOUND, message)
+        } else {
+            ApiError::bad_request(message)
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.status, Json(serde_json::json!({ "error": self.message }))).into_response()
+    }
+}
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+
+#[derive(Debug, Deserialize)]
+struct TokenQuery {
+    token: Option<String>,
+}
+
+fn authorize(ctx: &ApiContext, headers: &HeaderMap, query_token: Option<&str>, scope: ApiScope) -> Result<ApiTokenRecord, ApiError> {
+    let header_token = headers.get("authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(|v| v.trim());
+
+    let token = header_token.or(query_token)
+        .ok_or_else(|| ApiError::new(StatusCode::UNAUTHORIZED, "Missing bearer token"))?;
+
+    let record = ctx.tokens.lock()
+        .map_err(|_| ApiError::internal("Failed to lock token store"))?
+        .authenticate(token)
+        .ok_or_else(|| ApiError::new(StatusCode::UNAUTHORIZED, "Invalid token"))?;
+
+    if !record.allows(scope) {
+        return Err(ApiError::new(StatusCode::FORBIDDEN, format!("Token lacks the {} scope", scope)));
+    }
+
+    Ok(record)
+}
+
+/// Runs a `ServerManager` operation off the async runtime; starting a server
+/// waits for its supervisor and must not block the API's worker threads.
+async fn with_manager<T, F>(ctx: &ApiContext, f: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut ServerManager) -> Result<T, String> + Send + 'static,
+{
+    let server_manager = ctx.server_manager.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut manager = server_manager.lock().map_err(|_| "Failed to lock server manager".to_string())?;
+        f(&mut manager)
+    })
+    .await
+    .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+    .map_err(ApiError::from)
+}
+
+#[derive(Debug, Serialize)]
+struct VersionResponse {
+    api_version: u32,
+    app_version: &'static str,
+}
+
+async fn version() -> Json<VersionResponse> {
+    Json(VersionResponse {
+        api_version: API_VERSION,
+        app_version: env!("CARGO_PKG_VERSION"),
+    })
+}
+
+async fn list_servers(AxumState(ctx): AxumState<ApiContext>, headers: HeaderMap) -> ApiResult<Vec<ServerInfo>> {
+    authorize(&ctx, &headers, None, ApiScope::ServersRead)?;
+    Ok(Json(with_manager(&ctx, |m| Ok(m.list_servers())).await?))
+}
+
+async fn get_server(AxumState(ctx): AxumState<ApiContext>, headers: HeaderMap, UrlPath(id): UrlPath<String>) -> ApiResult<ServerInfo> {
+    authorize(&ctx, &headers, None, ApiScope::ServersRead)?;
+    Ok(Json(with_manager(&ctx, move |m| m.get_server_info(&id)).await?))
+}
+
+async fn start_server(AxumState(ctx): AxumState<ApiContext>, headers: HeaderMap, UrlPath(id): UrlPath<String>) -> ApiResult<ServerInfo> {
+    authorize(&ctx, &headers, None, ApiScope::ServersControl)?;
+    Ok(Json(with_manager(&ctx, move |m| {
+        m.start_server(&id)?;
+        m.get_server_info(&id)
+    }).await?))
+}
+
+async fn stop_server(AxumState(ctx): AxumState<ApiContext>, headers: HeaderMap, UrlPath(id): UrlPath<String>) -> ApiResult<ServerInfo> {
+    authorize(&ctx, &headers, None, ApiScope::ServersControl)?;
+    Ok(Json(with_manager(&ctx, move |m| {
+        m.stop_server(&id)?;
+        m.get_server_info(&id)
+    }).await?))
+}
+
+async fn restart_server(AxumState(ctx): AxumState<ApiContext>, headers: HeaderMap, UrlPath(id): UrlPath<String>) -> ApiResult<ServerInfo> {
+    authorize(&ctx, &headers, None, ApiScope::ServersControl)?;
+    Ok(Json(with_manager(&ctx, move |m| {
+        m.restart_server(&id)?;
+        m.get_server_info(&id)
+    }).await?))
+}
+
+#[derive(Debug, Deserialize)]
+struct CommandRequest {
+    command: String,
+}
+
+async fn send_command(
+    AxumState(ctx): AxumState<ApiContext>,
+    headers: HeaderMap,
+    UrlPath(id): UrlPath<String>,
+    Json(request): Json<CommandRequest>,
+) -> ApiResult<serde_json::Value> {
+    authorize(&ctx, &headers, None, ApiScope::Console)?;
+    with_manager(&ctx, move |m| m.send_server_command(&id, &request.command)).await?;
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+#[derive(Debug, Deserialize)]
+struct ConsoleQuery {
+    since: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+struct ConsoleResponse {
+    lines: Vec<String>,
+    next: usize,
+}
+
+async fn get_console(
+    AxumState(ctx): AxumState<ApiContext>,
+    headers: HeaderMap,
+    UrlPath(id): UrlPath<String>,
+    Query(query): Query<ConsoleQuery>,
+) -> ApiResult<ConsoleResponse> {
+    authorize(&ctx, &headers, None, ApiScope::Console)?;
+    let output = with_manager(&ctx, move |m| m.get_server_output(&id)).await?;
+    let since = query.since.unwrap_or(0).min(output.len());
+    Ok(Json(ConsoleResponse {
+        next: output.len(),
+        lines: output[since..].to_vec(),
+    }))
+}
+
+async fn console_ws(
+    AxumState(ctx): AxumState<ApiContext>,
+    headers: HeaderMap,
+    UrlPath(id): UrlPath<String>,
+    Query(query): Query<TokenQuery>,
+    ws: WebSocketUpgrade,
+) -> Result<Response, ApiError> {
+    let token = authorize(&ctx, &headers, query.token.as_deref(), ApiScope::Console)?;
+    with_manager(&ctx, {
+        let id = id.clone();
+        move |m| m.get_server_info(&id)
+    }).await?;
+
+    Ok(ws.on_upgrade(move |socket| stream_console(ctx, id, token, socket)))
+}
+
+/// Pushes new console lines as text frames and forwards text frames from the
+/// client to the server's stdin.
+async fn stream_console(ctx: ApiContext, id: String, token: ApiTokenRecord, mut socket: WebSocket) {
+    let mut sent = 0usize;
+    let mut ticker = tokio::time::interval(Duration::from_millis(250));
+
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                let output = {
+                    let id = id.clone();
+                    match with_manager(&ctx, move |m| m.get_server_output(&id)).await {
+                        Ok(output) => output,
+                        Err(_) => break,
+                    }
+                };
+                if output.len() < sent {
+                    sent = 0;
+                }
+                for line in &output[sent..] {
+                    if socket.send(Message::Text(line.clone().into())).await.is_err() {
+                        return;
+                    }
+                }
+                sent = output.len();
+            },
+            incoming = socket.recv() => {
+                match incoming {
+                    Some(Ok(Message::Text(command))) => {
+                        let command = command.to_string();
+                        let id = id.clone();
+                        let result = with_manager(&ctx, move |m| m.send_server_command(&id, &command)).await;
+                        if let Err(e) = result {
+                            let _ = socket.send(Message::Text(format!("[ServerMint] {}", e.message).into())).await;
+                        }
+                    },
+                    Some(Ok(Message::Close(_))) | None | Some(Err(_)) => break,
+                    Some(Ok(_)) => {},
+                }
+            },
+        }
+    }
+
+    println!("Console stream for {} closed (token {})", id, token.name);
+}
+
+#[derive(Debug, Deserialize)]
+struct FileQuery {
+    path: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct FileEntry {
+    name: String,
+    is_dir: bool,
+    size: u64,
+    modified: Option<DateTime<Utc>>,
+}
+
+/// Resolves a client supplied path inside the server directory, refusing
+/// absolute paths, `..` and links out of it so requests can't escape it.
+fn resolve_server_path(root: &str, relative: Option<&str>) -> Result<PathBuf, ApiError> {
+    server::resolve_server_path(root, relative.unwrap_or("")).map_err(ApiError::bad_request)
+}
+
+async fn server_root(ctx: &ApiContext, id: String) -> Result<String, ApiError> {
+    with_manager(ctx, move |m| m.get_server_info(&id).map(|s| s.config.path)).await
+}
+
+async fn list_files(
+    AxumState(ctx): AxumState<ApiContext>,
+    headers: HeaderMap,
+    UrlPath(id): UrlPath<String>,
+    Query(query): Query<FileQuery>,
+) -> ApiResult<Vec<FileEntry>> {
+    authorize(&ctx, &headers, None, ApiScope::FilesRead)?;
+    let dir = resolve_server_path(&server_root(&ctx, id).await?, query.path.as_deref())?;
+
+    let entries = fs::read_dir(&dir)
+        .map_err(|e| ApiError::bad_request(format!("Failed to read directory: {}", e)))?;
+
+    let mut files = Vec::new();
+    for entry in entries.flatten() {
+        if let Ok(metadata) = entry.metadata() {
+            files.push(FileEntry {
+                name: entry.file_name().to_string_lossy().to_string(),
+                is_dir: metadata.is_dir(),
+                size: metadata.len(),
+                modified: metadata.modified().ok().map(DateTime::<Utc>::from),
+            });
+        }
+    }
+    files.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then(a.name.cmp(&b.name)));
+
+    Ok(Json(files))
+}
+
+async fn read_file(
+    AxumState(ctx): AxumState<ApiContext>,
+    headers: HeaderMap,
+    UrlPath(id): UrlPath<String>,
+    Query(query): Query<FileQuery>,
+) -> Result<Vec<u8>, ApiError> {
+    authorize(&ctx, &headers, None, ApiScope::FilesRead)?;
+    let path = resolve_server_path(&server_root(&ctx, id).await?, query.path.as_deref())?;
+    tokio::fs::read(&path).await
+        .map_err(|e| ApiError::new(StatusCode::NOT_FOUND, format!("Failed to read file: {}", e)))
+}
+
+async fn write_file(
+    AxumState(ctx): AxumState<ApiContext>,
+    headers: HeaderMap,
+    UrlPath(id): UrlPath<String>,
+    Query(query): Query<FileQuery>,
+    body: Bytes,
+) -> ApiResult<serde_json::Value> {
+    authorize(&ctx, &headers, None, ApiScope::FilesWrite)?;
+    let path = resolve_server_path(&server_root(&ctx, id).await?, query.path.as_deref())?;
+
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await
+            .map_err(|e| ApiError::internal(format!("Failed to create directory: {}", e)))?;
+    }
+    tokio::fs::write(&path, &body).await
+        .map_err(|e| ApiError::internal(format!("Failed to write file: {}", e)))?;
+
+    Ok(Json(serde_json::json!({ "success": true, "size": body.len() })))
+}
+
+async fn delete_file(
+    AxumState(ctx): AxumState<ApiContext>,
+    headers: HeaderMap,
+    UrlPath(id): UrlPath<String>,
+    Query(query): Query<FileQuery>,
+) -> ApiResult<serde_json::Value> {
+    authorize(&ctx, &headers, None, ApiScope::FilesWrite)?;
+    if query.path.as_deref().unwrap_or("").trim_matches('/').is_empty() {
+        return Err(ApiError::bad_request("Refusing to delete the server directory"));
+    }
+    let path = resolve_server_path(&server_root(&ctx, id).await?, query.path.as_deref())?;
+
+    let result = if path.is_dir() {
+        tokio::fs::remove_dir_all(&path).await
+    } else {
+        tokio::fs::remove_file(&path).await
+    };
+    result.map_err(|e| ApiError::bad_request(format!("Failed to delete: {}", e)))?;
+
+    Ok(Json(serde_json::json!({ "success": true })))
+}
+
+/// Runs a blocking backup operation off the async runtime.
+async fn with_backups<T, F>(f: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| ApiError::internal(format!("Task join error: {}", e)))?
+        .map_err(ApiError::from)
+}
+
+async fn list_backups(AxumState(ctx): AxumState<ApiContext>, headers: HeaderMap, UrlPath(id): UrlPath<String>) -> ApiResult<Vec<BackupInfo>> {
+    authorize(&ctx, &headers, None, ApiScope::Backups)?;
+    server_root(&ctx, id.clone()).await?;
+    let retention = ctx.backup_retention.clone();
+    Ok(Json(with_backups(move || backup::list_with_pins(&retention, Some(&id))).await?))
+}
+
+async fn create_backup(AxumState(ctx): AxumState<ApiContext>, headers: HeaderMap, UrlPath(id): UrlPath<String>) -> ApiResult<BackupInfo> {
+    authorize(&ctx, &headers, None, ApiScope::Backups)?;
+    let manager = ctx.server_manager.clone();
+    let retention = ctx.backup_retention.clone();
+    let destinations = ctx.backup_destinations.clone();
+    Ok(Json(with_backups(move || backup::run_backup(&manager, &retention, &destinations, &id)).await?))
+}
+
+async fn delete_backup(
+    AxumState(ctx): AxumState<ApiContext>,
+    headers: HeaderMap,
+    UrlPath((id, backup_id)): UrlPath<(String, String)>,
+) -> ApiResult<GcReport> {
+    authorize(&ctx, &headers, None, ApiScope::Backups)?;
+    let retention = ctx.backup_retention.clone();
+    Ok(Json(with_backups(move || backup::delete_unpinned(&retention, &id, &backup_id)).await?))
+}
+
+pub fn router(ctx: ApiContext) -> Router {
+    Router::new()
+        .route("/api/v1/version", get(version))
+        .route("/api/v1/servers", get(list_servers))
+        .route("/api/v1/servers/{id}", get(get_server))
+        .route("/api/v1/servers/{id}/start", post(start_server))
+        .route("/api/v1/servers/{id}/stop", post(stop_server))
+        .route("/api/v1/servers/{id}/restart", post(restart_server))
+        .route("/api/v1/servers/{id}/command", post(send_command))
+        .route("/api/v1/servers/{id}/console", get(get_console))
+        .route("/api/v1/servers/{id}/console/ws", get(console_ws))
+        .route("/api/v1/servers/{id}/files", get(list_files).delete(delete_file))
+        .route("/api/v1/servers/{id}/files/content", get(read_file).put(write_file))
+        .route("/api/v1/servers/{id}/backups", get(list_backups).post(create_backup))
+        .route("/api/v1/servers/{id}/backups/{backup_id}", delete(delete_backup))
+        .with_state(ctx)
+}
+
+pub async fn serve(ctx: ApiContext, bind: String) -> Result<(), String> {
+    let listener = tokio::net::TcpListener::bind(&bind).await
+        .map_err(|e| format!("Failed to bind API on {}: {}", bind, e))?;
+    println!("Control API listening on http://{}/api/v1", bind);
+
+    axum::serve(listener, router(ctx)).await
+        .map_err(|e| format!("API server failed: {}", e))
+}
+
+type ApiTokenStoreState<'a> = State<'a, Arc<Mutex<ApiTokenStore>>>;
+
+#[tauri::command]
+pub fn list_api_tokens(state: ApiTokenStoreState) -> Result<Vec<ApiTokenRecord>, String> {
+    let store = state.lock().map_err(|_| "Failed to lock API token store")?;
+    Ok(store.list_tokens())
+}
+
+#[tauri::command]
+pub fn create_api_token(state: ApiTokenStoreState, name: String, scopes: Vec<ApiScope>) -> Result<CreatedApiToken, String> {
+    let mut store = state.lock().map_err(|_| "Failed to lock API token store")?;
+    store.create_token(name, scopes)
+}
+
+#[tauri::command]
+pub fn revoke_api_token(state: ApiTokenStoreState, id: String) -> Result<(), String> {
+    let mut store = state.lock().map_err(|_| "Failed to lock API token store")?;
+    store.revoke_token(&id)
+}
+
+#[tauri::command]
+pub fn get_api_settings(state: ApiTokenStoreState) -> Result<ApiSettings, String> {
+    let store = state.lock().map_err(|_| "Failed to lock API token store")?;
+    Ok(store.settings.clone())
+}
+
+/// Takes effect the next time ServerMint starts.
+#[tauri::command]
+pub fn update_api_settings(state: ApiTokenStoreState, settings: ApiSettings) -> Result<(), String> {
+    let mut store = state.lock().map_err(|_| "Failed to lock API token store")?;
+    store.settings = settings;
+    store.save()
+}
@@ -0,0 +1,731 @@
+//! Server backups, kept as snapshots in a deduplicated chunk store under
+//! `BACKUPS_DIR`. Each snapshot is a manifest at
+//! `snapshots/<server id>/<backup id>.json` describing the server and every
+//! file in it, with each file's content referenced as chunks.
+//!
+//! Running servers are asked to flush their world and stop writing it while
+//! the files are read, and to resume afterwards. Files whose size and
+//! modification time match the server's previous snapshot reuse its chunks
+//! without being read, so unchanged region files cost nothing.
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File, Metadata};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tauri::State;
+use tokio::task;
+
+use crate::chunk_store::{self, ChunkStore, GcReport, STORE_LOCK};
+use crate::destination::{self, DestinationStore};
+use crate::retention::{self, RetentionStore};
+use crate::server::{ServerConfig, ServerManager};
+use crate::setup::BACKUPS_DIR;
+
+const SAVE_TIMEOUT: Duration = Duration::from_secs(60);
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupFile {
+    /// Relative to the server directory, `/`-separated.
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+    /// Milliseconds since the Unix epoch.
+    #[serde(default)]
+    pub modified: Option<i64>,
+    /// The file's content, in order.
+    pub chunks: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub id: String,
+    pub server_id: String,
+    pub created_at: DateTime<Utc>,
+    pub server: ServerConfig,
+    pub game_version: String,
+    /// False when a running server didn't confirm its save in time, so the
+    /// world may have been mid-write.
+    pub consistent: bool,
+    /// Why the backup was taken, when it wasn't a regular backup.
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Every directory, so empty ones are restored too.
+    #[serde(default)]
+    pub directories: Vec<String>,
+    pub files: Vec<BackupFile>,
+    pub total_size: u64,
+    /// Chunks this backup added to the store, and their stored size.
+    pub added_chunks: usize,
+    pub added_size: u64,
+}
+
+/// A backup as listed to the UI.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupInfo {
+    pub id: String,
+    pub server_id: String,
+    pub server_name: String,
+    pub created_at: DateTime<Utc>,
+    pub game_version: String,
+    pub consistent: bool,
+    pub label: Option<String>,
+    pub file_count: usize,
+    pub total_size: u64,
+    pub added_size: u64,
+    /// Pinned backups are never pruned or deleted.
+    pub pinned: bool,
+    /// Problems after the backup was written, such as failed uploads.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+impl From<&BackupManifest> for BackupInfo {
+    fn from(manifest: &BackupManifest) -> Self {
+        BackupInfo {
+            id: manifest.id.clone(),
+            server_id: manifest.server_id.clone(),
+            server_name: manifest.server.name.clone(),
+            created_at: manifest.created_at,
+            game_version: manifest.game_version.clone(),
+            consistent: manifest.consistent,
+            label: manifest.label.clone(),
+            file_count: manifest.files.len(),
+            total_size: manifest.total_size,
+            added_size: manifest.added_size,
+            pinned: false,
+            warnings: Vec::new(),
+        }
+    }
+}
+
+/// Real space used by one snapshot. `referenced_size` is the stored size of
+/// every chunk it needs; `unique_size` counts only the chunks no other
+/// snapshot needs, which is what deleting it would free.
+#[derive(Debug, Clone, Serialize)]
+pub struct SnapshotStats {
+    pub server_id: String,
+    pub id: String,
+    pub created_at: DateTime<Utc>,
+    pub total_size: u64,
+    pub added_size: u64,
+    pub referenced_size: u64,
+    pub unique_size: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StoreStats {
+    pub chunk_count: usize,
+    /// Bytes the chunk store takes on disk.
+    pub stored_size: u64,
+    /// Bytes all snapshots would take as plain copies.
+    pub logical_size: u64,
+    pub snapshots: Vec<SnapshotStats>,
+}
+
+/// Which chunks each snapshot references, and how big they are on disk.
+#[derive(Debug, Default)]
+pub struct StoreUsage {
+    refs: HashMap<(String, String), HashSet<String>>,
+    pub sizes: HashMap<String, u64>,
+}
+
+impl StoreUsage {
+    pub fn new(manifests: &[BackupManifest], sizes: HashMap<String, u64>) -> Self {
+        let refs = manifests.iter()
+            .map(|m| {
+                let chunks = m.files.iter().flat_map(|f| f.chunks.iter().cloned()).collect();
+                ((m.server_id.clone(), m.id.clone()), chunks)
+            })
+            .collect();
+        StoreUsage { refs, sizes }
+    }
+
+    pub fn chunks(&self, server_id: &str, backup_id: &str) -> Option<&HashSet<String>> {
+        self.refs.get(&(server_id.to_string(), backup_id.to_string()))
+    }
+
+    /// How many snapshots reference each chunk.
+    pub fn ref_counts(&self) -> HashMap<&str, usize> {
+        let mut counts = HashMap::new();
+        for chunk in self.refs.values().flatten() {
+            *counts.entry(chunk.as_str()).or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+pub(crate) fn chunk_store() -> ChunkStore {
+    ChunkStore::new(BACKUPS_DIR)
+}
+
+/// Rejects ids that could point outside the backups directory.
+pub(crate) fn check_id(kind: &str, id: &str) -> Result<(), String> {
+    if id.is_empty() || id.contains(['/', '\\']) || id.contains("..") {
+        return Err(format!("Invalid {} id: {}", kind, id));
+    }
+    Ok(())
+}
+
+fn snapshots_dir() -> PathBuf {
+    Path::new(BACKUPS_DIR).join("snapshots")
+}
+
+fn server_snapshots_dir(server_id: &str) -> PathBuf {
+    snapshots_dir().join(server_id)
+}
+
+/// The save commands for `server_type`, and the console output that confirms
+/// the world was flushed.
+pub(crate) fn save_commands(server_type: &str) -> (&'static str, &'static str) {
+    if server_type.eq_ignore_ascii_case("pocketmine") {
+        ("save-all", "Saved")
+    } else {
+        ("save-all flush", "Saved the game")
+    }
+}
+
+fn send_command(manager: &Mutex<ServerManager>, server_id: &str, command: &str) -> Result<(), String> {
+    manager.lock()
+        .map_err(|_| "Failed to lock server manager")?
+        .send_server_command(server_id, command)
+}
+
+fn output_len(manager: &Mutex<ServerManager>, server_id: &str) -> usize {
+    manager.lock().ok()
+        .and_then(|m| m.get_server_output(server_id).ok())
+        .map(|output| output.len())
+        .unwrap_or(0)
+}
+
+/// Flushes the world. Returns whether the server confirmed the save within
+/// `SAVE_TIMEOUT`.
+fn flush_world(manager: &Mutex<ServerManager>, server_id: &str, server_type: &str) -> Result<bool, String> {
+    let (save_all, confirmation) = save_commands(server_type);
+    let seen = output_len(manager, server_id);
+    send_command(manager, server_id, save_all)?;
+
+    let deadline = Instant::now() + SAVE_TIMEOUT;
+    while Instant::now() < deadline {
+        let confirmed = manager.lock().ok()
+            .and_then(|m| m.get_server_output(server_id).ok())
+            .is_some_and(|output| output.iter().skip(seen).any(|line| line.contains(confirmation)));
+        if confirmed {
+            return Ok(true);
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+    warn!("Server {} did not confirm its save within {}s", server_id, SAVE_TIMEOUT.as_secs());
+    Ok(false)
+}
+
+/// Files that are never worth restoring: the world lock Minecraft holds
+/// open, and ZIP exports, which live in the server directory.
+pub(crate) fn skipped(relative: &str, name: &str) -> bool {
+    name == "session.lock" || relative == "exports"
+}
+
+fn modified_millis(metadata: &Metadata) -> Option<i64> {
+    metadata.modified().ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis() as i64)
+}
+
+/// Walks a server directory into the chunk store.
+struct SnapshotWriter<'a> {
+    store: &'a ChunkStore,
+    root: &'a Path,
+    previous: HashMap<String, BackupFile>,
+    directories: Vec<String>,
+    files: Vec<BackupFile>,
+    added_chunks: usize,
+    added_size: u64,
+    reused: usize,
+}
+
+impl SnapshotWriter<'_> {
+    fn walk(&mut self, dir: &Path) -> Result<(), String> {
+        let mut entries = fs::read_dir(dir)
+            .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let relative = path.strip_prefix(self.root)
+                .map(|p| p.to_string_lossy().replace('\\', "/"))
+                .unwrap_or_else(|_| name.clone());
+            if skipped(&relative, &name) {
+                continue;
+            }
+
+            let metadata = entry.metadata()
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+            if metadata.is_dir() {
+                self.directories.push(relative);
+                self.walk(&path)?;
+            } else if metadata.is_file() {
+                let file = self.add_file(&path, relative, &metadata)?;
+                self.files.push(file);
+            }
+        }
+        Ok(())
+    }
+
+    fn add_file(&mut self, path: &Path, relative: String, metadata: &Metadata) -> Result<BackupFile, String> {
+        let modified = modified_millis(metadata);
+        if let Some(previous) = self.previous.remove(&relative) {
+            let unchanged = modified.is_some()
+                && previous.modified == modified
+                && previous.size == metadata.len()
+                && previous.chunks.iter().all(|c| self.store.contains(c));
+            if unchanged {
+                self.reused += 1;
+                return Ok(previous);
+            }
+        }
+
+        let file = File::open(path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let mut hasher = Sha256::new();
+        let mut chunks = Vec::new();
+        let mut size = 0u64;
+        let (mut added_chunks, mut added_size) = (0, 0);
+        chunk_store::split(file, |data| {
+            hasher.update(data);
+            let (id, written) = self.store.put(data)?;
+            if written > 0 {
+                added_chunks += 1;
+                added_size += written;
+            }
+            chunks.push(id);
+            size += data.len() as u64;
+            Ok(())
+        }).map_err(|e| format!("Failed to back up {}: {}", relative, e))?;
+
+        self.added_chunks += added_chunks;
+        self.added_size += added_size;
+        Ok(BackupFile {
+            path: relative,
+            size,
+            sha256: hex::encode(hasher.finalize()),
+            modified,
+            chunks,
+        })
+    }
+}
+
+/// Picks an id from the current time to the millisecond plus a random
+/// suffix, so ids sort by age and backups taken at the same moment, here or
+/// on another machine sharing a destination, don't collide.
+fn new_backup_id(dir: &Path) -> String {
+    loop {
+        let suffix = uuid::Uuid::new_v4().simple().to_string();
+        let id = format!("{}-{}", Utc::now().format("%Y%m%d-%H%M%S%3f"), &suffix[..6]);
+        if !dir.join(format!("{}.json", id)).exists() {
+            return id;
+        }
+    }
+}
+
+/// Writes a snapshot of the server directory as it is now.
+pub(crate) fn snapshot(
+    server_id: &str,
+    config: &ServerConfig,
+    consistent: bool,
+    label: Option<String>,
+) -> Result<BackupInfo, String> {
+    let root = Path::new(&config.path);
+    if !root.is_dir() {
+        return Err(format!("Server directory {} does not exist", config.path));
+    }
+
+    let _guard = STORE_LOCK.read().map_err(|_| "Failed to lock backup store")?;
+    let previous = manifests(Some(server_id))?
+        .into_iter()
+        .next()
+        .map(|m| m.files.into_iter().map(|f| (f.path.clone(), f)).collect())
+        .unwrap_or_default();
+
+    let store = chunk_store();
+    let mut writer = SnapshotWriter {
+        store: &store,
+        root,
+        previous,
+        directories: Vec::new(),
+        files: Vec::new(),
+        added_chunks: 0,
+        added_size: 0,
+        reused: 0,
+    };
+    writer.walk(root)?;
+
+    let dir = server_snapshots_dir(server_id);
+    fs::create_dir_all(&dir)
+        .map_err(|e| format!("Failed to create backups directory: {}", e))?;
+    let id = new_backup_id(&dir);
+    let manifest = BackupManifest {
+        id: id.clone(),
+        server_id: server_id.to_string(),
+        created_at: Utc::now(),
+        server: config.clone(),
+        game_version: config.version.clone(),
+        consistent,
+        label,
+        directories: writer.directories,
+        total_size: writer.files.iter().map(|f| f.size).sum(),
+        files: writer.files,
+        added_chunks: writer.added_chunks,
+        added_size: writer.added_size,
+    };
+
+    let json = serde_json::to_vec(&manifest)
+        .map_err(|e| format!("Failed to serialize backup manifest: {}", e))?;
+    let path = dir.join(format!("{}.json", id));
+    let tmp_path = dir.join(format!("{}.json.tmp", id));
+    fs::write(&tmp_path, json)
+        .map_err(|e| format!("Failed to write backup manifest: {}", e))?;
+    fs::rename(&tmp_path, &path)
+        .map_err(|e| format!("Failed to save backup manifest: {}", e))?;
+
+    info!(
+        "Backed up {} as {} ({} files, {} bytes, {} unchanged, {} new chunks taking {} bytes)",
+        config.name,
+        id,
+        manifest.files.len(),
+        manifest.total_size,
+        writer.reused,
+        manifest.added_chunks,
+        manifest.added_size
+    );
+    Ok(BackupInfo::from(&manifest))
+}
+
+/// Backs up one local server, pausing saves while it's running. Blocks
+/// until the snapshot is written.
+pub fn backup_server(manager: &Mutex<ServerManager>, server_id: &str) -> Result<BackupInfo, String> {
+    let (config, running) = {
+        let manager = manager.lock().map_err(|_| "Failed to lock server manager")?;
+        (manager.get_server_info(server_id)?.config, manager.is_running(server_id))
+    };
+
+    if !running {
+        return snapshot(server_id, &config, true, None);
+    }
+
+    send_command(manager, server_id, "save-off")?;
+    let result = flush_world(manager, server_id, &config.server_type)
+        .and_then(|consistent| snapshot(server_id, &config, consistent, None));
+    // Autosave stays off until it is turned back on, whatever failed.
+    if let Err(e) = send_command(manager, server_id, "save-on") {
+        warn!("Failed to turn saving back on for {}: {}", config.name, e);
+    }
+    result
+}
+
+/// Backs up a server, prunes its backups by its retention policy and
+/// uploads the new one to every enabled destination. Pruning never removes
+/// the newest backup, so the new one survives it.
+pub fn run_backup(
+    manager: &Mutex<ServerManager>,
+    retention: &Mutex<RetentionStore>,
+    destinations: &Mutex<DestinationStore>,
+    server_id: &str,
+) -> Result<BackupInfo, String> {
+    let mut backup = backup_server(manager, server_id)?;
+    if let Err(e) = retention::prune(retention, server_id, false) {
+        warn!("Failed to prune backups of {}: {}", server_id, e);
+    }
+    backup.warnings = destination::replicate(destinations, retention, server_id, &backup.id);
+    Ok(backup)
+}
+
+/// Backs up every local server that isn't being installed. Returns the
+/// backups made and an error message for each server that failed.
+pub fn backup_all(
+    manager: &Mutex<ServerManager>,
+    retention: &Mutex<RetentionStore>,
+    destinations: &Mutex<DestinationStore>,
+) -> (Vec<BackupInfo>, Vec<String>) {
+    let servers = match manager.lock() {
+        Ok(manager) => manager.list_servers(),
+        Err(_) => return (Vec::new(), vec!["Failed to lock server manager".to_string()]),
+    };
+
+    let mut backups = Vec::new();
+    let mut errors = Vec::new();
+    for server in servers.into_iter().filter(|s| s.status != "installing") {
+        match run_backup(manager, retention, destinations, &server.id) {
+            Ok(backup) => {
+                errors.extend(backup.warnings.iter().map(|w| format!("{}: {}", server.config.name, w)));
+                backups.push(backup);
+            },
+            Err(e) => errors.push(format!("{}: {}", server.config.name, e)),
+        }
+    }
+    (backups, errors)
+}
+
+fn read_manifest(path: &Path) -> Result<BackupManifest, String> {
+    let contents = fs::read(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    serde_json::from_slice(&contents)
+        .map_err(|e| format!("Failed to parse {}: {}", path.display(), e))
+}
+
+pub fn load_manifest(server_id: &str, backup_id: &str) -> Result<BackupManifest, String> {
+    check_id("server", server_id)?;
+    check_id("backup", backup_id)?;
+    let path = server_snapshots_dir(server_id).join(format!("{}.json", backup_id));
+    if !path.exists() {
+        return Err(format!("Backup {} of server {} not found", backup_id, server_id));
+    }
+    read_manifest(&path)
+}
+
+/// Snapshots of `server_id`, or of every server, newest first, and an error
+/// for each manifest that couldn't be read.
+fn scan_manifests(server_id: Option<&str>) -> Result<(Vec<BackupManifest>, Vec<String>), String> {
+    let dirs: Vec<PathBuf> = match server_id {
+        Some(server_id) => {
+            check_id("server", server_id)?;
+            vec![server_snapshots_dir(server_id)]
+        },
+        None => match fs::read_dir(snapshots_dir()) {
+            Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).filter(|p| p.is_dir()).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(format!("Failed to read {}: {}", snapshots_dir().display(), e)),
+        },
+    };
+
+    let mut manifests = Vec::new();
+    let mut failures = Vec::new();
+    for dir in dirs {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                failures.push(format!("Failed to read {}: {}", dir.display(), e));
+                continue;
+            },
+        };
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    failures.push(format!("Failed to read {}: {}", dir.display(), e));
+                    continue;
+                },
+            };
+            if path.extension().and_then(|e| e.to_str()) != Some("json") {
+                continue;
+            }
+            match read_manifest(&path) {
+                Ok(manifest) => manifests.push(manifest),
+                Err(e) => failures.push(e),
+            }
+        }
+    }
+    manifests.sort_by_key(|m| std::cmp::Reverse(m.created_at));
+    Ok((manifests, failures))
+}
+
+/// Snapshots of `server_id`, or of every server, newest first. Manifests
+/// that can't be read are skipped.
+fn manifests(server_id: Option<&str>) -> Result<Vec<BackupManifest>, String> {
+    let (manifests, failures) = scan_manifests(server_id)?;
+    for failure in failures {
+        warn!("Skipping a backup: {}", failure);
+    }
+    Ok(manifests)
+}
+
+/// Every snapshot in the store. Fails if any manifest can't be read, since
+/// the chunks only that snapshot uses would look unreferenced.
+fn all_manifests() -> Result<Vec<BackupManifest>, String> {
+    let (manifests, failures) = scan_manifests(None)?;
+    match failures.first() {
+        Some(failure) => Err(format!("{} backup manifests could not be read: {}", failures.len(), failure)),
+        None => Ok(manifests),
+    }
+}
+
+/// Backups of `server_id`, or of every server, newest first.
+pub fn list(server_id: Option<&str>) -> Result<Vec<BackupInfo>, String> {
+    Ok(manifests(server_id)?.iter().map(BackupInfo::from).collect())
+}
+
+/// Removes a snapshot's manifest. Its chunks stay until the next garbage
+/// collection.
+pub fn delete(server_id: &str, backup_id: &str) -> Result<(), String> {
+    check_id("server", server_id)?;
+    check_id("backup", backup_id)?;
+    let path = server_snapshots_dir(server_id).join(format!("{}.json", backup_id));
+    if !path.exists() {
+        return Err(format!("Backup {} of server {} not found", backup_id, server_id));
+    }
+    fs::remove_file(&path)
+        .map_err(|e| format!("Failed to delete backup: {}", e))?;
+    info!("Deleted backup {} of {}", backup_id, server_id);
+    Ok(())
+}
+
+/// Like `list`, with each backup's pinned flag set.
+pub fn list_with_pins(retention: &Mutex<RetentionStore>, server_id: Option<&str>) -> Result<Vec<BackupInfo>, String> {
+    let mut backups = list(server_id)?;
+    let retention = retention.lock().map_err(|_| "Failed to lock backup retention")?;
+    for backup in &mut backups {
+        backup.pinned = retention.is_pinned(&backup.server_id, &backup.id);
+    }
+    Ok(backups)
+}
+
+/// Deletes a backup unless it's pinned, then the chunks only it used.
+pub fn delete_unpinned(retention: &Mutex<RetentionStore>, server_id: &str, backup_id: &str) -> Result<GcReport, String> {
+    if retention.lock().map_err(|_| "Failed to lock backup retention")?.is_pinned(server_id, backup_id) {
+        return Err(format!("Backup {} is pinned. Unpin it before deleting it.", backup_id));
+    }
+    delete(server_id, backup_id)?;
+    collect_garbage()
+}
+
+/// Which chunks every snapshot in the store references.
+pub fn usage() -> Result<StoreUsage, String> {
+    let manifests = all_manifests()?;
+    Ok(StoreUsage::new(&manifests, chunk_store().chunk_sizes()?))
+}
+
+/// Deletes every chunk no snapshot references. Removes nothing unless every
+/// manifest could be read.
+pub fn collect_garbage() -> Result<GcReport, String> {
+    let _guard = STORE_LOCK.write().map_err(|_| "Failed to lock backup store")?;
+    let referenced: HashSet<String> = all_manifests()
+        .map_err(|e| format!("Not removing unreferenced chunks: {}", e))?
+        .into_iter()
+        .flat_map(|m| m.files.into_iter().flat_map(|f| f.chunks))
+        .collect();
+    let report = chunk_store().remove_unreferenced(&referenced)?;
+    if report.removed_chunks > 0 {
+        info!("Removed {} unreferenced backup chunks, freeing {} bytes", report.removed_chunks, report.reclaimed);
+    }
+    Ok(report)
+}
+
+pub fn store_stats() -> Result<StoreStats, String> {
+    let manifests = manifests(None)?;
+    let usage = StoreUsage::new(&manifests, chunk_store().chunk_sizes()?);
+    let counts = usage.ref_counts();
+
+    let snapshots = manifests.iter()
+        .map(|m| {
+            let chunks = usage.chunks(&m.server_id, &m.id).into_iter().flatten();
+            let (mut referenced_size, mut unique_size) = (0, 0);
+            for chunk in chunks {
+                let size = usage.sizes.get(chunk).copied().unwrap_or(0);
+                referenced_size += size;
+                if counts.get(chunk.as_str()) == Some(&1) {
+                    unique_size += size;
+                }
+            }
+            SnapshotStats {
+                server_id: m.server_id.clone(),
+                id: m.id.clone(),
+                created_at: m.created_at,
+                total_size: m.total_size,
+                added_size: m.added_size,
+                referenced_size,
+                unique_size,
+            }
+        })
+        .collect();
+
+    Ok(StoreStats {
+        chunk_count: usage.sizes.len(),
+        stored_size: usage.sizes.values().sum(),
+        logical_size: manifests.iter().map(|m| m.total_size).sum(),
+        snapshots,
+    })
+}
+
+type ServerManagerState<'a> = State<'a, Arc<Mutex<ServerManager>>>;
+
+#[tauri::command]
+pub async fn create_backup(
+    state: ServerManagerState<'_>,
+    retention: State<'_, Arc<Mutex<RetentionStore>>>,
+    destinations: State<'_, Arc<Mutex<DestinationStore>>>,
+    server_id: String,
+) -> Result<BackupInfo, String> {
+    let manager = state.inner().clone();
+    let retention = retention.inner().clone();
+    let destinations = destinations.inner().clone();
+    task::spawn_blocking(move || run_backup(&manager, &retention, &destinations, &server_id))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+#[tauri::command]
+pub async fn list_backups(
+    retention: State<'_, Arc<Mutex<RetentionStore>>>,
+    server_id: Option<String>,
+) -> Result<Vec<BackupInfo>, String> {
+    let retention = retention.inner().clone();
+    task::spawn_blocking(move || list_with_pins(&retention, server_id.as_deref()))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+/// Deletes a backup and the chunks only it used.
+#[tauri::command]
+pub async fn delete_backup(
+    retention: State<'_, Arc<Mutex<RetentionStore>>>,
+    server_id: String,
+    backup_id: String,
+) -> Result<GcReport, String> {
+    let retention = retention.inner().clone();
+    task::spawn_blocking(move || delete_unpinned(&retention, &server_id, &backup_id))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+#[tauri::command]
+pub async fn collect_backup_garbage() -> Result<GcReport, String> {
+    task::spawn_blocking(collect_garbage)
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+#[tauri::command]
+pub async fn get_backup_store_stats() -> Result<StoreStats, String> {
+    task::spawn_blocking(store_stats)
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    #[test]
+    fn backup_ids_are_unique_and_sort_by_age() {
+        let dir = scratch_dir("backup-ids");
+        let ids: Vec<String> = (0..200)
+            .map(|_| {
+                let id = new_backup_id(&dir);
+                std::thread::sleep(Duration::from_micros(500));
+                id
+            })
+            .collect();
+        assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
+        assert!(ids.windows(2).all(|pair| pair[0][..18] <= pair[1][..18]), "{:?}", ids);
+        assert!(ids.iter().all(|id| check_id("backup", id).is_ok()));
+    }
+}
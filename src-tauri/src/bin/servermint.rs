@@ -0,0 +1,317 @@
+use std::path::PathBuf;
+
+use app_lib::ipc::{self, IpcReply, IpcRequest};
+use serde_json::Value;
+
+const USAGE: &str = "Usage: servermint [--socket <path>] [--json] <command> [args]
+
+Commands:
+  list                                   List servers
+  status <server>                        Show one server
+  start <server>                         Start a server
+  stop <server>                          Stop a server
+  restart <server>                       Restart a server
+  cmd <server> <command...>              Send a console command
+  console <server> [--lines N]           Attach to the console (input is sent to the server)
+  logs <server> [--lines N]              Print the end of logs/latest.log
+  install <name> --type <type> --version <version> [--path <dir>]
+  backup <server>                        Back up a server now
+  backup list [<server>]                 List backups of one server or all
+
+<server> is a server ID or name. The socket defaults to $SERVERMINT_SOCKET or
+~/ServerMint/servermint.sock.";
+
+struct Options {
+    socket: PathBuf,
+    json: bool,
+    args: Vec<String>,
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut socket = ipc::default_socket_path();
+    let mut json = false;
+    let mut args = Vec::new();
+
+    let mut argv = std::env::args().skip(1);
+    while let Some(arg) = argv.next() {
+        match arg.as_str() {
+            "--socket" => socket = PathBuf::from(argv.next().ok_or("--socket needs a path")?),
+            "--json" => json = true,
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ => args.push(arg),
+        }
+    }
+
+    if args.is_empty() {
+        return Err(USAGE.to_string());
+    }
+
+    Ok(Options { socket, json, args })
+}
+
+/// Removes `--name value` from `args` and returns the value.
+fn take_flag(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    match args.iter().position(|a| a == name) {
+        Some(index) => {
+            if index + 1 >= args.len() {
+                return Err(format!("{} needs a value", name));
+            }
+            let value = args.remove(index + 1);
+            args.remove(index);
+            Ok(Some(value))
+        },
+        None => Ok(None),
+    }
+}
+
+fn take_lines(args: &mut Vec<String>) -> Result<Option<usize>, String> {
+    take_flag(args, "--lines")?
+        .map(|v| v.parse::<usize>().map_err(|_| format!("Invalid --lines value: {}", v)))
+        .transpose()
+}
+
+fn server_arg(args: &[String]) -> Result<String, String> {
+    args.get(1).cloned().ok_or_else(|| format!("{} needs a server", args[0]))
+}
+
+fn build_request(mut args: Vec<String>) -> Result<IpcRequest, String> {
+    let request = match args[0].as_str() {
+        "list" | "ls" => IpcRequest::List,
+        "status" => IpcRequest::Status { server: server_arg(&args)? },
+        "start" => IpcRequest::Start { server: server_arg(&args)? },
+        "stop" => IpcRequest::Stop { server: server_arg(&args)? },
+        "restart" => IpcRequest::Restart { server: server_arg(&args)? },
+        "cmd" => {
+            let server = server_arg(&args)?;
+            if args.len() < 3 {
+                return Err("cmd needs a command".to_string());
+            }
+            IpcRequest::Command { server, command: args[2..].join(" ") }
+        },
+        "console" => {
+            let lines = take_lines(&mut args)?;
+            IpcRequest::Console { server: server_arg(&args)?, lines }
+        },
+        "logs" => {
+            let lines = take_lines(&mut args)?;
+            IpcRequest::Logs { server: server_arg(&args)?, lines }
+        },
+        "install" => {
+            let server_type = take_flag(&mut args, "--type")?.ok_or("install needs --type")?;
+            let version = take_flag(&mut args, "--version")?.ok_or("install needs --version")?;
+            let path = take_flag(&mut args, "--path")?;
+            let name = args.get(1).cloned().ok_or("install needs a server name")?;
+            IpcRequest::Install { name, server_type, version, path }
+        },
+        "backup" => match args.get(1).map(String::as_str) {
+            Some("list") | Some("ls") => IpcRequest::BackupList { server: args.get(2).cloned() },
+            _ => IpcRequest::Backup { server: server_arg(&args)? },
+        },
+        other => return Err(format!("Unknown command: {}\n\n{}", other, USAGE)),
+    };
+    Ok(request)
+}
+
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    let mut widths: Vec<usize> = headers.iter().map(|h| h.len()).collect();
+    for row in &rows {
+        for (i, cell) in row.iter().enumerate() {
+            widths[i] = widths[i].max(cell.len());
+        }
+    }
+
+    let format_row = |cells: Vec<String>| {
+        cells.iter()
+            .enumerate()
+            .map(|(i, c)| format!("{:width$}", c, width = widths[i]))
+            .collect::<Vec<_>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+
+    println!("{}", format_row(headers.iter().map(|h| h.to_string()).collect()));
+    for row in rows {
+        println!("{}", format_row(row));
+    }
+}
+
+fn server_row(server: &Value) -> Vec<String> {
+    let text = |v: &Value| match v {
+        Value::String(s) => s.clone(),
+        Value::Null => "-".to_string(),
+        other => other.to_string(),
+    };
+    vec![
+        text(&server["id"]),
+        text(&server["config"]["name"]),
+        text(&server["status"]),
+        format!("{} {}", text(&server["config"]["server_type"]), text(&server["config"]["version"])),
+        text(&server["config"]["port"]),
+    ]
+}
+
+const SERVER_HEADERS: [&str; 5] = ["ID", "NAME", "STATUS", "TYPE", "PORT"];
+
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn backup_row(backup: &Value) -> Vec<String> {
+    let mut notes = Vec::new();
+    if backup["pinned"].as_bool() == Some(true) {
+        notes.push("pinned".to_string());
+    }
+    if backup["consistent"].as_bool() == Some(false) {
+        notes.push("inconsistent".to_string());
+    }
+    if let Some(label) = backup["label"].as_str() {
+        notes.push(label.to_string());
+    }
+    vec![
+        backup["id"].as_str().unwrap_or("-").to_string(),
+        backup["server_name"].as_str().unwrap_or("-").to_string(),
+        backup["created_at"].as_str().unwrap_or("-").to_string(),
+        backup["file_count"].to_string(),
+        format_size(backup["total_size"].as_u64().unwrap_or(0)),
+        format_size(backup["added_size"].as_u64().unwrap_or(0)),
+        notes.join(", "),
+    ]
+}
+
+const BACKUP_HEADERS: [&str; 7] = ["ID", "SERVER", "CREATED", "FILES", "SIZE", "ADDED", "NOTES"];
+
+fn print_human(request: &IpcRequest, data: &Value) {
+    match request {
+        IpcRequest::List => {
+            let rows = data.as_array()
+                .map(|servers| servers.iter().map(server_row).collect())
+                .unwrap_or_default();
+            print_table(&SERVER_HEADERS, rows);
+        },
+        IpcRequest::Status { .. }
+        | IpcRequest::Start { .. }
+        | IpcRequest::Stop { .. }
+        | IpcRequest::Restart { .. }
+        | IpcRequest::Install { .. } => {
+            print_table(&SERVER_HEADERS, vec![server_row(data)]);
+        },
+        IpcRequest::Backup { .. } => {
+            print_table(&BACKUP_HEADERS, vec![backup_row(data)]);
+            if let Some(warnings) = data["warnings"].as_array() {
+                for warning in warnings {
+                    eprintln!("warning: {}", warning.as_str().unwrap_or_default());
+                }
+            }
+        },
+        IpcRequest::BackupList { .. } => {
+            let rows = data.as_array()
+                .map(|backups| backups.iter().map(backup_row).collect())
+                .unwrap_or_default();
+            print_table(&BACKUP_HEADERS, rows);
+        },
+        IpcRequest::Logs { .. } => {
+            if let Some(lines) = data["lines"].as_array() {
+                for line in lines {
+                    println!("{}", line.as_str().unwrap_or_default());
+                }
+            }
+        },
+        _ => println!("OK"),
+    }
+}
+
+#[cfg(unix)]
+fn run(options: Options) -> Result<(), String> {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+
+    let request = build_request(options.args)?;
+
+    let mut stream = UnixStream::connect(&options.socket)
+        .map_err(|e| format!("Failed to connect to ServerMint at {}: {}\nIs the app or servermintd running?", options.socket.display(), e))?;
+
+    let send = |stream: &mut UnixStream, request: &IpcRequest| -> Result<(), String> {
+        let mut line = serde_json::to_string(request).map_err(|e| e.to_string())?;
+        line.push('\n');
+        stream.write_all(line.as_bytes()).map_err(|e| format!("Failed to send request: {}", e))
+    };
+
+    send(&mut stream, &request)?;
+    let reader = BufReader::new(stream.try_clone().map_err(|e| e.to_string())?);
+
+    if let IpcRequest::Console { .. } = request {
+        let mut input_stream = stream;
+        std::thread::spawn(move || {
+            let stdin = std::io::stdin();
+            for line in stdin.lock().lines().map_while(Result::ok) {
+                if send(&mut input_stream, &IpcRequest::Input { line }).is_err() {
+                    break;
+                }
+            }
+        });
+
+        for line in reader.lines() {
+            let line = line.map_err(|e| format!("Connection lost: {}", e))?;
+            match serde_json::from_str::<IpcReply>(&line) {
+                Ok(IpcReply::Line { line }) if options.json => println!("{}", serde_json::json!({ "line": line })),
+                Ok(IpcReply::Line { line }) => println!("{}", line),
+                Ok(IpcReply::Error { message }) => eprintln!("error: {}", message),
+                Ok(IpcReply::Ok { .. }) => {},
+                Err(e) => return Err(format!("Invalid reply: {}", e)),
+            }
+        }
+        return Ok(());
+    }
+
+    let mut reply = String::new();
+    let mut reader = reader;
+    reader.read_line(&mut reply).map_err(|e| format!("Failed to read reply: {}", e))?;
+
+    match serde_json::from_str::<IpcReply>(&reply).map_err(|e| format!("Invalid reply: {}", e))? {
+        IpcReply::Ok { data } => {
+            if options.json {
+                println!("{}", serde_json::to_string_pretty(&data).unwrap_or_default());
+            } else {
+                print_human(&request, &data);
+            }
+            Ok(())
+        },
+        IpcReply::Error { message } => Err(message),
+        IpcReply::Line { line } => {
+            println!("{}", line);
+            Ok(())
+        },
+    }
+}
+
+#[cfg(not(unix))]
+fn run(_options: Options) -> Result<(), String> {
+    Err("The servermint CLI needs Unix domain sockets and is not available on this platform".to_string())
+}
+
+fn main() {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}", message);
+            std::process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(options) {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
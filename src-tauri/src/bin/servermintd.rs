@@ -0,0 +1,81 @@
+use std::path::PathBuf;
+
+use app_lib::api::{ApiScope, ApiTokenStore};
+use app_lib::{daemon, supervisor};
+
+fn create_token(args: &[String]) -> Result<(), String> {
+    let name = args.first().ok_or("Usage: servermintd create-token <name> <scope>[,<scope>...]")?;
+    let scopes = args.get(1)
+        .ok_or("Usage: servermintd create-token <name> <scope>[,<scope>...]")?
+        .split(',')
+        .map(|s| s.trim().parse::<ApiScope>())
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let created = ApiTokenStore::new().create_token(name.clone(), scopes)?;
+    println!("Created API token {} ({})", created.name, created.id);
+    println!("{}", created.token);
+    println!("Store it now; it cannot be shown again.");
+    Ok(())
+}
+
+fn pair(args: &[String]) -> Result<(), String> {
+    let node_id = match args {
+        [] => None,
+        [flag, id] if flag == "--node" => Some(id.clone()),
+        _ => return Err("Usage: servermintd pair [--node <node-id>]".to_string()),
+    };
+
+    let code = daemon::create_pairing_code(node_id)?;
+    println!("{}", code);
+    println!("Put it in the agent's [agent] token; it expires in 10 minutes and works once.");
+    Ok(())
+}
+
+fn main() {
+    if let Some(spec) = supervisor::spec_from_args() {
+        if let Err(e) = supervisor::run(&spec) {
+            eprintln!("Supervisor failed: {}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let argv: Vec<String> = std::env::args().skip(1).collect();
+    let subcommand = match argv.first().map(String::as_str) {
+        Some("create-token") => Some(create_token as fn(&[String]) -> Result<(), String>),
+        Some("pair") => Some(pair as fn(&[String]) -> Result<(), String>),
+        _ => None,
+    };
+    if let Some(subcommand) = subcommand {
+        if let Err(e) = subcommand(&argv[1..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let mut config_path = None;
+    let mut args = argv.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-c" | "--config" => config_path = args.next().map(PathBuf::from),
+            "-h" | "--help" => {
+                println!("Usage: servermintd [--config <path>]");
+                println!("       servermintd create-token <name> <scope>[,<scope>...]");
+                println!("       servermintd pair [--node <node-id>]");
+                println!();
+                println!("Runs ServerMint headless. Without --config, looks for {}.", daemon::DEFAULT_CONFIG_PATHS.join(" or "));
+                return;
+            },
+            other => {
+                eprintln!("Unknown argument: {}", other);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    if let Err(e) = daemon::run(config_path) {
+        eprintln!("servermintd failed: {}", e);
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,322 @@
+//! A content-addressed store of deduplicated chunks under
+//! `<root>/chunks/<first two hex digits>/<sha256>`.
+//!
+//! Files are split with content-defined chunking, so an edit in the middle
+//! of a file only changes the chunks around it, and a chunk that's already
+//! stored costs nothing to store again. Each chunk file is a one-byte
+//! encoding tag followed by the chunk, deflated when that makes it smaller.
+
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use log::warn;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// Chunk boundaries are never closer than this...
+const MIN_CHUNK: usize = 64 * 1024;
+/// ...aim for this on average...
+const AVG_CHUNK: usize = 256 * 1024;
+/// ...and are forced at this size.
+const MAX_CHUNK: usize = 1024 * 1024;
+/// Cut-point masks on the top bits of the rolling hash: harder to match
+/// before `AVG_CHUNK` and easier after, which narrows the size spread.
+const MASK_SMALL: u64 = !(u64::MAX >> 20);
+const MASK_LARGE: u64 = !(u64::MAX >> 16);
+
+const ENCODING_RAW: u8 = 0;
+const ENCODING_DEFLATE: u8 = 1;
+
+/// Random values for the gear rolling hash, generated with splitmix64 so
+/// chunk boundaries are stable across builds.
+const GEAR: [u64; 256] = {
+    let mut table = [0u64; 256];
+    let mut state: u64 = 0x5365_7276_6572_4d69;
+    let mut i = 0;
+    while i < 256 {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        table[i] = z ^ (z >> 31);
+        i += 1;
+    }
+    table
+};
+
+lazy_static::lazy_static! {
+    /// Writers hold this shared while they add chunks and reference them;
+    /// garbage collection holds it exclusively, so it never sees a chunk
+    /// that's been written but not yet referenced.
+    pub static ref STORE_LOCK: RwLock<()> = RwLock::new(());
+}
+
+/// Where the next chunk of `data` ends.
+fn cut_point(data: &[u8]) -> usize {
+    if data.len() <= MIN_CHUNK {
+        return data.len();
+    }
+    let end = data.len().min(MAX_CHUNK);
+    let normal = AVG_CHUNK.min(end);
+    let mut hash: u64 = 0;
+    let mut i = MIN_CHUNK;
+    while i < normal {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & MASK_SMALL == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    while i < end {
+        hash = (hash << 1).wrapping_add(GEAR[data[i] as usize]);
+        if hash & MASK_LARGE == 0 {
+            return i + 1;
+        }
+        i += 1;
+    }
+    end
+}
+
+/// Reads `reader` to the end, calling `f` with each chunk in order.
+pub fn split<R: Read>(mut reader: R, mut f: impl FnMut(&[u8]) -> Result<(), String>) -> Result<(), String> {
+    let mut buffer = vec![0u8; MAX_CHUNK * 2];
+    let mut len = 0;
+    let mut eof = false;
+    loop {
+        while !eof && len < buffer.len() {
+            let read = reader.read(&mut buffer[len..])
+                .map_err(|e| format!("Failed to read: {}", e))?;
+            if read == 0 {
+                eof = true;
+            }
+            len += read;
+        }
+        if len == 0 {
+            return Ok(());
+        }
+
+        let mut start = 0;
+        while len - start >= MAX_CHUNK || (eof && start < len) {
+            let cut = cut_point(&buffer[start..len]);
+            f(&buffer[start..start + cut])?;
+            start += cut;
+        }
+        buffer.copy_within(start..len, 0);
+        len -= start;
+    }
+}
+
+pub fn chunk_id(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+pub fn is_chunk_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit() && !b.is_ascii_uppercase())
+}
+
+/// What garbage collection removed.
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct GcReport {
+    pub removed_chunks: usize,
+    pub reclaimed: u64,
+    pub remaining_chunks: usize,
+    pub remaining_size: u64,
+}
+
+/// Makes entries created or renamed in `dir` durable. Windows has no
+/// equivalent for directories.
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+    #[cfg(not(unix))]
+    let _ = dir;
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+pub struct ChunkStore {
+    root: PathBuf,
+}
+
+impl ChunkStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        ChunkStore { root: root.into() }
+    }
+
+    fn chunks_dir(&self) -> PathBuf {
+        self.root.join("chunks")
+    }
+
+    fn chunk_path(&self, id: &str) -> PathBuf {
+        self.chunks_dir().join(&id[..2]).join(id)
+    }
+
+    pub fn contains(&self, id: &str) -> bool {
+        is_chunk_id(id) && self.chunk_path(id).is_file()
+    }
+
+    /// Stores a chunk unless it's already there. Returns its id and the bytes
+    /// written, which are zero for a chunk that was already stored. A stored
+    /// chunk that doesn't read back intact is written again.
+    pub fn put(&self, data: &[u8]) -> Result<(String, u64), String> {
+        let id = chunk_id(data);
+        let path = self.chunk_path(&id);
+        if path.is_file() {
+            match self.get(&id) {
+                Ok(_) => return Ok((id, 0)),
+                Err(e) => warn!("Replacing damaged chunk: {}", e),
+            }
+        }
+
+        let mut encoder = DeflateEncoder::new(vec![ENCODING_DEFLATE], Compression::default());
+        encoder.write_all(data)
+            .map_err(|e| format!("Failed to compress chunk: {}", e))?;
+        let mut encoded = encoder.finish()
+            .map_err(|e| format!("Failed to compress chunk: {}", e))?;
+        if encoded.len() > data.len() {
+            encoded.clear();
+            encoded.push(ENCODING_RAW);
+            encoded.extend_from_slice(data);
+        }
+
+        let dir = path.parent().expect("chunk paths have a parent");
+        let new_dir = !dir.is_dir();
+        fs::create_dir_all(dir)
+            .map_err(|e| format!("Failed to create chunk directory: {}", e))?;
+        let tmp_path = dir.join(format!("{}.{}.tmp", id, uuid::Uuid::new_v4()));
+        // Synced before it's renamed into place, so a crash can't leave a
+        // chunk that exists but is cut short.
+        let written = File::create(&tmp_path)
+            .and_then(|mut file| {
+                file.write_all(&encoded)?;
+                file.sync_all()
+            })
+            .map_err(|e| format!("Failed to write chunk {}: {}", id, e))
+            .and_then(|_| fs::rename(&tmp_path, &path).map_err(|e| format!("Failed to store chunk {}: {}", id, e)));
+        if let Err(e) = written {
+            let _ = fs::remove_file(&tmp_path);
+            return Err(e);
+        }
+
+        // Backups reference the chunk once this returns, so the rename has to
+        // be durable too.
+        sync_dir(dir).map_err(|e| format!("Failed to store chunk {}: {}", id, e))?;
+        if new_dir {
+            sync_dir(&self.chunks_dir()).map_err(|e| format!("Failed to store chunk {}: {}", id, e))?;
+        }
+        Ok((id, encoded.len() as u64))
+    }
+
+    /// A chunk as stored, still encoded, for copying it elsewhere.
+    pub fn read_encoded(&self, id: &str) -> Result<Vec<u8>, String> {
+        if !is_chunk_id(id) {
+            return Err(format!("Invalid chunk id: {}", id));
+        }
+        fs::read(self.chunk_path(id))
+            .map_err(|e| format!("Failed to read chunk {}: {}", id, e))
+    }
+
+    /// Reads a chunk back, checking it against its id.
+    pub fn get(&self, id: &str) -> Result<Vec<u8>, String> {
+        if !is_chunk_id(id) {
+            return Err(format!("Invalid chunk id: {}", id));
+        }
+        let encoded = fs::read(self.chunk_path(id))
+            .map_err(|e| format!("Failed to read chunk {}: {}", id, e))?;
+        let data = match encoded.split_first() {
+            Some((&ENCODING_RAW, data)) => data.to_vec(),
+            Some((&ENCODING_DEFLATE, compressed)) => {
+                let mut data = Vec::new();
+                DeflateDecoder::new(compressed).read_to_end(&mut data)
+                    .map_err(|e| format!("Failed to decompress chunk {}: {}", id, e))?;
+                data
+            },
+            _ => return Err(format!("Chunk {} has an unknown encoding", id)),
+        };
+        if chunk_id(&data) != id {
+            return Err(format!("Chunk {} is corrupt", id));
+        }
+        Ok(data)
+    }
+
+    /// The stored size of every chunk, keyed by id.
+    pub fn chunk_sizes(&self) -> Result<HashMap<String, u64>, String> {
+        let mut sizes = HashMap::new();
+        let Ok(prefixes) = fs::read_dir(self.chunks_dir()) else {
+            return Ok(sizes);
+        };
+        for prefix in prefixes.filter_map(|e| e.ok()) {
+            let entries = fs::read_dir(prefix.path())
+                .map_err(|e| format!("Failed to read {}: {}", prefix.path().display(), e))?;
+            for entry in entries.filter_map(|e| e.ok()) {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if is_chunk_id(&name) {
+                    let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+                    sizes.insert(name, size);
+                }
+            }
+        }
+        Ok(sizes)
+    }
+
+    /// Deletes every chunk not in `referenced`, and leftovers of interrupted
+    /// writes. The caller must hold `STORE_LOCK` exclusively.
+    pub fn remove_unreferenced(&self, referenced: &HashSet<String>) -> Result<GcReport, String> {
+        let mut report = GcReport::default();
+        let Ok(prefixes) = fs::read_dir(self.chunks_dir()) else {
+            return Ok(report);
+        };
+        for prefix in prefixes.filter_map(|e| e.ok()) {
+            let entries = fs::read_dir(prefix.path())
+                .map_err(|e| format!("Failed to read {}: {}", prefix.path().display(), e))?;
+            for entry in entries.filter_map(|e| e.ok()) {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let size = entry.metadata().map(|m| m.len()).unwrap_or(0);
+                if is_chunk_id(&name) && referenced.contains(&name) {
+                    report.remaining_chunks += 1;
+                    report.remaining_size += size;
+                    continue;
+                }
+                fs::remove_file(entry.path())
+                    .map_err(|e| format!("Failed to remove chunk {}: {}", name, e))?;
+                if is_chunk_id(&name) {
+                    report.removed_chunks += 1;
+                    report.reclaimed += size;
+                }
+            }
+            let _ = fs::remove_dir(prefix.path());
+        }
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    #[test]
+    fn damaged_chunks_are_replaced_instead_of_reused() {
+        let store = ChunkStore::new(scratch_dir("chunk-store-damaged"));
+        let data = b"region data ".repeat(1000);
+        let (id, written) = store.put(&data).unwrap();
+        assert!(written > 0);
+        assert_eq!(store.put(&data).unwrap(), (id.clone(), 0));
+
+        // Cut short, as a crash before the data reached the disk could.
+        let encoded = store.read_encoded(&id).unwrap();
+        fs::write(store.chunk_path(&id), &encoded[..encoded.len() / 2]).unwrap();
+        assert!(store.get(&id).is_err());
+
+        assert_eq!(store.put(&data).unwrap(), (id.clone(), written));
+        assert_eq!(store.get(&id).unwrap(), data);
+        let leftovers: Vec<_> = fs::read_dir(store.chunk_path(&id).parent().unwrap()).unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(leftovers.len(), 1);
+    }
+}
@@ -0,0 +1,242 @@
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use log::{info, warn, error};
+use tauri::State;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::node::{self, NodeManager};
+use crate::protocol::{self, AgentMessage, ControllerMessage, ErrorCode, ProtocolError};
+
+/// Agents on other machines need the listener bound to an address they can
+/// reach, such as `0.0.0.0:8766`; that has to be chosen explicitly.
+pub const DEFAULT_BIND: &str = "127.0.0.1:8766";
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Whether the desktop app listens for node agents. Off by default.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ControllerSettings {
+    pub enabled: bool,
+    pub bind: String,
+}
+
+impl Default for ControllerSettings {
+    fn default() -> Self {
+        ControllerSettings {
+            enabled: false,
+            bind: DEFAULT_BIND.to_string(),
+        }
+    }
+}
+
+/// `ControllerSettings`, persisted to `controller.json`.
+#[derive(Debug)]
+pub struct ControllerSettingsStore {
+    path: PathBuf,
+    pub settings: ControllerSettings,
+}
+
+impl ControllerSettingsStore {
+    pub fn new() -> Self {
+        let app_data_dir = std::env::var("APPDATA")
+            .unwrap_or_else(|_| std::env::var("HOME").unwrap_or_else(|_| ".".to_string()));
+
+        let mut store = ControllerSettingsStore {
+            path: PathBuf::from(format!("{}/ServerMint/controller.json", app_data_dir)),
+            settings: ControllerSettings::default(),
+        };
+
+        if let Err(e) = store.load() {
+            println!("Warning: Failed to load controller settings: {}", e);
+        }
+
+        store
+    }
+
+    fn load(&mut self) -> Result<(), String> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+
+        let contents = fs::read_to_string(&self.path)
+            .map_err(|e| format!("Failed to read {}: {}", self.path.display(), e))?;
+        self.settings = serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse {}: {}", self.path.display(), e))?;
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+
+        let json = serde_json::to_string_pretty(&self.settings)
+            .map_err(|e| format!("Failed to serialize controller settings: {}", e))?;
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)
+            .map_err(|e| format!("Failed to write controller settings: {}", e))?;
+        fs::rename(&tmp_path, &self.path)
+            .map_err(|e| format!("Failed to replace controller settings: {}", e))
+    }
+}
+
+impl Default for ControllerSettingsStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Accepts WebSocket connections from node agents and hands each
+/// authenticated session to the `NodeManager`.
+pub async fn serve(node_manager: Arc<Mutex<NodeManager>>, bind: String) -> Result<(), String> {
+    let listener = TcpListener::bind(&bind).await
+        .map_err(|e| format!("Failed to bind {}: {}", bind, e))?;
+    info!("Listening for node agents on {}", bind);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                tokio::spawn(handle_connection(node_manager.clone(), stream, addr));
+            },
+            Err(e) => error!("Failed to accept agent connection: {}", e),
+        }
+    }
+}
+
+fn encode(message: &ControllerMessage) -> Option<Message> {
+    serde_json::to_string(message).ok().map(Message::Text)
+}
+
+async fn handle_connection(node_manager: Arc<Mutex<NodeManager>>, stream: TcpStream, addr: SocketAddr) {
+    let socket = match tokio_tungstenite::accept_async(stream).await {
+        Ok(socket) => socket,
+        Err(e) => {
+            warn!("Agent handshake from {} failed: {}", addr, e);
+            return;
+        }
+    };
+    let (mut sink, mut stream) = socket.split();
+
+    let hello = match tokio::time::timeout(HELLO_TIMEOUT, stream.next()).await {
+        Ok(Some(Ok(Message::Text(text)))) => serde_json::from_str::<AgentMessage>(&text).ok(),
+        _ => None,
+    };
+    let Some(AgentMessage::Hello { auth, name, hostname, agent_version, protocol_versions }) = hello else {
+        warn!("Agent at {} did not send a hello", addr);
+        return;
+    };
+
+    let Some(protocol_version) = protocol::negotiate_version(&protocol_versions) else {
+        warn!("Agent {} at {} speaks unsupported protocol versions {:?}", name, addr, protocol_versions);
+        let message = format!("Supported protocol versions: {:?}", protocol::SUPPORTED_VERSIONS);
+        let error = ProtocolError::new(ErrorCode::UnsupportedVersion, message);
+        if let Some(frame) = encode(&ControllerMessage::Rejected { error }) {
+            let _ = sink.send(frame).await;
+        }
+        return;
+    };
+
+    let authenticated = match node_manager.lock() {
+        Ok(mut manager) => manager.authenticate_agent(auth, &name, &addr.to_string()),
+        Err(_) => return,
+    };
+    let (node_id, credential) = match authenticated {
+        Ok(authenticated) => authenticated,
+        Err(error) => {
+            if let Some(frame) = encode(&ControllerMessage::Rejected { error }) {
+                let _ = sink.send(frame).await;
+            }
+            return;
+        }
+    };
+
+    let (sender, mut outgoing) = mpsc::unbounded_channel();
+    let connection_id = match node_manager.lock() {
+        Ok(mut manager) => match manager.attach_connection(&node_id, hostname, protocol_version, sender) {
+            Ok(connection_id) => connection_id,
+            Err(e) => {
+                error!("Failed to register node {}: {}", node_id, e);
+                return;
+            }
+        },
+        Err(_) => return,
+    };
+    info!("Agent {} v{} at {} authenticated as node {}", name, agent_version, addr, node_id);
+
+    let welcome = ControllerMessage::Welcome { node_id: node_id.clone(), protocol_version, credential };
+    let mut result = match encode(&welcome) {
+        Some(frame) => sink.send(frame).await.map_err(|e| e.to_string()),
+        None => Err("Failed to serialize welcome".to_string()),
+    };
+
+    let rotate = node_manager.lock()
+        .map(|manager| manager.needs_credential_rotation(&node_id))
+        .unwrap_or(false);
+    if rotate {
+        let node_manager = node_manager.clone();
+        let node_id = node_id.clone();
+        tokio::spawn(async move {
+            if let Err(e) = node::rotate_credential(&node_manager, &node_id).await {
+                warn!("Failed to rotate credential of node {}: {}", node_id, e);
+            }
+        });
+    }
+
+    while result.is_ok() {
+        tokio::select! {
+            message = outgoing.recv() => {
+                let Some(frame) = message.as_ref().and_then(encode) else { break };
+                result = sink.send(frame).await.map_err(|e| e.to_string());
+            },
+            incoming = stream.next() => {
+                match incoming {
+                    Some(Ok(Message::Text(text))) => match serde_json::from_str::<AgentMessage>(&text) {
+                        Ok(message) => {
+                            if let Ok(mut manager) = node_manager.lock() {
+                                manager.handle_agent_message(&node_id, message);
+                            }
+                        },
+                        Err(e) => warn!("Invalid message from node {}: {}", node_id, e),
+                    },
+                    Some(Ok(Message::Ping(payload))) => {
+                        result = sink.send(Message::Pong(payload)).await.map_err(|e| e.to_string());
+                    },
+                    Some(Ok(Message::Close(_))) | None => break,
+                    Some(Ok(_)) => {},
+                    Some(Err(e)) => result = Err(e.to_string()),
+                }
+            },
+        }
+    }
+
+    if let Err(e) = result {
+        warn!("Connection to node {} failed: {}", node_id, e);
+    }
+    if let Ok(mut manager) = node_manager.lock() {
+        manager.detach_connection(&node_id, &connection_id);
+    }
+}
+
+type ControllerSettingsState<'a> = State<'a, Arc<Mutex<ControllerSettingsStore>>>;
+
+#[tauri::command]
+pub fn get_controller_settings(state: ControllerSettingsState) -> Result<ControllerSettings, String> {
+    let store = state.lock().map_err(|_| "Failed to lock controller settings")?;
+    Ok(store.settings.clone())
+}
+
+/// Takes effect the next time ServerMint starts.
+#[tauri::command]
+pub fn update_controller_settings(state: ControllerSettingsState, settings: ControllerSettings) -> Result<(), String> {
+    let mut store = state.lock().map_err(|_| "Failed to lock controller settings")?;
+    store.settings = settings;
+    store.save()
+}
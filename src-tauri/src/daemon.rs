@@ -0,0 +1,449 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use log::{info, warn, error};
+
+use crate::agent::{Agent, AgentConfig};
+use crate::api::{self, ApiContext, ApiTokenStore};
+use crate::controller;
+use crate::destination::DestinationStore;
+use crate::ipc;
+use crate::metrics;
+use crate::egg::EGG_MANAGER;
+use crate::node::{self, NodeManager, WatchdogConfig};
+use crate::pairing::PairingStore;
+use crate::retention::RetentionStore;
+use crate::server::ServerManager;
+use crate::sftp_server::{self, SftpServerContext, SftpServerStore};
+use crate::ssh;
+
+pub const DEFAULT_CONFIG_PATHS: [&str; 2] = ["servermintd.toml", "/etc/servermint/servermintd.toml"];
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DaemonConfig {
+    #[serde(default)]
+    pub daemon: DaemonSection,
+    #[serde(default)]
+    pub servers: ServersSection,
+    #[serde(default)]
+    pub api: ApiSection,
+    #[serde(default)]
+    pub control: ControlSection,
+    #[serde(default)]
+    pub controller: ControllerSection,
+    #[serde(default)]
+    pub agent: Option<AgentConfig>,
+}
+
+/// Accept connections from remote node agents.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ControllerSection {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_controller_bind")]
+    pub bind: String,
+    #[serde(default)]
+    pub watchdog: WatchdogConfig,
+}
+
+fn default_controller_bind() -> String {
+    controller::DEFAULT_BIND.to_string()
+}
+
+impl Default for ControllerSection {
+    fn default() -> Self {
+        ControllerSection {
+            enabled: false,
+            bind: default_controller_bind(),
+            watchdog: WatchdogConfig::default(),
+        }
+    }
+}
+
+/// Unix socket used by the `servermint` CLI.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ControlSection {
+    pub socket: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DaemonSection {
+    #[serde(default = "default_log_dir")]
+    pub log_dir: String,
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    /// Stop running servers when the daemon receives SIGTERM/SIGINT. When
+    /// false, servers keep running under their supervisors and are reattached
+    /// on the next start.
+    #[serde(default = "default_true")]
+    pub stop_servers_on_exit: bool,
+    #[serde(default = "default_stop_timeout")]
+    pub stop_timeout_secs: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ServersSection {
+    #[serde(default = "default_servers_directory")]
+    pub directory: String,
+}
+
+/// Tokens are shared with the desktop app's store and can be created with
+/// `servermintd create-token`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ApiSection {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_api_bind")]
+    pub bind: String,
+}
+
+fn default_api_bind() -> String {
+    "127.0.0.1:8765".to_string()
+}
+
+impl Default for ApiSection {
+    fn default() -> Self {
+        ApiSection {
+            enabled: false,
+            bind: default_api_bind(),
+        }
+    }
+}
+
+fn default_log_dir() -> String {
+    "logs".to_string()
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_stop_timeout() -> u64 {
+    30
+}
+
+fn default_servers_directory() -> String {
+    "servers".to_string()
+}
+
+impl Default for DaemonSection {
+    fn default() -> Self {
+        DaemonSection {
+            log_dir: default_log_dir(),
+            log_level: default_log_level(),
+            stop_servers_on_exit: true,
+            stop_timeout_secs: default_stop_timeout(),
+        }
+    }
+}
+
+impl Default for ServersSection {
+    fn default() -> Self {
+        ServersSection {
+            directory: default_servers_directory(),
+        }
+    }
+}
+
+impl DaemonConfig {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {}: {}", path.display(), e))?;
+        toml::from_str(&contents)
+            .map_err(|e| format!("Failed to parse config {}: {}", path.display(), e))
+    }
+
+    /// Loads `explicit` if given, otherwise the first of the default locations
+    /// that exists. Falls back to built-in defaults when none is found.
+    pub fn locate(explicit: Option<PathBuf>) -> Result<(Self, Option<PathBuf>), String> {
+        if let Some(path) = explicit {
+            return Ok((Self::load(&path)?, Some(path)));
+        }
+
+        for candidate in DEFAULT_CONFIG_PATHS {
+            let path = PathBuf::from(candidate);
+            if path.exists() {
+                return Ok((Self::load(&path)?, Some(path)));
+            }
+        }
+
+        Ok((DaemonConfig {
+            daemon: DaemonSection::default(),
+            servers: ServersSection::default(),
+            api: ApiSection::default(),
+            control: ControlSection::default(),
+            controller: ControllerSection::default(),
+            agent: None,
+        }, None))
+    }
+}
+
+/// Creates a one-time code that a node agent exchanges for its credential.
+/// Passing a node ID re-pairs that node instead of creating a new one.
+pub fn create_pairing_code(node_id: Option<String>) -> Result<String, String> {
+    PairingStore::new().create_code(node_id)
+}
+
+/// The managers shared between the daemon's background tasks.
+#[derive(Clone)]
+pub struct Daemon {
+    pub config: DaemonConfig,
+    pub server_manager: Arc<Mutex<ServerManager>>,
+    pub node_manager: Arc<Mutex<NodeManager>>,
+    pub api_tokens: Arc<Mutex<ApiTokenStore>>,
+    pub sftp_accounts: Arc<Mutex<SftpServerStore>>,
+    pub backup_retention: Arc<Mutex<RetentionStore>>,
+    pub backup_destinations: Arc<Mutex<DestinationStore>>,
+}
+
+impl Daemon {
+    pub fn new(config: DaemonConfig) -> Result<Self, String> {
+        fs::create_dir_all(&config.servers.directory)
+            .map_err(|e| format!("Failed to create servers directory: {}", e))?;
+
+        let server_manager = Arc::new(Mutex::new(ServerManager::new()));
+        let node_manager = Arc::new(Mutex::new(NodeManager::new(server_manager.clone())));
+
+        let egg_count = EGG_MANAGER.lock()
+            .map_err(|e| format!("Failed to lock egg manager: {}", e))?
+            .list_eggs()
+            .len();
+        info!("Loaded {} eggs", egg_count);
+
+        Ok(Daemon {
+            config,
+            server_manager,
+            node_manager,
+            api_tokens: Arc::new(Mutex::new(ApiTokenStore::new())),
+            sftp_accounts: Arc::new(Mutex::new(SftpServerStore::new())),
+            backup_retention: Arc::new(Mutex::new(RetentionStore::new())),
+            backup_destinations: Arc::new(Mutex::new(DestinationStore::new())),
+        })
+    }
+
+    /// Asks every running server to `stop` and waits up to
+    /// `stop_timeout_secs` before killing whatever is left.
+    pub fn stop_all_servers_gracefully(&self) {
+        let running: Vec<String> = match self.server_manager.lock() {
+            Ok(manager) => manager.list_servers()
+                .into_iter()
+                .filter(|s| s.status == "online")
+                .map(|s| s.id)
+                .collect(),
+            Err(_) => {
+                error!("Failed to lock server manager during shutdown");
+                return;
+            }
+        };
+
+        if running.is_empty() {
+            return;
+        }
+
+        info!("Stopping {} running servers", running.len());
+        if let Ok(mut manager) = self.server_manager.lock() {
+            for id in &running {
+                if let Err(e) = manager.send_server_command(id, "stop") {
+                    warn!("Failed to send stop to server {}: {}", id, e);
+                }
+            }
+        }
+
+        let deadline = Instant::now() + Duration::from_secs(self.config.daemon.stop_timeout_secs);
+        loop {
+            let remaining: Vec<String> = match self.server_manager.lock() {
+                Ok(manager) => running.iter()
+                    .filter(|id| manager.is_running(id))
+                    .cloned()
+                    .collect(),
+                Err(_) => break,
+            };
+
+            if remaining.is_empty() {
+                info!("All servers stopped");
+                break;
+            }
+
+            if Instant::now() > deadline {
+                if let Ok(mut manager) = self.server_manager.lock() {
+                    for id in &remaining {
+                        warn!("Server {} did not stop within {}s, killing it", id, self.config.daemon.stop_timeout_secs);
+                        if let Err(e) = manager.stop_server(id) {
+                            error!("Failed to kill server {}: {}", id, e);
+                        }
+                    }
+                }
+                break;
+            }
+
+            std::thread::sleep(Duration::from_millis(500));
+        }
+    }
+}
+
+fn init_logging(config: &DaemonSection) -> Result<(), String> {
+    fs::create_dir_all(&config.log_dir)
+        .map_err(|e| format!("Failed to create log directory: {}", e))?;
+
+    let log_path = Path::new(&config.log_dir).join("servermintd.log");
+    let log_file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&log_path)
+        .map_err(|e| format!("Failed to open log file {}: {}", log_path.display(), e))?;
+
+    let level = config.log_level.parse::<log::LevelFilter>()
+        .map_err(|_| format!("Invalid log level: {}", config.log_level))?;
+
+    env_logger::Builder::new()
+        .filter_level(level)
+        .target(env_logger::Target::Pipe(Box::new(log_file)))
+        .try_init()
+        .map_err(|e| format!("Failed to initialize logging: {}", e))
+}
+
+async fn wait_for_shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(e) => {
+                error!("Failed to install SIGTERM handler: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            }
+        };
+
+        tokio::select! {
+            _ = terminate.recv() => info!("Received SIGTERM"),
+            _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+        info!("Received Ctrl+C");
+    }
+}
+
+/// Runs ServerMint without Tauri until SIGTERM/SIGINT.
+pub fn run(config_path: Option<PathBuf>) -> Result<(), String> {
+    let (config, loaded_from) = DaemonConfig::locate(config_path)?;
+    init_logging(&config.daemon)?;
+
+    match &loaded_from {
+        Some(path) => info!("servermintd {} starting with config {}", env!("CARGO_PKG_VERSION"), path.display()),
+        None => info!("servermintd {} starting with default config", env!("CARGO_PKG_VERSION")),
+    }
+
+    let daemon = Daemon::new(config)?;
+
+    let runtime = tokio::runtime::Runtime::new()
+        .map_err(|e| format!("Failed to start async runtime: {}", e))?;
+
+    runtime.block_on(async {
+        if daemon.config.api.enabled {
+            let ctx = ApiContext {
+                server_manager: daemon.server_manager.clone(),
+                tokens: daemon.api_tokens.clone(),
+                backup_retention: daemon.backup_retention.clone(),
+                backup_destinations: daemon.backup_destinations.clone(),
+            };
+            let bind = daemon.config.api.bind.clone();
+            tokio::spawn(async move {
+                if let Err(e) = api::serve(ctx, bind).await {
+                    error!("Control API stopped: {}", e);
+                }
+            });
+        }
+
+        let sftp_settings = daemon.sftp_accounts.lock()
+            .map(|store| store.settings.clone())
+            .unwrap_or_default();
+        if sftp_settings.enabled {
+            let ctx = SftpServerContext {
+                server_manager: daemon.server_manager.clone(),
+                store: daemon.sftp_accounts.clone(),
+            };
+            tokio::spawn(async move {
+                if let Err(e) = sftp_server::serve(ctx, sftp_settings.bind).await {
+                    error!("SFTP server stopped: {}", e);
+                }
+            });
+        }
+
+        #[cfg(unix)]
+        {
+            let ctx = ipc::IpcContext {
+                server_manager: daemon.server_manager.clone(),
+                servers_dir: daemon.config.servers.directory.clone(),
+                backup_retention: daemon.backup_retention.clone(),
+                backup_destinations: daemon.backup_destinations.clone(),
+            };
+            let socket = daemon.config.control.socket.clone()
+                .map(PathBuf::from)
+                .unwrap_or_else(ipc::default_socket_path);
+            tokio::spawn(async move {
+                if let Err(e) = ipc::serve(ctx, socket).await {
+                    error!("Control socket stopped: {}", e);
+                }
+            });
+        }
+
+        tokio::spawn(metrics::collect_local(daemon.node_manager.clone()));
+
+        if daemon.config.controller.enabled {
+            let node_manager = daemon.node_manager.clone();
+            let bind = daemon.config.controller.bind.clone();
+            tokio::spawn(async move {
+                if let Err(e) = controller::serve(node_manager, bind).await {
+                    error!("Node controller stopped: {}", e);
+                }
+            });
+            tokio::spawn(node::run_watchdog(daemon.node_manager.clone(), daemon.config.controller.watchdog.clone()));
+            for node_id in ssh::ssh_node_ids(&daemon.node_manager) {
+                tokio::spawn(ssh::run_node(daemon.node_manager.clone(), node_id));
+            }
+        }
+
+        if let Some(agent_config) = daemon.config.agent.clone() {
+            info!("Running as node agent {}", agent_config.name);
+            let servers_dir = daemon.config.servers.directory.clone();
+            tokio::spawn(Agent::new(agent_config, daemon.server_manager.clone(), servers_dir).run());
+        }
+
+        wait_for_shutdown_signal().await;
+    });
+
+    if daemon.config.daemon.stop_servers_on_exit {
+        let shutdown = daemon.clone();
+        runtime.block_on(async move {
+            let _ = tokio::task::spawn_blocking(move || shutdown.stop_all_servers_gracefully()).await;
+        });
+    } else {
+        info!("Leaving servers running under their supervisors");
+    }
+
+    if let Ok(manager) = daemon.server_manager.lock() {
+        if let Err(e) = manager.save_servers() {
+            error!("Failed to save servers on shutdown: {}", e);
+        }
+    }
+    if let Ok(manager) = daemon.node_manager.lock() {
+        if let Err(e) = manager.save_nodes() {
+            error!("Failed to save nodes on shutdown: {}", e);
+        }
+    }
+
+    info!("servermintd stopped");
+    Ok(())
+}
@@ -0,0 +1,1143 @@
+//! Backup destinations: local directories, SFTP targets and S3-compatible
+//! object storage that backups are copied to after they're taken.
+//!
+//! A destination mirrors the local store's layout, `chunks/<xx>/<id>` and
+//! `snapshots/<server id>/<backup id>.json`, so only chunks it doesn't have
+//! yet are uploaded, and the manifest goes last so a snapshot is never
+//! visible before its chunks. Each destination has its own retention
+//! policy, applied to the snapshots it holds.
+//!
+//! Destinations persist to `backup_destinations.json` with their passwords
+//! and secret keys sealed with AES-256-GCM under a key kept beside it in
+//! `backup_destinations.key`. The key file is readable by the current user
+//! only (mode 0600 on Unix, an ACL without inherited entries on Windows), so
+//! the JSON alone, say in a copied settings folder or a support bundle,
+//! gives nothing away. Anyone who can read that user's files can still
+//! read both.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tauri::State;
+use tokio::task;
+
+use crate::backup::{self, BackupInfo, BackupManifest, StoreUsage};
+use crate::chunk_store::{self, ChunkStore, STORE_LOCK};
+use crate::retention::{self, PrunePlan, RetentionPolicy, RetentionStore};
+use crate::s3::{S3Client, S3Config};
+use crate::sftp::{SftpConfig, SftpError};
+use crate::transfer;
+
+const SEALED_PREFIX: &str = "enc:v1:";
+
+lazy_static::lazy_static! {
+    /// Held while writing to or pruning any destination, so pruning never
+    /// collects chunks an upload is about to reference.
+    static ref DESTINATION_LOCK: Mutex<()> = Mutex::new(());
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DestinationTarget {
+    Local { path: String },
+    Sftp { config: SftpConfig },
+    S3 { config: S3Config },
+}
+
+impl DestinationTarget {
+    fn secrets_mut(&mut self) -> Vec<&mut String> {
+        match self {
+            DestinationTarget::Local { .. } => Vec::new(),
+            DestinationTarget::Sftp { config } => {
+                let mut secrets = vec![&mut config.password];
+                if let Some(passphrase) = config.passphrase.as_mut() {
+                    secrets.push(passphrase);
+                }
+                secrets
+            },
+            DestinationTarget::S3 { config } => vec![&mut config.secret_access_key],
+        }
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            DestinationTarget::Local { path } => {
+                if !Path::new(path).is_absolute() {
+                    return Err("A local destination needs an absolute path".to_string());
+                }
+            },
+            DestinationTarget::Sftp { config } => {
+                if config.host.trim().is_empty() || config.username.is_empty() {
+                    return Err("An SFTP destination needs a host and a username".to_string());
+                }
+            },
+            DestinationTarget::S3 { config } => {
+                S3Client::new(config.clone())?;
+            },
+        }
+        Ok(())
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupDestination {
+    pub id: String,
+    pub name: String,
+    /// Enabled destinations receive every new backup.
+    pub enabled: bool,
+    pub target: DestinationTarget,
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    /// Reads every uploaded object back and compares it with what was sent.
+    #[serde(default = "default_true")]
+    pub verify_uploads: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A destination as the UI creates or edits it. Empty secrets keep the
+/// stored ones.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DestinationRequest {
+    pub name: String,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    pub target: DestinationTarget,
+    #[serde(default)]
+    pub retention: RetentionPolicy,
+    #[serde(default = "default_true")]
+    pub verify_uploads: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UploadReport {
+    pub destination_id: String,
+    pub server_id: String,
+    pub backup_id: String,
+    pub uploaded_chunks: usize,
+    /// Chunks the destination already had.
+    pub existing_chunks: usize,
+    pub uploaded_bytes: u64,
+    pub verified: bool,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct DestinationsFile {
+    destinations: Vec<BackupDestination>,
+}
+
+/// Configured destinations, persisted with their secrets sealed.
+#[derive(Debug)]
+pub struct DestinationStore {
+    path: PathBuf,
+    key_path: PathBuf,
+    destinations: Vec<BackupDestination>,
+}
+
+impl DestinationStore {
+    pub fn new() -> Self {
+        let app_data_dir = std::env::var("APPDATA")
+            .unwrap_or_else(|_| std::env::var("HOME").unwrap_or_else(|_| ".".to_string()));
+
+        let mut store = DestinationStore {
+            path: PathBuf::from(format!("{}/ServerMint/backup_destinations.json", app_data_dir)),
+            key_path: PathBuf::from(format!("{}/ServerMint/backup_destinations.key", app_data_dir)),
+            destinations: Vec::new(),
+        };
+
+        // Keys written before they were restricted may still be readable by
+        // others.
+        if store.key_path.exists() {
+            if let Err(e) = restrict_to_owner(&store.key_path) {
+                warn!("{}", e);
+            }
+        }
+        if let Err(e) = store.load() {
+            warn!("Failed to load backup destinations: {}", e);
+        }
+
+        store
+    }
+
+    /// Loads the sealing key, generating it on first use when `create`.
+    fn sealing_key(&self, create: bool) -> Result<LessSafeKey, String> {
+        let mut bytes = [0u8; 32];
+        if self.key_path.exists() {
+            let stored = fs::read(&self.key_path)
+                .map_err(|e| format!("Failed to read the destination key: {}", e))?;
+            if stored.len() != bytes.len() {
+                return Err("The destination key is damaged".to_string());
+            }
+            bytes.copy_from_slice(&stored);
+        } else if create {
+            SystemRandom::new().fill(&mut bytes)
+                .map_err(|_| "Failed to generate the destination key".to_string())?;
+            if let Some(parent) = self.key_path.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create directory: {}", e))?;
+            }
+            let mut options = OpenOptions::new();
+            options.write(true).create_new(true);
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            let mut file = options.open(&self.key_path)
+                .map_err(|e| format!("Failed to write the destination key: {}", e))?;
+            let written = restrict_to_owner(&self.key_path).and_then(|_| {
+                file.write_all(&bytes).map_err(|e| format!("Failed to write the destination key: {}", e))
+            });
+            if let Err(e) = written {
+                drop(file);
+                let _ = fs::remove_file(&self.key_path);
+                return Err(e);
+            }
+        } else {
+            return Err("The destination key is missing, so stored secrets can't be read".to_string());
+        }
+
+        let key = UnboundKey::new(&AES_256_GCM, &bytes)
+            .map_err(|_| "Invalid destination key".to_string())?;
+        Ok(LessSafeKey::new(key))
+    }
+
+    fn load(&mut self) -> Result<(), String> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+
+        let contents = fs::read_to_string(&self.path)
+            .map_err(|e| format!("Failed to read {}: {}", self.path.display(), e))?;
+        let file: DestinationsFile = serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse {}: {}", self.path.display(), e))?;
+
+        let mut destinations = file.destinations;
+        let mut key = None;
+        for destination in &mut destinations {
+            for secret in destination.target.secrets_mut() {
+                if secret.is_empty() {
+                    continue;
+                }
+                if key.is_none() {
+                    key = Some(self.sealing_key(false)?);
+                }
+                *secret = open_secret(key.as_ref().expect("key was just loaded"), &destination.id, secret)?;
+            }
+        }
+        self.destinations = destinations;
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+
+        let mut destinations = self.destinations.clone();
+        let mut key = None;
+        for destination in &mut destinations {
+            for secret in destination.target.secrets_mut() {
+                if secret.is_empty() {
+                    continue;
+                }
+                if key.is_none() {
+                    key = Some(self.sealing_key(true)?);
+                }
+                *secret = seal_secret(key.as_ref().expect("key was just loaded"), &destination.id, secret)?;
+            }
+        }
+
+        let json = serde_json::to_string_pretty(&DestinationsFile { destinations })
+            .map_err(|e| format!("Failed to serialize backup destinations: {}", e))?;
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)
+            .map_err(|e| format!("Failed to write backup destinations: {}", e))?;
+        fs::rename(&tmp_path, &self.path)
+            .map_err(|e| format!("Failed to replace backup destinations: {}", e))
+    }
+
+    /// Every destination, with secrets blanked.
+    pub fn list(&self) -> Vec<BackupDestination> {
+        self.destinations.iter().map(redacted).collect()
+    }
+
+    pub fn get(&self, id: &str) -> Result<BackupDestination, String> {
+        self.destinations.iter()
+            .find(|d| d.id == id)
+            .cloned()
+            .ok_or_else(|| format!("Backup destination {} not found", id))
+    }
+
+    pub fn enabled(&self) -> Vec<BackupDestination> {
+        self.destinations.iter().filter(|d| d.enabled).cloned().collect()
+    }
+
+    fn check_request(&self, request: &DestinationRequest, id: Option<&str>) -> Result<(), String> {
+        let name = request.name.trim();
+        if name.is_empty() {
+            return Err("A backup destination needs a name".to_string());
+        }
+        if self.destinations.iter().any(|d| d.name == name && Some(d.id.as_str()) != id) {
+            return Err(format!("A backup destination named {} already exists", name));
+        }
+        request.target.validate()
+    }
+
+    pub fn create(&mut self, request: DestinationRequest) -> Result<BackupDestination, String> {
+        self.check_request(&request, None)?;
+        let destination = BackupDestination {
+            id: uuid::Uuid::new_v4().to_string(),
+            name: request.name.trim().to_string(),
+            enabled: request.enabled,
+            target: request.target,
+            retention: request.retention,
+            verify_uploads: request.verify_uploads,
+            created_at: Utc::now(),
+        };
+        self.destinations.push(destination.clone());
+        if let Err(e) = self.save() {
+            self.destinations.pop();
+            return Err(e);
+        }
+        info!("Added backup destination {}", destination.name);
+        Ok(redacted(&destination))
+    }
+
+    pub fn update(&mut self, id: &str, request: DestinationRequest) -> Result<BackupDestination, String> {
+        self.check_request(&request, Some(id))?;
+        let index = self.destinations.iter()
+            .position(|d| d.id == id)
+            .ok_or_else(|| format!("Backup destination {} not found", id))?;
+
+        let previous = self.destinations[index].clone();
+        let mut target = request.target;
+        if std::mem::discriminant(&target) == std::mem::discriminant(&previous.target) {
+            let mut old = previous.target.clone();
+            for (new, old) in target.secrets_mut().into_iter().zip(old.secrets_mut()) {
+                if new.is_empty() {
+                    *new = std::mem::take(old);
+                }
+            }
+        }
+
+        let destination = &mut self.destinations[index];
+        destination.name = request.name.trim().to_string();
+        destination.enabled = request.enabled;
+        destination.target = target;
+        destination.retention = request.retention;
+        destination.verify_uploads = request.verify_uploads;
+        let updated = destination.clone();
+        if let Err(e) = self.save() {
+            self.destinations[index] = previous;
+            return Err(e);
+        }
+        Ok(redacted(&updated))
+    }
+
+    /// Forgets a destination. What it holds is left in place.
+    pub fn delete(&mut self, id: &str) -> Result<(), String> {
+        let index = self.destinations.iter()
+            .position(|d| d.id == id)
+            .ok_or_else(|| format!("Backup destination {} not found", id))?;
+        let removed = self.destinations.remove(index);
+        if let Err(e) = self.save() {
+            self.destinations.insert(index, removed);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+impl Default for DestinationStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Makes `path` readable and writable by the current user only.
+fn restrict_to_owner(path: &Path) -> Result<(), String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+            .map_err(|e| format!("Failed to restrict access to {}: {}", path.display(), e))?;
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        let user = match (std::env::var("USERDOMAIN"), std::env::var("USERNAME")) {
+            (Ok(domain), Ok(user)) => format!("{}\\{}", domain, user),
+            (Err(_), Ok(user)) => user,
+            _ => return Err(format!("Failed to restrict access to {}: USERNAME is not set", path.display())),
+        };
+        // Drops inherited entries and replaces any explicit ones, leaving
+        // full control for the current user alone.
+        let grant = format!("{}:F", user);
+        let output = std::process::Command::new("icacls")
+            .arg(path)
+            .args(["/inheritance:r", "/grant:r", grant.as_str()])
+            .creation_flags(0x08000000)
+            .output()
+            .map_err(|e| format!("Failed to run icacls on {}: {}", path.display(), e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "Failed to restrict access to {}: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stdout).trim()
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn redacted(destination: &BackupDestination) -> BackupDestination {
+    let mut destination = destination.clone();
+    for secret in destination.target.secrets_mut() {
+        secret.clear();
+    }
+    destination
+}
+
+/// Encrypts a secret, bound to the destination it belongs to.
+fn seal_secret(key: &LessSafeKey, destination_id: &str, secret: &str) -> Result<String, String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    SystemRandom::new().fill(&mut nonce)
+        .map_err(|_| "Failed to generate a nonce".to_string())?;
+    let mut sealed = secret.as_bytes().to_vec();
+    key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(destination_id.as_bytes()), &mut sealed)
+        .map_err(|_| "Failed to encrypt a destination secret".to_string())?;
+
+    let mut encoded = nonce.to_vec();
+    encoded.extend_from_slice(&sealed);
+    Ok(format!("{}{}", SEALED_PREFIX, STANDARD.encode(encoded)))
+}
+
+fn open_secret(key: &LessSafeKey, destination_id: &str, sealed: &str) -> Result<String, String> {
+    let invalid = || format!("A secret of backup destination {} can't be decrypted", destination_id);
+    let encoded = sealed.strip_prefix(SEALED_PREFIX).ok_or_else(invalid)?;
+    let mut bytes = STANDARD.decode(encoded).map_err(|_| invalid())?;
+    if bytes.len() < NONCE_LEN {
+        return Err(invalid());
+    }
+    let mut ciphertext = bytes.split_off(NONCE_LEN);
+    let nonce = Nonce::try_assume_unique_for_key(&bytes).map_err(|_| invalid())?;
+    let plaintext = key.open_in_place(nonce, Aad::from(destination_id.as_bytes()), &mut ciphertext)
+        .map_err(|_| invalid())?;
+    String::from_utf8(plaintext.to_vec()).map_err(|_| invalid())
+}
+
+/// Object storage as destinations use it. Keys are `/`-separated and
+/// relative to the destination's root; listing prefixes name directories
+/// and end in `/`.
+trait Backend {
+    fn list(&self, prefix: &str) -> Result<Vec<(String, u64)>, String>;
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), String>;
+    fn get(&self, key: &str) -> Result<Vec<u8>, String>;
+    fn delete(&self, key: &str) -> Result<(), String>;
+}
+
+struct LocalBackend {
+    root: PathBuf,
+}
+
+impl LocalBackend {
+    /// Keys name objects under the root, so they can't climb out of it.
+    fn path(&self, key: &str) -> Result<PathBuf, String> {
+        if !Path::new(key).components().all(|c| matches!(c, Component::Normal(_))) {
+            return Err(format!("Invalid object key: {}", key));
+        }
+        Ok(self.root.join(key))
+    }
+}
+
+impl Backend for LocalBackend {
+    fn list(&self, prefix: &str) -> Result<Vec<(String, u64)>, String> {
+        fn walk(dir: &Path, key: &str, objects: &mut Vec<(String, u64)>) -> Result<(), String> {
+            let Ok(entries) = fs::read_dir(dir) else { return Ok(()) };
+            for entry in entries.filter_map(|e| e.ok()) {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let metadata = entry.metadata()
+                    .map_err(|e| format!("Failed to read {}: {}", entry.path().display(), e))?;
+                if metadata.is_dir() {
+                    walk(&entry.path(), &format!("{}{}/", key, name), objects)?;
+                } else if !name.ends_with(".tmp") {
+                    objects.push((format!("{}{}", key, name), metadata.len()));
+                }
+            }
+            Ok(())
+        }
+
+        let mut objects = Vec::new();
+        walk(&self.path(prefix)?, prefix, &mut objects)?;
+        Ok(objects)
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), String> {
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+        fs::write(&tmp_path, data)
+            .map_err(|e| format!("Failed to write {}: {}", tmp_path.display(), e))?;
+        fs::rename(&tmp_path, &path)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        let path = self.path(key)?;
+        fs::read(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        let path = self.path(key)?;
+        fs::remove_file(&path).map_err(|e| format!("Failed to delete {}: {}", path.display(), e))
+    }
+}
+
+struct SftpBackend {
+    config: SftpConfig,
+    root: String,
+    /// Remote directories known to exist.
+    directories: Mutex<HashSet<String>>,
+}
+
+impl SftpBackend {
+    fn new(config: SftpConfig) -> Self {
+        let root = config.resolve("").trim_end_matches('/').to_string();
+        SftpBackend { config, root, directories: Mutex::new(HashSet::new()) }
+    }
+
+    fn path(&self, key: &str) -> String {
+        format!("{}/{}", self.root, key)
+    }
+
+    fn ensure_parent(&self, sftp: &ssh2::Sftp, key: &str) -> Result<(), SftpError> {
+        let mut directories = self.directories.lock().unwrap_or_else(|p| p.into_inner());
+        let mut dir = self.root.clone();
+        for part in key.split('/').collect::<Vec<_>>().split_last().map(|(_, dirs)| dirs).unwrap_or(&[]) {
+            dir = format!("{}/{}", dir, part);
+            if directories.contains(&dir) {
+                continue;
+            }
+            match sftp.stat(Path::new(&dir)) {
+                Ok(stat) if stat.is_dir() => {},
+                _ => sftp.mkdir(Path::new(&dir), 0o755).map_err(|e| SftpError::remote(&dir, e))?,
+            }
+            directories.insert(dir.clone());
+        }
+        Ok(())
+    }
+}
+
+impl Backend for SftpBackend {
+    fn list(&self, prefix: &str) -> Result<Vec<(String, u64)>, String> {
+        fn walk(sftp: &ssh2::Sftp, dir: &str, key: &str, objects: &mut Vec<(String, u64)>) -> Result<(), SftpError> {
+            let entries = match sftp.readdir(Path::new(dir)) {
+                Ok(entries) => entries,
+                Err(e) => match SftpError::remote(dir, e) {
+                    SftpError::NotFound(_) => return Ok(()),
+                    e => return Err(e),
+                },
+            };
+            for (path, stat) in entries {
+                let Some(name) = path.file_name().map(|n| n.to_string_lossy().into_owned()) else { continue };
+                if name == "." || name == ".." {
+                    continue;
+                }
+                if stat.is_dir() {
+                    walk(sftp, &format!("{}/{}", dir, name), &format!("{}{}/", key, name), objects)?;
+                } else if !name.ends_with(".partial") {
+                    objects.push((format!("{}{}", key, name), stat.size.unwrap_or(0)));
+                }
+            }
+            Ok(())
+        }
+
+        let dir = self.path(prefix.trim_end_matches('/'));
+        self.config.with_sftp(|sftp| {
+            let mut objects = Vec::new();
+            walk(sftp, &dir, prefix, &mut objects)?;
+            Ok(objects)
+        }).map_err(|e| e.to_string())
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), String> {
+        let path = self.path(key);
+        let partial = format!("{}.partial", path);
+        self.config.with_sftp(|sftp| {
+            self.ensure_parent(sftp, key)?;
+            let mut file = sftp.create(Path::new(&partial)).map_err(|e| SftpError::remote(&partial, e))?;
+            file.write_all(data).map_err(|source| SftpError::Transfer { path: partial.clone(), source })?;
+            drop(file);
+            transfer::replace_remote(sftp, &partial, &path)
+        }).map_err(|e| e.to_string())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        let path = self.path(key);
+        self.config.with_sftp(|sftp| {
+            let mut file = sftp.open(Path::new(&path)).map_err(|e| SftpError::remote(&path, e))?;
+            let mut data = Vec::new();
+            file.read_to_end(&mut data).map_err(|source| SftpError::Transfer { path: path.clone(), source })?;
+            Ok(data)
+        }).map_err(|e| e.to_string())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        let path = self.path(key);
+        self.config.with_sftp(|sftp| {
+            sftp.unlink(Path::new(&path)).map_err(|e| SftpError::remote(&path, e))
+        }).map_err(|e| e.to_string())
+    }
+}
+
+impl Backend for S3Client {
+    fn list(&self, prefix: &str) -> Result<Vec<(String, u64)>, String> {
+        Ok(S3Client::list(self, prefix)?.into_iter().map(|o| (o.key, o.size)).collect())
+    }
+
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), String> {
+        S3Client::put(self, key, data)
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        S3Client::get(self, key)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), String> {
+        S3Client::delete(self, key)
+    }
+}
+
+fn backend(target: &DestinationTarget) -> Result<Box<dyn Backend>, String> {
+    Ok(match target {
+        DestinationTarget::Local { path } => Box::new(LocalBackend { root: PathBuf::from(path) }),
+        DestinationTarget::Sftp { config } => Box::new(SftpBackend::new(config.clone())),
+        DestinationTarget::S3 { config } => Box::new(S3Client::new(config.clone())?),
+    })
+}
+
+fn chunk_key(id: &str) -> String {
+    format!("chunks/{}/{}", &id[..2], id)
+}
+
+fn manifest_key(server_id: &str, backup_id: &str) -> String {
+    format!("snapshots/{}/{}.json", server_id, backup_id)
+}
+
+/// Chunk ids on the destination, with their sizes.
+fn remote_chunks(backend: &dyn Backend) -> Result<Vec<(String, u64)>, String> {
+    Ok(backend.list("chunks/")?
+        .into_iter()
+        .filter_map(|(key, size)| {
+            let id = key.rsplit('/').next()?;
+            chunk_store::is_chunk_id(id).then(|| (id.to_string(), size))
+        })
+        .collect())
+}
+
+/// A snapshot on a destination, with the key its manifest is stored under.
+type ListedManifest = (String, BackupManifest);
+
+/// Snapshots on the destination, of one server or all, newest first, with
+/// the keys they're stored under, and a message for each manifest that
+/// couldn't be read.
+fn scan_remote_manifests(backend: &dyn Backend, server_id: Option<&str>) -> Result<(Vec<ListedManifest>, Vec<String>), String> {
+    let prefix = match server_id {
+        Some(server_id) => {
+            backup::check_id("server", server_id)?;
+            format!("snapshots/{}/", server_id)
+        },
+        None => "snapshots/".to_string(),
+    };
+    let mut manifests = Vec::new();
+    let mut failures = Vec::new();
+    for (key, _) in backend.list(&prefix)? {
+        if !key.ends_with(".json") {
+            continue;
+        }
+        // The ids come from the destination, and end up in local paths.
+        match backend.get(&key)
+            .and_then(|data| {
+                serde_json::from_slice::<BackupManifest>(&data).map_err(|e| format!("Failed to parse {}: {}", key, e))
+            })
+            .and_then(|manifest| {
+                backup::check_id("server", &manifest.server_id)?;
+                backup::check_id("backup", &manifest.id)?;
+                Ok(manifest)
+            }) {
+            Ok(manifest) => manifests.push((key, manifest)),
+            Err(e) => failures.push(format!("{}: {}", key, e)),
+        }
+    }
+    manifests.sort_by_key(|(_, m)| std::cmp::Reverse(m.created_at));
+    Ok((manifests, failures))
+}
+
+/// Snapshots on the destination, of one server or all, newest first.
+/// Unreadable manifests are skipped.
+fn remote_manifests(backend: &dyn Backend, server_id: Option<&str>) -> Result<Vec<BackupManifest>, String> {
+    let (manifests, failures) = scan_remote_manifests(backend, server_id)?;
+    for failure in failures {
+        warn!("Skipping remote backup {}", failure);
+    }
+    Ok(manifests.into_iter().map(|(_, manifest)| manifest).collect())
+}
+
+/// Every snapshot on the destination, with its key. Fails if any manifest
+/// can't be read, since the chunks only that snapshot uses would look
+/// unreferenced.
+fn all_remote_manifests(backend: &dyn Backend) -> Result<Vec<ListedManifest>, String> {
+    let (manifests, failures) = scan_remote_manifests(backend, None)?;
+    match failures.first() {
+        Some(failure) => Err(format!("{} remote backup manifests could not be read: {}", failures.len(), failure)),
+        None => Ok(manifests),
+    }
+}
+
+fn put_verified(backend: &dyn Backend, key: &str, data: &[u8], verify: bool) -> Result<(), String> {
+    backend.put(key, data)?;
+    if verify && backend.get(key)? != data {
+        return Err(format!("{} did not read back as it was uploaded", key));
+    }
+    Ok(())
+}
+
+/// Copies a local backup to a destination, skipping chunks it already has.
+pub fn upload(destination: &BackupDestination, server_id: &str, backup_id: &str) -> Result<UploadReport, String> {
+    let _store = STORE_LOCK.read().map_err(|_| "Failed to lock backup store")?;
+    let _destinations = DESTINATION_LOCK.lock().map_err(|_| "Failed to lock backup destinations")?;
+
+    let manifest = backup::load_manifest(server_id, backup_id)?;
+    let backend = backend(&destination.target)?;
+    let report = send_backup(destination, backend.as_ref(), &backup::chunk_store(), &manifest)?;
+
+    info!(
+        "Uploaded backup {} of {} to {} ({} new chunks, {} already there, {} bytes)",
+        backup_id,
+        server_id,
+        destination.name,
+        report.uploaded_chunks,
+        report.existing_chunks,
+        report.uploaded_bytes
+    );
+    Ok(report)
+}
+
+/// Puts the chunks of `manifest` that `backend` doesn't have yet, then the
+/// manifest itself.
+fn send_backup(
+    destination: &BackupDestination,
+    backend: &dyn Backend,
+    store: &ChunkStore,
+    manifest: &BackupManifest,
+) -> Result<UploadReport, String> {
+    let existing: HashSet<String> = remote_chunks(backend)?.into_iter().map(|(id, _)| id).collect();
+
+    let mut report = UploadReport {
+        destination_id: destination.id.clone(),
+        server_id: manifest.server_id.clone(),
+        backup_id: manifest.id.clone(),
+        uploaded_chunks: 0,
+        existing_chunks: 0,
+        uploaded_bytes: 0,
+        verified: destination.verify_uploads,
+    };
+    let mut seen = HashSet::new();
+    for id in manifest.files.iter().flat_map(|f| &f.chunks) {
+        if !seen.insert(id.as_str()) {
+            continue;
+        }
+        if existing.contains(id) {
+            report.existing_chunks += 1;
+            continue;
+        }
+        let data = store.read_encoded(id)?;
+        put_verified(backend, &chunk_key(id), &data, destination.verify_uploads)?;
+        report.uploaded_chunks += 1;
+        report.uploaded_bytes += data.len() as u64;
+    }
+
+    let json = serde_json::to_vec(manifest)
+        .map_err(|e| format!("Failed to serialize backup manifest: {}", e))?;
+    put_verified(backend, &manifest_key(&manifest.server_id, &manifest.id), &json, destination.verify_uploads)?;
+    report.uploaded_bytes += json.len() as u64;
+    Ok(report)
+}
+
+/// Applies the destination's retention policy to a server's snapshots there,
+/// then deletes the chunks no remaining snapshot uses.
+pub fn prune(
+    destination: &BackupDestination,
+    pinned: &BTreeSet<String>,
+    server_id: &str,
+    dry_run: bool,
+) -> Result<PrunePlan, String> {
+    backup::check_id("server", server_id)?;
+    let _destinations = DESTINATION_LOCK.lock().map_err(|_| "Failed to lock backup destinations")?;
+    let backend = backend(&destination.target)?;
+    let (keys, manifests): (Vec<String>, Vec<BackupManifest>) = all_remote_manifests(backend.as_ref())?.into_iter().unzip();
+    let chunks = remote_chunks(backend.as_ref())?;
+    let usage = StoreUsage::new(&manifests, chunks.iter().cloned().collect());
+
+    let backups = manifests.iter()
+        .filter(|m| m.server_id == server_id)
+        .map(BackupInfo::from)
+        .collect();
+    let mut plan = retention::plan(server_id, &destination.retention, backups, pinned, &usage);
+    plan.dry_run = dry_run;
+    if dry_run || plan.remove.is_empty() {
+        return Ok(plan);
+    }
+
+    let removed: HashSet<&str> = plan.remove.iter().map(|e| e.backup.id.as_str()).collect();
+    // Deleted by the key they were listed under, not one built from the ids
+    // they claim.
+    for (key, _) in keys.iter().zip(&manifests).filter(|(_, m)| m.server_id == server_id && removed.contains(m.id.as_str())) {
+        backend.delete(key)?;
+    }
+    let referenced: HashSet<&String> = manifests.iter()
+        .filter(|m| m.server_id != server_id || !removed.contains(m.id.as_str()))
+        .flat_map(|m| m.files.iter().flat_map(|f| &f.chunks))
+        .collect();
+    let mut collected = 0;
+    for (id, _) in chunks.iter().filter(|(id, _)| !referenced.contains(id)) {
+        backend.delete(&chunk_key(id))?;
+        collected += 1;
+    }
+
+    info!(
+        "Pruned {} backups of {} on {}, removing {} chunks",
+        plan.remove.len(),
+        server_id,
+        destination.name,
+        collected
+    );
+    Ok(plan)
+}
+
+/// Uploads a new backup to every enabled destination and prunes each by
+/// its policy. Returns a message for every destination that failed.
+pub fn replicate(
+    destinations: &Mutex<DestinationStore>,
+    retention: &Mutex<RetentionStore>,
+    server_id: &str,
+    backup_id: &str,
+) -> Vec<String> {
+    let targets = match destinations.lock() {
+        Ok(destinations) => destinations.enabled(),
+        Err(_) => return vec!["Failed to lock backup destinations".to_string()],
+    };
+    let pinned = retention.lock().map(|r| r.pinned(server_id)).unwrap_or_default();
+
+    let mut warnings = Vec::new();
+    for destination in targets {
+        if let Err(e) = upload(&destination, server_id, backup_id) {
+            warn!("Failed to upload backup {} to {}: {}", backup_id, destination.name, e);
+            warnings.push(format!("Upload to {} failed: {}", destination.name, e));
+            continue;
+        }
+        if destination.retention != RetentionPolicy::default() {
+            if let Err(e) = prune(&destination, &pinned, server_id, false) {
+                warn!("Failed to prune backups on {}: {}", destination.name, e);
+                warnings.push(format!("Pruning {} failed: {}", destination.name, e));
+            }
+        }
+    }
+    warnings
+}
+
+/// Writes, reads back and deletes a small object.
+pub fn test(destination: &BackupDestination) -> Result<(), String> {
+    let backend = backend(&destination.target)?;
+    let key = format!("servermint-probe-{}", uuid::Uuid::new_v4());
+    let data = format!("ServerMint destination check {}", Utc::now().to_rfc3339());
+    backend.put(&key, data.as_bytes())?;
+    let read = backend.get(&key);
+    backend.delete(&key)?;
+    if read? != data.as_bytes() {
+        return Err("The test object did not read back as it was written".to_string());
+    }
+    Ok(())
+}
+
+type DestinationState<'a> = State<'a, Arc<Mutex<DestinationStore>>>;
+
+fn destination(state: &DestinationState, id: &str) -> Result<BackupDestination, String> {
+    state.lock().map_err(|_| "Failed to lock backup destinations")?.get(id)
+}
+
+#[tauri::command]
+pub fn list_backup_destinations(state: DestinationState) -> Result<Vec<BackupDestination>, String> {
+    Ok(state.lock().map_err(|_| "Failed to lock backup destinations")?.list())
+}
+
+#[tauri::command]
+pub fn create_backup_destination(state: DestinationState, request: DestinationRequest) -> Result<BackupDestination, String> {
+    state.lock().map_err(|_| "Failed to lock backup destinations")?.create(request)
+}
+
+#[tauri::command]
+pub fn update_backup_destination(state: DestinationState, id: String, request: DestinationRequest) -> Result<BackupDestination, String> {
+    state.lock().map_err(|_| "Failed to lock backup destinations")?.update(&id, request)
+}
+
+#[tauri::command]
+pub fn delete_backup_destination(state: DestinationState, id: String) -> Result<(), String> {
+    state.lock().map_err(|_| "Failed to lock backup destinations")?.delete(&id)
+}
+
+#[tauri::command]
+pub async fn test_backup_destination(state: DestinationState<'_>, id: String) -> Result<(), String> {
+    let destination = destination(&state, &id)?;
+    task::spawn_blocking(move || test(&destination))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+#[tauri::command]
+pub async fn upload_backup(
+    state: DestinationState<'_>,
+    destination_id: String,
+    server_id: String,
+    backup_id: String,
+) -> Result<UploadReport, String> {
+    let destination = destination(&state, &destination_id)?;
+    task::spawn_blocking(move || upload(&destination, &server_id, &backup_id))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+#[tauri::command]
+pub async fn list_destination_backups(
+    state: DestinationState<'_>,
+    retention: State<'_, Arc<Mutex<RetentionStore>>>,
+    destination_id: String,
+    server_id: Option<String>,
+) -> Result<Vec<BackupInfo>, String> {
+    let destination = destination(&state, &destination_id)?;
+    let manifests = task::spawn_blocking(move || {
+        let backend = backend(&destination.target)?;
+        remote_manifests(backend.as_ref(), server_id.as_deref())
+    })
+    .await
+    .map_err(|e| format!("Task join error: {}", e))??;
+
+    let retention = retention.lock().map_err(|_| "Failed to lock backup retention")?;
+    Ok(manifests.iter()
+        .map(|m| {
+            let mut backup = BackupInfo::from(m);
+            backup.pinned = retention.is_pinned(&backup.server_id, &backup.id);
+            backup
+        })
+        .collect())
+}
+
+/// Prunes a server's backups on a destination now, or with `dry_run` set,
+/// only reports what pruning would remove.
+#[tauri::command]
+pub async fn prune_destination_backups(
+    state: DestinationState<'_>,
+    retention: State<'_, Arc<Mutex<RetentionStore>>>,
+    destination_id: String,
+    server_id: String,
+    dry_run: bool,
+) -> Result<PrunePlan, String> {
+    let destination = destination(&state, &destination_id)?;
+    let pinned = retention.lock().map_err(|_| "Failed to lock backup retention")?.pinned(&server_id);
+    task::spawn_blocking(move || prune(&destination, &pinned, &server_id, dry_run))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::backup::BackupFile;
+    use crate::server::ServerConfig;
+    use crate::test_support::{scratch_dir, S3Stub};
+    use chrono::Duration;
+
+    const SERVER_ID: &str = "server-1";
+
+    fn manifest(server_id: &str, id: &str, age_minutes: i64, chunks: &[&str]) -> BackupManifest {
+        BackupManifest {
+            id: id.to_string(),
+            server_id: server_id.to_string(),
+            created_at: Utc::now() - Duration::minutes(age_minutes),
+            server: ServerConfig {
+                name: "Survival".to_string(),
+                path: "/srv/survival".to_string(),
+                version: "1.21.1".to_string(),
+                server_type: "paper".to_string(),
+                java_path: None,
+                min_memory: 1024,
+                max_memory: 4096,
+                jvm_args: None,
+                port: 25565,
+            },
+            game_version: "1.21.1".to_string(),
+            consistent: true,
+            label: None,
+            directories: Vec::new(),
+            files: vec![BackupFile {
+                path: "world/level.dat".to_string(),
+                size: 0,
+                sha256: String::new(),
+                modified: None,
+                chunks: chunks.iter().map(|c| c.to_string()).collect(),
+            }],
+            total_size: 0,
+            added_chunks: chunks.len(),
+            added_size: 0,
+        }
+    }
+
+    fn keep_last_one(target: DestinationTarget) -> BackupDestination {
+        BackupDestination {
+            id: "destination-1".to_string(),
+            name: "Offsite".to_string(),
+            enabled: true,
+            target,
+            retention: RetentionPolicy { keep_last: Some(1), ..RetentionPolicy::default() },
+            verify_uploads: true,
+            created_at: Utc::now(),
+        }
+    }
+
+    fn put_manifest(backend: &dyn Backend, manifest: &BackupManifest) {
+        let json = serde_json::to_vec(manifest).unwrap();
+        backend.put(&manifest_key(&manifest.server_id, &manifest.id), &json).unwrap();
+    }
+
+    #[test]
+    fn prune_keeps_every_chunk_while_a_manifest_is_unreadable() {
+        let root = scratch_dir("destination-prune");
+        let backend = LocalBackend { root: root.clone() };
+        let destination = keep_last_one(DestinationTarget::Local { path: root.display().to_string() });
+
+        let (old, new, other) = (chunk_store::chunk_id(b"old"), chunk_store::chunk_id(b"new"), chunk_store::chunk_id(b"other"));
+        for id in [&old, &new, &other] {
+            backend.put(&chunk_key(id), id.as_bytes()).unwrap();
+        }
+        put_manifest(&backend, &manifest(SERVER_ID, "old", 10, &[&old]));
+        put_manifest(&backend, &manifest(SERVER_ID, "new", 0, &[&new]));
+        // Only this snapshot of another server uses `other`.
+        backend.put(&manifest_key("server-2", "broken"), b"{\"id\":").unwrap();
+
+        let error = prune(&destination, &BTreeSet::new(), SERVER_ID, false).unwrap_err();
+        assert!(error.contains("could not be read"), "{}", error);
+        assert!(backend.get(&manifest_key(SERVER_ID, "old")).is_ok());
+        for id in [&old, &new, &other] {
+            assert!(backend.get(&chunk_key(id)).is_ok());
+        }
+
+        // Listing still shows the readable snapshots.
+        assert_eq!(remote_manifests(&backend, None).unwrap().len(), 2);
+
+        backend.put(&manifest_key("server-2", "broken"), &serde_json::to_vec(&manifest("server-2", "broken", 0, &[&other])).unwrap()).unwrap();
+        let plan = prune(&destination, &BTreeSet::new(), SERVER_ID, false).unwrap();
+        assert_eq!(plan.remove.iter().map(|e| e.backup.id.as_str()).collect::<Vec<_>>(), ["old"]);
+        assert!(backend.get(&manifest_key(SERVER_ID, "old")).is_err());
+        assert!(backend.get(&chunk_key(&old)).is_err());
+        assert!(backend.get(&chunk_key(&new)).is_ok());
+        assert!(backend.get(&chunk_key(&other)).is_ok());
+    }
+
+    #[test]
+    fn remote_manifest_ids_are_checked_before_anything_is_deleted() {
+        let dir = scratch_dir("destination-remote-ids");
+        let root = dir.join("destination");
+        let backend = LocalBackend { root: root.clone() };
+        let destination = keep_last_one(DestinationTarget::Local { path: root.display().to_string() });
+        fs::write(dir.join("victim.json"), "keep me").unwrap();
+        assert!(backend.delete("../victim.json").is_err());
+        assert!(backend.get("snapshots/../../victim.json").is_err());
+
+        // Listed under an innocent key, but claiming an id that climbs out.
+        put_manifest(&backend, &manifest(SERVER_ID, "new", 0, &[]));
+        backend.put("snapshots/server-1/old.json", &serde_json::to_vec(&manifest(SERVER_ID, "../../victim", 10, &[])).unwrap()).unwrap();
+        let error = prune(&destination, &BTreeSet::new(), SERVER_ID, false).unwrap_err();
+        assert!(error.contains("Invalid backup id"), "{}", error);
+        assert_eq!(remote_manifests(&backend, None).unwrap().len(), 1);
+        assert!(dir.join("victim.json").exists());
+
+        // A valid id under another key: the key it's listed under goes.
+        backend.put("snapshots/server-1/old.json", &serde_json::to_vec(&manifest(SERVER_ID, "renamed", 10, &[])).unwrap()).unwrap();
+        let plan = prune(&destination, &BTreeSet::new(), SERVER_ID, false).unwrap();
+        assert_eq!(plan.remove.iter().map(|e| e.backup.id.as_str()).collect::<Vec<_>>(), ["renamed"]);
+        assert!(backend.get("snapshots/server-1/old.json").is_err());
+        assert!(backend.get(&manifest_key(SERVER_ID, "new")).is_ok());
+    }
+
+    #[test]
+    fn backups_upload_to_and_prune_from_s3() {
+        let store = ChunkStore::new(scratch_dir("destination-s3-store"));
+        let (shared, _) = store.put(b"level.dat").unwrap();
+        let (old_only, _) = store.put(b"old region").unwrap();
+        let (new_only, _) = store.put(b"new region").unwrap();
+        let stub = S3Stub::start();
+        let destination = keep_last_one(DestinationTarget::S3 { config: stub.config("servermint") });
+        let backend = backend(&destination.target).unwrap();
+
+        let report = send_backup(&destination, backend.as_ref(), &store, &manifest(SERVER_ID, "old", 10, &[&shared, &old_only])).unwrap();
+        assert_eq!((report.uploaded_chunks, report.existing_chunks, report.verified), (2, 0, true));
+        let report = send_backup(&destination, backend.as_ref(), &store, &manifest(SERVER_ID, "new", 0, &[&shared, &new_only, &shared])).unwrap();
+        assert_eq!((report.uploaded_chunks, report.existing_chunks), (1, 1));
+        assert_eq!(stub.object(&format!("servermint/{}", chunk_key(&new_only))), Some(store.read_encoded(&new_only).unwrap()));
+        let listed: Vec<String> = remote_manifests(backend.as_ref(), Some(SERVER_ID)).unwrap().into_iter().map(|m| m.id).collect();
+        assert_eq!(listed, ["new", "old"]);
+
+        let plan = prune(&destination, &BTreeSet::new(), SERVER_ID, true).unwrap();
+        assert_eq!(plan.remove.len(), 1);
+        assert_eq!(stub.keys().len(), 5);
+
+        stub.insert("servermint/snapshots/server-2/broken.json", b"not json");
+        assert!(prune(&destination, &BTreeSet::new(), SERVER_ID, false).is_err());
+        assert_eq!(stub.keys().len(), 6);
+
+        stub.insert("servermint/snapshots/server-2/broken.json", &serde_json::to_vec(&manifest("server-2", "broken", 0, &[&shared])).unwrap());
+        let plan = prune(&destination, &BTreeSet::new(), SERVER_ID, false).unwrap();
+        assert_eq!(plan.remove[0].backup.id, "old");
+        let remaining: HashSet<String> = remote_chunks(backend.as_ref()).unwrap().into_iter().map(|(id, _)| id).collect();
+        assert_eq!(remaining, HashSet::from([shared, new_only]));
+        assert!(backend.get(&manifest_key(SERVER_ID, "old")).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn the_sealing_key_is_readable_by_its_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = scratch_dir("destination-key");
+        let store = DestinationStore {
+            path: dir.join("backup_destinations.json"),
+            key_path: dir.join("backup_destinations.key"),
+            destinations: Vec::new(),
+        };
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        store.sealing_key(true).unwrap();
+        assert_eq!(mode(&store.key_path), 0o600);
+
+        fs::set_permissions(&store.key_path, fs::Permissions::from_mode(0o644)).unwrap();
+        restrict_to_owner(&store.key_path).unwrap();
+        assert_eq!(mode(&store.key_path), 0o600);
+        store.sealing_key(false).unwrap();
+    }
+}
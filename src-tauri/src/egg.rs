@@ -420,7 +420,7 @@ impl EggManager {
 }
 
 lazy_static::lazy_static! {
-    static ref EGG_MANAGER: Arc<Mutex<EggManager>> = {
+    pub(crate) static ref EGG_MANAGER: Arc<Mutex<EggManager>> = {
         Arc::new(Mutex::new(EggManager::new().expect("Failed to initialize egg manager")))
     };
 }
@@ -1,10 +1,19 @@
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, State};
 use zip::write::FileOptions;
 use zip::read::ZipArchive;
 use serde::{Deserialize, Serialize};
 
+use crate::backup;
+use crate::server::{default_max_memory, ServerConfig, ServerManager};
+use crate::setup;
+use crate::sftp::SftpConfig;
+use crate::transfer::{self, TransferDirection};
+
 #[derive(Deserialize, Serialize, Debug)]
 pub struct FileToZip {
     pub name: String,
@@ -145,4 +154,445 @@ fn detect_launcher_type(server_path: &str, files: Vec<FileToZip>) -> Result<Serv
         export_date: None,
         files,
     })
-} 
\ No newline at end of file
+} 
+/// A server pulled in with `import_from_sftp`, and what was detected about it.
+#[derive(Serialize, Debug)]
+pub struct ImportedServer {
+    pub server_id: String,
+    pub name: String,
+    pub path: String,
+    pub server_type: String,
+    pub version: String,
+    pub port: u16,
+    /// The jar ServerMint launches, relative to the server directory.
+    pub server_jar: Option<String>,
+    pub files: u64,
+    pub bytes: u64,
+    /// Anything that could not be detected or had to be changed.
+    pub notes: Vec<String>,
+}
+
+struct DetectedServer {
+    server_type: String,
+    version: Option<String>,
+    /// The jar the server runs from, relative to its directory.
+    jar: Option<String>,
+}
+
+fn read_properties(path: &Path) -> HashMap<String, String> {
+    std::fs::read_to_string(path)
+        .map(|content| {
+            content.lines()
+                .filter(|line| !line.trim_start().starts_with('#'))
+                .filter_map(|line| line.split_once('='))
+                .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The first Minecraft version such as `1.20.4` or `26.1` found in `text`.
+fn find_minecraft_version(text: &str) -> Option<String> {
+    text.split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .map(|part| part.trim_matches('.'))
+        .find(|part| {
+            let numbers: Vec<&str> = part.split('.').collect();
+            let major = numbers[0].parse::<u32>().unwrap_or(0);
+            (2..=3).contains(&numbers.len())
+                && numbers.iter().all(|n| !n.is_empty())
+                && (major == 1 || (24..100).contains(&major))
+        })
+        .map(str::to_string)
+}
+
+fn server_type_from_name(file_name: &str) -> Option<&'static str> {
+    let name = file_name.to_lowercase();
+    [
+        ("paper", "paper"),
+        ("purpur", "purpur"),
+        ("spigot", "spigot"),
+        ("craftbukkit", "spigot"),
+        ("fabric-server", "fabric"),
+        ("neoforge", "neoforge"),
+        ("forge", "forge"),
+        ("minecraft_server", "vanilla"),
+    ]
+    .into_iter()
+    .find(|(prefix, _)| name.starts_with(prefix))
+    .map(|(_, server_type)| server_type)
+}
+
+fn server_type_from_main_class(main_class: &str) -> Option<&'static str> {
+    if main_class.contains("paperclip") {
+        Some("paper")
+    } else if main_class.starts_with("net.fabricmc") {
+        Some("fabric")
+    } else if main_class.starts_with("org.bukkit.craftbukkit") {
+        Some("spigot")
+    } else if main_class.starts_with("net.minecraftforge") {
+        Some("forge")
+    } else if main_class.starts_with("net.minecraft.") {
+        Some("vanilla")
+    } else {
+        None
+    }
+}
+
+fn read_jar_entry(archive: &mut ZipArchive<File>, name: &str) -> Option<String> {
+    let mut entry = archive.by_name(name).ok()?;
+    let mut content = String::new();
+    entry.read_to_string(&mut content).ok()?;
+    Some(content)
+}
+
+fn manifest_value<'a>(manifest: &'a str, key: &str) -> Option<&'a str> {
+    manifest.lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim() == key)
+        .map(|(_, value)| value.trim())
+}
+
+/// The server type and Minecraft version of a jar, from its file name and
+/// then its contents.
+fn inspect_jar(path: &Path) -> (Option<&'static str>, Option<String>) {
+    let file_name = path.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+    let mut server_type = server_type_from_name(&file_name);
+    let mut version = find_minecraft_version(&file_name);
+    if server_type.is_some() && version.is_some() {
+        return (server_type, version);
+    }
+
+    let Ok(mut archive) = File::open(path).map_err(|e| e.to_string())
+        .and_then(|file| ZipArchive::new(file).map_err(|e| e.to_string()))
+    else {
+        return (server_type, version);
+    };
+    let manifest = read_jar_entry(&mut archive, "META-INF/MANIFEST.MF").unwrap_or_default();
+
+    if server_type.is_none() {
+        server_type = manifest_value(&manifest, "Main-Class").and_then(server_type_from_main_class);
+    }
+    if version.is_none() {
+        version = read_jar_entry(&mut archive, "version.json")
+            .and_then(|json| serde_json::from_str::<serde_json::Value>(&json).ok())
+            .and_then(|json| json.get("id").and_then(|id| id.as_str()).map(str::to_string))
+            .or_else(|| read_jar_entry(&mut archive, "META-INF/versions.list").and_then(|l| find_minecraft_version(&l)))
+            .or_else(|| {
+                let implementation = manifest_value(&manifest, "Implementation-Version")?;
+                let mc = implementation.split_once("MC:").map(|(_, v)| v).unwrap_or(implementation);
+                find_minecraft_version(mc)
+            });
+    }
+    (server_type, version)
+}
+
+/// The newest version directory under a Maven group in `libraries/`.
+fn library_version(server_path: &Path, group: &str) -> Option<String> {
+    std::fs::read_dir(server_path.join("libraries").join(group)).ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().is_dir())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        // By number, so 21.1.65 is newer than 21.1.9.
+        .max_by_key(|version| {
+            let numbers: Vec<u64> = version.split(['.', '-']).map(|part| part.parse().unwrap_or(0)).collect();
+            (numbers, version.clone())
+        })
+}
+
+fn detect_server(server_path: &Path) -> DetectedServer {
+    if server_path.join("PocketMine-MP.phar").exists() {
+        return DetectedServer { server_type: "pocketmine".to_string(), version: None, jar: None };
+    }
+
+    let mut jars: Vec<String> = std::fs::read_dir(server_path)
+        .map(|entries| {
+            entries.filter_map(Result::ok)
+                .map(|entry| entry.file_name().to_string_lossy().to_string())
+                .filter(|name| name.to_lowercase().ends_with(".jar") && !name.to_lowercase().contains("installer"))
+                .collect()
+        })
+        .unwrap_or_default();
+    jars.sort_by_key(|name| (name != "server.jar", name.clone()));
+    let inspected: Vec<(String, Option<&'static str>, Option<String>)> = jars.into_iter()
+        .map(|jar| {
+            let (server_type, version) = inspect_jar(&server_path.join(&jar));
+            (jar, server_type, version)
+        })
+        .collect();
+
+    // Modern Forge and NeoForge start from their libraries through run.sh
+    // rather than from a jar in the server directory.
+    for (server_type, group) in [("neoforge", "net/neoforged/neoforge"), ("forge", "net/minecraftforge/forge")] {
+        if let Some(version) = library_version(server_path, group) {
+            let jar = inspected.iter().find(|(_, t, _)| *t == Some(server_type)).map(|(jar, _, _)| jar.clone());
+            return DetectedServer { server_type: server_type.to_string(), version: Some(version), jar };
+        }
+    }
+
+    // A loader such as Fabric sits next to the vanilla jar it launches.
+    let Some((jar, server_type, version)) = inspected.iter()
+        .find(|(_, server_type, _)| server_type.is_some_and(|t| t != "vanilla"))
+        .or_else(|| inspected.iter().find(|(_, server_type, _)| server_type.is_some()))
+        .or_else(|| inspected.first())
+        .cloned()
+    else {
+        return DetectedServer { server_type: "vanilla".to_string(), version: None, jar: None };
+    };
+
+    let version = version.or_else(|| {
+        inspected.iter().filter(|(other, _, _)| *other != jar).find_map(|(_, _, version)| version.clone())
+    });
+    DetectedServer { server_type: server_type.unwrap_or("vanilla").to_string(), version, jar: Some(jar) }
+}
+
+/// Makes the detected jar available as `server.jar`, which is what
+/// `ServerManager` launches. Returns a note when something was changed.
+fn prepare_server_jar(server_path: &Path, detected: &DetectedServer) -> Result<Option<String>, String> {
+    let Some(jar) = detected.jar.as_deref().filter(|jar| *jar != "server.jar") else {
+        return Ok(None);
+    };
+    let server_jar = server_path.join("server.jar");
+
+    if server_jar.exists() {
+        if detected.server_type != "fabric" {
+            return Ok(Some(format!("Kept the existing server.jar; {} was not used", jar)));
+        }
+        // The old Fabric launcher runs the vanilla jar named in its
+        // properties, which is server.jar unless configured otherwise.
+        std::fs::rename(&server_jar, server_path.join("vanilla-server.jar"))
+            .map_err(|e| format!("Failed to rename server.jar: {}", e))?;
+        std::fs::write(server_path.join("fabric-server-launcher.properties"), "serverJarPath=vanilla-server.jar\n")
+            .map_err(|e| format!("Failed to write fabric-server-launcher.properties: {}", e))?;
+    }
+
+    std::fs::copy(server_path.join(jar), &server_jar)
+        .map_err(|e| format!("Failed to copy {} to server.jar: {}", jar, e))?;
+    Ok(Some(format!("Copied {} to server.jar", jar)))
+}
+
+/// Downloads a server directory over SFTP, detects what it runs and
+/// registers it as a new local server.
+#[tauri::command]
+pub async fn import_from_sftp(
+    app: AppHandle,
+    state: State<'_, Arc<Mutex<ServerManager>>>,
+    config: SftpConfig,
+    remote_path: String,
+    name: Option<String>,
+    server_id: Option<String>,
+) -> Result<ImportedServer, String> {
+    let server_id = server_id.unwrap_or_else(|| uuid::Uuid::new_v4().to_string());
+    backup::check_id("server", &server_id)?;
+    if state.lock().map_err(|_| "Failed to lock server manager")?.get_server_info(&server_id).is_ok() {
+        return Err(format!("Server {} already exists", server_id));
+    }
+
+    let server_path = format!("{}/{}", setup::SERVERS_DIR, server_id);
+    if Path::new(&server_path).exists() {
+        return Err(format!("{} already exists", server_path));
+    }
+    println!("Importing {} from {} into {}", remote_path, config.host, server_path);
+    let imported = download_and_register(app, &state, config, remote_path, name, server_id, server_path.clone()).await;
+    if imported.is_err() {
+        // Don't leave a partial download where the next import would go.
+        if let Err(e) = std::fs::remove_dir_all(&server_path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                println!("Warning: Failed to remove {}: {}", server_path, e);
+            }
+        }
+    }
+    imported
+}
+
+async fn download_and_register(
+    app: AppHandle,
+    state: &Mutex<ServerManager>,
+    config: SftpConfig,
+    remote_path: String,
+    name: Option<String>,
+    server_id: String,
+    server_path: String,
+) -> Result<ImportedServer, String> {
+    let (_, done) = transfer::enqueue(
+        config,
+        TransferDirection::Download,
+        remote_path.clone(),
+        server_path.clone(),
+        transfer::emit_to(app),
+    )?;
+    let progress = transfer::wait(done).await
+        .map_err(|e| format!("Failed to download server: {}", e))?;
+
+    let path = Path::new(&server_path);
+    let detected = detect_server(path);
+    let mut notes = Vec::new();
+    if let Some(note) = prepare_server_jar(path, &detected)? {
+        notes.push(note);
+    }
+    let server_jar = if path.join("server.jar").exists() { Some("server.jar".to_string()) } else { None };
+    let is_bedrock = detected.server_type == "pocketmine";
+    if server_jar.is_none() && !is_bedrock {
+        notes.push(format!(
+            "No server jar was found; {} servers installed with libraries must be started with their own run script",
+            detected.server_type
+        ));
+    }
+
+    let version = detected.version.clone().unwrap_or_else(|| {
+        notes.push("Could not detect the Minecraft version".to_string());
+        "unknown".to_string()
+    });
+
+    let properties = read_properties(&path.join("server.properties"));
+    let default_port = if is_bedrock { 19132 } else { 25565 };
+    let port = match properties.get("server-port") {
+        Some(port) => port.parse().unwrap_or_else(|_| {
+            notes.push(format!("Invalid server-port {}, using {}", port, default_port));
+            default_port
+        }),
+        None => {
+            notes.push(format!("server.properties has no server-port, using {}", default_port));
+            default_port
+        },
+    };
+
+    let name = name.filter(|n| !n.trim().is_empty())
+        .or_else(|| properties.get("motd").filter(|m| !m.is_empty() && *m != "A Minecraft Server").cloned())
+        .or_else(|| {
+            Path::new(remote_path.trim_end_matches('/')).file_name()
+                .map(|n| n.to_string_lossy().to_string())
+        })
+        .unwrap_or_else(|| "Imported Server".to_string());
+
+    let config = ServerConfig {
+        name: name.clone(),
+        path: server_path.clone(),
+        version: version.clone(),
+        server_type: detected.server_type.clone(),
+        java_path: None,
+        min_memory: if is_bedrock { 512 } else { 1024 },
+        max_memory: default_max_memory(&detected.server_type),
+        jvm_args: None,
+        port,
+    };
+    state.lock().map_err(|_| "Failed to lock server manager")?
+        .add_server(server_id.clone(), config)?;
+
+    println!("Imported {} server {} ({}) on port {}", detected.server_type, server_id, version, port);
+    Ok(ImportedServer {
+        server_id,
+        name,
+        path: server_path,
+        server_type: detected.server_type,
+        version,
+        port,
+        server_jar,
+        files: progress.files_total,
+        bytes: progress.bytes_total,
+        notes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    fn write_jar(path: &Path, entries: &[(&str, &str)]) {
+        let mut zip = zip::ZipWriter::new(File::create(path).unwrap());
+        for (name, content) in entries {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    fn fs_write(path: &Path, contents: &str) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+
+    #[test]
+    fn minecraft_versions_are_found_in_names_and_text() {
+        for (text, version) in [
+            ("paper-1.20.4-496.jar", Some("1.20.4")),
+            ("fabric-server-mc.1.21.1-loader.0.16.5-launcher.1.0.1.jar", Some("1.21.1")),
+            ("minecraft_server.1.8.jar", Some("1.8")),
+            ("git-Paper-196 (MC: 1.20.1)", Some("1.20.1")),
+            ("26.1", Some("26.1")),
+            ("server.jar", None),
+            ("spigot-3.0.jar", None),
+            ("build 1234", None),
+        ] {
+            assert_eq!(find_minecraft_version(text).as_deref(), version, "{}", text);
+        }
+    }
+
+    #[test]
+    fn jars_are_identified_by_name_then_contents() {
+        let dir = scratch_dir("export-inspect-jar");
+
+        // Both from the name, without opening the file.
+        fs_write(&dir.join("purpur-1.21.jar"), "not a zip");
+        assert_eq!(inspect_jar(&dir.join("purpur-1.21.jar")), (Some("purpur"), Some("1.21".to_string())));
+
+        write_jar(&dir.join("server.jar"), &[
+            ("META-INF/MANIFEST.MF", "Manifest-Version: 1.0\nMain-Class: net.fabricmc.installer.ServerLauncher\n"),
+            ("version.json", r#"{"id": "1.21.1", "name": "1.21.1"}"#),
+        ]);
+        assert_eq!(inspect_jar(&dir.join("server.jar")), (Some("fabric"), Some("1.21.1".to_string())));
+
+        write_jar(&dir.join("paperclip.jar"), &[
+            ("META-INF/MANIFEST.MF", "Main-Class: io.papermc.paperclip.Main\n"),
+            ("META-INF/versions.list", "abc123\tpaper-1.20.4\tpaper-1.20.4.jar\n"),
+        ]);
+        assert_eq!(inspect_jar(&dir.join("paperclip.jar")), (Some("paper"), Some("1.20.4".to_string())));
+
+        write_jar(&dir.join("custom.jar"), &[
+            ("META-INF/MANIFEST.MF", "Main-Class: org.bukkit.craftbukkit.Main\nImplementation-Version: 4031-Spigot (MC: 1.20.2)\n"),
+        ]);
+        assert_eq!(inspect_jar(&dir.join("custom.jar")), (Some("spigot"), Some("1.20.2".to_string())));
+
+        fs_write(&dir.join("broken.jar"), "not a zip");
+        assert_eq!(inspect_jar(&dir.join("broken.jar")), (None, None));
+    }
+
+    fn detected(server_path: &Path) -> (String, Option<String>, Option<String>) {
+        let detected = detect_server(server_path);
+        (detected.server_type, detected.version, detected.jar)
+    }
+
+    #[test]
+    fn servers_are_detected_from_their_directory() {
+        let dir = scratch_dir("export-detect-server");
+        let some = |s: &str| Some(s.to_string());
+
+        let empty = dir.join("empty");
+        std::fs::create_dir_all(&empty).unwrap();
+        assert_eq!(detected(&empty), ("vanilla".to_string(), None, None));
+
+        let pocketmine = dir.join("pocketmine");
+        fs_write(&pocketmine.join("PocketMine-MP.phar"), "");
+        fs_write(&pocketmine.join("server.jar"), "");
+        assert_eq!(detected(&pocketmine), ("pocketmine".to_string(), None, None));
+
+        // The loader wins over the vanilla jar it launches, which gives the version.
+        let fabric = dir.join("fabric");
+        std::fs::create_dir_all(&fabric).unwrap();
+        write_jar(&fabric.join("fabric-server-launch.jar"), &[("META-INF/MANIFEST.MF", "Main-Class: net.fabricmc.loader.impl.launch.server.FabricServerLauncher\n")]);
+        write_jar(&fabric.join("server.jar"), &[("version.json", r#"{"id": "1.20.1"}"#)]);
+        fs_write(&fabric.join("forge-installer.jar"), "");
+        assert_eq!(detected(&fabric), ("fabric".to_string(), some("1.20.1"), some("fabric-server-launch.jar")));
+
+        let neoforge = dir.join("neoforge");
+        fs_write(&neoforge.join("libraries/net/neoforged/neoforge/21.1.65/neoforge-21.1.65-server.jar"), "");
+        fs_write(&neoforge.join("libraries/net/neoforged/neoforge/21.1.9/neoforge-21.1.9-server.jar"), "");
+        fs_write(&neoforge.join("run.sh"), "");
+        assert_eq!(detected(&neoforge), ("neoforge".to_string(), some("21.1.65"), None));
+
+        let unknown = dir.join("unknown");
+        std::fs::create_dir_all(&unknown).unwrap();
+        write_jar(&unknown.join("custom-1.19.2.jar"), &[("META-INF/MANIFEST.MF", "Main-Class: com.example.Main\n")]);
+        assert_eq!(detected(&unknown), ("vanilla".to_string(), some("1.19.2"), some("custom-1.19.2.jar")));
+    }
+}
@@ -0,0 +1,330 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::destination::DestinationStore;
+use crate::retention::RetentionStore;
+use crate::server::{ServerInfo, ServerManager};
+
+/// Requests understood by the local control socket. Each request is one JSON
+/// line; replies are JSON lines as well.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum IpcRequest {
+    List,
+    Status { server: String },
+    Start { server: String },
+    Stop { server: String },
+    Restart { server: String },
+    Command { server: String, command: String },
+    /// Replies with `Line`s for the last `lines` of console output, then keeps
+    /// streaming new output. Further requests on the same connection must be
+    /// `Input`.
+    Console { server: String, lines: Option<usize> },
+    Input { line: String },
+    Logs { server: String, lines: Option<usize> },
+    Install {
+        name: String,
+        server_type: String,
+        version: String,
+        path: Option<String>,
+    },
+    /// Backs a server up, prunes its backups and uploads the new one to the
+    /// enabled destinations.
+    Backup { server: String },
+    /// Backups of one server, or of every server.
+    BackupList { server: Option<String> },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum IpcReply {
+    Ok { data: serde_json::Value },
+    Error { message: String },
+    Line { line: String },
+}
+
+pub fn default_socket_path() -> PathBuf {
+    if let Ok(path) = std::env::var("SERVERMINT_SOCKET") {
+        return PathBuf::from(path);
+    }
+
+    let app_data_dir = std::env::var("APPDATA")
+        .unwrap_or_else(|_| std::env::var("HOME").unwrap_or_else(|_| ".".to_string()));
+    PathBuf::from(format!("{}/ServerMint/servermint.sock", app_data_dir))
+}
+
+#[derive(Clone)]
+pub struct IpcContext {
+    pub server_manager: Arc<Mutex<ServerManager>>,
+    pub servers_dir: String,
+    pub backup_retention: Arc<Mutex<RetentionStore>>,
+    pub backup_destinations: Arc<Mutex<DestinationStore>>,
+}
+
+#[cfg(unix)]
+mod listener {
+    use super::*;
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::unix::OwnedWriteHalf;
+    use tokio::net::{UnixListener, UnixStream};
+
+    use crate::backup;
+    use crate::server::install_server;
+
+    /// Accepts a server ID or, if unambiguous, a server name.
+    fn resolve_server(manager: &ServerManager, server: &str) -> Result<ServerInfo, String> {
+        if let Ok(info) = manager.get_server_info(server) {
+            return Ok(info);
+        }
+
+        let matches: Vec<ServerInfo> = manager.list_servers()
+            .into_iter()
+            .filter(|s| s.config.name.eq_ignore_ascii_case(server))
+            .collect();
+
+        match matches.len() {
+            0 => Err(format!("Server {} not found", server)),
+            1 => Ok(matches.into_iter().next().unwrap()),
+            _ => Err(format!("Server name {} is ambiguous, use its ID", server)),
+        }
+    }
+
+    fn tail_lines(lines: Vec<String>, count: Option<usize>) -> Vec<String> {
+        match count {
+            Some(count) if count < lines.len() => lines[lines.len() - count..].to_vec(),
+            _ => lines,
+        }
+    }
+
+    async fn send(writer: &mut OwnedWriteHalf, reply: &IpcReply) -> Result<(), String> {
+        let mut line = serde_json::to_string(reply)
+            .map_err(|e| format!("Failed to serialize reply: {}", e))?;
+        line.push('\n');
+        writer.write_all(line.as_bytes()).await
+            .map_err(|e| format!("Failed to write reply: {}", e))
+    }
+
+    async fn with_manager<T, F>(ctx: &IpcContext, f: F) -> Result<T, String>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut ServerManager) -> Result<T, String> + Send + 'static,
+    {
+        let server_manager = ctx.server_manager.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut manager = server_manager.lock().map_err(|_| "Failed to lock server manager".to_string())?;
+            f(&mut manager)
+        })
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+    }
+
+    async fn handle_request(ctx: &IpcContext, request: IpcRequest) -> Result<serde_json::Value, String> {
+        let to_value = |v: ServerInfo| serde_json::to_value(v).map_err(|e| e.to_string());
+
+        match request {
+            IpcRequest::List => {
+                let servers = with_manager(ctx, |m| Ok(m.list_servers())).await?;
+                serde_json::to_value(servers).map_err(|e| e.to_string())
+            },
+            IpcRequest::Status { server } => {
+                to_value(with_manager(ctx, move |m| resolve_server(m, &server)).await?)
+            },
+            IpcRequest::Start { server } => {
+                to_value(with_manager(ctx, move |m| {
+                    let id = resolve_server(m, &server)?.id;
+                    m.start_server(&id)?;
+                    m.get_server_info(&id)
+                }).await?)
+            },
+            IpcRequest::Stop { server } => {
+                to_value(with_manager(ctx, move |m| {
+                    let id = resolve_server(m, &server)?.id;
+                    m.stop_server(&id)?;
+                    m.get_server_info(&id)
+                }).await?)
+            },
+            IpcRequest::Restart { server } => {
+                to_value(with_manager(ctx, move |m| {
+                    let id = resolve_server(m, &server)?.id;
+                    m.restart_server(&id)?;
+                    m.get_server_info(&id)
+                }).await?)
+            },
+            IpcRequest::Command { server, command } => {
+                with_manager(ctx, move |m| {
+                    let id = resolve_server(m, &server)?.id;
+                    m.send_server_command(&id, &command)
+                }).await?;
+                Ok(serde_json::json!({ "success": true }))
+            },
+            IpcRequest::Logs { server, lines } => {
+                let info = with_manager(ctx, move |m| resolve_server(m, &server)).await?;
+                let log_path = PathBuf::from(&info.config.path).join("logs").join("latest.log");
+                let contents = tokio::fs::read_to_string(&log_path).await
+                    .map_err(|e| format!("Failed to read {}: {}", log_path.display(), e))?;
+                let all: Vec<String> = contents.lines().map(str::to_string).collect();
+                Ok(serde_json::json!({ "lines": tail_lines(all, lines.or(Some(100))) }))
+            },
+            IpcRequest::Install { name, server_type, version, path } => {
+                let id = uuid::Uuid::new_v4().to_string();
+                let path = path.unwrap_or_else(|| format!("{}/{}", ctx.servers_dir, id));
+                install_server(&ctx.server_manager, id.clone(), path, server_type, version, None, name).await?;
+                to_value(with_manager(ctx, move |m| m.get_server_info(&id)).await?)
+            },
+            IpcRequest::Backup { server } => {
+                let id = with_manager(ctx, move |m| resolve_server(m, &server).map(|s| s.id)).await?;
+                let manager = ctx.server_manager.clone();
+                let retention = ctx.backup_retention.clone();
+                let destinations = ctx.backup_destinations.clone();
+                let backup = tokio::task::spawn_blocking(move || backup::run_backup(&manager, &retention, &destinations, &id))
+                    .await
+                    .map_err(|e| format!("Task join error: {}", e))??;
+                serde_json::to_value(backup).map_err(|e| e.to_string())
+            },
+            IpcRequest::BackupList { server } => {
+                let id = match server {
+                    Some(server) => Some(with_manager(ctx, move |m| resolve_server(m, &server).map(|s| s.id)).await?),
+                    None => None,
+                };
+                let retention = ctx.backup_retention.clone();
+                let backups = tokio::task::spawn_blocking(move || backup::list_with_pins(&retention, id.as_deref()))
+                    .await
+                    .map_err(|e| format!("Task join error: {}", e))??;
+                serde_json::to_value(backups).map_err(|e| e.to_string())
+            },
+            IpcRequest::Console { .. } | IpcRequest::Input { .. } => {
+                Err("Console requests are handled per connection".to_string())
+            },
+        }
+    }
+
+    async fn stream_console(
+        ctx: &IpcContext,
+        server: String,
+        lines: Option<usize>,
+        reader: &mut BufReader<tokio::net::unix::OwnedReadHalf>,
+        writer: &mut OwnedWriteHalf,
+    ) -> Result<(), String> {
+        let id = with_manager(ctx, move |m| resolve_server(m, &server).map(|s| s.id)).await?;
+
+        let output = {
+            let id = id.clone();
+            with_manager(ctx, move |m| m.get_server_output(&id)).await?
+        };
+        let mut sent = output.len();
+        for line in tail_lines(output, lines.or(Some(50))) {
+            send(writer, &IpcReply::Line { line }).await?;
+        }
+
+        let mut ticker = tokio::time::interval(Duration::from_millis(250));
+        let mut input = String::new();
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let output = {
+                        let id = id.clone();
+                        with_manager(ctx, move |m| m.get_server_output(&id)).await?
+                    };
+                    if output.len() < sent {
+                        sent = 0;
+                    }
+                    for line in &output[sent..] {
+                        send(writer, &IpcReply::Line { line: line.clone() }).await?;
+                    }
+                    sent = output.len();
+                },
+                read = reader.read_line(&mut input) => {
+                    match read {
+                        Ok(0) | Err(_) => return Ok(()),
+                        Ok(_) => {
+                            match serde_json::from_str::<IpcRequest>(&input) {
+                                Ok(IpcRequest::Input { line }) => {
+                                    let id = id.clone();
+                                    if let Err(e) = with_manager(ctx, move |m| m.send_server_command(&id, &line)).await {
+                                        send(writer, &IpcReply::Error { message: e }).await?;
+                                    }
+                                },
+                                _ => send(writer, &IpcReply::Error { message: "Expected an input request".to_string() }).await?,
+                            }
+                            input.clear();
+                        },
+                    }
+                },
+            }
+        }
+    }
+
+    async fn handle_connection(ctx: IpcContext, stream: UnixStream) {
+        let (read_half, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(read_half);
+        let mut line = String::new();
+
+        loop {
+            line.clear();
+            match reader.read_line(&mut line).await {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {},
+            }
+
+            let request = match serde_json::from_str::<IpcRequest>(&line) {
+                Ok(request) => request,
+                Err(e) => {
+                    let _ = send(&mut writer, &IpcReply::Error { message: format!("Invalid request: {}", e) }).await;
+                    continue;
+                }
+            };
+
+            if let IpcRequest::Console { server, lines } = request {
+                if let Err(e) = stream_console(&ctx, server, lines, &mut reader, &mut writer).await {
+                    let _ = send(&mut writer, &IpcReply::Error { message: e }).await;
+                }
+                return;
+            }
+
+            let reply = match handle_request(&ctx, request).await {
+                Ok(data) => IpcReply::Ok { data },
+                Err(message) => IpcReply::Error { message },
+            };
+            if send(&mut writer, &reply).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Serves the control socket used by the `servermint` CLI. The socket is
+    /// created owner-only; filesystem permissions are its authentication.
+    pub async fn serve(ctx: IpcContext, socket_path: PathBuf) -> Result<(), String> {
+        use std::os::unix::fs::PermissionsExt;
+
+        if let Some(parent) = socket_path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create socket directory: {}", e))?;
+        }
+        if socket_path.exists() {
+            if UnixStream::connect(&socket_path).await.is_ok() {
+                return Err(format!("Another ServerMint instance is listening on {}", socket_path.display()));
+            }
+            let _ = std::fs::remove_file(&socket_path);
+        }
+
+        let listener = UnixListener::bind(&socket_path)
+            .map_err(|e| format!("Failed to bind {}: {}", socket_path.display(), e))?;
+        std::fs::set_permissions(&socket_path, std::fs::Permissions::from_mode(0o600))
+            .map_err(|e| format!("Failed to restrict socket permissions: {}", e))?;
+        println!("Control socket listening on {}", socket_path.display());
+
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    tokio::spawn(handle_connection(ctx.clone(), stream));
+                },
+                Err(e) => println!("Failed to accept control connection: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+pub use listener::serve;
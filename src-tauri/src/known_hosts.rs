@@ -0,0 +1,305 @@
+//! ServerMint's known-hosts store, shared by SFTP and SSH node connections.
+//!
+//! Keys are trusted on first use, but only once the user has accepted the
+//! fingerprint they were shown: an unknown key fails the connection and is
+//! remembered as pending, and `trust_host_key` stores it if the fingerprint
+//! still matches. A key that differs from the stored one is always refused
+//! until the stored key is removed.
+
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use ssh2::{HostKeyType, Session};
+use thiserror::Error;
+
+/// How long a presented key can be accepted after it was shown.
+const PENDING_TTL_MINUTES: i64 = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownHost {
+    pub host: String,
+    pub port: u16,
+    pub key_type: String,
+    /// `SHA256:` and the unpadded base64 digest of the key, as OpenSSH
+    /// prints it.
+    pub fingerprint: String,
+    /// The full public key blob, base64-encoded.
+    pub key: String,
+    pub added_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HostKeyStatus {
+    Trusted,
+    Unknown,
+    Mismatch,
+}
+
+/// The key a host presented, for showing to the user.
+#[derive(Debug, Clone, Serialize)]
+pub struct PresentedKey {
+    pub host: String,
+    pub port: u16,
+    pub key_type: String,
+    pub fingerprint: String,
+    pub status: HostKeyStatus,
+    /// The stored fingerprint when `status` is `mismatch`.
+    pub expected_fingerprint: Option<String>,
+}
+
+#[derive(Debug, Error)]
+pub enum HostKeyError {
+    #[error("{host} did not present a host key")]
+    Missing { host: String },
+    #[error("The host key of {host}:{port} is not trusted yet. Its {key_type} fingerprint is {fingerprint}. Accept the fingerprint to connect.")]
+    Unknown { host: String, port: u16, key_type: String, fingerprint: String },
+    #[error("HOST KEY MISMATCH for {host}:{port}: expected {expected} but the server presented {actual}. The server may have been reinstalled, or someone may be intercepting the connection. Only remove the stored key if you know why it changed.")]
+    Mismatch { host: String, port: u16, expected: String, actual: String },
+}
+
+#[derive(Debug)]
+struct PendingKey {
+    key_type: String,
+    key: Vec<u8>,
+    fingerprint: String,
+    seen_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct KnownHostsFile {
+    hosts: Vec<KnownHost>,
+}
+
+/// Trusted host keys, persisted to `known_hosts.json`, and the keys
+/// presented since that are waiting to be accepted.
+#[derive(Debug)]
+pub struct KnownHostsStore {
+    path: PathBuf,
+    hosts: Vec<KnownHost>,
+    pending: HashMap<(String, u16), PendingKey>,
+}
+
+lazy_static::lazy_static! {
+    static ref KNOWN_HOSTS: Mutex<KnownHostsStore> = Mutex::new(KnownHostsStore::new());
+}
+
+/// Strips a `sftp://` style scheme and trailing slash, and lowercases.
+pub(crate) fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    host.split_once("://")
+        .map(|(_, rest)| rest)
+        .unwrap_or(host)
+        .trim_end_matches('/')
+        .to_lowercase()
+}
+
+pub fn fingerprint(key: &[u8]) -> String {
+    format!("SHA256:{}", STANDARD_NO_PAD.encode(Sha256::digest(key)))
+}
+
+fn key_type_name(key_type: HostKeyType) -> &'static str {
+    match key_type {
+        HostKeyType::Rsa => "ssh-rsa",
+        HostKeyType::Dss => "ssh-dss",
+        HostKeyType::Ecdsa256 => "ecdsa-sha2-nistp256",
+        HostKeyType::Ecdsa384 => "ecdsa-sha2-nistp384",
+        HostKeyType::Ecdsa521 => "ecdsa-sha2-nistp521",
+        HostKeyType::Ed25519 => "ssh-ed25519",
+        HostKeyType::Unknown => "unknown",
+    }
+}
+
+impl KnownHostsStore {
+    pub fn new() -> Self {
+        let app_data_dir = std::env::var("APPDATA")
+            .unwrap_or_else(|_| std::env::var("HOME").unwrap_or_else(|_| ".".to_string()));
+
+        let mut store = KnownHostsStore {
+            path: PathBuf::from(format!("{}/ServerMint/known_hosts.json", app_data_dir)),
+            hosts: Vec::new(),
+            pending: HashMap::new(),
+        };
+
+        if let Err(e) = store.load() {
+            println!("Warning: Failed to load known hosts: {}", e);
+        }
+
+        store
+    }
+
+    fn load(&mut self) -> Result<(), String> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+
+        let contents = fs::read_to_string(&self.path)
+            .map_err(|e| format!("Failed to read {}: {}", self.path.display(), e))?;
+        let file: KnownHostsFile = serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse {}: {}", self.path.display(), e))?;
+
+        self.hosts = file.hosts;
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+
+        let file = KnownHostsFile { hosts: self.hosts.clone() };
+        let json = serde_json::to_string_pretty(&file)
+            .map_err(|e| format!("Failed to serialize known hosts: {}", e))?;
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)
+            .map_err(|e| format!("Failed to write known hosts: {}", e))?;
+        fs::rename(&tmp_path, &self.path)
+            .map_err(|e| format!("Failed to replace known hosts: {}", e))
+    }
+
+    fn find(&self, host: &str, port: u16) -> Option<&KnownHost> {
+        self.hosts.iter().find(|h| h.host == host && h.port == port)
+    }
+
+    /// Compares a presented key with the stored one. Keys that aren't
+    /// trusted are kept as pending so the user can accept them.
+    pub fn check(&mut self, host: &str, port: u16, key_type: &str, key: &[u8]) -> PresentedKey {
+        let host = normalize_host(host);
+        let fingerprint = fingerprint(key);
+        let encoded = STANDARD.encode(key);
+
+        let (status, expected_fingerprint) = match self.find(&host, port) {
+            Some(known) if known.key == encoded => (HostKeyStatus::Trusted, None),
+            Some(known) => (HostKeyStatus::Mismatch, Some(known.fingerprint.clone())),
+            None => (HostKeyStatus::Unknown, None),
+        };
+
+        let now = Utc::now();
+        self.pending.retain(|_, p| now - p.seen_at < Duration::minutes(PENDING_TTL_MINUTES));
+        if status != HostKeyStatus::Trusted {
+            self.pending.insert((host.clone(), port), PendingKey {
+                key_type: key_type.to_string(),
+                key: key.to_vec(),
+                fingerprint: fingerprint.clone(),
+                seen_at: now,
+            });
+        }
+
+        PresentedKey {
+            host,
+            port,
+            key_type: key_type.to_string(),
+            fingerprint,
+            status,
+            expected_fingerprint,
+        }
+    }
+
+    /// Stores the key `host` presented last, provided its fingerprint is the
+    /// one the user accepted.
+    pub fn trust(&mut self, host: &str, port: u16, fingerprint: &str) -> Result<KnownHost, String> {
+        let host = normalize_host(host);
+        if let Some(known) = self.find(&host, port) {
+            if known.fingerprint == fingerprint {
+                return Ok(known.clone());
+            }
+            return Err(format!(
+                "{}:{} already has a different trusted key. Remove it before accepting a new one",
+                host, port
+            ));
+        }
+
+        let pending = self.pending.get(&(host.clone(), port))
+            .filter(|p| Utc::now() - p.seen_at < Duration::minutes(PENDING_TTL_MINUTES))
+            .ok_or_else(|| format!("{}:{} has not presented a key recently, connect to it first", host, port))?;
+        if pending.fingerprint != fingerprint {
+            return Err(format!(
+                "{}:{} presented {}, not {}",
+                host, port, pending.fingerprint, fingerprint
+            ));
+        }
+
+        let known = KnownHost {
+            host: host.clone(),
+            port,
+            key_type: pending.key_type.clone(),
+            fingerprint: pending.fingerprint.clone(),
+            key: STANDARD.encode(&pending.key),
+            added_at: Utc::now(),
+        };
+        self.hosts.push(known.clone());
+        self.save()?;
+        self.pending.remove(&(host, port));
+        Ok(known)
+    }
+
+    pub fn remove(&mut self, host: &str, port: u16) -> Result<(), String> {
+        let host = normalize_host(host);
+        let count = self.hosts.len();
+        self.hosts.retain(|h| !(h.host == host && h.port == port));
+        if self.hosts.len() == count {
+            return Err(format!("No key is stored for {}:{}", host, port));
+        }
+        self.save()
+    }
+
+    pub fn list(&self) -> Vec<KnownHost> {
+        self.hosts.clone()
+    }
+}
+
+fn with_store<T>(f: impl FnOnce(&mut KnownHostsStore) -> T) -> T {
+    let mut store = KNOWN_HOSTS.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    f(&mut store)
+}
+
+/// Checks the key presented during `session`'s handshake.
+pub fn inspect(session: &Session, host: &str, port: u16) -> Result<PresentedKey, HostKeyError> {
+    let (key, key_type) = session.host_key()
+        .ok_or_else(|| HostKeyError::Missing { host: host.to_string() })?;
+    Ok(with_store(|store| store.check(host, port, key_type_name(key_type), key)))
+}
+
+/// Fails unless the key presented during `session`'s handshake is trusted.
+pub fn verify(session: &Session, host: &str, port: u16) -> Result<(), HostKeyError> {
+    let presented = inspect(session, host, port)?;
+    match presented.status {
+        HostKeyStatus::Trusted => Ok(()),
+        HostKeyStatus::Unknown => Err(HostKeyError::Unknown {
+            host: presented.host,
+            port,
+            key_type: presented.key_type,
+            fingerprint: presented.fingerprint,
+        }),
+        HostKeyStatus::Mismatch => Err(HostKeyError::Mismatch {
+            host: presented.host,
+            port,
+            expected: presented.expected_fingerprint.unwrap_or_default(),
+            actual: presented.fingerprint,
+        }),
+    }
+}
+
+#[tauri::command]
+pub fn list_known_hosts() -> Result<Vec<KnownHost>, String> {
+    Ok(with_store(|store| store.list()))
+}
+
+/// Accepts the key `host` presented, after the user compared `fingerprint`.
+#[tauri::command]
+pub fn trust_host_key(host: String, port: u16, fingerprint: String) -> Result<KnownHost, String> {
+    with_store(|store| store.trust(&host, port, fingerprint.trim()))
+}
+
+#[tauri::command]
+pub fn remove_known_host(host: String, port: u16) -> Result<(), String> {
+    with_store(|store| store.remove(&host, port))
+}
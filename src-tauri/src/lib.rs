@@ -5,6 +5,30 @@ mod sftp;
 mod export;
 mod modpack;
 mod egg;
+mod pairing;
+mod metrics;
+mod migration;
+mod ssh;
+mod known_hosts;
+mod transfer;
+mod sync;
+mod ssh_server;
+mod sftp_server;
+mod chunk_store;
+mod backup;
+mod retention;
+mod restore;
+mod s3;
+mod destination;
+#[cfg(test)]
+mod test_support;
+pub mod supervisor;
+pub mod daemon;
+pub mod api;
+pub mod ipc;
+pub mod agent;
+pub mod protocol;
+pub mod controller;
 
 use std::sync::{Arc, Mutex};
 use server::ServerManager;
@@ -373,27 +397,36 @@ async fn stop_all_servers(state: tauri::State<'_, Arc<Mutex<ServerManager>>>) ->
 }
 
 #[tauri::command]
-async fn backup_all_servers(state: tauri::State<'_, Arc<Mutex<ServerManager>>>) -> Result<serde_json::Value, String> {
+async fn backup_all_servers(
+    state: tauri::State<'_, Arc<Mutex<ServerManager>>>,
+    retention: tauri::State<'_, Arc<Mutex<retention::RetentionStore>>>,
+    destinations: tauri::State<'_, Arc<Mutex<destination::DestinationStore>>>,
+) -> Result<serde_json::Value, String> {
     println!("Creating backups for all servers...");
-    
-    let server_manager_guard = state.lock().map_err(|_| "Failed to lock server manager")?;
-    let mut backup_count = 0;
-    
-    let server_list = server_manager_guard.list_servers();
-    for server in server_list {
-        if server.status != "installing" {
-            println!("Would create backup for server: {}", server.config.name);
-            backup_count += 1;
-        }
+
+    let manager = state.inner().clone();
+    let retention = retention.inner().clone();
+    let destinations = destinations.inner().clone();
+    let (backups, errors) = tokio::task::spawn_blocking(move || backup::backup_all(&manager, &retention, &destinations))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?;
+
+    for error in &errors {
+        println!("Backup error: {}", error);
     }
-    
+
+    let message = if errors.is_empty() {
+        format!("Created backups for {} servers", backups.len())
+    } else {
+        format!("Created backups for {} servers, with {} errors", backups.len(), errors.len())
+    };
     Ok(serde_json::json!({
-        "success": true,
-        "message": format!("Created backups for {} servers", backup_count),
-        "count": backup_count
+        "success": errors.is_empty(),
+        "message": message,
+        "count": backups.len(),
+        "errors": errors
     }))
 }
-
 #[tauri::command]
 async fn check_for_updates() -> Result<serde_json::Value, String> {
     println!("Checking for application updates...");
@@ -618,10 +651,46 @@ pub fn run() {
 
   let server_manager = Arc::new(Mutex::new(ServerManager::new()));
   let node_manager = Arc::new(Mutex::new(node::NodeManager::new(server_manager.clone())));
+  let controller_nodes = node_manager.clone();
+  let watchdog_nodes = node_manager.clone();
+  let mut node_status = node_manager.lock()
+    .map(|manager| manager.subscribe_status())
+    .expect("Failed to lock node manager");
+  let backup_retention = Arc::new(Mutex::new(retention::RetentionStore::new()));
+  let backup_destinations = Arc::new(Mutex::new(destination::DestinationStore::new()));
+  let controller_settings = Arc::new(Mutex::new(controller::ControllerSettingsStore::new()));
+  let controller_bind = controller_settings.lock()
+    .map(|store| store.settings.clone())
+    .ok()
+    .filter(|settings| settings.enabled)
+    .map(|settings| settings.bind);
+  let api_tokens = Arc::new(Mutex::new(api::ApiTokenStore::new()));
+  let api_context = api::ApiContext {
+    server_manager: server_manager.clone(),
+    tokens: api_tokens.clone(),
+    backup_retention: backup_retention.clone(),
+    backup_destinations: backup_destinations.clone(),
+  };
+  let sftp_accounts = Arc::new(Mutex::new(sftp_server::SftpServerStore::new()));
+  let sftp_context = sftp_server::SftpServerContext {
+    server_manager: server_manager.clone(),
+    store: sftp_accounts.clone(),
+  };
+  let ipc_context = ipc::IpcContext {
+    server_manager: server_manager.clone(),
+    servers_dir: setup::SERVERS_DIR.to_string(),
+    backup_retention: backup_retention.clone(),
+    backup_destinations: backup_destinations.clone(),
+  };
 
   tauri::Builder::default()
     .manage(server_manager)
     .manage(node_manager)
+    .manage(api_tokens)
+    .manage(controller_settings)
+    .manage(sftp_accounts)
+    .manage(backup_retention)
+    .manage(backup_destinations)
     .plugin(tauri_plugin_fs::init())
     .plugin(tauri_plugin_http::init())
     .plugin(tauri_plugin_shell::init())
@@ -654,6 +723,12 @@ pub fn run() {
       node::get_node_info_by_token,
       node::update_node_metrics,
       node::update_node_status,
+      node::rotate_node_credential,
+      node::revoke_node_credential,
+      node::list_pairing_audit,
+      migration::migrate_server,
+      ssh::add_ssh_node,
+      ssh::install_java_on_node,
       
       open_folder,
       
@@ -661,10 +736,51 @@ pub fn run() {
       sftp::upload_file_sftp,
       sftp::download_file_sftp,
       sftp::list_remote_files,
+      sftp::create_remote_directory,
+      sftp::rename_remote_path,
+      sftp::delete_remote_path,
+      sftp::chmod_remote_path,
       sftp::run_sftp_command,
+      sftp::get_sftp_host_key,
+      transfer::start_sftp_transfer,
+      transfer::list_sftp_transfers,
+      transfer::cancel_sftp_transfer,
+      sync::sync_server_sftp,
+      known_hosts::list_known_hosts,
+      known_hosts::trust_host_key,
+      known_hosts::remove_known_host,
+      backup::create_backup,
+      backup::list_backups,
+      backup::delete_backup,
+      backup::collect_backup_garbage,
+      backup::get_backup_store_stats,
+      restore::restore_backup,
+      destination::list_backup_destinations,
+      destination::create_backup_destination,
+      destination::update_backup_destination,
+      destination::delete_backup_destination,
+      destination::test_backup_destination,
+      destination::upload_backup,
+      destination::list_destination_backups,
+      destination::prune_destination_backups,
+      retention::get_backup_retention,
+      retention::set_backup_retention,
+      retention::pin_backup,
+      retention::prune_backups,
+      sftp_server::list_sftp_accounts,
+      sftp_server::create_sftp_account,
+      sftp_server::update_sftp_account,
+      sftp_server::delete_sftp_account,
+      sftp_server::get_sftp_denylist,
+      sftp_server::set_sftp_denylist,
+      sftp_server::get_sftp_server_settings,
+      sftp_server::update_sftp_server_settings,
+      sftp_server::get_sftp_server_fingerprint,
+      sftp_server::read_sftp_audit_log,
       
       export::export_server_zip,
       export::import_server_from_zip,
+      export::import_from_sftp,
       
       install_modpack_from_file,
       analyze_modpack_file,
@@ -697,6 +813,14 @@ pub fn run() {
       egg::add_custom_egg,
       egg::remove_custom_egg,
       egg::install_server_from_egg,
+      
+      api::list_api_tokens,
+      api::create_api_token,
+      api::revoke_api_token,
+      api::get_api_settings,
+      api::update_api_settings,
+      controller::get_controller_settings,
+      controller::update_controller_settings,
     ])
     .setup(|app| {
       app.handle().plugin(
@@ -705,6 +829,64 @@ pub fn run() {
           .build(),
       )?;
       
+      if let Some(bind) = controller_bind {
+        tauri::async_runtime::spawn(async move {
+          if let Err(e) = controller::serve(controller_nodes, bind).await {
+            eprintln!("Node controller stopped: {}", e);
+          }
+        });
+      }
+      
+      let app_handle = app.handle().clone();
+      tauri::async_runtime::spawn(async move {
+        use tauri::Emitter;
+        loop {
+          match node_status.recv().await {
+            Ok(event) => {
+              let _ = app_handle.emit("node-status-changed", event);
+            },
+            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(_) => break,
+          }
+        }
+      });
+      tauri::async_runtime::spawn(metrics::collect_local(watchdog_nodes.clone()));
+      for node_id in ssh::ssh_node_ids(&watchdog_nodes) {
+        tauri::async_runtime::spawn(ssh::run_node(watchdog_nodes.clone(), node_id));
+      }
+      tauri::async_runtime::spawn(node::run_watchdog(watchdog_nodes, node::WatchdogConfig::default()));
+      
+      #[cfg(unix)]
+      tauri::async_runtime::spawn(async move {
+        if let Err(e) = ipc::serve(ipc_context, ipc::default_socket_path()).await {
+          eprintln!("Control socket stopped: {}", e);
+        }
+      });
+      #[cfg(not(unix))]
+      drop(ipc_context);
+      
+      let api_settings = api_context.tokens.lock()
+        .map(|store| store.settings.clone())
+        .unwrap_or_default();
+      if api_settings.enabled {
+        tauri::async_runtime::spawn(async move {
+          if let Err(e) = api::serve(api_context, api_settings.bind).await {
+            eprintln!("Control API stopped: {}", e);
+          }
+        });
+      }
+      
+      let sftp_settings = sftp_context.store.lock()
+        .map(|store| store.settings.clone())
+        .unwrap_or_default();
+      if sftp_settings.enabled {
+        tauri::async_runtime::spawn(async move {
+          if let Err(e) = sftp_server::serve(sftp_context, sftp_settings.bind).await {
+            eprintln!("SFTP server stopped: {}", e);
+          }
+        });
+      }
+      
       Ok(())
     })
     .run(tauri::generate_context!())
@@ -2,5 +2,13 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 fn main() {
+  if let Some(spec) = app_lib::supervisor::spec_from_args() {
+    if let Err(e) = app_lib::supervisor::run(&spec) {
+      eprintln!("Supervisor failed: {}", e);
+      std::process::exit(1);
+    }
+    return;
+  }
+
   app_lib::run();
 }
@@ -0,0 +1,441 @@
+//! Host metrics for the local node, remote agents and SSH nodes. Everything
+//! is read from `/proc` and `statvfs` (`df` over SSH), so other platforms
+//! report zeros.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use log::warn;
+
+use crate::node::{DiskUsage, LoadAverage, NetworkThroughput, NodeManager, NodeMetrics};
+
+pub const LOCAL_METRICS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Filesystems that never hold server data.
+const PSEUDO_FILESYSTEMS: [&str; 20] = [
+    "proc", "sysfs", "devtmpfs", "devpts", "tmpfs", "cgroup", "cgroup2", "pstore",
+    "securityfs", "debugfs", "tracefs", "configfs", "fusectl", "mqueue", "hugetlbfs",
+    "bpf", "autofs", "binfmt_misc", "nsfs", "squashfs",
+];
+
+/// Prints the readings `MetricsSampler::sample_remote` expects, one
+/// `--- name` header per section.
+pub const REMOTE_READINGS_COMMAND: &str = "for f in stat meminfo loadavg net/dev; do \
+echo \"--- $f\"; cat /proc/$f; done; echo '--- df'; df -PTk 2>/dev/null";
+
+/// The raw files a sample is computed from.
+#[derive(Debug, Default)]
+struct Readings {
+    stat: String,
+    meminfo: String,
+    loadavg: String,
+    net_dev: String,
+    disks: Vec<DiskUsage>,
+}
+
+impl Readings {
+    fn local() -> Self {
+        let read = |path: &str| std::fs::read_to_string(path).unwrap_or_default();
+        Readings {
+            stat: read("/proc/stat"),
+            meminfo: read("/proc/meminfo"),
+            loadavg: read("/proc/loadavg"),
+            net_dev: read("/proc/net/dev"),
+            disks: disk_usage(),
+        }
+    }
+
+    /// Splits the output of `REMOTE_READINGS_COMMAND`.
+    fn parse_remote(output: &str) -> Self {
+        let mut readings = Readings::default();
+        let mut seen_devices = Vec::new();
+        let mut section = None;
+        for line in output.lines() {
+            if let Some(name) = line.strip_prefix("--- ") {
+                section = Some(name.trim());
+                continue;
+            }
+            let target = match section {
+                Some("stat") => &mut readings.stat,
+                Some("meminfo") => &mut readings.meminfo,
+                Some("loadavg") => &mut readings.loadavg,
+                Some("net/dev") => &mut readings.net_dev,
+                Some("df") => {
+                    if let Some(disk) = parse_df_line(line, &mut seen_devices) {
+                        readings.disks.push(disk);
+                    }
+                    continue;
+                },
+                _ => continue,
+            };
+            target.push_str(line);
+            target.push('\n');
+        }
+        readings
+    }
+}
+
+/// Samples host metrics. CPU usage and network throughput are deltas, so
+/// the first sample reports zero for both.
+#[derive(Debug, Default)]
+pub struct MetricsSampler {
+    last_cpu: Option<(u64, u64)>,
+    last_network: Option<(Instant, u64, u64)>,
+}
+
+impl MetricsSampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn sample(&mut self) -> NodeMetrics {
+        self.compute(Readings::local())
+    }
+
+    /// Samples another host from the output of `REMOTE_READINGS_COMMAND`.
+    pub fn sample_remote(&mut self, output: &str) -> NodeMetrics {
+        self.compute(Readings::parse_remote(output))
+    }
+
+    fn compute(&mut self, readings: Readings) -> NodeMetrics {
+        let cpu_times = cpu_times(&readings.stat);
+        let cpu = match (self.last_cpu, cpu_times) {
+            (Some((last_total, last_idle)), Some((total, idle))) if total > last_total => {
+                let busy = (total - last_total).saturating_sub(idle.saturating_sub(last_idle));
+                busy as f32 / (total - last_total) as f32 * 100.0
+            },
+            _ => 0.0,
+        };
+        self.last_cpu = cpu_times;
+
+        let (memory_total_bytes, memory_used_bytes) = memory_bytes(&readings.meminfo).unwrap_or((0, 0));
+        let memory = if memory_total_bytes > 0 {
+            memory_used_bytes as f32 / memory_total_bytes as f32 * 100.0
+        } else {
+            0.0
+        };
+
+        let disks = readings.disks;
+        let disk = disks.iter()
+            .find(|d| d.mount_point == "/")
+            .or_else(|| disks.first())
+            .map(|d| d.percent)
+            .unwrap_or(0.0);
+
+        NodeMetrics {
+            cpu,
+            memory,
+            disk,
+            memory_total_bytes,
+            memory_used_bytes,
+            load_average: load_average(&readings.loadavg).unwrap_or_default(),
+            disks,
+            network: self.network_throughput(&readings.net_dev),
+        }
+    }
+
+    fn network_throughput(&mut self, net_dev: &str) -> NetworkThroughput {
+        let Some((rx, tx)) = network_totals(net_dev) else {
+            return NetworkThroughput::default();
+        };
+        let now = Instant::now();
+
+        let throughput = match self.last_network {
+            Some((at, last_rx, last_tx)) => {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                if elapsed > 0.0 {
+                    NetworkThroughput {
+                        rx_bytes_per_sec: (rx.saturating_sub(last_rx) as f64 / elapsed) as u64,
+                        tx_bytes_per_sec: (tx.saturating_sub(last_tx) as f64 / elapsed) as u64,
+                    }
+                } else {
+                    NetworkThroughput::default()
+                }
+            },
+            None => NetworkThroughput::default(),
+        };
+
+        self.last_network = Some((now, rx, tx));
+        throughput
+    }
+}
+
+/// Total and idle jiffies from the aggregate line of `/proc/stat`.
+fn cpu_times(stat: &str) -> Option<(u64, u64)> {
+    let values: Vec<u64> = stat.lines()
+        .next()?
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|v| v.parse().ok())
+        .collect();
+    let idle = values.get(3)? + values.get(4).unwrap_or(&0);
+    Some((values.iter().sum(), idle))
+}
+
+fn memory_bytes(meminfo: &str) -> Option<(u64, u64)> {
+    let field = |name: &str| -> Option<u64> {
+        meminfo.lines()
+            .find(|l| l.starts_with(name))?
+            .split_whitespace()
+            .nth(1)?
+            .parse::<u64>()
+            .ok()
+            .map(|kib| kib * 1024)
+    };
+    let total = field("MemTotal:")?;
+    let available = field("MemAvailable:")?;
+    Some((total, total.saturating_sub(available)))
+}
+
+fn load_average(loadavg: &str) -> Option<LoadAverage> {
+    let mut values = loadavg.split_whitespace().map(|v| v.parse::<f32>().ok());
+    Some(LoadAverage {
+        one: values.next()??,
+        five: values.next()??,
+        fifteen: values.next()??,
+    })
+}
+
+/// Received and transmitted byte counters summed over `/proc/net/dev`.
+fn network_totals(dev: &str) -> Option<(u64, u64)> {
+    if dev.is_empty() {
+        return None;
+    }
+    let mut rx = 0;
+    let mut tx = 0;
+    for line in dev.lines().skip(2) {
+        let Some((interface, counters)) = line.split_once(':') else { continue };
+        if interface.trim() == "lo" {
+            continue;
+        }
+        let counters: Vec<u64> = counters.split_whitespace()
+            .filter_map(|v| v.parse().ok())
+            .collect();
+        rx += counters.first().copied().unwrap_or(0);
+        tx += counters.get(8).copied().unwrap_or(0);
+    }
+    Some((rx, tx))
+}
+
+/// Usage of every mounted block filesystem, one entry per device.
+fn disk_usage() -> Vec<DiskUsage> {
+    let Ok(mounts) = std::fs::read_to_string("/proc/mounts") else {
+        return Vec::new();
+    };
+
+    let mut seen_devices = Vec::new();
+    let mut disks = Vec::new();
+    for line in mounts.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let [device, mount_point, filesystem, ..] = fields[..] else { continue };
+        if PSEUDO_FILESYSTEMS.contains(&filesystem) || seen_devices.contains(&device) {
+            continue;
+        }
+        // /proc/mounts escapes spaces and other separators as octal.
+        let mount_point = mount_point.replace("\\040", " ").replace("\\011", "\t");
+
+        if let Some(usage) = statvfs(&mount_point, filesystem) {
+            seen_devices.push(device);
+            disks.push(usage);
+        }
+    }
+    disks
+}
+
+/// One line of `df -PTk`, skipping pseudo filesystems and devices that were
+/// already listed.
+fn parse_df_line(line: &str, seen_devices: &mut Vec<String>) -> Option<DiskUsage> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let [device, filesystem, total, used, available, _, ref mount_point @ ..] = fields[..] else { return None };
+    if mount_point.is_empty() || PSEUDO_FILESYSTEMS.contains(&filesystem) || seen_devices.iter().any(|d| d == device) {
+        return None;
+    }
+    let total_bytes = total.parse::<u64>().ok()? * 1024;
+    let used_bytes = used.parse::<u64>().ok()? * 1024;
+    let available_bytes = available.parse::<u64>().ok()? * 1024;
+    if total_bytes == 0 {
+        return None;
+    }
+    seen_devices.push(device.to_string());
+    let usable = used_bytes + available_bytes;
+
+    Some(DiskUsage {
+        mount_point: mount_point.join(" "),
+        filesystem: filesystem.to_string(),
+        total_bytes,
+        used_bytes,
+        available_bytes,
+        percent: if usable > 0 { used_bytes as f32 / usable as f32 * 100.0 } else { 0.0 },
+    })
+}
+
+#[cfg(unix)]
+fn statvfs(mount_point: &str, filesystem: &str) -> Option<DiskUsage> {
+    let path = std::ffi::CString::new(mount_point).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid out-pointer.
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+
+    let fragment = stat.f_frsize as u64;
+    let total_bytes = stat.f_blocks as u64 * fragment;
+    if total_bytes == 0 {
+        return None;
+    }
+    let free_bytes = stat.f_bfree as u64 * fragment;
+    let available_bytes = stat.f_bavail as u64 * fragment;
+    let used_bytes = total_bytes.saturating_sub(free_bytes);
+    // Same formula as df: blocks reserved for root count as neither used nor
+    // available.
+    let usable = used_bytes + available_bytes;
+    let percent = if usable > 0 { used_bytes as f32 / usable as f32 * 100.0 } else { 0.0 };
+
+    Some(DiskUsage {
+        mount_point: mount_point.to_string(),
+        filesystem: filesystem.to_string(),
+        total_bytes,
+        used_bytes,
+        available_bytes,
+        percent,
+    })
+}
+
+#[cfg(not(unix))]
+fn statvfs(_mount_point: &str, _filesystem: &str) -> Option<DiskUsage> {
+    None
+}
+
+/// Keeps the built-in local node's metrics current.
+pub async fn collect_local(node_manager: Arc<Mutex<NodeManager>>) {
+    let mut sampler = MetricsSampler::new();
+    let mut ticker = tokio::time::interval(LOCAL_METRICS_INTERVAL);
+
+    loop {
+        ticker.tick().await;
+
+        // statvfs can block on a hung network mount.
+        let sampled = tokio::task::spawn_blocking(move || {
+            let metrics = sampler.sample();
+            (sampler, metrics)
+        }).await;
+        let metrics = match sampled {
+            Ok((returned, metrics)) => {
+                sampler = returned;
+                metrics
+            },
+            Err(e) => {
+                warn!("Failed to sample local metrics: {}", e);
+                return;
+            },
+        };
+
+        if let Ok(mut manager) = node_manager.lock() {
+            if let Err(e) = manager.update_node_metrics("local", metrics) {
+                warn!("Failed to update local node metrics: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `REMOTE_READINGS_COMMAND` on a Debian VPS, trimmed.
+    const REMOTE_OUTPUT: &str = "\
+--- stat
+cpu  4705 356 584 3699 23 23 0 0 0 0
+cpu0 1393 280 278 1819 10 12 0 0 0 0
+cpu1 3312 76 306 1880 13 11 0 0 0 0
+intr 114930548 113199788 3 0 5 263 0 4 [...]
+ctxt 1990473
+btime 1062191376
+--- meminfo
+MemTotal:        8029784 kB
+MemFree:          335120 kB
+MemAvailable:    5321928 kB
+Buffers:          313208 kB
+Cached:          4562788 kB
+--- loadavg
+0.52 0.38 0.31 2/412 31337
+--- net/dev
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo: 9216047   63521    0    0    0     0          0         0  9216047   63521    0    0    0     0       0          0
+  eth0: 734216845 912341    0   12    0     0          0         0 98123411  402113    0    0    0     0       0          0
+  wg0:   1048576    2048    0    0    0     0          0         0  2097152    4096    0    0    0     0       0          0
+--- df
+Filesystem     Type     1024-blocks     Used Available Capacity Mounted on
+udev           devtmpfs     4001476        0   4001476       0% /dev
+tmpfs          tmpfs         802980     1044    801936       1% /run
+/dev/vda1      ext4        81000000 40500000  36000000      53% /
+/dev/vdb1      xfs        209715200 10485760 199229440       6% /srv/minecraft servers
+/dev/vda1      ext4        81000000 40500000  36000000      53% /var/lib/docker
+";
+
+    #[test]
+    fn proc_files_are_parsed() {
+        assert_eq!(cpu_times("cpu  4705 356 584 3699 23 23 0 0 0 0\ncpu0 1 2 3 4"), Some((9390, 3722)));
+        // Kernels before 2.6 had no iowait column.
+        assert_eq!(cpu_times("cpu  100 0 50 850"), Some((1000, 850)));
+        assert_eq!(cpu_times("cpu  100 0"), None);
+        assert_eq!(cpu_times(""), None);
+
+        assert_eq!(memory_bytes("MemTotal: 1024 kB\nMemFree: 10 kB\nMemAvailable: 256 kB\n"), Some((1024 * 1024, 768 * 1024)));
+        assert_eq!(memory_bytes("MemTotal: 1024 kB\nMemFree: 10 kB\n"), None);
+
+        let load = load_average("0.52 0.38 0.31 2/412 31337\n").unwrap();
+        assert_eq!((load.one, load.five, load.fifteen), (0.52, 0.38, 0.31));
+        assert!(load_average("0.52 0.38").is_none());
+
+        let dev = REMOTE_OUTPUT.split("--- net/dev\n").nth(1).unwrap().split("--- df").next().unwrap();
+        assert_eq!(network_totals(dev), Some((734216845 + 1048576, 98123411 + 2097152)));
+        assert_eq!(network_totals(""), None);
+    }
+
+    #[test]
+    fn df_lines_skip_headers_pseudo_filesystems_and_repeated_devices() {
+        let mut seen = Vec::new();
+        let disks: Vec<DiskUsage> = REMOTE_OUTPUT.split("--- df\n").nth(1).unwrap()
+            .lines()
+            .filter_map(|line| parse_df_line(line, &mut seen))
+            .collect();
+        let summary: Vec<(&str, &str, u64, u64, u64)> = disks.iter()
+            .map(|d| (d.mount_point.as_str(), d.filesystem.as_str(), d.total_bytes, d.used_bytes, d.available_bytes))
+            .collect();
+        assert_eq!(summary, [
+            ("/", "ext4", 81000000 * 1024, 40500000 * 1024, 36000000 * 1024),
+            ("/srv/minecraft servers", "xfs", 209715200 * 1024, 10485760 * 1024, 199229440 * 1024),
+        ]);
+        // Reserved blocks count as neither used nor available, as in df.
+        assert!((disks[0].percent - 52.941).abs() < 0.01, "{}", disks[0].percent);
+    }
+
+    #[test]
+    fn remote_samples_compute_usage_from_deltas() {
+        let mut sampler = MetricsSampler::new();
+        let first = sampler.sample_remote(REMOTE_OUTPUT);
+        assert_eq!(first.cpu, 0.0);
+        assert_eq!(first.memory_total_bytes, 8029784 * 1024);
+        assert_eq!(first.memory_used_bytes, (8029784 - 5321928) * 1024);
+        assert!((first.memory - 33.72).abs() < 0.01, "{}", first.memory);
+        assert_eq!(first.load_average.one, 0.52);
+        assert_eq!(first.disks.len(), 2);
+        assert!((first.disk - first.disks[0].percent).abs() < f32::EPSILON);
+        assert_eq!(first.network.rx_bytes_per_sec, 0);
+
+        // 1000 more jiffies, 250 of them idle or waiting on I/O.
+        let later = REMOTE_OUTPUT
+            .replace("cpu  4705 356 584 3699 23", "cpu  5205 356 834 3899 73")
+            .replace("734216845", "744216845");
+        std::thread::sleep(Duration::from_millis(5));
+        let second = sampler.sample_remote(&later);
+        assert!((second.cpu - 75.0).abs() < 0.01, "{}", second.cpu);
+        assert!(second.network.rx_bytes_per_sec > 0);
+        assert_eq!(second.network.tx_bytes_per_sec, 0);
+
+        let empty = MetricsSampler::new().sample_remote("");
+        assert_eq!((empty.cpu, empty.memory, empty.disk, empty.memory_total_bytes), (0.0, 0.0, 0.0, 0));
+        assert!(empty.disks.is_empty());
+    }
+}
@@ -0,0 +1,746 @@
+//! Moving servers between nodes. The source node packages the stopped
+//! server's directory into a zip in its staging directory, the controller
+//! copies that archive to the target's staging directory in chunks, and the
+//! target verifies its SHA-256, unpacks it and registers the server with the
+//! same config. Partial archives are named after their checksum and kept, so
+//! retrying a failed migration resumes the transfer where it stopped.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use log::{info, warn, error};
+use tauri::State;
+use zip::write::FileOptions;
+use zip::ZipArchive;
+
+use crate::node::{self, NodeManager};
+use crate::protocol::NodeRequest;
+use crate::server::{ServerConfig, ServerInfo, ServerManager};
+use crate::setup;
+
+/// Bytes moved per request while copying an archive between nodes.
+pub const TRANSFER_CHUNK_SIZE: u64 = 1024 * 1024;
+/// First protocol version whose agents understand the migration requests.
+pub const MIGRATION_PROTOCOL_VERSION: u32 = 3;
+const STOP_TIMEOUT: Duration = Duration::from_secs(60);
+/// Packaging and unpacking large worlds takes a while on slow disks.
+const ARCHIVE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// A packaged server waiting in a node's staging directory.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveInfo {
+    pub archive: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+fn staging_dir() -> PathBuf {
+    let app_data_dir = std::env::var("APPDATA")
+        .unwrap_or_else(|_| std::env::var("HOME").unwrap_or_else(|_| ".".to_string()));
+    PathBuf::from(format!("{}/ServerMint/migrations", app_data_dir))
+}
+
+/// Resolves an archive name inside the staging directory. Names come from
+/// the controller, so anything that could leave the directory is rejected.
+pub fn archive_path(archive: &str) -> Result<PathBuf, String> {
+    let valid = !archive.is_empty()
+        && !archive.starts_with('.')
+        && archive.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(format!("Invalid archive name: {}", archive));
+    }
+    Ok(staging_dir().join(archive))
+}
+
+fn server_config(server_manager: &Arc<Mutex<ServerManager>>, server_id: &str) -> Result<ServerConfig, String> {
+    let manager = server_manager.lock().map_err(|_| "Failed to lock server manager")?;
+    manager.get_server_info(server_id).map(|info| info.config)
+}
+
+/// Zips a stopped server's directory into the staging directory. An archive
+/// left by an earlier attempt is reused if no file changed since.
+pub fn package_server(server_manager: &Arc<Mutex<ServerManager>>, server_id: &str) -> Result<ArchiveInfo, String> {
+    let config = server_config(server_manager, server_id)?;
+    let root = PathBuf::from(&config.path);
+    let archive = format!("{}.zip", server_id);
+    let path = archive_path(&archive)?;
+
+    let reusable = match fs::metadata(&path).and_then(|m| m.modified()) {
+        Ok(packaged_at) => newest_modification(&root)
+            .map(|changed_at| changed_at <= packaged_at)
+            .unwrap_or(false),
+        Err(_) => false,
+    };
+
+    if reusable {
+        info!("Reusing packaged archive of server {}", server_id);
+    } else {
+        fs::create_dir_all(staging_dir())
+            .map_err(|e| format!("Failed to create staging directory: {}", e))?;
+        let temp_path = path.with_extension("zip.tmp");
+        write_zip(&root, &temp_path)?;
+        fs::rename(&temp_path, &path)
+            .map_err(|e| format!("Failed to move archive into place: {}", e))?;
+    }
+
+    let size = fs::metadata(&path)
+        .map_err(|e| format!("Failed to read archive: {}", e))?
+        .len();
+    Ok(ArchiveInfo { archive, size, sha256: sha256_file(&path)? })
+}
+
+fn newest_modification(dir: &Path) -> std::io::Result<SystemTime> {
+    let mut newest = fs::metadata(dir)?.modified()?;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let modified = if entry.file_type()?.is_dir() {
+            newest_modification(&entry.path())?
+        } else {
+            entry.metadata()?.modified()?
+        };
+        newest = newest.max(modified);
+    }
+    Ok(newest)
+}
+
+fn write_zip(root: &Path, dest: &Path) -> Result<(), String> {
+    let file = File::create(dest).map_err(|e| format!("Failed to create archive: {}", e))?;
+    let mut zip = zip::ZipWriter::new(file);
+    add_to_zip(&mut zip, root, root)?;
+    zip.finish().map_err(|e| format!("Failed to finish archive: {}", e))?;
+    Ok(())
+}
+
+fn add_to_zip(zip: &mut zip::ZipWriter<File>, root: &Path, dir: &Path) -> Result<(), String> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+
+    for entry in entries {
+        let entry = entry.map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+        let path = entry.path();
+        let name = path.strip_prefix(root)
+            .map_err(|e| e.to_string())?
+            .to_string_lossy()
+            .replace('\\', "/");
+        let metadata = entry.metadata()
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+        let mut options = FileOptions::default()
+            .compression_method(zip::CompressionMethod::Deflated)
+            .large_file(metadata.len() >= u32::MAX as u64);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            options = options.unix_permissions(metadata.permissions().mode());
+        }
+
+        if metadata.is_dir() {
+            zip.add_directory(name, options).map_err(|e| e.to_string())?;
+            add_to_zip(zip, root, &path)?;
+        } else {
+            zip.start_file(name, options).map_err(|e| e.to_string())?;
+            let mut file = File::open(&path)
+                .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+            std::io::copy(&mut file, zip)
+                .map_err(|e| format!("Failed to add {} to archive: {}", path.display(), e))?;
+        }
+    }
+    Ok(())
+}
+
+pub fn sha256_file(path: &Path) -> Result<String, String> {
+    let mut file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Bytes of `archive` already staged; zero if there is none.
+pub fn archive_size(archive: &str) -> Result<u64, String> {
+    match fs::metadata(archive_path(archive)?) {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+        Err(e) => Err(format!("Failed to read archive: {}", e)),
+    }
+}
+
+pub fn read_archive(archive: &str, offset: u64, length: u64) -> Result<Vec<u8>, String> {
+    let mut file = File::open(archive_path(archive)?)
+        .map_err(|e| format!("Failed to open archive: {}", e))?;
+    file.seek(SeekFrom::Start(offset))
+        .map_err(|e| format!("Failed to seek archive: {}", e))?;
+
+    let mut bytes = Vec::with_capacity(length.min(TRANSFER_CHUNK_SIZE) as usize);
+    file.take(length).read_to_end(&mut bytes)
+        .map_err(|e| format!("Failed to read archive: {}", e))?;
+    Ok(bytes)
+}
+
+/// Opens `archive` for appending at `offset`, which must be its current
+/// size so that a resumed transfer can't leave a gap or overlap.
+pub fn open_archive_for_append(archive: &str, offset: u64) -> Result<File, String> {
+    let size = archive_size(archive)?;
+    if size != offset {
+        return Err(format!("Archive {} has {} bytes, cannot write at offset {}", archive, size, offset));
+    }
+
+    fs::create_dir_all(staging_dir())
+        .map_err(|e| format!("Failed to create staging directory: {}", e))?;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(archive_path(archive)?)
+        .map_err(|e| format!("Failed to open archive: {}", e))
+}
+
+pub fn append_archive(archive: &str, offset: u64, bytes: &[u8]) -> Result<u64, String> {
+    let mut file = open_archive_for_append(archive, offset)?;
+    file.write_all(bytes).map_err(|e| format!("Failed to write archive: {}", e))?;
+    file.flush().map_err(|e| format!("Failed to write archive: {}", e))?;
+    Ok(offset + bytes.len() as u64)
+}
+
+pub fn delete_archive(archive: &str) -> Result<(), String> {
+    match fs::remove_file(archive_path(archive)?) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(format!("Failed to delete archive: {}", e)),
+    }
+}
+
+/// Verifies and unpacks a staged archive into `servers_dir` and registers
+/// the server with `config`, moved to its new directory and, if `port` is
+/// set, to that port. The archive is deleted once the server is registered.
+pub fn import_server(
+    server_manager: &Arc<Mutex<ServerManager>>,
+    servers_dir: &str,
+    server_id: &str,
+    mut config: ServerConfig,
+    archive: &str,
+    sha256: &str,
+    port: Option<u16>,
+) -> Result<ServerInfo, String> {
+    let path = archive_path(archive)?;
+    let actual = sha256_file(&path)?;
+    if actual != sha256 {
+        delete_archive(archive)?;
+        return Err(format!("Archive checksum mismatch: expected {}, got {}", sha256, actual));
+    }
+
+    let server_dir = PathBuf::from(format!("{}/{}", servers_dir, server_id));
+    if server_dir.exists() {
+        return Err(format!("{} already exists", server_dir.display()));
+    }
+    if let Err(e) = extract_zip(&path, &server_dir) {
+        let _ = fs::remove_dir_all(&server_dir);
+        return Err(e);
+    }
+
+    if let Some(port) = port {
+        set_server_port(&server_dir, port)?;
+        config.port = port;
+    }
+    config.path = server_dir.to_string_lossy().to_string();
+
+    let info = {
+        let mut manager = server_manager.lock().map_err(|_| "Failed to lock server manager")?;
+        manager.add_server(server_id.to_string(), config)?;
+        manager.get_server_info(server_id)?
+    };
+
+    if let Err(e) = delete_archive(archive) {
+        warn!("Failed to clean up archive {}: {}", archive, e);
+    }
+    Ok(info)
+}
+
+fn extract_zip(archive: &Path, dest: &Path) -> Result<(), String> {
+    let file = File::open(archive).map_err(|e| format!("Failed to open archive: {}", e))?;
+    let mut zip = ZipArchive::new(file).map_err(|e| format!("Failed to read archive: {}", e))?;
+
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(|e| format!("Failed to read archive: {}", e))?;
+        let relative = entry.enclosed_name()
+            .map(Path::to_path_buf)
+            .ok_or_else(|| format!("Archive entry {} escapes the server directory", entry.name()))?;
+        let out_path = dest.join(relative);
+
+        if entry.is_dir() {
+            fs::create_dir_all(&out_path)
+                .map_err(|e| format!("Failed to create {}: {}", out_path.display(), e))?;
+            continue;
+        }
+
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+        }
+        let mut out = File::create(&out_path)
+            .map_err(|e| format!("Failed to create {}: {}", out_path.display(), e))?;
+        std::io::copy(&mut entry, &mut out)
+            .map_err(|e| format!("Failed to extract {}: {}", out_path.display(), e))?;
+
+        #[cfg(unix)]
+        if let Some(mode) = entry.unix_mode() {
+            use std::os::unix::fs::PermissionsExt;
+            let _ = fs::set_permissions(&out_path, fs::Permissions::from_mode(mode));
+        }
+    }
+    Ok(())
+}
+
+/// Points `server-port` in server.properties at `port`. PocketMine servers
+/// use the same key.
+fn set_server_port(server_dir: &Path, port: u16) -> Result<(), String> {
+    let properties_path = server_dir.join("server.properties");
+    let Ok(contents) = fs::read_to_string(&properties_path) else {
+        return Ok(());
+    };
+
+    let mut replaced = false;
+    let mut lines: Vec<String> = contents.lines()
+        .map(|line| {
+            if line.trim_start().starts_with("server-port=") {
+                replaced = true;
+                format!("server-port={}", port)
+            } else {
+                line.to_string()
+            }
+        })
+        .collect();
+    if !replaced {
+        lines.push(format!("server-port={}", port));
+    }
+
+    fs::write(&properties_path, lines.join("\n") + "\n")
+        .map_err(|e| format!("Failed to update server.properties: {}", e))
+}
+
+/// Unregisters a server and, with `delete_files`, removes its directory.
+pub fn remove_server(server_manager: &Arc<Mutex<ServerManager>>, server_id: &str, delete_files: bool) -> Result<(), String> {
+    let config = server_config(server_manager, server_id)?;
+    server_manager.lock()
+        .map_err(|_| "Failed to lock server manager")?
+        .remove_server(server_id)?;
+
+    if delete_files {
+        fs::remove_dir_all(&config.path)
+            .map_err(|e| format!("Failed to delete {}: {}", config.path, e))?;
+    }
+    Ok(())
+}
+
+async fn blocking<T, F>(f: F) -> Result<T, String>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T, String> + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+/// Where a server lives during a migration.
+#[derive(Debug, Clone, PartialEq)]
+enum Endpoint {
+    Local,
+    Remote(String),
+}
+
+impl Endpoint {
+    fn node_id(&self) -> &str {
+        match self {
+            Endpoint::Local => "local",
+            Endpoint::Remote(node_id) => node_id,
+        }
+    }
+}
+
+/// Runs the steps of a migration against local or remote endpoints.
+struct Migrator {
+    server_manager: Arc<Mutex<ServerManager>>,
+    node_manager: Arc<Mutex<NodeManager>>,
+    servers_dir: String,
+}
+
+impl Migrator {
+    async fn request(&self, node_id: &str, request: NodeRequest, timeout: Duration) -> Result<serde_json::Value, String> {
+        node::remote_request_with_timeout(&self.node_manager, node_id, request, timeout).await
+            .map(|reply| reply.data)
+    }
+
+    async fn server_info(&self, endpoint: &Endpoint, server_id: &str) -> Result<ServerInfo, String> {
+        match endpoint {
+            Endpoint::Local => {
+                let manager = self.server_manager.lock().map_err(|_| "Failed to lock server manager")?;
+                manager.get_server_info(server_id)
+            },
+            Endpoint::Remote(node_id) => {
+                let request = NodeRequest::GetServer { server_id: server_id.to_string() };
+                let data = self.request(node_id, request, node::REQUEST_TIMEOUT).await?;
+                serde_json::from_value(data).map_err(|e| format!("Invalid server info from node {}: {}", node_id, e))
+            },
+        }
+    }
+
+    async fn used_ports(&self, endpoint: &Endpoint) -> Result<Vec<u16>, String> {
+        let servers = match endpoint {
+            Endpoint::Local => {
+                let manager = self.server_manager.lock().map_err(|_| "Failed to lock server manager")?;
+                manager.list_servers()
+            },
+            Endpoint::Remote(node_id) => {
+                let data = self.request(node_id, NodeRequest::ListServers, node::REQUEST_TIMEOUT).await?;
+                serde_json::from_value::<Vec<ServerInfo>>(data)
+                    .map_err(|e| format!("Invalid server list from node {}: {}", node_id, e))?
+            },
+        };
+        Ok(servers.into_iter().map(|s| s.config.port).collect())
+    }
+
+    async fn send_command(&self, endpoint: &Endpoint, server_id: &str, command: &str) -> Result<(), String> {
+        match endpoint {
+            Endpoint::Local => {
+                let mut manager = self.server_manager.lock().map_err(|_| "Failed to lock server manager")?;
+                manager.send_server_command(server_id, command)
+            },
+            Endpoint::Remote(node_id) => {
+                let request = NodeRequest::SendCommand { server_id: server_id.to_string(), command: command.to_string() };
+                self.request(node_id, request, node::REQUEST_TIMEOUT).await.map(|_| ())
+            },
+        }
+    }
+
+    /// Asks a running server to `stop` and kills it if it hasn't exited
+    /// within `STOP_TIMEOUT`. Returns whether it was running.
+    async fn stop_gracefully(&self, endpoint: &Endpoint, server_id: &str) -> Result<bool, String> {
+        if self.server_info(endpoint, server_id).await?.status != "online" {
+            return Ok(false);
+        }
+
+        info!("Stopping server {} for migration", server_id);
+        self.send_command(endpoint, server_id, "stop").await?;
+
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        while Instant::now() < deadline {
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            if self.server_info(endpoint, server_id).await?.status != "online" {
+                return Ok(true);
+            }
+        }
+
+        warn!("Server {} did not stop within {}s, killing it", server_id, STOP_TIMEOUT.as_secs());
+        match endpoint {
+            Endpoint::Local => {
+                let mut manager = self.server_manager.lock().map_err(|_| "Failed to lock server manager")?;
+                manager.stop_server(server_id)?;
+            },
+            Endpoint::Remote(node_id) => {
+                let request = NodeRequest::StopServer { server_id: server_id.to_string() };
+                self.request(node_id, request, node::REQUEST_TIMEOUT).await?;
+            },
+        }
+        Ok(true)
+    }
+
+    async fn start(&self, endpoint: &Endpoint, server_id: &str) -> Result<(), String> {
+        match endpoint {
+            Endpoint::Local => {
+                let mut manager = self.server_manager.lock().map_err(|_| "Failed to lock server manager")?;
+                manager.start_server(server_id)
+            },
+            Endpoint::Remote(node_id) => {
+                let request = NodeRequest::StartServer { server_id: server_id.to_string() };
+                self.request(node_id, request, node::REQUEST_TIMEOUT).await.map(|_| ())
+            },
+        }
+    }
+
+    async fn package(&self, endpoint: &Endpoint, server_id: &str) -> Result<ArchiveInfo, String> {
+        match endpoint {
+            Endpoint::Local => {
+                let server_manager = self.server_manager.clone();
+                let server_id = server_id.to_string();
+                blocking(move || package_server(&server_manager, &server_id)).await
+            },
+            Endpoint::Remote(node_id) => {
+                let request = NodeRequest::PackageServer { server_id: server_id.to_string() };
+                let data = self.request(node_id, request, ARCHIVE_TIMEOUT).await?;
+                serde_json::from_value(data).map_err(|e| format!("Invalid archive info from node {}: {}", node_id, e))
+            },
+        }
+    }
+
+    async fn archive_size(&self, endpoint: &Endpoint, archive: &str) -> Result<u64, String> {
+        match endpoint {
+            Endpoint::Local => archive_size(archive),
+            Endpoint::Remote(node_id) => {
+                let request = NodeRequest::ArchiveStatus { archive: archive.to_string() };
+                let data = self.request(node_id, request, node::REQUEST_TIMEOUT).await?;
+                data["size"].as_u64().ok_or_else(|| format!("Invalid archive status from node {}", node_id))
+            },
+        }
+    }
+
+    async fn read_chunk(&self, endpoint: &Endpoint, archive: &str, offset: u64, length: u64) -> Result<Vec<u8>, String> {
+        match endpoint {
+            Endpoint::Local => {
+                let archive = archive.to_string();
+                blocking(move || read_archive(&archive, offset, length)).await
+            },
+            Endpoint::Remote(node_id) => {
+                let request = NodeRequest::ReadArchive { archive: archive.to_string(), offset, length };
+                let reply = node::remote_request(&self.node_manager, node_id, request).await?;
+                Ok(reply.file)
+            },
+        }
+    }
+
+    async fn write_chunk(&self, endpoint: &Endpoint, archive: &str, offset: u64, bytes: Vec<u8>) -> Result<u64, String> {
+        match endpoint {
+            Endpoint::Local => {
+                let archive = archive.to_string();
+                blocking(move || append_archive(&archive, offset, &bytes)).await
+            },
+            Endpoint::Remote(node_id) => {
+                let request = NodeRequest::WriteArchive {
+                    archive: archive.to_string(),
+                    offset,
+                    size: bytes.len() as u64,
+                };
+                let reply = node::request_with_file(&self.node_manager, node_id, request, &bytes).await?;
+                reply.data["size"].as_u64().ok_or_else(|| format!("Invalid write reply from node {}", node_id))
+            },
+        }
+    }
+
+    /// Copies the source archive to the target, continuing a partial copy
+    /// left by an earlier attempt.
+    async fn transfer(&self, source: &Endpoint, target: &Endpoint, packaged: &ArchiveInfo, target_archive: &str) -> Result<(), String> {
+        let mut offset = self.archive_size(target, target_archive).await?;
+        if offset > packaged.size {
+            self.delete_archive(target, target_archive).await;
+            offset = 0;
+        }
+        if offset > 0 {
+            info!("Resuming transfer of {} at {} of {} bytes", target_archive, offset, packaged.size);
+        }
+
+        while offset < packaged.size {
+            let length = TRANSFER_CHUNK_SIZE.min(packaged.size - offset);
+            let bytes = self.read_chunk(source, &packaged.archive, offset, length).await?;
+            if bytes.len() as u64 != length {
+                return Err(format!("Expected {} bytes of {} at offset {}, got {}", length, packaged.archive, offset, bytes.len()));
+            }
+            offset = self.write_chunk(target, target_archive, offset, bytes).await?;
+        }
+        Ok(())
+    }
+
+    async fn import(
+        &self,
+        endpoint: &Endpoint,
+        server_id: &str,
+        config: ServerConfig,
+        archive: &str,
+        sha256: &str,
+        port: Option<u16>,
+    ) -> Result<ServerInfo, String> {
+        match endpoint {
+            Endpoint::Local => {
+                let server_manager = self.server_manager.clone();
+                let servers_dir = self.servers_dir.clone();
+                let (server_id, archive, sha256) = (server_id.to_string(), archive.to_string(), sha256.to_string());
+                blocking(move || import_server(&server_manager, &servers_dir, &server_id, config, &archive, &sha256, port)).await
+            },
+            Endpoint::Remote(node_id) => {
+                let request = NodeRequest::ImportServer {
+                    server_id: server_id.to_string(),
+                    config,
+                    archive: archive.to_string(),
+                    sha256: sha256.to_string(),
+                    port,
+                };
+                let data = self.request(node_id, request, ARCHIVE_TIMEOUT).await?;
+                serde_json::from_value(data).map_err(|e| format!("Invalid server info from node {}: {}", node_id, e))
+            },
+        }
+    }
+
+    async fn remove(&self, endpoint: &Endpoint, server_id: &str) -> Result<(), String> {
+        match endpoint {
+            Endpoint::Local => {
+                let server_manager = self.server_manager.clone();
+                let server_id = server_id.to_string();
+                blocking(move || remove_server(&server_manager, &server_id, true)).await
+            },
+            Endpoint::Remote(node_id) => {
+                let request = NodeRequest::RemoveServer { server_id: server_id.to_string(), delete_files: true };
+                self.request(node_id, request, ARCHIVE_TIMEOUT).await.map(|_| ())
+            },
+        }
+    }
+
+    /// Best-effort cleanup of a staged archive.
+    async fn delete_archive(&self, endpoint: &Endpoint, archive: &str) {
+        let result = match endpoint {
+            Endpoint::Local => delete_archive(archive),
+            Endpoint::Remote(node_id) => {
+                let request = NodeRequest::DeleteArchive { archive: archive.to_string() };
+                self.request(node_id, request, node::REQUEST_TIMEOUT).await.map(|_| ())
+            },
+        };
+        if let Err(e) = result {
+            warn!("Failed to delete archive {} on node {}: {}", archive, endpoint.node_id(), e);
+        }
+    }
+
+    /// The port the server gets on the target, or an error if it clashes
+    /// and may not be changed.
+    async fn target_port(&self, target: &Endpoint, port: u16, rewrite_port: bool) -> Result<Option<u16>, String> {
+        let used = self.used_ports(target).await?;
+        if !used.contains(&port) {
+            return Ok(None);
+        }
+        if !rewrite_port {
+            return Err(format!("Port {} is already used on node {}", port, target.node_id()));
+        }
+        (port.saturating_add(1)..=u16::MAX)
+            .find(|candidate| !used.contains(candidate))
+            .map(Some)
+            .ok_or_else(|| format!("No free port on node {}", target.node_id()))
+    }
+
+    async fn migrate(&self, server_id: &str, source: Endpoint, target: Endpoint, rewrite_port: bool) -> Result<ServerInfo, String> {
+        let info = self.server_info(&source, server_id).await?;
+        let port = self.target_port(&target, info.config.port, rewrite_port).await?;
+
+        let was_running = self.stop_gracefully(&source, server_id).await?;
+        let migrated = self.copy_and_start(server_id, &source, &target, info.config, port).await;
+
+        let installed = match migrated {
+            Ok(installed) => installed,
+            Err(e) => {
+                if was_running {
+                    if let Err(restart_error) = self.start(&source, server_id).await {
+                        error!("Failed to restart server {} after a failed migration: {}", server_id, restart_error);
+                    }
+                }
+                return Err(e);
+            },
+        };
+
+        // The target is up; only now is the source copy removed.
+        if let Err(e) = self.remove(&source, server_id).await {
+            warn!("Migrated server {} but failed to remove it from node {}: {}", server_id, source.node_id(), e);
+        }
+        Ok(installed)
+    }
+
+    async fn copy_and_start(
+        &self,
+        server_id: &str,
+        source: &Endpoint,
+        target: &Endpoint,
+        config: ServerConfig,
+        port: Option<u16>,
+    ) -> Result<ServerInfo, String> {
+        let packaged = self.package(source, server_id).await?;
+        let target_archive = format!("{}-{}.zip", server_id, &packaged.sha256[..16]);
+        info!("Transferring server {} ({} bytes) from node {} to node {}", server_id, packaged.size, source.node_id(), target.node_id());
+
+        self.transfer(source, target, &packaged, &target_archive).await?;
+        let installed = self.import(target, server_id, config, &target_archive, &packaged.sha256, port).await?;
+        self.delete_archive(source, &packaged.archive).await;
+
+        if let Err(e) = self.start(target, server_id).await {
+            warn!("Migrated server {} failed to start on node {}: {}", server_id, target.node_id(), e);
+            if let Err(cleanup_error) = self.remove(target, server_id).await {
+                error!("Failed to remove the copy of server {} on node {}: {}", server_id, target.node_id(), cleanup_error);
+            }
+            return Err(format!("Server failed to start on node {}: {}", target.node_id(), e));
+        }
+        Ok(installed)
+    }
+}
+
+/// Moves a server to `target_node`. The server is stopped, copied, started on
+/// the target and only then removed from its source. With `rewrite_port` a
+/// port already taken on the target is replaced by the next free one.
+pub async fn migrate(
+    server_manager: Arc<Mutex<ServerManager>>,
+    node_manager: Arc<Mutex<NodeManager>>,
+    servers_dir: String,
+    server_id: &str,
+    target_node: &str,
+    rewrite_port: bool,
+) -> Result<ServerInfo, String> {
+    let (source, target) = {
+        let manager = node_manager.lock().map_err(|e| format!("Failed to lock node manager: {}", e))?;
+        let source = match manager.node_for_server(server_id) {
+            Some(node_id) => Endpoint::Remote(node_id),
+            None => Endpoint::Local,
+        };
+        let target = match target_node {
+            "local" => Endpoint::Local,
+            node_id if manager.get_node(node_id).is_some() => Endpoint::Remote(node_id.to_string()),
+            node_id => return Err(format!("Node with ID {} not found", node_id)),
+        };
+
+        if source == target {
+            return Err(format!("Server {} is already on node {}", server_id, target.node_id()));
+        }
+        for endpoint in [&source, &target] {
+            if let Endpoint::Remote(node_id) = endpoint {
+                if !manager.agent_supports(node_id, MIGRATION_PROTOCOL_VERSION) {
+                    return Err(format!("Node {} must be connected with an up-to-date agent to migrate servers", node_id));
+                }
+            }
+        }
+        (source, target)
+    };
+
+    let migrator = Migrator { server_manager, node_manager: node_manager.clone(), servers_dir };
+    let mut installed = migrator.migrate(server_id, source.clone(), target.clone(), rewrite_port).await?;
+
+    let mut manager = node_manager.lock().map_err(|e| format!("Failed to lock node manager: {}", e))?;
+    if let Endpoint::Remote(node_id) = &source {
+        manager.forget_remote_server(node_id, server_id);
+    }
+    match &target {
+        Endpoint::Remote(node_id) => {
+            installed.node_id = node_id.clone();
+            manager.register_remote_server(node_id, installed.clone());
+        },
+        Endpoint::Local => installed.node_id = "local".to_string(),
+    }
+
+    info!("Migrated server {} from node {} to node {}", server_id, source.node_id(), target.node_id());
+    Ok(installed)
+}
+
+type ServerManagerState<'a> = State<'a, Arc<Mutex<ServerManager>>>;
+type NodeManagerState<'a> = State<'a, Arc<Mutex<NodeManager>>>;
+
+#[tauri::command]
+pub async fn migrate_server(
+    state: ServerManagerState<'_>,
+    node_manager: NodeManagerState<'_>,
+    id: String,
+    target_node: String,
+    rewrite_port: Option<bool>,
+) -> Result<ServerInfo, String> {
+    migrate(
+        state.inner().clone(),
+        node_manager.inner().clone(),
+        setup::SERVERS_DIR.to_string(),
+        &id,
+        &target_node,
+        rewrite_port.unwrap_or(false),
+    ).await
+}
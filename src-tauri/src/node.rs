@@ -1,20 +1,43 @@
 use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use serde::{Serialize, Deserialize};
 use tauri::State;
+use tokio::sync::{broadcast, mpsc, oneshot};
 use uuid::Uuid;
 use chrono::{DateTime, Utc};
-use log::{info, error};
+use log::{info, warn, error};
 
-use crate::server::ServerManager;
+use crate::pairing::{PairingAuditEntry, PairingEvent, PairingStore};
+use crate::protocol::{AgentAuth, AgentMessage, ControllerMessage, ErrorCode, FileChunk, NodeEvent, NodeRequest, ProtocolError};
+use crate::server::{ServerInfo, ServerManager};
+
+const REMOTE_OUTPUT_LINES: usize = 1000;
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_SESSIONS: usize = 20;
+const PAIRING_CLEANUP_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const STATUS_EVENT_CAPACITY: usize = 64;
+/// Installs download the server jar and run installers on the node.
+const INSTALL_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+/// Automatic placement skips nodes with less free disk than this.
+const PLACEMENT_MIN_FREE_DISK: u64 = 5 * 1024 * 1024 * 1024;
+/// First protocol version whose agents understand `InstallServer`.
+const INSTALL_PROTOCOL_VERSION: u32 = 2;
+
+/// Passed as the node ID to let `place_server` choose the node.
+pub const AUTO_PLACEMENT: &str = "auto";
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum NodeType {
     Local,
     Remote,
+    /// Managed over SSH by `ssh::run_node` without an agent.
+    Ssh,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum NodeStatus {
     Online,
     Offline,
@@ -42,20 +65,160 @@ pub struct Node {
     pub last_seen: Option<DateTime<Utc>>,
     pub servers: Vec<String>,   
     pub metrics: Option<NodeMetrics>,
+    /// Most recent agent sessions, oldest first.
+    #[serde(default)]
+    pub sessions: Vec<NodeSession>,
+    /// When the agent's credential expires. Filled in from the pairing store
+    /// by listings, never stored with the node.
+    #[serde(default, skip_deserializing)]
+    pub credential_expires_at: Option<DateTime<Utc>>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeSession {
+    pub connected_at: DateTime<Utc>,
+    pub disconnected_at: Option<DateTime<Utc>>,
+}
+
+/// Host usage as sampled by `metrics::MetricsSampler`. Percentages are 0-100;
+/// `disk` is the usage of the root filesystem. Fields beyond the first three
+/// are absent from older agents and default to zero.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeMetrics {
     pub cpu: f32,
     pub memory: f32,
     pub disk: f32,
+    #[serde(default)]
+    pub memory_total_bytes: u64,
+    #[serde(default)]
+    pub memory_used_bytes: u64,
+    #[serde(default)]
+    pub load_average: LoadAverage,
+    #[serde(default)]
+    pub disks: Vec<DiskUsage>,
+    #[serde(default)]
+    pub network: NetworkThroughput,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LoadAverage {
+    pub one: f32,
+    pub five: f32,
+    pub fifteen: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiskUsage {
+    pub mount_point: String,
+    pub filesystem: String,
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub available_bytes: u64,
+    pub percent: f32,
+}
+
+/// Bytes per second across all interfaces except loopback, averaged since
+/// the previous sample.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NetworkThroughput {
+    pub rx_bytes_per_sec: u64,
+    pub tx_bytes_per_sec: u64,
+}
+
+/// Published when a node goes down or comes back online.
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeStatusEvent {
+    pub node_id: String,
+    pub name: String,
+    pub previous: NodeStatus,
+    pub status: NodeStatus,
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Thresholds for the heartbeat watchdog. A node whose agent has been silent
+/// for `offline_after_missed` heartbeats is marked offline, and after
+/// `error_after_missed` it is flagged as an error.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchdogConfig {
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval_secs: u64,
+    #[serde(default = "default_offline_after_missed")]
+    pub offline_after_missed: u32,
+    #[serde(default = "default_error_after_missed")]
+    pub error_after_missed: u32,
+}
+
+fn default_heartbeat_interval() -> u64 {
+    crate::agent::HEARTBEAT_INTERVAL.as_secs()
+}
+
+fn default_offline_after_missed() -> u32 {
+    3
+}
+
+fn default_error_after_missed() -> u32 {
+    20
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        WatchdogConfig {
+            heartbeat_interval_secs: default_heartbeat_interval(),
+            offline_after_missed: default_offline_after_missed(),
+            error_after_missed: default_error_after_missed(),
+        }
+    }
+}
+
+/// The live WebSocket session of a remote node's agent.
+#[derive(Debug)]
+struct NodeConnection {
+    connection_id: String,
+    protocol_version: u32,
+    sender: mpsc::UnboundedSender<ControllerMessage>,
+    pending: HashMap<String, PendingRequest>,
+}
+
+#[derive(Debug)]
+struct PendingRequest {
+    reply: oneshot::Sender<Result<RemoteReply, ProtocolError>>,
+    file: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RemoteReply {
+    pub data: serde_json::Value,
+    /// Bytes received as `file_chunk` frames for this request.
+    pub file: Vec<u8>,
+}
+
+/// A server hosted on a remote node, as last reported by its agent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RemoteServer {
+    node_id: String,
+    info: ServerInfo,
+    #[serde(skip)]
+    output: Vec<String>,
+}
+
+/// On-disk layout of `nodes.json`. The local node is rebuilt on every start
+/// and never stored.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct NodesFile {
+    nodes: Vec<Node>,
+    #[serde(default)]
+    servers: Vec<RemoteServer>,
 }
 
 #[derive(Debug)]
 pub struct NodeManager {
     nodes: HashMap<String, Node>,
     server_manager: Arc<Mutex<ServerManager>>,
-    pub active_tokens: HashMap<String, (String, DateTime<Utc>)>,
+    pairing: PairingStore,
+    connections: HashMap<String, NodeConnection>,
+    remote_servers: HashMap<String, RemoteServer>,
+    status_events: broadcast::Sender<NodeStatusEvent>,
+    persistence_file: PathBuf,
 }
 
 impl NodeManager {
@@ -77,18 +240,103 @@ impl NodeManager {
             last_seen: Some(Utc::now()),
             servers: Vec::new(),
             metrics: None,
+            sessions: Vec::new(),
+            credential_expires_at: None,
         };
         nodes.insert(local_node.id.clone(), local_node);
         
-        Self {
+        let app_data_dir = std::env::var("APPDATA")
+            .unwrap_or_else(|_| std::env::var("HOME").unwrap_or_else(|_| ".".to_string()));
+        let persistence_file = PathBuf::from(format!("{}/ServerMint/nodes.json", app_data_dir));
+        
+        let mut manager = Self {
             nodes,
             server_manager,
-            active_tokens: HashMap::new(),
+            pairing: PairingStore::new(),
+            connections: HashMap::new(),
+            remote_servers: HashMap::new(),
+            status_events: broadcast::channel(STATUS_EVENT_CAPACITY).0,
+            persistence_file,
+        };
+        
+        if let Err(e) = manager.load_nodes() {
+            println!("Warning: Failed to load nodes from disk: {}", e);
+        }
+        
+        manager
+    }
+    
+    /// Restores remote nodes as `Connecting`; their agents (or SSH sessions)
+    /// flip them back to `Online` when they reconnect.
+    fn load_nodes(&mut self) -> Result<(), String> {
+        if !self.persistence_file.exists() {
+            return Ok(());
+        }
+        
+        let contents = fs::read_to_string(&self.persistence_file)
+            .map_err(|e| format!("Failed to read {}: {}", self.persistence_file.display(), e))?;
+        let file: NodesFile = serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse {}: {}", self.persistence_file.display(), e))?;
+        
+        for mut node in file.nodes {
+            if node.id == "local" {
+                continue;
+            }
+            node.status = NodeStatus::Connecting;
+            node.metrics = None;
+            // Agents authenticate with hashed credentials from the pairing
+            // store; never keep a plaintext token around.
+            node.config.api_token = None;
+            // Sessions still open when the app last exited ended no later than
+            // the last message we saw.
+            for session in node.sessions.iter_mut().filter(|s| s.disconnected_at.is_none()) {
+                session.disconnected_at = node.last_seen.or(Some(session.connected_at));
+            }
+            self.nodes.insert(node.id.clone(), node);
+        }
+        
+        for mut server in file.servers {
+            server.info.node_id = server.node_id.clone();
+            if self.nodes.contains_key(&server.node_id) {
+                self.remote_servers.insert(server.info.id.clone(), server);
+            }
+        }
+        
+        info!("Loaded {} remote nodes", self.nodes.len() - 1);
+        Ok(())
+    }
+    
+    pub fn save_nodes(&self) -> Result<(), String> {
+        if let Some(parent) = self.persistence_file.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        
+        let file = NodesFile {
+            nodes: self.nodes.values().filter(|n| n.id != "local").cloned().collect(),
+            servers: self.remote_servers.values().cloned().collect(),
+        };
+        let json = serde_json::to_string_pretty(&file)
+            .map_err(|e| format!("Failed to serialize nodes: {}", e))?;
+        
+        let tmp_path = self.persistence_file.with_extension("json.tmp");
+        fs::write(&tmp_path, json)
+            .map_err(|e| format!("Failed to write nodes: {}", e))?;
+        fs::rename(&tmp_path, &self.persistence_file)
+            .map_err(|e| format!("Failed to replace nodes file: {}", e))
+    }
+    
+    fn persist(&self) {
+        if let Err(e) = self.save_nodes() {
+            error!("Failed to save nodes: {}", e);
         }
     }
     
     pub fn list_nodes(&self) -> Vec<Node> {
         let mut nodes = self.nodes.values().cloned().collect::<Vec<_>>();
+        for node in &mut nodes {
+            node.credential_expires_at = self.pairing.credential_expires_at(&node.id);
+        }
         
         if let Some(local_node) = nodes.iter_mut().find(|n| n.id == "local") {
             if let Ok(server_manager) = self.server_manager.lock() {
@@ -101,7 +349,9 @@ impl NodeManager {
     }
     
     pub fn get_node(&self, id: &str) -> Option<Node> {
-        self.nodes.get(id).cloned()
+        let mut node = self.nodes.get(id).cloned()?;
+        node.credential_expires_at = self.pairing.credential_expires_at(id);
+        Some(node)
     }
     
     pub fn add_node(&mut self, node: Node) -> Result<(), String> {
@@ -109,6 +359,7 @@ impl NodeManager {
             return Err(format!("Node with ID {} already exists", node.id));
         }
         self.nodes.insert(node.id.clone(), node);
+        self.persist();
         Ok(())
     }
     
@@ -117,6 +368,7 @@ impl NodeManager {
             return Err(format!("Node with ID {} not found", id));
         }
         self.nodes.insert(id.to_string(), node);
+        self.persist();
         Ok(())
     }
     
@@ -128,21 +380,31 @@ impl NodeManager {
             return Err(format!("Node with ID {} not found", id));
         }
         self.nodes.remove(id);
+        if let Err(e) = self.pairing.revoke(id) {
+            error!("Failed to revoke credential of removed node {}: {}", id, e);
+        }
+        self.remote_servers.retain(|_, s| s.node_id != id);
+        if let Some(connection) = self.connections.remove(id) {
+            fail_pending(connection, "Node removed");
+        }
+        self.persist();
         Ok(())
     }
     
-    pub fn generate_pairing_token(&mut self) -> String {
-        let token = Uuid::new_v4().to_string();
-        let expiry = Utc::now() + chrono::Duration::minutes(10);
-        self.active_tokens.insert(token.clone(), ("pending".to_string(), expiry));
-        token
+    pub fn generate_pairing_token(&mut self, node_id: Option<String>) -> Result<String, String> {
+        if let Some(id) = &node_id {
+            if !self.nodes.contains_key(id) || id == "local" {
+                return Err(format!("Node with ID {} not found", id));
+            }
+        }
+        self.pairing.create_code(node_id)
     }
     
     pub fn update_node_metrics(&mut self, node_id: &str, metrics: NodeMetrics) -> Result<(), String> {
         if let Some(node) = self.nodes.get_mut(node_id) {
             node.metrics = Some(metrics);
             node.last_seen = Some(Utc::now());
-            node.status = NodeStatus::Online;
+            self.set_status(node_id, NodeStatus::Online);
             Ok(())
         } else {
             Err(format!("Node with ID {} not found", node_id))
@@ -150,91 +412,665 @@ impl NodeManager {
     }
 
     pub fn update_node_status(&mut self, node_id: &str, status: NodeStatus) -> Result<(), String> {
-        if let Some(node) = self.nodes.get_mut(node_id) {
-            node.status = status;
+        if self.nodes.contains_key(node_id) {
+            self.set_status(node_id, status);
             Ok(())
         } else {
             Err(format!("Node with ID {} not found", node_id))
         }
     }
 
-    pub fn validate_token(&mut self, token: &str) -> Option<String> {
-        if let Some((node_id, expiry)) = self.active_tokens.get(token) {
-            if expiry > &Utc::now() {
-                if node_id == "pending" {
-                    let node_id = format!("node-{}", Uuid::new_v4().to_string());
-                    self.active_tokens.insert(token.to_string(), (node_id.clone(), *expiry));
-                    
-                    let node = Node {
-                        id: node_id.clone(),
-                        name: format!("VPS Node {}", &node_id[5..11]),
-                        node_type: NodeType::Remote,
-                        status: NodeStatus::Connecting,
-                        config: NodeConfig {
-                            name: format!("VPS Node {}", &node_id[5..11]),
-                            hostname: None,
-                            port: None,
-                            ssh_key_path: None,
-                            username: None,
-                            api_token: Some(token.to_string()),
-                        },
-                        last_seen: Some(Utc::now()),
-                        servers: Vec::new(),
-                        metrics: None,
-                    };
-                    
-                    if let Err(e) = self.add_node(node) {
-                        error!("Failed to create node for token {}: {}", token, e);
-                        return None;
-                    }
-                    
-                    return Some(node_id);
+    /// Receives an event whenever a node goes down or recovers.
+    pub fn subscribe_status(&self) -> broadcast::Receiver<NodeStatusEvent> {
+        self.status_events.subscribe()
+    }
+
+    /// Changes a node's status. Going down marks the node's servers
+    /// `unknown` until its agent reports them again.
+    fn set_status(&mut self, node_id: &str, status: NodeStatus) {
+        let Some(node) = self.nodes.get_mut(node_id) else { return };
+        if node.status == status {
+            return;
+        }
+        let previous = std::mem::replace(&mut node.status, status.clone());
+        let name = node.name.clone();
+
+        let went_down = matches!(status, NodeStatus::Offline | NodeStatus::Error);
+        if went_down {
+            warn!("Node {} is now {:?}", node_id, status);
+            for server in self.remote_servers.values_mut().filter(|s| s.node_id == node_id) {
+                server.info.status = "unknown".to_string();
+            }
+        } else if status == NodeStatus::Online {
+            info!("Node {} is online", node_id);
+        } else {
+            return;
+        }
+
+        let _ = self.status_events.send(NodeStatusEvent {
+            node_id: node_id.to_string(),
+            name,
+            previous,
+            status,
+            timestamp: Utc::now(),
+        });
+    }
+
+    /// Drops a node's agent session without waiting for the socket to close.
+    fn drop_connection(&mut self, node_id: &str, reason: &str) -> bool {
+        let Some(connection) = self.connections.remove(node_id) else { return false };
+        fail_pending(connection, reason);
+        if let Some(node) = self.nodes.get_mut(node_id) {
+            if let Some(open) = node.sessions.iter_mut().rev().find(|s| s.disconnected_at.is_none()) {
+                open.disconnected_at = node.last_seen;
+            }
+        }
+        true
+    }
+
+    /// Marks remote nodes whose agents stopped sending heartbeats offline,
+    /// and later as errored. A silent session is dropped so that the agent
+    /// has to reconnect.
+    pub fn check_heartbeats(&mut self, config: &WatchdogConfig) {
+        let now = Utc::now();
+        let interval = config.heartbeat_interval_secs.max(1) as i64;
+
+        let overdue: Vec<(String, NodeStatus)> = self.nodes.values()
+            .filter(|node| matches!(node.node_type, NodeType::Remote | NodeType::Ssh))
+            .filter_map(|node| {
+                let missed = (now - node.last_seen?).num_seconds() / interval;
+                let status = if missed >= config.error_after_missed as i64 {
+                    NodeStatus::Error
+                } else if missed >= config.offline_after_missed as i64 {
+                    NodeStatus::Offline
                 } else {
-                    return Some(node_id.clone());
-                }
+                    return None;
+                };
+                let escalates = node.status != status
+                    && !(node.status == NodeStatus::Error && status == NodeStatus::Offline);
+                escalates.then(|| (node.id.clone(), status))
+            })
+            .collect();
+
+        let mut dropped = false;
+        for (node_id, status) in overdue {
+            if self.drop_connection(&node_id, "Node stopped sending heartbeats") {
+                warn!("Node {} stopped sending heartbeats, dropping its session", node_id);
+                dropped = true;
             }
+            self.set_status(&node_id, status);
+        }
+        if dropped {
+            self.persist();
+        }
+    }
+
+    /// Authenticates an agent's hello. Agents present either the credential
+    /// bound to their node ID or a one-time pairing code; pairing creates the
+    /// node (unless the code re-pairs an existing one) and returns the newly
+    /// issued credential alongside the node ID.
+    pub fn authenticate_agent(
+        &mut self,
+        auth: AgentAuth,
+        name: &str,
+        remote_addr: &str,
+    ) -> Result<(String, Option<String>), ProtocolError> {
+        let unauthorized = |message: String| ProtocolError::new(ErrorCode::Unauthorized, message);
+
+        let result = match auth {
+            AgentAuth::Credential { node_id, credential } => {
+                if !self.nodes.contains_key(&node_id) {
+                    Err(format!("Node {} not found", node_id))
+                } else {
+                    self.pairing.verify_credential(&node_id, &credential).map(|_| (node_id, None))
+                }
+            },
+            AgentAuth::PairingCode { code } => self.pair_node(&code, name, remote_addr)
+                .map(|(node_id, credential)| (node_id, Some(credential))),
+        };
+
+        result.map_err(|e| {
+            warn!("Rejected agent {} from {}: {}", name, remote_addr, e);
+            self.pairing.record(PairingEvent::Rejected, None, Some(remote_addr.to_string()), Some(e.clone()));
+            unauthorized(e)
+        })
+    }
+
+    fn pair_node(&mut self, code: &str, name: &str, remote_addr: &str) -> Result<(String, String), String> {
+        let node_id = match self.pairing.check_code(code)? {
+            Some(node_id) if self.nodes.contains_key(&node_id) => node_id,
+            Some(node_id) => return Err(format!("Node {} no longer exists", node_id)),
+            None => {
+                let node_id = format!("node-{}", Uuid::new_v4());
+                let node = Node {
+                    id: node_id.clone(),
+                    name: name.to_string(),
+                    node_type: NodeType::Remote,
+                    status: NodeStatus::Connecting,
+                    config: NodeConfig {
+                        name: name.to_string(),
+                        hostname: None,
+                        port: None,
+                        ssh_key_path: None,
+                        username: None,
+                        api_token: None,
+                    },
+                    last_seen: Some(Utc::now()),
+                    servers: Vec::new(),
+                    metrics: None,
+                    sessions: Vec::new(),
+                    credential_expires_at: None,
+                };
+                self.add_node(node)?;
+                node_id
+            },
+        };
+
+        let credential = self.pairing.complete_pairing(code, &node_id)?;
+        info!("Paired node {} ({}) from {}", node_id, name, remote_addr);
+        self.pairing.record(PairingEvent::Paired, Some(node_id.clone()), Some(remote_addr.to_string()), Some(name.to_string()));
+        Ok((node_id, credential))
+    }
+    
+    /// The node a pairing code was exchanged for, once its agent has paired.
+    pub fn node_for_pairing_code(&self, code: &str) -> Option<Node> {
+        self.pairing.node_for_code(code).and_then(|id| self.get_node(&id))
+    }
+    
+    pub fn needs_credential_rotation(&self, node_id: &str) -> bool {
+        self.pairing.needs_rotation(node_id)
+    }
+    
+    /// Revokes a node's credential and disconnects its agent. The node stays
+    /// listed and can be re-paired with a code bound to it.
+    pub fn revoke_node_credential(&mut self, node_id: &str) -> Result<(), String> {
+        if !self.nodes.contains_key(node_id) || node_id == "local" {
+            return Err(format!("Node with ID {} not found", node_id));
         }
         
-        if token.starts_with("sm-") {
-            let node_id = format!("node-{}", Uuid::new_v4().to_string());
-            let expiry = Utc::now() + chrono::Duration::minutes(10);
-            self.active_tokens.insert(token.to_string(), (node_id.clone(), expiry));
-            
-            let node = Node {
-                id: node_id.clone(),
-                name: format!("VPS Node {}", &node_id[5..11]),
-                node_type: NodeType::Remote,
-                status: NodeStatus::Connecting,
-                config: NodeConfig {
-                    name: format!("VPS Node {}", &node_id[5..11]),
-                    hostname: None,
-                    port: None,
-                    ssh_key_path: None,
-                    username: None,
-                    api_token: Some(token.to_string()),
-                },
-                last_seen: Some(Utc::now()),
-                servers: Vec::new(),
-                metrics: None,
-            };
-            
-            if let Err(e) = self.add_node(node) {
-                error!("Failed to create node for token {}: {}", token, e);
-                return None;
-            }
-            
-            return Some(node_id);
+        if !self.pairing.revoke(node_id)? {
+            return Err(format!("Node {} has no credential to revoke", node_id));
         }
+        self.drop_connection(node_id, "Node credential revoked");
+        self.set_status(node_id, NodeStatus::Offline);
         
-        None
+        self.pairing.record(PairingEvent::Revoked, Some(node_id.to_string()), None, None);
+        self.persist();
+        Ok(())
     }
     
     pub fn cleanup_expired_tokens(&mut self) {
+        match self.pairing.prune() {
+            Ok(0) => {},
+            Ok(removed) => info!("Removed {} expired pairing codes", removed),
+            Err(e) => warn!("Failed to clean up pairing codes: {}", e),
+        }
+    }
+
+    /// Issues a replacement credential for a connected node. The current one
+    /// keeps working until the agent is known to have the new one.
+    pub fn begin_credential_rotation(&mut self, node_id: &str) -> Result<String, String> {
+        if !self.connections.contains_key(node_id) {
+            return Err(format!("Node {} must be connected to rotate its credential", node_id));
+        }
+        self.pairing.begin_rotation(node_id)
+    }
+
+    pub fn finish_credential_rotation(&mut self, node_id: &str, delivered: bool) -> Result<(), String> {
+        if delivered {
+            self.pairing.confirm_rotation(node_id)?;
+            self.pairing.record(PairingEvent::Rotated, Some(node_id.to_string()), None, None);
+            Ok(())
+        } else {
+            self.pairing.abort_rotation(node_id)
+        }
+    }
+
+    pub fn pairing_audit(&self) -> Result<Vec<PairingAuditEntry>, String> {
+        self.pairing.read_audit()
+    }
+
+    /// Registers an authenticated agent session and returns its connection
+    /// ID. A newer session for the same node replaces the old one.
+    pub fn attach_connection(
+        &mut self,
+        node_id: &str,
+        hostname: Option<String>,
+        protocol_version: u32,
+        sender: mpsc::UnboundedSender<ControllerMessage>,
+    ) -> Result<String, String> {
+        let node = self.nodes.get_mut(node_id)
+            .ok_or_else(|| format!("Node with ID {} not found", node_id))?;
         let now = Utc::now();
-        self.active_tokens.retain(|_, (_, expiry)| *expiry > now);
+        node.last_seen = Some(now);
+        if hostname.is_some() {
+            node.config.hostname = hostname;
+        }
+        if let Some(open) = node.sessions.iter_mut().rev().find(|s| s.disconnected_at.is_none()) {
+            open.disconnected_at = Some(now);
+        }
+        node.sessions.push(NodeSession { connected_at: now, disconnected_at: None });
+        if node.sessions.len() > MAX_SESSIONS {
+            let excess = node.sessions.len() - MAX_SESSIONS;
+            node.sessions.drain(..excess);
+        }
+
+        let connection_id = Uuid::new_v4().to_string();
+        let connection = NodeConnection {
+            connection_id: connection_id.clone(),
+            protocol_version,
+            sender,
+            pending: HashMap::new(),
+        };
+        if let Some(old) = self.connections.insert(node_id.to_string(), connection) {
+            warn!("Node {} reconnected, dropping its previous session", node_id);
+            fail_pending(old, "Node reconnected");
+        }
+
+        info!("Node {} connected with protocol v{}", node_id, protocol_version);
+        self.set_status(node_id, NodeStatus::Online);
+        self.persist();
+        Ok(connection_id)
+    }
+
+    pub fn detach_connection(&mut self, node_id: &str, connection_id: &str) {
+        let is_current = self.connections.get(node_id)
+            .map(|c| c.connection_id == connection_id)
+            .unwrap_or(false);
+        if !is_current {
+            return;
+        }
+
+        self.drop_connection(node_id, "Node disconnected");
+        self.set_status(node_id, NodeStatus::Offline);
+        info!("Node {} disconnected", node_id);
+        self.persist();
+    }
+
+    pub fn is_connected(&self, node_id: &str) -> bool {
+        self.connections.contains_key(node_id)
+    }
+
+    /// The protocol version negotiated with the node's agent, if connected.
+    pub fn protocol_version(&self, node_id: &str) -> Option<u32> {
+        self.connections.get(node_id).map(|c| c.protocol_version)
+    }
+
+    /// Sends `request` to the node and returns its ID and a receiver for the
+    /// correlated reply. Use `remote_request` unless the request needs
+    /// follow-up frames.
+    pub fn send_request(
+        &mut self,
+        node_id: &str,
+        request: NodeRequest,
+    ) -> Result<(String, oneshot::Receiver<Result<RemoteReply, ProtocolError>>), String> {
+        let connection = self.connections.get_mut(node_id)
+            .ok_or_else(|| format!("Node {} is not connected", node_id))?;
+
+        let request_id = Uuid::new_v4().to_string();
+        let (reply, receiver) = oneshot::channel();
+        connection.sender.send(ControllerMessage::Request { request_id: request_id.clone(), request })
+            .map_err(|_| format!("Node {} is not connected", node_id))?;
+        connection.pending.insert(request_id.clone(), PendingRequest { reply, file: Vec::new() });
+
+        Ok((request_id, receiver))
+    }
+
+    pub fn send_file_chunks(&self, node_id: &str, chunks: Vec<FileChunk>) -> Result<(), String> {
+        let connection = self.connections.get(node_id)
+            .ok_or_else(|| format!("Node {} is not connected", node_id))?;
+        for chunk in chunks {
+            connection.sender.send(ControllerMessage::FileChunk(chunk))
+                .map_err(|_| format!("Node {} is not connected", node_id))?;
+        }
+        Ok(())
+    }
+
+    pub fn cancel_request(&mut self, node_id: &str, request_id: &str) {
+        if let Some(connection) = self.connections.get_mut(node_id) {
+            connection.pending.remove(request_id);
+        }
+    }
+
+    /// Applies a message received from a node's agent after the handshake.
+    pub fn handle_agent_message(&mut self, node_id: &str, message: AgentMessage) {
+        if let Some(node) = self.nodes.get_mut(node_id) {
+            node.last_seen = Some(Utc::now());
+        }
+
+        match message {
+            AgentMessage::Response { request_id, data } => {
+                self.complete_request(node_id, &request_id, |file| Ok(RemoteReply { data, file }));
+            },
+            AgentMessage::Error { request_id, error } => {
+                self.complete_request(node_id, &request_id, |_| Err(error));
+            },
+            AgentMessage::FileChunk(chunk) => {
+                let pending = self.connections.get_mut(node_id)
+                    .and_then(|c| c.pending.get_mut(&chunk.transfer_id));
+                match (pending, chunk.bytes()) {
+                    (Some(pending), Ok(bytes)) => pending.file.extend_from_slice(&bytes),
+                    (None, _) => warn!("Node {} sent a chunk for unknown transfer {}", node_id, chunk.transfer_id),
+                    (_, Err(e)) => warn!("Node {} sent an invalid chunk: {}", node_id, e),
+                }
+            },
+            AgentMessage::Event { event } => self.apply_event(node_id, event),
+            AgentMessage::Hello { .. } => warn!("Node {} sent a second hello", node_id),
+        }
+    }
+
+    fn complete_request<F>(&mut self, node_id: &str, request_id: &str, result: F)
+    where
+        F: FnOnce(Vec<u8>) -> Result<RemoteReply, ProtocolError>,
+    {
+        let pending = self.connections.get_mut(node_id)
+            .and_then(|c| c.pending.remove(request_id));
+        match pending {
+            Some(pending) => {
+                let _ = pending.reply.send(result(pending.file));
+            },
+            None => warn!("Node {} answered unknown request {}", node_id, request_id),
+        }
+    }
+
+    fn apply_event(&mut self, node_id: &str, event: NodeEvent) {
+        match event {
+            NodeEvent::Metrics { metrics, servers } => {
+                if let Err(e) = self.update_node_metrics(node_id, metrics) {
+                    error!("Failed to update metrics for node {}: {}", node_id, e);
+                }
+                let server_ids: Vec<String> = servers.iter().map(|s| s.id.clone()).collect();
+                let assignments_changed = match self.nodes.get_mut(node_id) {
+                    Some(node) if node.servers != server_ids => {
+                        node.servers = server_ids;
+                        true
+                    },
+                    _ => false,
+                };
+
+                self.remote_servers.retain(|id, s| s.node_id != node_id || servers.iter().any(|i| &i.id == id));
+                for mut info in servers {
+                    info.node_id = node_id.to_string();
+                    match self.remote_servers.get_mut(&info.id) {
+                        // A migrated server is reported by its new node.
+                        Some(server) => {
+                            server.node_id = node_id.to_string();
+                            server.info = info;
+                        },
+                        None => {
+                            self.remote_servers.insert(info.id.clone(), RemoteServer {
+                                node_id: node_id.to_string(),
+                                info,
+                                output: Vec::new(),
+                            });
+                        },
+                    }
+                }
+
+                if assignments_changed {
+                    self.persist();
+                }
+            },
+            NodeEvent::StatusChanged { server_id, status } => {
+                if let Some(server) = self.remote_servers.get_mut(&server_id) {
+                    server.info.status = status;
+                }
+            },
+            NodeEvent::ConsoleLine { server_id, line } => {
+                if let Some(server) = self.remote_servers.get_mut(&server_id) {
+                    server.output.push(line);
+                    if server.output.len() > REMOTE_OUTPUT_LINES {
+                        let excess = server.output.len() - REMOTE_OUTPUT_LINES;
+                        server.output.drain(..excess);
+                    }
+                }
+            },
+        }
+    }
+
+    /// Picks the node a new server is installed on. `requested` is a node ID,
+    /// `AUTO_PLACEMENT`, or `None` for the local node. Automatic placement
+    /// prefers the node with the most free memory that can fit `memory_mb`
+    /// and has room on disk, and falls back to the local node only when it
+    /// doesn't report metrics.
+    pub fn place_server(&self, requested: Option<&str>, memory_mb: u32) -> Result<String, String> {
+        match requested {
+            None | Some("local") => Ok("local".to_string()),
+            Some(AUTO_PLACEMENT) => {
+                let required_memory = memory_mb as u64 * 1024 * 1024;
+                let best = self.nodes.values()
+                    .filter(|node| node.id == "local" || self.can_install_on(&node.id))
+                    .filter_map(|node| {
+                        let metrics = node.metrics.as_ref()?;
+                        let free_memory = metrics.memory_total_bytes.saturating_sub(metrics.memory_used_bytes);
+                        let free_disk = metrics.disks.iter().map(|d| d.available_bytes).max().unwrap_or(0);
+                        (free_memory >= required_memory && free_disk >= PLACEMENT_MIN_FREE_DISK)
+                            .then_some((free_memory, free_disk, node.id.clone()))
+                    })
+                    .max();
+                if let Some((_, _, node_id)) = best {
+                    return Ok(node_id);
+                }
+
+                let local_unmeasured = self.nodes.get("local")
+                    .and_then(|node| node.metrics.as_ref())
+                    .map(|metrics| metrics.memory_total_bytes == 0)
+                    .unwrap_or(true);
+                if local_unmeasured {
+                    Ok("local".to_string())
+                } else {
+                    Err(format!("No node has {} MB of memory and {} GB of disk free", memory_mb, PLACEMENT_MIN_FREE_DISK / (1024 * 1024 * 1024)))
+                }
+            },
+            Some(node_id) => {
+                if !self.nodes.contains_key(node_id) {
+                    return Err(format!("Node with ID {} not found", node_id));
+                }
+                if !self.is_connected(node_id) {
+                    return Err(format!("Node {} is not connected", node_id));
+                }
+                if !self.can_install_on(node_id) {
+                    return Err(format!("The agent on node {} is too old to install servers, update it first", node_id));
+                }
+                Ok(node_id.to_string())
+            },
+        }
+    }
+
+    fn can_install_on(&self, node_id: &str) -> bool {
+        self.agent_supports(node_id, INSTALL_PROTOCOL_VERSION)
+    }
+
+    /// Whether the node is connected with at least protocol `version`.
+    pub fn agent_supports(&self, node_id: &str, version: u32) -> bool {
+        self.protocol_version(node_id)
+            .map(|negotiated| negotiated >= version)
+            .unwrap_or(false)
+    }
+
+    /// Records a server just installed on a remote node so it is listed
+    /// before the node's next heartbeat.
+    pub fn register_remote_server(&mut self, node_id: &str, mut info: ServerInfo) {
+        info.node_id = node_id.to_string();
+        if let Some(node) = self.nodes.get_mut(node_id) {
+            if !node.servers.contains(&info.id) {
+                node.servers.push(info.id.clone());
+            }
+        }
+        self.remote_servers.insert(info.id.clone(), RemoteServer {
+            node_id: node_id.to_string(),
+            info,
+            output: Vec::new(),
+        });
+        self.persist();
+    }
+
+    /// Drops a server that no longer lives on `node_id`.
+    pub fn forget_remote_server(&mut self, node_id: &str, server_id: &str) {
+        if self.remote_servers.get(server_id).map(|s| s.node_id == node_id).unwrap_or(false) {
+            self.remote_servers.remove(server_id);
+        }
+        if let Some(node) = self.nodes.get_mut(node_id) {
+            node.servers.retain(|id| id != server_id);
+        }
+        self.persist();
+    }
+
+    /// The remote node hosting `server_id`, if it isn't a local server.
+    pub fn node_for_server(&self, server_id: &str) -> Option<String> {
+        self.remote_servers.get(server_id).map(|s| s.node_id.clone())
+    }
+
+    /// Servers hosted on `node_id`, as last reported.
+    pub fn servers_on_node(&self, node_id: &str) -> Vec<ServerInfo> {
+        self.remote_servers.values()
+            .filter(|s| s.node_id == node_id)
+            .map(|s| s.info.clone())
+            .collect()
+    }
+
+    pub fn list_remote_servers(&self) -> Vec<ServerInfo> {
+        self.remote_servers.values().map(|s| s.info.clone()).collect()
+    }
+
+    pub fn get_remote_server_info(&self, server_id: &str) -> Option<ServerInfo> {
+        self.remote_servers.get(server_id).map(|s| s.info.clone())
+    }
+
+    pub fn get_remote_server_output(&self, server_id: &str) -> Option<Vec<String>> {
+        self.remote_servers.get(server_id).map(|s| s.output.clone())
     }
 }
 
+/// Runs the heartbeat watchdog and periodically prunes expired pairing codes.
+pub async fn run_watchdog(node_manager: Arc<Mutex<NodeManager>>, config: WatchdogConfig) {
+    let mut heartbeats = tokio::time::interval(Duration::from_secs(config.heartbeat_interval_secs.max(1)));
+    let mut cleanup = tokio::time::interval(PAIRING_CLEANUP_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = heartbeats.tick() => {
+                if let Ok(mut manager) = node_manager.lock() {
+                    manager.check_heartbeats(&config);
+                }
+            },
+            _ = cleanup.tick() => {
+                if let Ok(mut manager) = node_manager.lock() {
+                    manager.cleanup_expired_tokens();
+                }
+            },
+        }
+    }
+}
+
+fn fail_pending(connection: NodeConnection, message: &str) {
+    for (_, pending) in connection.pending {
+        let _ = pending.reply.send(Err(ProtocolError::new(ErrorCode::Disconnected, message)));
+    }
+}
+
+async fn wait_for_reply(
+    node_manager: &Arc<Mutex<NodeManager>>,
+    node_id: &str,
+    request_id: &str,
+    reply: oneshot::Receiver<Result<RemoteReply, ProtocolError>>,
+    timeout: Duration,
+) -> Result<RemoteReply, String> {
+    match tokio::time::timeout(timeout, reply).await {
+        Ok(Ok(Ok(reply))) => Ok(reply),
+        Ok(Ok(Err(e))) => Err(e.message),
+        Ok(Err(_)) => Err(format!("Node {} disconnected", node_id)),
+        Err(_) => {
+            if let Ok(mut manager) = node_manager.lock() {
+                manager.cancel_request(node_id, request_id);
+            }
+            Err(format!("Node {} did not respond within {}s", node_id, timeout.as_secs()))
+        },
+    }
+}
+
+/// Sends `request` to a remote node and waits for its reply.
+pub async fn remote_request(
+    node_manager: &Arc<Mutex<NodeManager>>,
+    node_id: &str,
+    request: NodeRequest,
+) -> Result<RemoteReply, String> {
+    remote_request_with_timeout(node_manager, node_id, request, REQUEST_TIMEOUT).await
+}
+
+/// Like `remote_request`, for requests that take longer than usual.
+pub async fn remote_request_with_timeout(
+    node_manager: &Arc<Mutex<NodeManager>>,
+    node_id: &str,
+    request: NodeRequest,
+    timeout: Duration,
+) -> Result<RemoteReply, String> {
+    let (request_id, reply) = node_manager.lock()
+        .map_err(|e| format!("Failed to lock node manager: {}", e))?
+        .send_request(node_id, request)?;
+    wait_for_reply(node_manager, node_id, &request_id, reply, timeout).await
+}
+
+/// Sends a request that is followed by `bytes` as `file_chunk` frames.
+pub async fn request_with_file(
+    node_manager: &Arc<Mutex<NodeManager>>,
+    node_id: &str,
+    request: NodeRequest,
+    bytes: &[u8],
+) -> Result<RemoteReply, String> {
+    let (request_id, reply) = {
+        let mut manager = node_manager.lock()
+            .map_err(|e| format!("Failed to lock node manager: {}", e))?;
+        let (request_id, reply) = manager.send_request(node_id, request)?;
+        manager.send_file_chunks(node_id, FileChunk::split(&request_id, bytes))?;
+        (request_id, reply)
+    };
+    wait_for_reply(node_manager, node_id, &request_id, reply, REQUEST_TIMEOUT).await
+}
+
+/// Installs a server on a remote node and registers it with the manager.
+pub async fn install_on_node(
+    node_manager: &Arc<Mutex<NodeManager>>,
+    node_id: &str,
+    request: NodeRequest,
+) -> Result<ServerInfo, String> {
+    let reply = remote_request_with_timeout(node_manager, node_id, request, INSTALL_TIMEOUT).await?;
+    let mut info: ServerInfo = serde_json::from_value(reply.data)
+        .map_err(|e| format!("Invalid install response from node {}: {}", node_id, e))?;
+    info.node_id = node_id.to_string();
+
+    node_manager.lock()
+        .map_err(|e| format!("Failed to lock node manager: {}", e))?
+        .register_remote_server(node_id, info.clone());
+    info!("Installed server {} on node {}", info.id, node_id);
+    Ok(info)
+}
+
+/// Gives a connected node's agent a fresh credential and retires the old one
+/// once the agent has stored it.
+pub async fn rotate_credential(node_manager: &Arc<Mutex<NodeManager>>, node_id: &str) -> Result<(), String> {
+    let credential = node_manager.lock()
+        .map_err(|e| format!("Failed to lock node manager: {}", e))?
+        .begin_credential_rotation(node_id)?;
+
+    let delivered = remote_request(node_manager, node_id, NodeRequest::RotateCredential { credential }).await;
+
+    let mut manager = node_manager.lock()
+        .map_err(|e| format!("Failed to lock node manager: {}", e))?;
+    manager.finish_credential_rotation(node_id, delivered.is_ok())?;
+    delivered.map(|_| info!("Rotated credential of node {}", node_id))
+}
+
+/// The node hosting `server_id` when it lives on a remote node.
+pub fn remote_node_for_server(
+    node_manager: &Arc<Mutex<NodeManager>>,
+    server_id: &str,
+) -> Result<Option<String>, String> {
+    let manager = node_manager.lock()
+        .map_err(|e| format!("Failed to lock node manager: {}", e))?;
+    Ok(manager.node_for_server(server_id))
+}
+
 type NodeManagerState<'a> = State<'a, Arc<Mutex<NodeManager>>>;
 
 #[tauri::command]
@@ -268,23 +1104,22 @@ pub fn remove_node(state: NodeManagerState, id: String) -> Result<(), String> {
 }
 
 #[tauri::command]
-pub fn generate_pairing_token(state: NodeManagerState) -> Result<String, String> {
+pub fn generate_pairing_token(state: NodeManagerState, node_id: Option<String>) -> Result<String, String> {
     let mut node_manager = state.lock().map_err(|e| format!("Failed to lock node manager: {}", e))?;
-    Ok(node_manager.generate_pairing_token())
+    node_manager.generate_pairing_token(node_id)
 } 
 
 #[tauri::command]
 pub fn check_node_connected(state: NodeManagerState, token: String) -> Result<bool, String> {
-    let mut node_manager = state.lock().map_err(|e| format!("Failed to lock node manager: {}", e))?;
+    let node_manager = state.lock().map_err(|e| format!("Failed to lock node manager: {}", e))?;
     
-    if let Some(node_id) = node_manager.validate_token(&token) {
-        info!("Token {} is valid in node manager, associated with node ID {}", token, node_id);
-        return Ok(true);
-    } else {
-        info!("Token {} not found in node manager", token);
+    match node_manager.node_for_pairing_code(&token) {
+        Some(node) => {
+            info!("Pairing code was redeemed by node {}", node.id);
+            Ok(node_manager.is_connected(&node.id))
+        },
+        None => Ok(false),
     }
-    
-    Ok(false)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -295,22 +1130,33 @@ pub struct NodeInfoResponse {
 
 #[tauri::command]
 pub fn get_node_info_by_token(state: NodeManagerState, token: String) -> Result<NodeInfoResponse, String> {
-    let mut node_manager = state.lock().map_err(|e| format!("Failed to lock node manager: {}", e))?;
+    let node_manager = state.lock().map_err(|e| format!("Failed to lock node manager: {}", e))?;
     
-    if let Some(node_id) = node_manager.validate_token(&token) {
-        if let Some(node) = node_manager.get_node(&node_id) {
-            Ok(NodeInfoResponse {
-                id: node_id,
-                hostname: node.config.hostname.clone(),
-            })
-        } else {
-            Err(format!("Node info not available for token: {}", token))
-        }
-    } else {
-        Err(format!("Invalid or unused token: {}", token))
-    }
+    node_manager.node_for_pairing_code(&token)
+        .map(|node| NodeInfoResponse {
+            id: node.id,
+            hostname: node.config.hostname,
+        })
+        .ok_or_else(|| "Pairing code has not been used yet".to_string())
 } 
 
+#[tauri::command]
+pub async fn rotate_node_credential(state: NodeManagerState<'_>, node_id: String) -> Result<(), String> {
+    rotate_credential(&state, &node_id).await
+}
+
+#[tauri::command]
+pub fn revoke_node_credential(state: NodeManagerState, node_id: String) -> Result<(), String> {
+    let mut node_manager = state.lock().map_err(|e| format!("Failed to lock node manager: {}", e))?;
+    node_manager.revoke_node_credential(&node_id)
+}
+
+#[tauri::command]
+pub fn list_pairing_audit(state: NodeManagerState) -> Result<Vec<PairingAuditEntry>, String> {
+    let node_manager = state.lock().map_err(|e| format!("Failed to lock node manager: {}", e))?;
+    node_manager.pairing_audit()
+}
+
 #[tauri::command]
 pub fn update_node_metrics(
     state: NodeManagerState,
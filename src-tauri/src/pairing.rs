@@ -0,0 +1,441 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use uuid::Uuid;
+
+use crate::api::hash_token;
+
+const PAIRING_CODE_TTL_MINUTES: i64 = 10;
+const CREDENTIAL_TTL_DAYS: i64 = 90;
+/// Credentials this close to expiry are rotated when their agent connects.
+const ROTATE_BEFORE_DAYS: i64 = 14;
+/// How long a rotated-out credential keeps working while the new one is
+/// being delivered.
+const ROTATION_GRACE_MINUTES: i64 = 60;
+const AUDIT_LIST_LIMIT: usize = 200;
+
+/// A one-time code an agent exchanges for a node credential. Only its hash
+/// is stored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PairingCode {
+    code_hash: String,
+    created_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    /// Set when the code re-pairs an existing node.
+    node_id: Option<String>,
+    redeemed_by: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct NodeCredential {
+    hash: String,
+    issued_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+    /// The credential being rotated out, accepted until the agent confirms
+    /// the new one or `previous_valid_until` passes.
+    previous_hash: Option<String>,
+    previous_valid_until: Option<DateTime<Utc>>,
+    /// When the rotated-out credential itself expires.
+    #[serde(default)]
+    previous_expires_at: Option<DateTime<Utc>>,
+    /// Set when the new credential may not have reached the agent. Both stay
+    /// valid until the agent authenticates with one, which retires the other.
+    #[serde(default)]
+    rotation_unresolved: bool,
+}
+
+impl NodeCredential {
+    fn clear_previous(&mut self) {
+        self.previous_hash = None;
+        self.previous_valid_until = None;
+        self.previous_expires_at = None;
+        self.rotation_unresolved = false;
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PairingEvent {
+    CodeCreated,
+    Paired,
+    Rejected,
+    Rotated,
+    Revoked,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairingAuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub event: PairingEvent,
+    pub node_id: Option<String>,
+    pub remote_addr: Option<String>,
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PairingFile {
+    codes: Vec<PairingCode>,
+    credentials: HashMap<String, NodeCredential>,
+}
+
+/// Pairing codes and node credentials, persisted to `pairing.json`, plus an
+/// append-only audit log in `pairing-audit.log`.
+#[derive(Debug)]
+pub struct PairingStore {
+    path: PathBuf,
+    audit_path: PathBuf,
+    codes: Vec<PairingCode>,
+    credentials: HashMap<String, NodeCredential>,
+}
+
+fn new_credential() -> String {
+    format!("smn_{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+impl PairingStore {
+    pub fn new() -> Self {
+        let app_data_dir = std::env::var("APPDATA")
+            .unwrap_or_else(|_| std::env::var("HOME").unwrap_or_else(|_| ".".to_string()));
+
+        let mut store = PairingStore {
+            path: PathBuf::from(format!("{}/ServerMint/pairing.json", app_data_dir)),
+            audit_path: PathBuf::from(format!("{}/ServerMint/pairing-audit.log", app_data_dir)),
+            codes: Vec::new(),
+            credentials: HashMap::new(),
+        };
+
+        if let Err(e) = store.load() {
+            println!("Warning: Failed to load pairing data: {}", e);
+        }
+
+        store
+    }
+
+    fn load(&mut self) -> Result<(), String> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+
+        let contents = fs::read_to_string(&self.path)
+            .map_err(|e| format!("Failed to read {}: {}", self.path.display(), e))?;
+        let file: PairingFile = serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse {}: {}", self.path.display(), e))?;
+
+        self.codes = file.codes;
+        self.credentials = file.credentials;
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+
+        let file = PairingFile {
+            codes: self.codes.clone(),
+            credentials: self.credentials.clone(),
+        };
+        let json = serde_json::to_string_pretty(&file)
+            .map_err(|e| format!("Failed to serialize pairing data: {}", e))?;
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)
+            .map_err(|e| format!("Failed to write pairing data: {}", e))?;
+        fs::rename(&tmp_path, &self.path)
+            .map_err(|e| format!("Failed to replace pairing data: {}", e))
+    }
+
+    /// Creates a pairing code, optionally bound to an existing node so its
+    /// agent can be re-paired after a revocation.
+    pub fn create_code(&mut self, node_id: Option<String>) -> Result<String, String> {
+        self.cleanup_expired();
+
+        let code = format!("sm-{}", Uuid::new_v4());
+        let now = Utc::now();
+        self.codes.push(PairingCode {
+            code_hash: hash_token(&code),
+            created_at: now,
+            expires_at: now + Duration::minutes(PAIRING_CODE_TTL_MINUTES),
+            node_id: node_id.clone(),
+            redeemed_by: None,
+        });
+        self.save()?;
+
+        self.record(PairingEvent::CodeCreated, node_id, None, None);
+        Ok(code)
+    }
+
+    /// Validates an unused, unexpired code and returns the node it is bound
+    /// to, if any. The code stays usable until `complete_pairing`.
+    pub fn check_code(&mut self, code: &str) -> Result<Option<String>, String> {
+        let hash = hash_token(code);
+        // `servermintd pair` writes codes from a separate process; every
+        // change here is saved immediately, so re-reading the file is safe.
+        if !self.codes.iter().any(|c| c.code_hash == hash) {
+            self.load()?;
+        }
+        let entry = self.codes.iter()
+            .find(|c| c.code_hash == hash)
+            .ok_or("Unknown pairing code")?;
+
+        if entry.redeemed_by.is_some() {
+            return Err("Pairing code has already been used".to_string());
+        }
+        if entry.expires_at <= Utc::now() {
+            return Err("Pairing code has expired".to_string());
+        }
+        Ok(entry.node_id.clone())
+    }
+
+    /// Burns `code` and issues the node's credential, replacing any previous
+    /// one. Returns the plaintext credential, which is never stored.
+    pub fn complete_pairing(&mut self, code: &str, node_id: &str) -> Result<String, String> {
+        let hash = hash_token(code);
+        if let Some(entry) = self.codes.iter_mut().find(|c| c.code_hash == hash) {
+            entry.redeemed_by = Some(node_id.to_string());
+        }
+
+        let credential = new_credential();
+        let now = Utc::now();
+        self.credentials.insert(node_id.to_string(), NodeCredential {
+            hash: hash_token(&credential),
+            issued_at: now,
+            expires_at: now + Duration::days(CREDENTIAL_TTL_DAYS),
+            previous_hash: None,
+            previous_valid_until: None,
+            previous_expires_at: None,
+            rotation_unresolved: false,
+        });
+        self.save()?;
+        Ok(credential)
+    }
+
+    /// The node a redeemed code was exchanged for.
+    pub fn node_for_code(&self, code: &str) -> Option<String> {
+        let hash = hash_token(code);
+        self.codes.iter()
+            .find(|c| c.code_hash == hash)
+            .and_then(|c| c.redeemed_by.clone())
+    }
+
+    /// Checks `credential` against the one bound to `node_id`. Presenting the
+    /// new credential after a rotation retires the old one, and presenting
+    /// the old one after an unresolved rotation retires the new one.
+    pub fn verify_credential(&mut self, node_id: &str, credential: &str) -> Result<(), String> {
+        let now = Utc::now();
+        let hash = hash_token(credential);
+        let stored = self.credentials.get_mut(node_id)
+            .ok_or("Node has no credential, pair it again")?;
+
+        if stored.hash == hash {
+            if stored.expires_at <= now {
+                return Err("Node credential has expired, pair it again".to_string());
+            }
+            if stored.previous_hash.is_some() {
+                stored.clear_previous();
+                self.save()?;
+            }
+            return Ok(());
+        }
+
+        let previous_valid = stored.previous_hash.as_deref() == Some(hash.as_str())
+            && stored.previous_valid_until.map(|until| until > now).unwrap_or(false);
+        if previous_valid {
+            if stored.rotation_unresolved {
+                // The agent never stored the new credential.
+                stored.hash = hash;
+                stored.expires_at = stored.previous_expires_at.or(stored.previous_valid_until).unwrap_or(now);
+                stored.clear_previous();
+                self.save()?;
+            }
+            return Ok(());
+        }
+
+        Err("Invalid node credential".to_string())
+    }
+
+    pub fn needs_rotation(&self, node_id: &str) -> bool {
+        self.credentials.get(node_id)
+            .map(|c| c.previous_hash.is_none() && c.expires_at - Utc::now() < Duration::days(ROTATE_BEFORE_DAYS))
+            .unwrap_or(false)
+    }
+
+    /// Issues a replacement credential while keeping the current one valid
+    /// for a grace period. Returns the new plaintext credential.
+    pub fn begin_rotation(&mut self, node_id: &str) -> Result<String, String> {
+        let stored = self.credentials.get_mut(node_id)
+            .ok_or_else(|| format!("Node {} has no credential", node_id))?;
+
+        let credential = new_credential();
+        let now = Utc::now();
+        stored.previous_hash = Some(std::mem::replace(&mut stored.hash, hash_token(&credential)));
+        stored.previous_valid_until = Some(now + Duration::minutes(ROTATION_GRACE_MINUTES));
+        stored.previous_expires_at = Some(stored.expires_at);
+        stored.rotation_unresolved = false;
+        stored.issued_at = now;
+        stored.expires_at = now + Duration::days(CREDENTIAL_TTL_DAYS);
+        self.save()?;
+        Ok(credential)
+    }
+
+    /// Drops the rotated-out credential once the agent has stored the new one.
+    pub fn confirm_rotation(&mut self, node_id: &str) -> Result<(), String> {
+        if let Some(stored) = self.credentials.get_mut(node_id) {
+            stored.clear_previous();
+        }
+        self.save()
+    }
+
+    /// For when delivering the new credential failed. The agent may still
+    /// have stored it, so both credentials stay valid until it authenticates
+    /// with one of them.
+    pub fn abort_rotation(&mut self, node_id: &str) -> Result<(), String> {
+        if let Some(stored) = self.credentials.get_mut(node_id) {
+            if stored.previous_hash.is_some() {
+                stored.previous_valid_until = stored.previous_expires_at.or(stored.previous_valid_until);
+                stored.rotation_unresolved = true;
+            }
+        }
+        self.save()
+    }
+
+    pub fn revoke(&mut self, node_id: &str) -> Result<bool, String> {
+        let removed = self.credentials.remove(node_id).is_some();
+        self.codes.retain(|c| c.node_id.as_deref() != Some(node_id) || c.redeemed_by.is_some());
+        self.save()?;
+        Ok(removed)
+    }
+
+    pub fn credential_expires_at(&self, node_id: &str) -> Option<DateTime<Utc>> {
+        self.credentials.get(node_id).map(|c| c.expires_at)
+    }
+
+    /// Forgets codes that expired more than a day ago; redeemed codes are kept
+    /// that long so the UI can still look up the node they created.
+    pub fn cleanup_expired(&mut self) -> usize {
+        let cutoff = Utc::now() - Duration::days(1);
+        let before = self.codes.len();
+        self.codes.retain(|c| c.expires_at > cutoff);
+        before - self.codes.len()
+    }
+
+    /// Periodic cleanup. Reloads first so codes created by `servermintd pair`
+    /// since the last read aren't dropped when the file is rewritten.
+    pub fn prune(&mut self) -> Result<usize, String> {
+        self.load()?;
+        let removed = self.cleanup_expired();
+        if removed > 0 {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    pub fn record(
+        &self,
+        event: PairingEvent,
+        node_id: Option<String>,
+        remote_addr: Option<String>,
+        detail: Option<String>,
+    ) {
+        let entry = PairingAuditEntry {
+            timestamp: Utc::now(),
+            event,
+            node_id,
+            remote_addr,
+            detail,
+        };
+
+        let result = serde_json::to_string(&entry)
+            .map_err(|e| e.to_string())
+            .and_then(|line| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.audit_path)
+                    .and_then(|mut file| writeln!(file, "{}", line))
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = result {
+            println!("Warning: Failed to write pairing audit entry: {}", e);
+        }
+    }
+
+    /// The most recent audit entries, newest first.
+    pub fn read_audit(&self) -> Result<Vec<PairingAuditEntry>, String> {
+        if !self.audit_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let contents = fs::read_to_string(&self.audit_path)
+            .map_err(|e| format!("Failed to read audit log: {}", e))?;
+        Ok(contents.lines()
+            .rev()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .take(AUDIT_LIST_LIMIT)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    fn paired_store(name: &str) -> (PairingStore, String) {
+        let dir = scratch_dir(name);
+        let mut store = PairingStore {
+            path: dir.join("pairing.json"),
+            audit_path: dir.join("pairing-audit.log"),
+            codes: Vec::new(),
+            credentials: HashMap::new(),
+        };
+        let code = store.create_code(None).unwrap();
+        let credential = store.complete_pairing(&code, "node-1").unwrap();
+        (store, credential)
+    }
+
+    #[test]
+    fn delivered_rotations_retire_the_old_credential() {
+        let (mut store, old) = paired_store("pairing-rotation-delivered");
+        let new = store.begin_rotation("node-1").unwrap();
+        // Both work while the new one is on its way.
+        store.verify_credential("node-1", &old).unwrap();
+        store.verify_credential("node-1", &new).unwrap();
+
+        store.confirm_rotation("node-1").unwrap();
+        assert!(store.verify_credential("node-1", &old).is_err());
+        store.verify_credential("node-1", &new).unwrap();
+    }
+
+    #[test]
+    fn failed_deliveries_keep_both_credentials_until_the_agent_uses_one() {
+        // The agent kept the old credential.
+        let (mut store, old) = paired_store("pairing-rotation-kept-old");
+        let expires_at = store.credential_expires_at("node-1").unwrap();
+        let new = store.begin_rotation("node-1").unwrap();
+        store.abort_rotation("node-1").unwrap();
+        assert!(!store.needs_rotation("node-1"));
+        // Past the grace period an in-flight rotation gets.
+        assert_eq!(store.credentials["node-1"].previous_valid_until, Some(expires_at));
+        store.verify_credential("node-1", &old).unwrap();
+        assert!(store.verify_credential("node-1", &new).is_err());
+        store.verify_credential("node-1", &old).unwrap();
+        assert_eq!(store.credential_expires_at("node-1"), Some(expires_at));
+
+        // The agent stored the new one even though the reply was lost.
+        let (mut store, old) = paired_store("pairing-rotation-kept-new");
+        let new = store.begin_rotation("node-1").unwrap();
+        store.abort_rotation("node-1").unwrap();
+        store.verify_credential("node-1", &new).unwrap();
+        assert!(store.verify_credential("node-1", &old).is_err());
+
+        // Resolution survives a restart.
+        store.load().unwrap();
+        store.verify_credential("node-1", &new).unwrap();
+        assert!(store.verify_credential("node-1", &old).is_err());
+    }
+}
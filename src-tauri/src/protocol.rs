@@ -0,0 +1,222 @@
+//! Wire protocol between the controller (desktop app or servermintd) and node
+//! agents. Every WebSocket text frame is one JSON message tagged by `type`.
+//!
+//! A session starts with the agent's `hello`, listing the protocol versions it
+//! speaks; the controller answers `welcome` with the version it picked, or
+//! `rejected`. Afterwards the controller sends `request`s, each answered by
+//! exactly one `response` or `error` carrying the same `request_id`, while the
+//! agent pushes `event`s on its own. File contents travel as `file_chunk`
+//! frames whose `transfer_id` is the ID of the request they belong to.
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::node::NodeMetrics;
+use crate::server::{ServerConfig, ServerInfo};
+
+pub const PROTOCOL_VERSION: u32 = 3;
+pub const SUPPORTED_VERSIONS: [u32; 3] = [1, 2, 3];
+pub const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Picks the newest version both sides speak.
+pub fn negotiate_version(offered: &[u32]) -> Option<u32> {
+    offered.iter()
+        .copied()
+        .filter(|v| SUPPORTED_VERSIONS.contains(v))
+        .max()
+}
+
+/// Messages sent by the agent to the controller.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AgentMessage {
+    Hello {
+        auth: AgentAuth,
+        name: String,
+        hostname: Option<String>,
+        agent_version: String,
+        protocol_versions: Vec<u32>,
+    },
+    Response {
+        request_id: String,
+        data: serde_json::Value,
+    },
+    Error {
+        request_id: String,
+        error: ProtocolError,
+    },
+    Event {
+        event: NodeEvent,
+    },
+    FileChunk(FileChunk),
+}
+
+/// How an agent proves its identity in `hello`: the credential bound to its
+/// node, or a one-time pairing code that the controller exchanges for one.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "method", rename_all = "snake_case")]
+pub enum AgentAuth {
+    Credential { node_id: String, credential: String },
+    PairingCode { code: String },
+}
+
+/// Messages sent by the controller to the agent.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControllerMessage {
+    Welcome {
+        node_id: String,
+        protocol_version: u32,
+        /// Issued when the agent paired with a code. The agent must store it
+        /// and authenticate with it from then on.
+        credential: Option<String>,
+    },
+    Rejected {
+        error: ProtocolError,
+    },
+    Request {
+        request_id: String,
+        request: NodeRequest,
+    },
+    FileChunk(FileChunk),
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum NodeRequest {
+    ListServers,
+    GetServer { server_id: String },
+    StartServer { server_id: String },
+    StopServer { server_id: String },
+    RestartServer { server_id: String },
+    SendCommand { server_id: String, command: String },
+    GetOutput { server_id: String },
+    /// The agent answers with `file_chunk` frames followed by a response
+    /// holding the file size.
+    ReadFile { server_id: String, path: String },
+    /// The controller follows up with `size` bytes of `file_chunk` frames; the
+    /// agent responds once the chunk marked `eof` has been written.
+    WriteFile { server_id: String, path: String, size: u64 },
+    /// Downloads and registers a new server in the agent's servers directory
+    /// and responds with its `ServerInfo`. Requires protocol v2.
+    InstallServer {
+        server_id: String,
+        server_type: String,
+        version: String,
+        download_url: Option<String>,
+        name: String,
+    },
+    /// Zips a stopped server into the agent's staging directory and responds
+    /// with a `migration::ArchiveInfo`. Requires protocol v3, as do the other
+    /// archive requests below.
+    PackageServer { server_id: String },
+    /// Responds with the `size` of a staged archive, zero if it is missing.
+    ArchiveStatus { archive: String },
+    /// Sends up to `length` bytes of a staged archive as `file_chunk` frames.
+    ReadArchive { archive: String, offset: u64, length: u64 },
+    /// Appends the `size` bytes that follow as `file_chunk` frames to a
+    /// staged archive. `offset` must equal its current size; the response
+    /// holds the new size.
+    WriteArchive { archive: String, offset: u64, size: u64 },
+    /// Verifies a staged archive against `sha256`, unpacks it into the servers
+    /// directory and registers the server, optionally on a new port.
+    ImportServer {
+        server_id: String,
+        config: ServerConfig,
+        archive: String,
+        sha256: String,
+        port: Option<u16>,
+    },
+    DeleteArchive { archive: String },
+    RemoveServer { server_id: String, delete_files: bool },
+    /// Replaces the agent's stored credential. The old one stops working
+    /// once the agent has acknowledged this request.
+    RotateCredential { credential: String },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum NodeEvent {
+    Metrics {
+        metrics: NodeMetrics,
+        servers: Vec<ServerInfo>,
+    },
+    StatusChanged {
+        server_id: String,
+        status: String,
+    },
+    ConsoleLine {
+        server_id: String,
+        line: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    Unauthorized,
+    UnsupportedVersion,
+    InvalidRequest,
+    NotFound,
+    Failed,
+    Timeout,
+    Disconnected,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProtocolError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ProtocolError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        ProtocolError { code, message: message.into() }
+    }
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FileChunk {
+    pub transfer_id: String,
+    pub offset: u64,
+    /// Base64-encoded bytes, at most `FILE_CHUNK_SIZE` before encoding.
+    pub data: String,
+    pub eof: bool,
+}
+
+impl FileChunk {
+    pub fn new(transfer_id: &str, offset: u64, bytes: &[u8], eof: bool) -> Self {
+        FileChunk {
+            transfer_id: transfer_id.to_string(),
+            offset,
+            data: base64::engine::general_purpose::STANDARD.encode(bytes),
+            eof,
+        }
+    }
+
+    pub fn bytes(&self) -> Result<Vec<u8>, String> {
+        base64::engine::general_purpose::STANDARD.decode(&self.data)
+            .map_err(|e| format!("Invalid file chunk: {}", e))
+    }
+
+    /// Splits `bytes` into frames for `transfer_id`. An empty file still
+    /// produces one `eof` frame.
+    pub fn split(transfer_id: &str, bytes: &[u8]) -> Vec<FileChunk> {
+        if bytes.is_empty() {
+            return vec![FileChunk::new(transfer_id, 0, &[], true)];
+        }
+
+        let count = bytes.len().div_ceil(FILE_CHUNK_SIZE);
+        bytes.chunks(FILE_CHUNK_SIZE)
+            .enumerate()
+            .map(|(i, chunk)| FileChunk::new(transfer_id, (i * FILE_CHUNK_SIZE) as u64, chunk, i + 1 == count))
+            .collect()
+    }
+}
@@ -0,0 +1,367 @@
+//! Restores a server from one of its backups.
+//!
+//! The server is stopped and snapshotted first, so a restore can itself be
+//! undone. Every file that needs writing is rebuilt from its chunks into a
+//! staging file beside its destination and checked against the hash in the
+//! manifest; the server directory is only touched once all of them verify.
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use tauri::State;
+use tokio::task;
+
+use crate::backup::{self, BackupFile, BackupManifest};
+use crate::chunk_store::{ChunkStore, STORE_LOCK};
+use crate::server::ServerManager;
+use crate::supervisor;
+
+const STOP_TIMEOUT: Duration = Duration::from_secs(60);
+const KILL_TIMEOUT: Duration = Duration::from_secs(10);
+const STAGING_SUFFIX: &str = ".servermint-restore";
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RestoreOptions {
+    /// Paths relative to the server directory to restore, such as
+    /// `world_nether` or `plugins/Essentials/config.yml`. Everything is
+    /// restored when empty.
+    pub paths: Vec<String>,
+    /// Leaves files that aren't in the backup alone instead of deleting
+    /// them.
+    pub keep_extra_files: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Removed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreChange {
+    pub path: String,
+    pub change: ChangeKind,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RestoreReport {
+    pub server_id: String,
+    pub backup_id: String,
+    /// The snapshot of the server taken just before restoring.
+    pub safety_backup_id: String,
+    pub changes: Vec<RestoreChange>,
+    pub unchanged: usize,
+    pub restarted: bool,
+}
+
+/// Normalizes a selected path to the manifest's `/`-separated form.
+fn normalize_selection(path: &str) -> Result<String, String> {
+    let path = path.replace('\\', "/");
+    let parts: Vec<&str> = path.split('/').filter(|p| !p.is_empty() && *p != ".").collect();
+    if parts.is_empty() || parts.contains(&"..") || path.starts_with('/') || path.contains(':') {
+        return Err(format!("Invalid restore path: {}", path));
+    }
+    Ok(parts.join("/"))
+}
+
+fn in_scope(path: &str, selections: &[String]) -> bool {
+    selections.is_empty()
+        || selections.iter().any(|s| path == s || path.strip_prefix(s.as_str()).is_some_and(|rest| rest.starts_with('/')))
+}
+
+fn staging_path(target: &Path) -> PathBuf {
+    let mut name = target.file_name().unwrap_or_default().to_os_string();
+    name.push(STAGING_SUFFIX);
+    target.with_file_name(name)
+}
+
+/// The files and directories under `root` that backups would include.
+fn walk(root: &Path, dir: &Path, files: &mut Vec<String>, dirs: &mut Vec<String>) -> Result<(), String> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    for entry in entries.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let relative = path.strip_prefix(root)
+            .map(|p| p.to_string_lossy().replace('\\', "/"))
+            .unwrap_or_else(|_| name.clone());
+        if backup::skipped(&relative, &name) || name.ends_with(STAGING_SUFFIX) {
+            continue;
+        }
+        let Ok(file_type) = entry.file_type() else { continue };
+        if file_type.is_dir() {
+            dirs.push(relative.clone());
+            walk(root, &path, files, dirs)?;
+        } else {
+            files.push(relative);
+        }
+    }
+    Ok(())
+}
+
+fn file_hash(path: &Path) -> Result<String, String> {
+    let mut file = File::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Whether the file on disk already matches the backup.
+fn matches(target: &Path, file: &BackupFile) -> Result<bool, String> {
+    match fs::metadata(target) {
+        Ok(metadata) if metadata.is_file() && metadata.len() == file.size => {
+            Ok(file_hash(target)? == file.sha256)
+        },
+        _ => Ok(false),
+    }
+}
+
+/// Rebuilds a file from its chunks into `staging` and verifies it.
+fn stage(store: &ChunkStore, file: &BackupFile, staging: &Path) -> Result<(), String> {
+    if let Some(parent) = staging.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+    }
+    let mut out = File::create(staging)
+        .map_err(|e| format!("Failed to create {}: {}", staging.display(), e))?;
+    let mut hasher = Sha256::new();
+    let mut size = 0u64;
+    for chunk in &file.chunks {
+        let data = store.get(chunk)
+            .map_err(|e| format!("Failed to restore {}: {}", file.path, e))?;
+        hasher.update(&data);
+        size += data.len() as u64;
+        out.write_all(&data)
+            .map_err(|e| format!("Failed to write {}: {}", staging.display(), e))?;
+    }
+    if size != file.size || hex::encode(hasher.finalize()) != file.sha256 {
+        return Err(format!("{} does not match the hash recorded in the backup", file.path));
+    }
+    if let Some(modified) = file.modified.and_then(|m| u64::try_from(m).ok()) {
+        let _ = out.set_modified(UNIX_EPOCH + Duration::from_millis(modified));
+    }
+    out.sync_all()
+        .map_err(|e| format!("Failed to write {}: {}", staging.display(), e))
+}
+
+/// Replaces whatever is at `path` with nothing.
+fn remove_path(path: &Path) -> Result<(), String> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(_) => return Ok(()),
+    };
+    result.map_err(|e| format!("Failed to remove {}: {}", path.display(), e))
+}
+
+/// Writes the selected part of `manifest` over the server directory. The
+/// caller must hold `STORE_LOCK` so the chunks can't be collected midway.
+fn apply(
+    manifest: &BackupManifest,
+    root: &Path,
+    selections: &[String],
+    keep_extra_files: bool,
+) -> Result<(Vec<RestoreChange>, usize), String> {
+    let store = backup::chunk_store();
+    let wanted: Vec<&BackupFile> = manifest.files.iter().filter(|f| in_scope(&f.path, selections)).collect();
+    let wanted_dirs: Vec<&String> = manifest.directories.iter().filter(|d| in_scope(d, selections)).collect();
+    let wanted_paths: HashSet<&str> = wanted.iter().map(|f| f.path.as_str())
+        .chain(wanted_dirs.iter().map(|d| d.as_str()))
+        .collect();
+
+    let (mut current_files, mut current_dirs) = (Vec::new(), Vec::new());
+    walk(root, root, &mut current_files, &mut current_dirs)?;
+    let current: HashSet<&str> = current_files.iter().map(|f| f.as_str()).collect();
+
+    let mut changes = Vec::new();
+    let mut staged = Vec::new();
+    let mut unchanged = 0;
+    let result = (|| {
+        for file in &wanted {
+            let target = root.join(&file.path);
+            if matches(&target, file)? {
+                unchanged += 1;
+                continue;
+            }
+            let staging = staging_path(&target);
+            staged.push(staging.clone());
+            stage(&store, file, &staging)?;
+            let change = if current.contains(file.path.as_str()) { ChangeKind::Modified } else { ChangeKind::Added };
+            changes.push((RestoreChange { path: file.path.clone(), change }, target, staging));
+        }
+        Ok(())
+    })();
+    if let Err(e) = result {
+        for staging in &staged {
+            let _ = fs::remove_file(staging);
+        }
+        return Err(e);
+    }
+
+    let mut report = Vec::new();
+    if !keep_extra_files {
+        for path in current_files.iter().filter(|f| in_scope(f, selections) && !wanted_paths.contains(f.as_str())) {
+            remove_path(&root.join(path))?;
+            report.push(RestoreChange { path: path.clone(), change: ChangeKind::Removed });
+        }
+        for dir in current_dirs.iter().rev().filter(|d| in_scope(d, selections) && !wanted_paths.contains(d.as_str())) {
+            remove_path(&root.join(dir))?;
+        }
+    }
+    for dir in wanted_dirs {
+        let path = root.join(dir);
+        if !path.is_dir() {
+            remove_path(&path)?;
+            fs::create_dir_all(&path)
+                .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+        }
+    }
+    for (change, target, staging) in changes {
+        if target.is_dir() {
+            remove_path(&target)?;
+        }
+        fs::rename(&staging, &target)
+            .map_err(|e| format!("Failed to restore {}: {}", change.path, e))?;
+        report.push(change);
+    }
+    report.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok((report, unchanged))
+}
+
+/// Waits up to `timeout` for the server's supervisor to exit.
+fn wait_for_exit(server_id: &str, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while supervisor::read_state(server_id).is_some() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(Duration::from_millis(250));
+    }
+    true
+}
+
+/// Asks a running server to save and `stop`, and waits for its supervisor
+/// to exit. The server is only killed if it hasn't stopped within
+/// `STOP_TIMEOUT`. Returns whether it was running.
+fn stop_and_wait(manager: &Mutex<ServerManager>, server_id: &str) -> Result<bool, String> {
+    {
+        let mut manager = manager.lock().map_err(|_| "Failed to lock server manager")?;
+        if !manager.is_running(server_id) {
+            return Ok(false);
+        }
+        let server_type = manager.get_server_info(server_id)?.config.server_type;
+        let (save_all, _) = backup::save_commands(&server_type);
+        manager.send_server_command(server_id, save_all)?;
+        manager.send_server_command(server_id, "stop")?;
+    }
+    info!("Stopping server {} for restore", server_id);
+
+    if wait_for_exit(server_id, STOP_TIMEOUT) {
+        return Ok(true);
+    }
+
+    warn!("Server {} did not stop within {}s, killing it", server_id, STOP_TIMEOUT.as_secs());
+    manager.lock()
+        .map_err(|_| "Failed to lock server manager")?
+        .stop_server(server_id)?;
+    if !wait_for_exit(server_id, KILL_TIMEOUT) {
+        return Err(format!("Server {} did not exit after being killed", server_id));
+    }
+    Ok(true)
+}
+
+/// Restores a backup over its server, stopping the server and taking a
+/// safety snapshot first, and starts it again if it was running.
+pub fn restore(
+    manager: &Mutex<ServerManager>,
+    server_id: &str,
+    backup_id: &str,
+    options: &RestoreOptions,
+) -> Result<RestoreReport, String> {
+    let manifest = backup::load_manifest(server_id, backup_id)?;
+    let selections = options.paths.iter()
+        .map(|p| normalize_selection(p))
+        .collect::<Result<Vec<_>, _>>()?;
+    for selection in &selections {
+        let found = manifest.files.iter().map(|f| &f.path)
+            .chain(&manifest.directories)
+            .any(|p| in_scope(p, std::slice::from_ref(selection)));
+        if !found {
+            return Err(format!("{} is not in backup {}", selection, backup_id));
+        }
+    }
+
+    let config = manager.lock()
+        .map_err(|_| "Failed to lock server manager")?
+        .get_server_info(server_id)?
+        .config;
+    let was_running = stop_and_wait(manager, server_id)?;
+
+    let label = format!("Before restoring {}", backup_id);
+    let safety = backup::snapshot(server_id, &config, true, Some(label))
+        .map_err(|e| format!("Failed to take a safety snapshot, nothing was restored: {}", e))?;
+
+    let (changes, unchanged) = {
+        let _guard = STORE_LOCK.read().map_err(|_| "Failed to lock backup store")?;
+        apply(&manifest, Path::new(&config.path), &selections, options.keep_extra_files)
+            .map_err(|e| format!("{}. The server's previous state is in backup {}", e, safety.id))?
+    };
+    info!(
+        "Restored backup {} of {} ({} changes, {} files unchanged)",
+        backup_id,
+        config.name,
+        changes.len(),
+        unchanged
+    );
+
+    let mut restarted = false;
+    if was_running {
+        match manager.lock().map_err(|_| "Failed to lock server manager".to_string())
+            .and_then(|mut m| m.start_server(server_id))
+        {
+            Ok(()) => restarted = true,
+            Err(e) => warn!("Failed to restart {} after restoring: {}", config.name, e),
+        }
+    }
+
+    Ok(RestoreReport {
+        server_id: server_id.to_string(),
+        backup_id: backup_id.to_string(),
+        safety_backup_id: safety.id,
+        changes,
+        unchanged,
+        restarted,
+    })
+}
+
+#[tauri::command]
+pub async fn restore_backup(
+    state: State<'_, Arc<Mutex<ServerManager>>>,
+    server_id: String,
+    backup_id: String,
+    options: Option<RestoreOptions>,
+) -> Result<RestoreReport, String> {
+    let manager = state.inner().clone();
+    let options = options.unwrap_or_default();
+    task::spawn_blocking(move || restore(&manager, &server_id, &backup_id, &options))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
@@ -0,0 +1,447 @@
+//! Per-server backup retention, persisted to `backup_retention.json`.
+//!
+//! A policy keeps the newest N backups plus the newest backup of each of the
+//! last N days, ISO weeks and months that have one, in the spirit of
+//! grandfather-father-son rotation. A backup kept by any rule survives;
+//! when a policy sets no count rules at all, every backup is kept. A size
+//! cap then removes the oldest survivors until the chunks the rest need fit,
+//! counting chunks they share once. Pinned backups and the newest backup
+//! are never pruned.
+
+use chrono::Local;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tauri::State;
+use tokio::task;
+
+use crate::backup::{self, BackupInfo, StoreUsage};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionPolicy {
+    pub keep_last: Option<u32>,
+    pub keep_daily: Option<u32>,
+    pub keep_weekly: Option<u32>,
+    pub keep_monthly: Option<u32>,
+    /// Stored bytes the server's backups may take in the chunk store.
+    pub max_total_size: Option<u64>,
+}
+
+impl RetentionPolicy {
+    fn has_count_rules(&self) -> bool {
+        self.keep_last.is_some()
+            || self.keep_daily.is_some()
+            || self.keep_weekly.is_some()
+            || self.keep_monthly.is_some()
+    }
+}
+
+/// A backup in a prune plan and the rules that decided its fate.
+#[derive(Debug, Clone, Serialize)]
+pub struct PruneEntry {
+    #[serde(flatten)]
+    pub backup: BackupInfo,
+    pub reasons: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PrunePlan {
+    pub server_id: String,
+    pub dry_run: bool,
+    pub keep: Vec<PruneEntry>,
+    pub remove: Vec<PruneEntry>,
+    /// Stored bytes freed once the chunks only the removed backups used are
+    /// collected.
+    pub reclaimed: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct RetentionFile {
+    policies: HashMap<String, RetentionPolicy>,
+    pinned: HashMap<String, BTreeSet<String>>,
+}
+
+/// Retention policies and pinned backups, keyed by server id.
+#[derive(Debug)]
+pub struct RetentionStore {
+    path: PathBuf,
+    policies: HashMap<String, RetentionPolicy>,
+    pinned: HashMap<String, BTreeSet<String>>,
+}
+
+impl RetentionStore {
+    pub fn new() -> Self {
+        let app_data_dir = std::env::var("APPDATA")
+            .unwrap_or_else(|_| std::env::var("HOME").unwrap_or_else(|_| ".".to_string()));
+
+        let mut store = RetentionStore {
+            path: PathBuf::from(format!("{}/ServerMint/backup_retention.json", app_data_dir)),
+            policies: HashMap::new(),
+            pinned: HashMap::new(),
+        };
+
+        if let Err(e) = store.load() {
+            warn!("Failed to load backup retention: {}", e);
+        }
+
+        store
+    }
+
+    fn load(&mut self) -> Result<(), String> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+
+        let contents = fs::read_to_string(&self.path)
+            .map_err(|e| format!("Failed to read {}: {}", self.path.display(), e))?;
+        let file: RetentionFile = serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse {}: {}", self.path.display(), e))?;
+
+        self.policies = file.policies;
+        self.pinned = file.pinned;
+        Ok(())
+    }
+
+    fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+
+        let file = RetentionFile {
+            policies: self.policies.clone(),
+            pinned: self.pinned.clone(),
+        };
+        let json = serde_json::to_string_pretty(&file)
+            .map_err(|e| format!("Failed to serialize backup retention: {}", e))?;
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)
+            .map_err(|e| format!("Failed to write backup retention: {}", e))?;
+        fs::rename(&tmp_path, &self.path)
+            .map_err(|e| format!("Failed to replace backup retention: {}", e))
+    }
+
+    pub fn policy(&self, server_id: &str) -> RetentionPolicy {
+        self.policies.get(server_id).cloned().unwrap_or_default()
+    }
+
+    pub fn set_policy(&mut self, server_id: &str, policy: RetentionPolicy) -> Result<(), String> {
+        if policy.max_total_size == Some(0) {
+            return Err("The size cap must be greater than zero".to_string());
+        }
+        if policy == RetentionPolicy::default() {
+            self.policies.remove(server_id);
+        } else {
+            self.policies.insert(server_id.to_string(), policy);
+        }
+        self.save()
+    }
+
+    pub fn pinned(&self, server_id: &str) -> BTreeSet<String> {
+        self.pinned.get(server_id).cloned().unwrap_or_default()
+    }
+
+    pub fn is_pinned(&self, server_id: &str, backup_id: &str) -> bool {
+        self.pinned.get(server_id).is_some_and(|ids| ids.contains(backup_id))
+    }
+
+    pub fn set_pinned(&mut self, server_id: &str, backup_id: &str, pinned: bool) -> Result<(), String> {
+        if pinned {
+            self.pinned.entry(server_id.to_string()).or_default().insert(backup_id.to_string());
+        } else if let Some(ids) = self.pinned.get_mut(server_id) {
+            ids.remove(backup_id);
+            if ids.is_empty() {
+                self.pinned.remove(server_id);
+            }
+        }
+        self.save()
+    }
+}
+
+impl Default for RetentionStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Keeps the newest backup in each of the first `count` distinct periods,
+/// where `period` names the period a backup falls in.
+fn keep_periods(
+    backups: &[BackupInfo],
+    count: Option<u32>,
+    label: &str,
+    period: impl Fn(&BackupInfo) -> String,
+    reasons: &mut [Vec<String>],
+) {
+    let Some(count) = count else { return };
+    let mut seen = HashSet::new();
+    for (i, backup) in backups.iter().enumerate() {
+        if seen.len() >= count as usize {
+            break;
+        }
+        let key = period(backup);
+        if seen.insert(key.clone()) {
+            reasons[i].push(format!("{} {}", label, key));
+        }
+    }
+}
+
+/// Decides which of a server's backups `policy` keeps. `backups` must be
+/// newest first, and `usage` covers every snapshot in the store.
+pub fn plan(
+    server_id: &str,
+    policy: &RetentionPolicy,
+    backups: Vec<BackupInfo>,
+    pinned: &BTreeSet<String>,
+    usage: &StoreUsage,
+) -> PrunePlan {
+    let mut reasons: Vec<Vec<String>> = vec![Vec::new(); backups.len()];
+    for (i, backup) in backups.iter().enumerate() {
+        if pinned.contains(&backup.id) {
+            reasons[i].push("pinned".to_string());
+        }
+    }
+    if let Some(first) = reasons.first_mut() {
+        first.push("newest".to_string());
+    }
+
+    if policy.has_count_rules() {
+        if let Some(count) = policy.keep_last {
+            for r in reasons.iter_mut().take(count as usize) {
+                r.push("last".to_string());
+            }
+        }
+        let local = |b: &BackupInfo| b.created_at.with_timezone(&Local);
+        keep_periods(&backups, policy.keep_daily, "daily", |b| local(b).format("%Y-%m-%d").to_string(), &mut reasons);
+        keep_periods(&backups, policy.keep_weekly, "weekly", |b| local(b).format("%G-W%V").to_string(), &mut reasons);
+        keep_periods(&backups, policy.keep_monthly, "monthly", |b| local(b).format("%Y-%m").to_string(), &mut reasons);
+    } else {
+        for r in reasons.iter_mut().filter(|r| r.is_empty()) {
+            r.push("no count rules".to_string());
+        }
+    }
+
+    let mut keep: Vec<bool> = reasons.iter().map(|r| !r.is_empty()).collect();
+    let mut removal_reasons: Vec<String> = keep.iter()
+        .map(|_| "not kept by any rule".to_string())
+        .collect();
+
+    let chunks = |b: &BackupInfo| usage.chunks(server_id, &b.id).into_iter().flatten();
+    let size = |chunk: &str| usage.sizes.get(chunk).copied().unwrap_or(0);
+
+    if let Some(cap) = policy.max_total_size {
+        let mut counts: HashMap<&str, usize> = HashMap::new();
+        for (backup, _) in backups.iter().zip(&keep).filter(|(_, kept)| **kept) {
+            for chunk in chunks(backup) {
+                *counts.entry(chunk.as_str()).or_insert(0) += 1;
+            }
+        }
+        let mut total: u64 = counts.keys().map(|chunk| size(chunk)).sum();
+        for i in (1..backups.len()).rev() {
+            if total <= cap {
+                break;
+            }
+            if keep[i] && !pinned.contains(&backups[i].id) {
+                keep[i] = false;
+                removal_reasons[i] = format!("over the {} byte size cap", cap);
+                for chunk in chunks(&backups[i]) {
+                    if let Some(count) = counts.get_mut(chunk.as_str()) {
+                        *count -= 1;
+                        if *count == 0 {
+                            total -= size(chunk);
+                        }
+                    }
+                }
+            }
+        }
+        if total > cap {
+            warn!("Backups of {} still use {} bytes, over their {} byte cap", server_id, total, cap);
+        }
+    }
+
+    let mut counts = usage.ref_counts();
+    let mut reclaimed = 0;
+    for (backup, _) in backups.iter().zip(&keep).filter(|(_, kept)| !**kept) {
+        for chunk in chunks(backup) {
+            if let Some(count) = counts.get_mut(chunk.as_str()) {
+                *count -= 1;
+                if *count == 0 {
+                    reclaimed += size(chunk);
+                }
+            }
+        }
+    }
+
+    let mut plan = PrunePlan {
+        server_id: server_id.to_string(),
+        dry_run: true,
+        keep: Vec::new(),
+        remove: Vec::new(),
+        reclaimed,
+    };
+    for ((backup, reasons), (kept, removal_reason)) in backups.into_iter().zip(reasons).zip(keep.into_iter().zip(removal_reasons)) {
+        if kept {
+            plan.keep.push(PruneEntry { backup, reasons });
+        } else {
+            plan.remove.push(PruneEntry { backup, reasons: vec![removal_reason] });
+        }
+    }
+    plan
+}
+
+/// Applies the server's policy to its backups, deleting those it doesn't
+/// keep unless `dry_run` is set.
+pub fn prune(store: &Mutex<RetentionStore>, server_id: &str, dry_run: bool) -> Result<PrunePlan, String> {
+    let (policy, pinned) = {
+        let store = store.lock().map_err(|_| "Failed to lock backup retention")?;
+        (store.policy(server_id), store.pinned(server_id))
+    };
+    let backups = backup::list(Some(server_id))?;
+    let usage = backup::usage()?;
+    let mut plan = plan(server_id, &policy, backups, &pinned, &usage);
+    plan.dry_run = dry_run;
+
+    if !dry_run {
+        for entry in &plan.remove {
+            backup::delete(server_id, &entry.backup.id)?;
+        }
+        if !plan.remove.is_empty() {
+            backup::collect_garbage()?;
+            info!(
+                "Pruned {} backups of {}, freeing {} bytes",
+                plan.remove.len(),
+                server_id,
+                plan.reclaimed
+            );
+        }
+    }
+    Ok(plan)
+}
+
+type RetentionState<'a> = State<'a, Arc<Mutex<RetentionStore>>>;
+
+#[tauri::command]
+pub fn get_backup_retention(state: RetentionState, server_id: String) -> Result<RetentionPolicy, String> {
+    let store = state.lock().map_err(|_| "Failed to lock backup retention")?;
+    Ok(store.policy(&server_id))
+}
+
+#[tauri::command]
+pub fn set_backup_retention(state: RetentionState, server_id: String, policy: RetentionPolicy) -> Result<(), String> {
+    let mut store = state.lock().map_err(|_| "Failed to lock backup retention")?;
+    store.set_policy(&server_id, policy)
+}
+
+#[tauri::command]
+pub fn pin_backup(state: RetentionState, server_id: String, backup_id: String, pinned: bool) -> Result<(), String> {
+    let mut store = state.lock().map_err(|_| "Failed to lock backup retention")?;
+    store.set_pinned(&server_id, &backup_id, pinned)
+}
+
+/// Prunes a server's backups now, or with `dry_run` set, only reports what
+/// pruning would remove.
+#[tauri::command]
+pub async fn prune_backups(state: RetentionState<'_>, server_id: String, dry_run: bool) -> Result<PrunePlan, String> {
+    let store = state.inner().clone();
+    task::spawn_blocking(move || prune(&store, &server_id, dry_run))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    /// Newest first, at local times so the periods don't depend on the zone
+    /// the tests run in.
+    const BACKUPS: [(&str, (i32, u32, u32, u32)); 8] = [
+        ("sun-evening", (2024, 3, 31, 18)), // 2024-W13
+        ("sun-noon", (2024, 3, 31, 12)),
+        ("sat", (2024, 3, 30, 12)),
+        ("mon", (2024, 3, 25, 12)),
+        ("prev-sun", (2024, 3, 24, 12)), // 2024-W12
+        ("march-1", (2024, 3, 1, 12)), // 2024-W09
+        ("leap-day", (2024, 2, 29, 12)), // 2024-W09, February
+        ("january", (2024, 1, 15, 12)), // 2024-W03
+    ];
+
+    fn backups() -> Vec<BackupInfo> {
+        BACKUPS.iter()
+            .map(|(id, (year, month, day, hour))| BackupInfo {
+                id: id.to_string(),
+                server_id: "survival".to_string(),
+                server_name: "Survival".to_string(),
+                created_at: Local.with_ymd_and_hms(*year, *month, *day, *hour, 0, 0).unwrap().with_timezone(&Utc),
+                game_version: "1.21.1".to_string(),
+                consistent: true,
+                label: None,
+                file_count: 0,
+                total_size: 0,
+                added_size: 0,
+                pinned: false,
+                warnings: Vec::new(),
+            })
+            .collect()
+    }
+
+    fn kept(policy: &RetentionPolicy, pinned: &[&str]) -> PrunePlan {
+        let pinned = pinned.iter().map(|id| id.to_string()).collect();
+        plan("survival", policy, backups(), &pinned, &StoreUsage::new(&[], HashMap::new()))
+    }
+
+    #[test]
+    fn policies_keep_the_backups_their_rules_select() {
+        let policy = |keep_last, keep_daily, keep_weekly, keep_monthly| RetentionPolicy {
+            keep_last,
+            keep_daily,
+            keep_weekly,
+            keep_monthly,
+            max_total_size: None,
+        };
+        let cases: [(&str, RetentionPolicy, &[&str], &[&str]); 11] = [
+            ("no count rules keep everything", policy(None, None, None, None), &[],
+                &["sun-evening", "sun-noon", "sat", "mon", "prev-sun", "march-1", "leap-day", "january"]),
+            ("last", policy(Some(2), None, None, None), &[], &["sun-evening", "sun-noon"]),
+            ("the newest survives keeping none", policy(Some(0), None, None, None), &[], &["sun-evening"]),
+            ("daily keeps each day's newest", policy(None, Some(3), None, None), &[], &["sun-evening", "sat", "mon"]),
+            ("weekly uses ISO weeks", policy(None, None, Some(2), None), &[], &["sun-evening", "prev-sun"]),
+            ("weekly skips weeks without backups", policy(None, None, Some(10), None), &[],
+                &["sun-evening", "prev-sun", "march-1", "january"]),
+            ("monthly", policy(None, None, None, Some(3)), &[], &["sun-evening", "leap-day", "january"]),
+            ("rules add up", policy(Some(1), Some(2), None, Some(2)), &[], &["sun-evening", "sat", "leap-day"]),
+            ("overlapping rules", policy(Some(1), Some(1), Some(1), Some(1)), &[], &["sun-evening"]),
+            ("pins", policy(Some(1), None, None, None), &["march-1", "january"], &["sun-evening", "march-1", "january"]),
+            ("pins with no count rules", policy(None, None, None, None), &["mon"],
+                &["sun-evening", "sun-noon", "sat", "mon", "prev-sun", "march-1", "leap-day", "january"]),
+        ];
+
+        for (name, policy, pinned, expected) in cases {
+            let plan = kept(&policy, pinned);
+            let ids: Vec<&str> = plan.keep.iter().map(|e| e.backup.id.as_str()).collect();
+            assert_eq!(ids, expected, "{}", name);
+            assert_eq!(plan.keep.len() + plan.remove.len(), BACKUPS.len(), "{}", name);
+            assert!(plan.remove.iter().all(|e| e.reasons == ["not kept by any rule"]), "{}", name);
+        }
+    }
+
+    #[test]
+    fn kept_backups_say_which_rules_kept_them() {
+        let policy = RetentionPolicy { keep_last: Some(1), keep_weekly: Some(2), ..RetentionPolicy::default() };
+        let plan = kept(&policy, &["january"]);
+        let reasons: Vec<(&str, Vec<&str>)> = plan.keep.iter()
+            .map(|e| (e.backup.id.as_str(), e.reasons.iter().map(String::as_str).collect()))
+            .collect();
+        assert_eq!(reasons, [
+            ("sun-evening", vec!["newest", "last", "weekly 2024-W13"]),
+            ("prev-sun", vec!["weekly 2024-W12"]),
+            ("january", vec!["pinned"]),
+        ]);
+    }
+}
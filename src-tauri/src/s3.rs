@@ -0,0 +1,366 @@
+//! A small blocking client for S3-compatible object storage: AWS S3, MinIO,
+//! Backblaze B2, Cloudflare R2 and the like. Requests are signed with
+//! Signature Version 4, and objects larger than the part size go up as
+//! multipart uploads.
+
+use chrono::Utc;
+use ring::hmac;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(60);
+/// S3 refuses parts smaller than this, except the last.
+const MIN_PART_SIZE: u64 = 5 * 1024 * 1024;
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+fn default_part_size() -> u64 {
+    8 * 1024 * 1024
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-central-1.amazonaws.com` or `http://127.0.0.1:9000`.
+    pub endpoint: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    pub bucket: String,
+    /// Prepended to every key, so several installs can share a bucket.
+    #[serde(default)]
+    pub prefix: String,
+    pub access_key_id: String,
+    #[serde(default)]
+    pub secret_access_key: String,
+    /// Addresses the bucket in the path (`endpoint/bucket/key`), as MinIO and
+    /// most self-hosted stores expect, instead of as a subdomain.
+    #[serde(default)]
+    pub path_style: bool,
+    /// Objects larger than this are uploaded in parts of this size.
+    #[serde(default = "default_part_size")]
+    pub part_size: u64,
+}
+
+/// One object of a listing, with its key relative to the configured prefix.
+#[derive(Debug, Clone)]
+pub struct S3Object {
+    pub key: String,
+    pub size: u64,
+}
+
+/// Percent-encodes everything but RFC 3986 unreserved characters, and `/`
+/// too unless `keep_slash`.
+fn uri_encode(value: &str, keep_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => encoded.push(byte as char),
+            b'/' if keep_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn xml_unescape(value: &str) -> String {
+    value.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// The text of every `<tag>` element in `xml`, in order.
+fn xml_values<'a>(xml: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{}>", tag);
+    let close = format!("</{}>", tag);
+    let mut values = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        rest = &rest[start + open.len()..];
+        let Some(end) = rest.find(&close) else { break };
+        values.push(&rest[..end]);
+        rest = &rest[end + close.len()..];
+    }
+    values
+}
+
+fn xml_value(xml: &str, tag: &str) -> Option<String> {
+    xml_values(xml, tag).first().map(|v| xml_unescape(v))
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data.as_bytes()).as_ref().to_vec()
+}
+
+pub struct S3Client {
+    config: S3Config,
+    agent: ureq::Agent,
+    scheme: String,
+    /// `host[:port]`, without the port when it's the scheme's default, as
+    /// the `Host` header will carry it.
+    authority: String,
+}
+
+impl S3Client {
+    pub fn new(config: S3Config) -> Result<Self, String> {
+        let endpoint = config.endpoint.trim().trim_end_matches('/');
+        let (scheme, authority) = endpoint.split_once("://")
+            .ok_or_else(|| format!("Invalid S3 endpoint {}: expected http:// or https://", config.endpoint))?;
+        let scheme = scheme.to_lowercase();
+        let default_port = match scheme.as_str() {
+            "http" => ":80",
+            "https" => ":443",
+            _ => return Err(format!("Invalid S3 endpoint {}: expected http:// or https://", config.endpoint)),
+        };
+        if authority.is_empty() || authority.contains('/') {
+            return Err(format!("Invalid S3 endpoint {}: it can't contain a path", config.endpoint));
+        }
+        if config.bucket.is_empty() || config.access_key_id.is_empty() {
+            return Err("An S3 destination needs a bucket and an access key".to_string());
+        }
+        if config.part_size < MIN_PART_SIZE {
+            return Err(format!("The S3 part size must be at least {} bytes", MIN_PART_SIZE));
+        }
+
+        let authority = authority.strip_suffix(default_port).unwrap_or(authority).to_lowercase();
+        let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+        Ok(S3Client { config, agent, scheme, authority })
+    }
+
+    fn full_key(&self, key: &str) -> String {
+        let prefix = self.config.prefix.trim_matches('/');
+        if prefix.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}/{}", prefix, key)
+        }
+    }
+
+    /// Sends a signed request. `key` is the full object key, or `None` for
+    /// the bucket itself.
+    fn send(&self, method: &str, key: Option<&str>, query: &[(&str, &str)], body: &[u8]) -> Result<ureq::Response, String> {
+        let (host, path) = if self.config.path_style {
+            let key = key.map(|k| format!("/{}", uri_encode(k, true))).unwrap_or_default();
+            (self.authority.clone(), format!("/{}{}", uri_encode(&self.config.bucket, false), key))
+        } else {
+            let host = format!("{}.{}", self.config.bucket, self.authority);
+            (host, format!("/{}", key.map(|k| uri_encode(k, true)).unwrap_or_default()))
+        };
+
+        let mut params: Vec<(String, String)> = query.iter()
+            .map(|(k, v)| (uri_encode(k, false), uri_encode(v, false)))
+            .collect();
+        params.sort();
+        let query_string = params.iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let now = Utc::now();
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = sha256_hex(body);
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!(
+            "{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method, path, query_string, host, payload_hash, amz_date, signed_headers, payload_hash
+        );
+        let scope = format!("{}/{}/s3/aws4_request", date, self.config.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            sha256_hex(canonical_request.as_bytes())
+        );
+        let mut signing_key = hmac_sha256(format!("AWS4{}", self.config.secret_access_key).as_bytes(), &date);
+        for part in [self.config.region.as_str(), "s3", "aws4_request"] {
+            signing_key = hmac_sha256(&signing_key, part);
+        }
+        let signature = hex::encode(hmac_sha256(&signing_key, &string_to_sign));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.config.access_key_id, scope, signed_headers, signature
+        );
+
+        let mut url = format!("{}://{}{}", self.scheme, host, path);
+        if !query_string.is_empty() {
+            url.push('?');
+            url.push_str(&query_string);
+        }
+        let request = self.agent.request(method, &url)
+            .set("x-amz-date", &amz_date)
+            .set("x-amz-content-sha256", &payload_hash)
+            .set("Authorization", &authorization);
+
+        match request.send_bytes(body) {
+            Ok(response) => Ok(response),
+            Err(ureq::Error::Status(status, response)) => {
+                let body = response.into_string().unwrap_or_default();
+                let code = xml_value(&body, "Code").unwrap_or_else(|| status.to_string());
+                let message = xml_value(&body, "Message").unwrap_or_default();
+                Err(format!("S3 {} {} failed: {} {}", method, key.unwrap_or(&self.config.bucket), code, message).trim_end().to_string())
+            },
+            Err(e) => Err(format!("Failed to reach {}: {}", host, e)),
+        }
+    }
+
+    fn read_body(response: ureq::Response) -> Result<Vec<u8>, String> {
+        let mut body = Vec::new();
+        response.into_reader().read_to_end(&mut body)
+            .map_err(|e| format!("Failed to read S3 response: {}", e))?;
+        Ok(body)
+    }
+
+    pub fn put(&self, key: &str, data: &[u8]) -> Result<(), String> {
+        let key = self.full_key(key);
+        if data.len() as u64 > self.config.part_size {
+            return self.put_multipart(&key, data);
+        }
+        self.send("PUT", Some(&key), &[], data).map(|_| ())
+    }
+
+    fn put_multipart(&self, key: &str, data: &[u8]) -> Result<(), String> {
+        let response = self.send("POST", Some(key), &[("uploads", "")], &[])?;
+        let body = String::from_utf8_lossy(&Self::read_body(response)?).into_owned();
+        let upload_id = xml_value(&body, "UploadId")
+            .ok_or_else(|| format!("S3 did not start a multipart upload of {}", key))?;
+
+        let result = (|| {
+            let mut parts = Vec::new();
+            for (i, part) in data.chunks(self.config.part_size as usize).enumerate() {
+                let number = (i + 1).to_string();
+                let response = self.send("PUT", Some(key), &[("partNumber", &number), ("uploadId", &upload_id)], part)?;
+                let etag = response.header("ETag")
+                    .ok_or_else(|| format!("S3 returned no ETag for part {} of {}", number, key))?
+                    .to_string();
+                parts.push(format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", number, etag));
+            }
+
+            let complete = format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", parts.concat());
+            let response = self.send("POST", Some(key), &[("uploadId", &upload_id)], complete.as_bytes())?;
+            // Completion can fail after the 200 status has been sent.
+            let body = String::from_utf8_lossy(&Self::read_body(response)?).into_owned();
+            if body.contains("<Error>") {
+                return Err(format!(
+                    "S3 failed to complete the upload of {}: {}",
+                    key,
+                    xml_value(&body, "Message").unwrap_or_default()
+                ));
+            }
+            Ok(())
+        })();
+
+        if result.is_err() {
+            let _ = self.send("DELETE", Some(key), &[("uploadId", &upload_id)], &[]);
+        }
+        result
+    }
+
+    pub fn get(&self, key: &str) -> Result<Vec<u8>, String> {
+        let response = self.send("GET", Some(&self.full_key(key)), &[], &[])?;
+        Self::read_body(response)
+    }
+
+    pub fn delete(&self, key: &str) -> Result<(), String> {
+        self.send("DELETE", Some(&self.full_key(key)), &[], &[]).map(|_| ())
+    }
+
+    /// Every object whose key starts with `prefix`.
+    pub fn list(&self, prefix: &str) -> Result<Vec<S3Object>, String> {
+        let full_prefix = self.full_key(prefix);
+        let strip = full_prefix.len() - prefix.len();
+        let mut objects = Vec::new();
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", full_prefix.as_str())];
+            if let Some(token) = token.as_deref() {
+                query.push(("continuation-token", token));
+            }
+            let response = self.send("GET", None, &query, &[])?;
+            let body = String::from_utf8_lossy(&Self::read_body(response)?).into_owned();
+
+            for contents in xml_values(&body, "Contents") {
+                let (Some(key), Some(size)) = (xml_value(contents, "Key"), xml_value(contents, "Size")) else {
+                    continue;
+                };
+                if key.len() >= strip {
+                    objects.push(S3Object {
+                        key: key[strip..].to_string(),
+                        size: size.parse().unwrap_or(0),
+                    });
+                }
+            }
+
+            token = xml_value(&body, "NextContinuationToken");
+            if xml_value(&body, "IsTruncated").as_deref() != Some("true") || token.is_none() {
+                return Ok(objects);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::S3Stub;
+
+    #[test]
+    fn signed_requests_store_list_and_delete_objects() {
+        let stub = S3Stub::start();
+        let client = S3Client::new(stub.config("install-1/")).unwrap();
+
+        for key in ["snapshots/a.json", "snapshots/b c+d.json", "snapshots/e&f.json", "chunks/ab/abcd"] {
+            client.put(key, key.as_bytes()).unwrap();
+        }
+        assert_eq!(stub.object("install-1/snapshots/b c+d.json").as_deref(), Some(&b"snapshots/b c+d.json"[..]));
+        assert_eq!(client.get("snapshots/e&f.json").unwrap(), b"snapshots/e&f.json");
+
+        // Three objects take two pages of the stub's listing.
+        let listed: Vec<(String, u64)> = client.list("snapshots/").unwrap().into_iter().map(|o| (o.key, o.size)).collect();
+        assert_eq!(listed, [
+            ("snapshots/a.json".to_string(), 16),
+            ("snapshots/b c+d.json".to_string(), 20),
+            ("snapshots/e&f.json".to_string(), 18),
+        ]);
+
+        client.delete("snapshots/a.json").unwrap();
+        assert!(client.get("snapshots/a.json").unwrap_err().contains("NoSuchKey"));
+        assert_eq!(stub.keys(), ["install-1/chunks/ab/abcd", "install-1/snapshots/b c+d.json", "install-1/snapshots/e&f.json"]);
+    }
+
+    #[test]
+    fn requests_signed_with_the_wrong_secret_are_refused() {
+        let stub = S3Stub::start();
+        let mut config = stub.config("");
+        config.secret_access_key = "not-the-secret".to_string();
+        let client = S3Client::new(config).unwrap();
+
+        let error = client.put("probe", b"data").unwrap_err();
+        assert!(error.contains("SignatureDoesNotMatch"), "{}", error);
+        assert!(stub.keys().is_empty());
+    }
+
+    #[test]
+    fn objects_larger_than_a_part_go_up_in_parts() {
+        let stub = S3Stub::start();
+        let client = S3Client::new(stub.config("")).unwrap();
+        let data: Vec<u8> = (0..MIN_PART_SIZE * 2 + 1000).map(|i| (i % 251) as u8).collect();
+
+        client.put("chunks/big", &data).unwrap();
+        assert_eq!(client.get("chunks/big").unwrap(), data);
+
+        let requests = stub.requests();
+        assert_eq!(requests[0], "POST chunks/big?uploads=");
+        let parts = requests.iter().filter(|r| r.starts_with("PUT chunks/big?partNumber=")).count();
+        assert_eq!(parts, 3);
+        assert!(requests[4].starts_with("POST chunks/big?uploadId="));
+    }
+}
@@ -1,14 +1,17 @@
 use serde::{Deserialize, Serialize};
-use std::process::{Child, Command, Stdio};
+use std::process::Command;
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
 use tauri::State;
-use std::fs::{File, OpenOptions};
-use std::io::{Write, BufRead, BufReader, Read};
-use std::thread;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Write, Read};
 use std::path::Path;
 use std::path::PathBuf;
 
+use crate::node::{self, NodeManager};
+use crate::protocol::NodeRequest;
+use crate::supervisor::{self, LaunchSpec, SupervisorHandle, SupervisorState};
+
 #[cfg(target_os = "windows")]
 use std::os::windows::process::CommandExt;
 
@@ -32,6 +35,18 @@ pub struct ServerInfo {
     pub status: String,
     pub players: u32,
     pub max_players: u32,
+    /// The node hosting the server; `local` for servers on this machine.
+    #[serde(default = "default_node_id")]
+    pub node_id: String,
+}
+
+fn default_node_id() -> String {
+    "local".to_string()
+}
+
+/// Memory given to a freshly installed server, in megabytes.
+pub(crate) fn default_max_memory(server_type: &str) -> u32 {
+    if server_type.eq_ignore_ascii_case("pocketmine") { 2048 } else { 4096 }
 }
 
 #[derive(Debug)]
@@ -43,10 +58,20 @@ pub struct ServerManager {
 #[derive(Debug)]
 struct ServerProcess {
     config: ServerConfig,
-    process: Option<Child>,
     status: String,
     output: Arc<Mutex<Vec<String>>>,
-    stdin: Option<std::process::ChildStdin>,
+    supervisor: Option<SupervisorHandle>,
+}
+
+impl ServerProcess {
+    /// A server whose supervisor has gone away (crash, `stop` from the
+    /// console) is reported offline even if we never asked it to stop.
+    fn effective_status(&self) -> String {
+        match &self.supervisor {
+            Some(supervisor) if !supervisor.is_alive() => "offline".to_string(),
+            _ => self.status.clone(),
+        }
+    }
 }
 
 impl ServerManager {
@@ -74,10 +99,9 @@ impl ServerManager {
 
         self.servers.insert(id.clone(), ServerProcess {
             config,
-            process: None,
             status: "offline".to_string(),
             output: Arc::new(Mutex::new(Vec::new())),
-            stdin: None,
+            supervisor: None,
         });
 
         if let Err(e) = self.save_servers() {
@@ -87,12 +111,27 @@ impl ServerManager {
         Ok(())
     }
 
+    /// Starts a server, holding the manager for as long as the supervisor
+    /// takes to come up. Callers sharing the manager should use
+    /// [`start_local_server`] instead.
     pub fn start_server(&mut self, id: &str) -> Result<(), String> {
+        let spec = self.prepare_start(id)?;
+        let launched = SupervisorHandle::launch(&spec);
+        self.finish_start(id, launched)
+    }
+
+    /// Checks that a server can start and marks it starting. Returns what to
+    /// launch, which must then be handed to [`Self::finish_start`].
+    fn prepare_start(&mut self, id: &str) -> Result<LaunchSpec, String> {
         let server = self.servers.get_mut(id).ok_or_else(|| format!("Server {} not found", id))?;
         
-        if server.process.is_some() {
+        if server.supervisor.as_ref().map(|s| s.is_alive()).unwrap_or(false) {
             return Err("Server is already running".to_string());
         }
+        if server.supervisor.is_none() && server.status == "starting" {
+            return Err("Server is already starting".to_string());
+        }
+        server.supervisor = None;
 
         let is_bedrock = server.config.server_type.to_lowercase() == "pocketmine";
         
@@ -108,15 +147,19 @@ impl ServerManager {
             }
         }
 
-        self.try_start_server(id)
+        let spec = self.launch_spec(id)?;
+        if let Some(server) = self.servers.get_mut(id) {
+            server.status = "starting".to_string();
+        }
+        Ok(spec)
     }
     
-    fn try_start_server(&mut self, id: &str) -> Result<(), String> {
-        let server = self.servers.get_mut(id).ok_or_else(|| format!("Server {} not found", id))?;
+    fn launch_spec(&self, id: &str) -> Result<LaunchSpec, String> {
+        let server = self.servers.get(id).ok_or_else(|| format!("Server {} not found", id))?;
 
         let is_bedrock = server.config.server_type.to_lowercase() == "pocketmine";
 
-        if is_bedrock {
+        let (program, args) = if is_bedrock {
             let php_path = if std::path::Path::new(&format!("{}/bin/php/php.exe", server.config.path)).exists() {
                 format!("{}/bin/php/php.exe", server.config.path)
             } else if std::path::Path::new(&format!("{}/php.exe", server.config.path)).exists() {
@@ -127,64 +170,7 @@ impl ServerManager {
             
             println!("Starting Bedrock server {} with PHP at: {}", id, php_path);
             
-            let mut command = Command::new(&php_path);
-            command.arg("PocketMine-MP.phar");
-            
-            command.current_dir(&server.config.path);
-            
-            command.stdout(Stdio::piped());
-            command.stderr(Stdio::piped());
-            command.stdin(Stdio::piped());
-            
-            #[cfg(target_os = "windows")]
-            command.creation_flags(0x08000000);
-            
-            println!("Executing Bedrock command: {:?}", command);
-            
-            match command.spawn() {
-                Ok(mut child) => {
-                    println!("Bedrock server {} started successfully with PID: {}", id, child.id());
-                    
-                    let stdout = child.stdout.take().expect("Failed to capture stdout");
-                    let stderr = child.stderr.take().expect("Failed to capture stderr");
-                    let stdin = child.stdin.take().expect("Failed to capture stdin");
-                    
-                    let output_clone = server.output.clone();
-                    
-                    thread::spawn(move || {
-                        let reader = BufReader::new(stdout);
-                        for line in reader.lines() {
-                            if let Ok(line) = line {
-                                if let Ok(mut output) = output_clone.lock() {
-                                    output.push(line);
-                                }
-                            }
-                        }
-                    });
-                    
-                    let output_clone = server.output.clone();
-                    thread::spawn(move || {
-                        let reader = BufReader::new(stderr);
-                        for line in reader.lines() {
-                            if let Ok(line) = line {
-                                if let Ok(mut output) = output_clone.lock() {
-                                    output.push(line);
-                                }
-                            }
-                        }
-                    });
-                    
-                    server.process = Some(child);
-                    server.stdin = Some(stdin);
-                    server.status = "online".to_string();
-                    
-                    Ok(())
-                },
-                Err(e) => {
-                    println!("Failed to start Bedrock server {}: {}", id, e);
-                    Err(format!("Failed to start Bedrock server: {}", e))
-                },
-            }
+            (php_path, vec!["PocketMine-MP.phar".to_string()])
         } else {
             let java_path = if let Some(custom_path) = &server.config.java_path {
                 custom_path.clone()
@@ -206,115 +192,134 @@ impl ServerManager {
             
             println!("Starting Java server {} with Java path: {}", id, java_path);
             
-            let mut command = Command::new(&java_path);
-            
-            command.arg(format!("-Xms{}M", server.config.min_memory));
-            command.arg(format!("-Xmx{}M", server.config.max_memory));
+            let mut args = vec![
+                format!("-Xms{}M", server.config.min_memory),
+                format!("-Xmx{}M", server.config.max_memory),
+            ];
             
             if let Some(jvm_args) = &server.config.jvm_args {
                 for arg in jvm_args.split_whitespace() {
-                    command.arg(arg);
+                    args.push(arg.to_string());
                 }
             }
             
-            command.arg("-jar");
-            command.arg("server.jar");
-            command.arg("nogui");
-            
-            command.current_dir(&server.config.path);
-            
-            command.stdout(Stdio::piped());
-            command.stderr(Stdio::piped());
-            command.stdin(Stdio::piped());
+            args.push("-jar".to_string());
+            args.push("server.jar".to_string());
+            args.push("nogui".to_string());
             
-            #[cfg(target_os = "windows")]
-            command.creation_flags(0x08000000);
-            
-            println!("Executing Java command: {:?}", command);
-            
-            match command.spawn() {
-                Ok(mut child) => {
-                    println!("Java server {} started successfully with PID: {}", id, child.id());
-                    
-                    let stdout = child.stdout.take().expect("Failed to capture stdout");
-                    let stderr = child.stderr.take().expect("Failed to capture stderr");
-                    let stdin = child.stdin.take().expect("Failed to capture stdin");
-                    
-                    let output_clone = server.output.clone();
-                    
-                    thread::spawn(move || {
-                        let reader = BufReader::new(stdout);
-                        for line in reader.lines() {
-                            if let Ok(line) = line {
-                                if let Ok(mut output) = output_clone.lock() {
-                                    output.push(line);
-                                }
-                            }
-                        }
-                    });
-                    
-                    let output_clone = server.output.clone();
-                    thread::spawn(move || {
-                        let reader = BufReader::new(stderr);
-                        for line in reader.lines() {
-                            if let Ok(line) = line {
-                                if let Ok(mut output) = output_clone.lock() {
-                                    output.push(line);
-                                }
-                            }
-                        }
-                    });
-                    
-                    server.process = Some(child);
-                    server.stdin = Some(stdin);
-                    server.status = "online".to_string();
-                    
-                    Ok(())
-                },
-                Err(e) => {
-                    println!("Failed to start Java server {}: {}", id, e);
-                    Err(format!("Failed to start Java server: {}", e))
-                },
+            (java_path, args)
+        };
+
+        let spec = LaunchSpec {
+            server_id: id.to_string(),
+            program,
+            args,
+            working_dir: server.config.path.clone(),
+        };
+        
+        println!("Launching server {} under supervisor: {:?}", id, spec);
+        Ok(spec)
+    }
+
+    /// Records the outcome of launching what [`Self::prepare_start`] returned.
+    fn finish_start(&mut self, id: &str, launched: Result<SupervisorState, String>) -> Result<(), String> {
+        let Some(server) = self.servers.get_mut(id) else {
+            // Removed while it was starting.
+            if let Ok(state) = launched {
+                let _ = SupervisorHandle::attach(state, Arc::new(Mutex::new(Vec::new()))).stop();
             }
+            return Err(format!("Server {} not found", id));
+        };
+
+        match launched {
+            Ok(state) => {
+                println!("Server {} started successfully with PID: {}", id, state.pid);
+                
+                if let Ok(mut output) = server.output.lock() {
+                    output.clear();
+                }
+                server.supervisor = Some(SupervisorHandle::attach(state, server.output.clone()));
+                server.status = "online".to_string();
+                
+                Ok(())
+            },
+            Err(e) => {
+                println!("Failed to start server {}: {}", id, e);
+                server.status = "offline".to_string();
+                Err(format!("Failed to start server: {}", e))
+            },
         }
     }
 
     pub fn stop_server(&mut self, id: &str) -> Result<(), String> {
         let server = self.servers.get_mut(id).ok_or_else(|| format!("Server {} not found", id))?;
         
-        if let Some(mut process) = server.process.take() {
-            match process.kill() {
+        if let Some(supervisor) = server.supervisor.take() {
+            match supervisor.stop() {
                 Ok(_) => {
                     server.status = "offline".to_string();
                     Ok(())
                 },
-                Err(e) => Err(format!("Failed to stop server: {}", e)),
+                Err(e) => {
+                    let still_running = supervisor.is_alive();
+                    if still_running {
+                        server.supervisor = Some(supervisor);
+                        Err(format!("Failed to stop server: {}", e))
+                    } else {
+                        server.status = "offline".to_string();
+                        Ok(())
+                    }
+                },
             }
         } else {
             Err("Server is not running".to_string())
         }
     }
 
+    /// Stops the server if it is running, waits for its supervisor to exit,
+    /// then starts it again.
+    pub fn restart_server(&mut self, id: &str) -> Result<(), String> {
+        if self.is_running(id) {
+            self.stop_server(id)?;
+
+            let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+            while supervisor::read_state(id).is_some() && std::time::Instant::now() < deadline {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+        }
+
+        self.start_server(id)
+    }
+
     pub fn get_server_info(&self, id: &str) -> Result<ServerInfo, String> {
         let server = self.servers.get(id).ok_or_else(|| format!("Server {} not found", id))?;
         
         Ok(ServerInfo {
             id: id.to_string(),
             config: server.config.clone(),
-            status: server.status.clone(),
+            status: server.effective_status(),
             players: 0,
             max_players: 20,
+            node_id: default_node_id(),
         })
     }
 
+    pub fn is_running(&self, id: &str) -> bool {
+        self.servers.get(id)
+            .and_then(|server| server.supervisor.as_ref())
+            .map(|supervisor| supervisor.is_alive())
+            .unwrap_or(false)
+    }
+
     pub fn list_servers(&self) -> Vec<ServerInfo> {
         self.servers.iter().map(|(id, server)| {
             ServerInfo {
                 id: id.clone(),
                 config: server.config.clone(),
-                status: server.status.clone(),
+                status: server.effective_status(),
                 players: 0,
                 max_players: 20,
+                node_id: default_node_id(),
             }
         }).collect()
     }
@@ -343,8 +348,8 @@ impl ServerManager {
     
     pub fn clear_all_servers(&mut self) {
         for (id, server) in self.servers.iter_mut() {
-            if let Some(mut process) = server.process.take() {
-                let _ = process.kill();
+            if let Some(supervisor) = server.supervisor.take() {
+                let _ = supervisor.stop();
                 println!("Stopped server: {}", id);
             }
         }
@@ -369,15 +374,8 @@ impl ServerManager {
     pub fn send_server_command(&mut self, id: &str, command: &str) -> Result<(), String> {
         let server = self.servers.get_mut(id).ok_or_else(|| format!("Server {} not found", id))?;
         
-        if let Some(stdin) = &mut server.stdin {
-            let command_with_newline = format!("{}\n", command);
-            if let Err(e) = stdin.write_all(command_with_newline.as_bytes()) {
-                return Err(format!("Failed to send command: {}", e));
-            }
-            if let Err(e) = stdin.flush() {
-                return Err(format!("Failed to flush command: {}", e));
-            }
-            Ok(())
+        if let Some(supervisor) = &server.supervisor {
+            supervisor.send_command(command)
         } else {
             Err("Server stdin not available".to_string())
         }
@@ -399,6 +397,7 @@ impl ServerManager {
                 status: server.status.clone(),
                 players: 0,
                 max_players: 20,
+                node_id: default_node_id(),
             });
         }
         
@@ -445,22 +444,76 @@ impl ServerManager {
         
         let server_count = server_data.len();
         
+        let mut reattached_count = 0;
+        
         for server_info in server_data {
+            let output = Arc::new(Mutex::new(Vec::new()));
+            let supervisor = supervisor::find_running(&server_info.id)
+                .map(|state| {
+                    println!("Reattaching to running server {} (PID {})", server_info.id, state.pid);
+                    reattached_count += 1;
+                    SupervisorHandle::attach(state, output.clone())
+                });
+            let status = if supervisor.is_some() { "online" } else { "offline" };
+            
             self.servers.insert(server_info.id.clone(), ServerProcess {
                 config: server_info.config,
-                process: None,
-                status: "offline".to_string(),
-                output: Arc::new(Mutex::new(Vec::new())),
-                stdin: None,
+                status: status.to_string(),
+                output,
+                supervisor,
             });
         }
         
-        println!("Loaded {} servers from {}", server_count, self.persistence_file);
+        println!("Loaded {} servers from {} ({} still running)", server_count, self.persistence_file, reattached_count);
         Ok(())
     }
 }
 
+/// Joins `relative` onto a server directory, rejecting absolute paths, `..`
+/// and symlinks that lead out of it, so callers can't reach outside of it.
+pub(crate) fn resolve_server_path(root: &str, relative: &str) -> Result<PathBuf, String> {
+    let mut resolved = PathBuf::from(root);
+    for component in Path::new(relative).components() {
+        match component {
+            std::path::Component::Normal(part) => resolved.push(part),
+            std::path::Component::CurDir => {},
+            _ => return Err(format!("Invalid path: {}", relative)),
+        }
+    }
+
+    // Only the deepest existing ancestor can be a link, as everything below
+    // it doesn't exist yet.
+    let canonical_root = Path::new(root).canonicalize()
+        .map_err(|e| format!("Failed to resolve server directory {}: {}", root, e))?;
+    let mut existing = resolved.as_path();
+    loop {
+        if let Ok(metadata) = fs::symlink_metadata(existing) {
+            let inside = existing.canonicalize()
+                .map(|target| target.starts_with(&canonical_root))
+                .unwrap_or(!metadata.is_symlink());
+            if !inside {
+                return Err(format!("Path leads outside the server directory: {}", relative));
+            }
+            break;
+        }
+        match existing.parent() {
+            Some(parent) => existing = parent,
+            None => break,
+        }
+    }
+    Ok(resolved)
+}
+
+/// Starts a server, locking the manager only to check it and to record the
+/// result, so it stays usable while the supervisor comes up.
+pub fn start_local_server(manager: &Mutex<ServerManager>, id: &str) -> Result<(), String> {
+    let spec = manager.lock().map_err(|_| "Failed to lock server manager")?.prepare_start(id)?;
+    let launched = SupervisorHandle::launch(&spec);
+    manager.lock().map_err(|_| "Failed to lock server manager")?.finish_start(id, launched)
+}
+
 type ServerManagerState<'a> = State<'a, Arc<Mutex<ServerManager>>>;
+type NodeManagerState<'a> = State<'a, Arc<Mutex<NodeManager>>>;
 
 #[tauri::command]
 pub fn add_server(
@@ -473,19 +526,31 @@ pub fn add_server(
 }
 
 #[tauri::command]
-pub fn start_server(
-    state: ServerManagerState,
+pub async fn start_server(
+    state: ServerManagerState<'_>,
+    node_manager: NodeManagerState<'_>,
     id: String,
 ) -> Result<(), String> {
-    let mut manager = state.lock().map_err(|_| "Failed to lock server manager")?;
-    manager.start_server(&id)
+    if let Some(node_id) = node::remote_node_for_server(&node_manager, &id)? {
+        let request = NodeRequest::StartServer { server_id: id };
+        return node::remote_request(&node_manager, &node_id, request).await.map(|_| ());
+    }
+    let manager = state.inner().clone();
+    tokio::task::spawn_blocking(move || start_local_server(&manager, &id))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
 }
 
 #[tauri::command]
-pub fn stop_server(
-    state: ServerManagerState,
+pub async fn stop_server(
+    state: ServerManagerState<'_>,
+    node_manager: NodeManagerState<'_>,
     id: String,
 ) -> Result<(), String> {
+    if let Some(node_id) = node::remote_node_for_server(&node_manager, &id)? {
+        let request = NodeRequest::StopServer { server_id: id };
+        return node::remote_request(&node_manager, &node_id, request).await.map(|_| ());
+    }
     let mut manager = state.lock().map_err(|_| "Failed to lock server manager")?;
     manager.stop_server(&id)
 }
@@ -493,15 +558,21 @@ pub fn stop_server(
 #[tauri::command]
 pub fn get_server_info(
     state: ServerManagerState,
+    node_manager: NodeManagerState,
     id: String,
 ) -> Result<ServerInfo, String> {
+    if let Some(info) = node_manager.lock().ok().and_then(|m| m.get_remote_server_info(&id)) {
+        return Ok(info);
+    }
     let manager = state.lock().map_err(|_| "Failed to lock server manager")?;
     manager.get_server_info(&id)
 }
 
+/// Lists local servers followed by those reported by connected nodes.
 #[tauri::command]
 pub fn list_servers(
     state: ServerManagerState,
+    node_manager: NodeManagerState,
 ) -> Vec<ServerInfo> {
     let manager = match state.lock() {
         Ok(guard) => guard,
@@ -510,7 +581,11 @@ pub fn list_servers(
             return Vec::new();
         }
     };
-    manager.list_servers()
+    let mut servers = manager.list_servers();
+    if let Ok(nodes) = node_manager.lock() {
+        servers.extend(nodes.list_remote_servers());
+    }
+    servers
 }
 
 #[tauri::command]
@@ -525,18 +600,27 @@ pub fn remove_server(
 #[tauri::command]
 pub fn get_server_output(
     state: ServerManagerState,
+    node_manager: NodeManagerState,
     id: String,
 ) -> Result<Vec<String>, String> {
+    if let Some(output) = node_manager.lock().ok().and_then(|m| m.get_remote_server_output(&id)) {
+        return Ok(output);
+    }
     let manager = state.lock().map_err(|_| "Failed to lock server manager")?;
     manager.get_server_output(&id)
 }
 
 #[tauri::command]
-pub fn send_server_command(
-    state: ServerManagerState,
+pub async fn send_server_command(
+    state: ServerManagerState<'_>,
+    node_manager: NodeManagerState<'_>,
     id: String,
     command: String,
 ) -> Result<(), String> {
+    if let Some(node_id) = node::remote_node_for_server(&node_manager, &id)? {
+        let request = NodeRequest::SendCommand { server_id: id, command };
+        return node::remote_request(&node_manager, &node_id, request).await.map(|_| ());
+    }
     let mut manager = state.lock().map_err(|_| "Failed to lock server manager")?;
     manager.send_server_command(&id, &command)
 }
@@ -730,9 +814,75 @@ async fn extract_zip(zip_path: &str, extract_to: &str) -> Result<(), String> {
     Ok(())
 }
 
+/// What the UI sends to install a new server.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SetupServerRequest {
+    pub server_id: String,
+    /// Remote nodes choose the server directory themselves, so this only
+    /// applies locally.
+    pub server_path: String,
+    pub server_type: String,
+    pub version: String,
+    #[serde(default)]
+    pub download_url: Option<String>,
+    pub server_name: String,
+    /// Omitted or `local` for this machine, a remote node's ID, or `auto` to
+    /// pick the node with the most free memory.
+    #[serde(default)]
+    pub node_id: Option<String>,
+}
+
+/// Installs a new server on the node `request` names. Returns the directory
+/// the server was installed in.
 #[tauri::command]
 pub async fn setup_server(
     state: ServerManagerState<'_>,
+    node_manager: NodeManagerState<'_>,
+    request: SetupServerRequest,
+) -> Result<String, String> {
+    let target = node_manager.lock()
+        .map_err(|e| format!("Failed to lock node manager: {}", e))?
+        .place_server(request.node_id.as_deref(), default_max_memory(&request.server_type))?;
+
+    if target != "local" {
+        let install = NodeRequest::InstallServer {
+            server_id: request.server_id,
+            server_type: request.server_type,
+            version: request.version,
+            download_url: request.download_url,
+            name: request.server_name,
+        };
+        let info = node::install_on_node(node_manager.inner(), &target, install).await?;
+        return Ok(info.config.path);
+    }
+
+    install_server(
+        state.inner(),
+        request.server_id,
+        request.server_path,
+        request.server_type,
+        request.version,
+        request.download_url,
+        request.server_name,
+    ).await
+}
+
+/// Where the server software of `server_type` is downloaded from.
+pub(crate) fn server_download_url(server_type: &str, version: &str) -> Result<String, String> {
+    match server_type.to_lowercase().as_str() {
+        "vanilla" => Ok(format!("https://download.servermint.app/vanilla/vanilla_{}.jar", version)),
+        "paper" => Ok(format!("https://download.servermint.app/paper/paper-{}.jar", version)),
+        "fabric" => Ok(format!("https://download.servermint.app/fabric/fabric-{}.jar", version)),
+        "forge" => Ok(format!("https://maven.minecraftforge.net/net/minecraftforge/forge/{}/forge-{}-installer.jar", version, version)),
+        "neoforge" => Ok(format!("https://maven.neoforged.net/releases/net/neoforged/forge/{}/forge-{}-installer.jar", version, version)),
+        "pocketmine" => Ok(format!("https://github.com/pmmp/PocketMine-MP/releases/download/{}/PocketMine-MP.phar", version)),
+        _ => Err(format!("Unsupported server type: {}", server_type)),
+    }
+}
+
+/// Downloads and lays out a new server, then registers it with the manager.
+pub async fn install_server(
+    server_manager: &Arc<Mutex<ServerManager>>,
     server_id: String,
     server_path: String,
     server_type: String,
@@ -747,17 +897,7 @@ pub async fn setup_server(
     
     let final_url = match download_url {
         Some(url) => url,
-        None => {
-            match server_type.to_lowercase().as_str() {
-                "vanilla" => format!("https://download.servermint.app/vanilla/vanilla_{}.jar", version),
-                "paper" => format!("https://download.servermint.app/paper/paper-{}.jar", version),
-                "fabric" => format!("https://download.servermint.app/fabric/fabric-{}.jar", version),
-                "forge" => format!("https://maven.minecraftforge.net/net/minecraftforge/forge/{}/forge-{}-installer.jar", version, version),
-                "neoforge" => format!("https://maven.neoforged.net/releases/net/neoforged/forge/{}/forge-{}-installer.jar", version, version),
-                "pocketmine" => format!("https://github.com/pmmp/PocketMine-MP/releases/download/{}/PocketMine-MP.phar", version),
-                _ => return Err(format!("Unsupported server type: {}", server_type)),
-            }
-        }
+        None => server_download_url(&server_type, &version)?,
     };
     
     let is_bedrock = server_type.to_lowercase() == "pocketmine";
@@ -993,8 +1133,9 @@ eula=true",
             .map_err(|e| format!("Failed to create {} directory: {}", dir, e))?;
     }
     
-    let mut manager = state.lock().map_err(|_| "Failed to lock server manager")?;
+    let mut manager = server_manager.lock().map_err(|_| "Failed to lock server manager")?;
     
+    let max_memory = default_max_memory(&server_type);
     let config = ServerConfig {
         name: server_name,
         path: server_path.clone(),
@@ -1002,7 +1143,7 @@ eula=true",
         server_type,
         java_path: None,
         min_memory: if is_bedrock { 512 } else { 1024 },
-        max_memory: if is_bedrock { 2048 } else { 4096 },
+        max_memory,
         jvm_args: None,
         port: if is_bedrock { 19132 } else { 25565 },
     };
@@ -1189,4 +1330,64 @@ pub fn get_local_ip() -> Result<String, String> {
     }
 
     Ok(Ipv4Addr::LOCALHOST.to_string())
-}
\ No newline at end of file
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn server_paths_stay_inside_the_server_directory() {
+        let dir = scratch_dir("server-paths");
+        let root = dir.join("server");
+        let outside = dir.join("outside");
+        fs::create_dir_all(root.join("plugins")).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("secret.txt"), "secret").unwrap();
+        symlink(&outside, root.join("escape")).unwrap();
+        symlink(outside.join("secret.txt"), root.join("secret-link")).unwrap();
+        symlink(dir.join("missing"), root.join("dangling")).unwrap();
+        symlink(root.join("plugins"), root.join("mods")).unwrap();
+        let root = root.to_str().unwrap();
+
+        for path in ["../outside/secret.txt", "/etc/passwd", "escape", "escape/secret.txt", "escape/new.txt", "secret-link", "dangling"] {
+            assert!(resolve_server_path(root, path).is_err(), "{}", path);
+        }
+        assert_eq!(resolve_server_path(root, "").unwrap(), Path::new(root));
+        assert_eq!(resolve_server_path(root, "./plugins/new.jar").unwrap(), Path::new(root).join("plugins/new.jar"));
+        assert_eq!(resolve_server_path(root, "mods/a/b.jar").unwrap(), Path::new(root).join("mods/a/b.jar"));
+    }
+
+    #[test]
+    fn a_server_that_is_starting_cannot_start_again() {
+        let dir = scratch_dir("server-start");
+        fs::write(dir.join("server.jar"), "").unwrap();
+        let mut manager = ServerManager {
+            servers: HashMap::new(),
+            persistence_file: dir.join("servers.json").display().to_string(),
+        };
+        manager.add_server("survival".to_string(), ServerConfig {
+            name: "Survival".to_string(),
+            path: dir.display().to_string(),
+            version: "1.21.1".to_string(),
+            server_type: "paper".to_string(),
+            java_path: Some("java".to_string()),
+            min_memory: 1024,
+            max_memory: 2048,
+            jvm_args: None,
+            port: 25565,
+        }).unwrap();
+
+        let spec = manager.prepare_start("survival").unwrap();
+        assert_eq!(spec.args, ["-Xms1024M", "-Xmx2048M", "-jar", "server.jar", "nogui"]);
+        assert_eq!(manager.get_server_info("survival").unwrap().status, "starting");
+        assert_eq!(manager.prepare_start("survival").unwrap_err(), "Server is already starting");
+
+        let error = manager.finish_start("survival", Err("java not found".to_string())).unwrap_err();
+        assert!(error.contains("java not found"), "{}", error);
+        assert_eq!(manager.get_server_info("survival").unwrap().status, "offline");
+        assert!(manager.prepare_start("survival").is_ok());
+    }
+}
@@ -1,6 +1,11 @@
 use std::fs;
 use std::path::Path;
 
+/// Where the desktop app creates servers.
+pub const SERVERS_DIR: &str = "C:/servermint/servers";
+/// Where server backups are kept, one subdirectory per server.
+pub const BACKUPS_DIR: &str = "C:/servermint/backups";
+
 pub fn ensure_app_directories() -> Result<(), Box<dyn std::error::Error>> {
     let app_dir = Path::new("C:/servermint");
     if !app_dir.exists() {
@@ -14,13 +19,11 @@ pub fn ensure_app_directories() -> Result<(), Box<dyn std::error::Error>> {
         println!("Created servers directory: {:?}", servers_dir);
     }
 
-    // Create any other required directories here
-    // For example:
-    // let backups_dir = app_dir.join("backups");
-    // if !backups_dir.exists() {
-    //     fs::create_dir_all(&backups_dir)?;
-    //     println!("Created backups directory: {:?}", backups_dir);
-    // }
+    let backups_dir = Path::new(BACKUPS_DIR);
+    if !backups_dir.exists() {
+        fs::create_dir_all(backups_dir)?;
+        println!("Created backups directory: {:?}", backups_dir);
+    }
 
     Ok(())
 } 
\ No newline at end of file
@@ -1,238 +1,599 @@
+//! SFTP client for exchanging files with hosting providers, built on `ssh2`.
+//! Sessions are pooled per host, user and credentials, and reused until they
+//! sit idle for `IDLE_TIMEOUT` or the connection fails.
+
+use chrono::{DateTime, TimeZone, Utc};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io;
+use std::net::{TcpStream, ToSocketAddrs};
 use std::path::Path;
-use std::process::Command;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use log::info;
+use sha2::{Digest, Sha256};
+use ssh2::{ErrorCode, FileStat, Session, Sftp};
+use thiserror::Error;
+use tauri::AppHandle;
 use tokio::task;
-use std::fs;
+
+use crate::known_hosts::{self, HostKeyError, PresentedKey};
+use crate::transfer::{self, TransferDirection};
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Applies to every blocking call on a session.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+// SFTP status codes from draft-ietf-secsh-filexfer.
+const FX_NO_SUCH_FILE: i32 = 2;
+const FX_PERMISSION_DENIED: i32 = 3;
+
+#[derive(Debug, Error)]
+pub enum SftpError {
+    #[error("Could not resolve hostname {host}. Please check your SFTP host.")]
+    Resolve { host: String },
+    #[error("Connection to {host}:{port} was refused. Please check your host and port.")]
+    ConnectionRefused { host: String, port: u16 },
+    #[error("Failed to connect to {host}:{port}: {source}")]
+    Connect { host: String, port: u16, source: io::Error },
+    #[error("SSH handshake with {host} failed: {source}")]
+    Handshake { host: String, source: ssh2::Error },
+    #[error(transparent)]
+    HostKey(#[from] HostKeyError),
+    #[error("Authentication as {username} on {host} failed. Please check your username and password or key.")]
+    Authentication { username: String, host: String },
+    #[error("The server does not offer SFTP: {0}")]
+    Subsystem(ssh2::Error),
+    #[error("The SFTP connection was lost: {0}")]
+    Connection(ssh2::Error),
+    #[error("{0} does not exist")]
+    NotFound(String),
+    #[error("Permission denied on {0}")]
+    PermissionDenied(String),
+    #[error("SFTP operation on {path} failed: {source}")]
+    Remote { path: String, source: ssh2::Error },
+    #[error("Failed to access local file {path}: {source}")]
+    Local { path: String, source: io::Error },
+    #[error("Transfer of {path} failed: {source}")]
+    Transfer { path: String, source: io::Error },
+    #[error("Unsupported SFTP command: {0}")]
+    UnsupportedCommand(String),
+    #[error("Invalid SFTP command: {0}")]
+    InvalidCommand(String),
+    #[error("Transfer cancelled")]
+    Cancelled,
+}
+
+impl SftpError {
+    /// Classifies an error returned by an SFTP operation on `path`.
+    pub(crate) fn remote(path: &str, source: ssh2::Error) -> Self {
+        match source.code() {
+            ErrorCode::SFTP(FX_NO_SUCH_FILE) => SftpError::NotFound(path.to_string()),
+            ErrorCode::SFTP(FX_PERMISSION_DENIED) => SftpError::PermissionDenied(path.to_string()),
+            ErrorCode::SFTP(_) => SftpError::Remote { path: path.to_string(), source },
+            ErrorCode::Session(_) => SftpError::Connection(source),
+        }
+    }
+
+    /// Whether the session is unusable, as opposed to the operation failing.
+    /// A stream that breaks mid-transfer counts, as it almost always means
+    /// the connection went away.
+    pub(crate) fn is_connection_lost(&self) -> bool {
+        matches!(self, SftpError::Connection(_) | SftpError::Transfer { .. })
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SftpConfig {
     pub host: String,
     pub port: u16,
     pub username: String,
+    #[serde(default)]
     pub password: String,
+    /// Authenticates with this private key instead of, or before, the
+    /// password.
+    #[serde(default)]
+    pub private_key_path: Option<String>,
+    #[serde(default)]
+    pub passphrase: Option<String>,
+    /// Relative paths are resolved against this directory.
     pub remote_path: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RemoteFileType {
+    File,
+    Directory,
+    Symlink,
+    Other,
+}
+
+/// One entry of a remote directory listing.
+#[derive(Debug, Clone, Serialize)]
+pub struct RemoteEntry {
+    pub name: String,
+    pub path: String,
+    #[serde(rename = "type")]
+    pub file_type: RemoteFileType,
+    pub size: u64,
+    /// `ls -l` style, e.g. `drwxr-xr-x`.
+    pub permissions: String,
+    pub mode: u32,
+    pub modified: Option<DateTime<Utc>>,
+    pub symlink_target: Option<String>,
+}
+
+struct Connection {
+    sftp: Sftp,
+    last_used: Instant,
+}
+
+lazy_static::lazy_static! {
+    static ref CONNECTIONS: Mutex<HashMap<String, Arc<Mutex<Connection>>>> = Mutex::new(HashMap::new());
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
 impl SftpConfig {
-    fn create_temp_files(&self, commands: &[&str]) -> Result<(std::path::PathBuf, std::path::PathBuf), String> {
-        let temp_dir = std::env::temp_dir();
-        let batch_path = temp_dir.join("sftp_commands.txt");
-        let ps_path = temp_dir.join("sftp_script.ps1");
-
-        fs::write(&batch_path, commands.join("\n"))
-            .map_err(|e| format!("Failed to create batch file: {}", e))?;
-
-        let ps_script = format!(
-            r#"
-            $password = "{}"
-            $securePassword = ConvertTo-SecureString $password -AsPlainText -Force
-            $username = "{}"
-            $sftpHost = "{}"
-            $port = {}
-            $batchFile = "{}"
-            
-            $psftp = Join-Path $env:TEMP "psftp.exe"
-            $plink = Join-Path $env:TEMP "plink.exe"
-            if (-not (Test-Path $psftp)) {{
-                $url = "https://the.earth.li/~sgtatham/putty/latest/w64/psftp.exe"
-                Invoke-WebRequest -Uri $url -OutFile $psftp
-            }}
-            if (-not (Test-Path $plink)) {{
-                $url = "https://the.earth.li/~sgtatham/putty/latest/w64/plink.exe"
-                Invoke-WebRequest -Uri $url -OutFile $plink
-            }}
-
-            $plinkOutput = & $plink -v -P $port -pw $password "$username@$sftpHost" "exit" 2>&1
-            $hostKey = $plinkOutput | Select-String "Server host key is" | ForEach-Object {{ $_.ToString().Split(":")[1].Trim() }}
-            
-            if ($hostKey) {{
-                $regPath = "HKCU:\Software\SimonTatham\PuTTY\SshHostKeys"
-                $keyName = "ssh-ed25519@$($port):$($sftpHost)"
-                
-                if (-not (Test-Path $regPath)) {{
-                    New-Item -Path $regPath -Force | Out-Null
-                }}
-                New-ItemProperty -Path $regPath -Name $keyName -Value $hostKey -PropertyType String -Force | Out-Null
-            }}
-
-            $output = & $psftp -P $port -pw $password -batch -b $batchFile "$username@$sftpHost" 2>&1
-            $output | ForEach-Object {{ Write-Host $_ }}
-            "#,
-            self.password,
-            self.username,
-            self.host,
-            self.port,
-            batch_path.display()
-        );
-
-        fs::write(&ps_path, ps_script)
-            .map_err(|e| format!("Failed to create PowerShell script: {}", e))?;
-
-        Ok((batch_path, ps_path))
-    }
-
-    fn run_sftp_command(&self, _batch_path: &Path, ps_path: &Path) -> Result<String, String> {
-        let output = Command::new("powershell")
-            .args([
-                "-ExecutionPolicy", "Bypass",
-                "-NoProfile",
-                "-File", ps_path.to_str().unwrap(),
-            ])
-            .output()
-            .map_err(|e| format!("Failed to execute PowerShell script: {}", e))?;
-
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-        let combined = format!("{}{}", stdout, stderr);
-
-        println!("PowerShell output: {}", combined);
-
-        if combined.contains("No such host is known") {
-            return Err("Could not resolve hostname. Please check your SFTP host.".to_string());
+    /// The host without a `sftp://` style scheme or trailing slash.
+    fn hostname(&self) -> &str {
+        let host = self.host.trim();
+        host.split_once("://").map(|(_, rest)| rest).unwrap_or(host).trim_end_matches('/')
+    }
+
+    /// Identifies pooled sessions without keeping the password around.
+    fn connection_key(&self) -> String {
+        let mut hasher = Sha256::new();
+        for part in [
+            self.hostname(),
+            &self.port.to_string(),
+            &self.username,
+            &self.password,
+            self.private_key_path.as_deref().unwrap_or(""),
+            self.passphrase.as_deref().unwrap_or(""),
+        ] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
         }
-        if combined.contains("Permission denied") {
-            return Err("Permission denied. Please check your username and password.".to_string());
+        hex::encode(hasher.finalize())
+    }
+
+    /// Connects and completes the SSH handshake, without checking the host
+    /// key or authenticating.
+    fn handshake(&self) -> Result<Session, SftpError> {
+        let host = self.hostname().to_string();
+        let address = (host.as_str(), self.port).to_socket_addrs()
+            .ok()
+            .and_then(|mut addresses| addresses.next())
+            .ok_or_else(|| SftpError::Resolve { host: host.clone() })?;
+        let tcp = TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).map_err(|source| {
+            if source.kind() == io::ErrorKind::ConnectionRefused {
+                SftpError::ConnectionRefused { host: host.clone(), port: self.port }
+            } else {
+                SftpError::Connect { host: host.clone(), port: self.port, source }
+            }
+        })?;
+
+        let mut session = Session::new()
+            .map_err(|source| SftpError::Handshake { host: host.clone(), source })?;
+        session.set_tcp_stream(tcp);
+        session.set_timeout(SESSION_TIMEOUT.as_millis() as u32);
+        session.handshake()
+            .map_err(|source| SftpError::Handshake { host, source })?;
+        Ok(session)
+    }
+
+    fn connect(&self) -> Result<Connection, SftpError> {
+        let host = self.hostname().to_string();
+        let session = self.handshake()?;
+        known_hosts::verify(&session, &host, self.port)?;
+
+        if let Some(key) = self.private_key_path.as_deref().filter(|k| !k.is_empty()) {
+            let _ = session.userauth_pubkey_file(&self.username, None, Path::new(key), self.passphrase.as_deref());
         }
-        if combined.contains("Connection refused") {
-            return Err("Connection refused. Please check your host and port.".to_string());
+        if !session.authenticated() && !self.password.is_empty() {
+            let _ = session.userauth_password(&self.username, &self.password);
         }
-        if combined.contains("FATAL ERROR") {
-            return Err(format!("SFTP command failed: {}", combined.trim()));
+        if !session.authenticated() {
+            return Err(SftpError::Authentication { username: self.username.clone(), host });
         }
 
-        if combined.contains("Remote working directory is") || 
-           combined.contains("-rw-") || 
-           combined.contains("-r--") || 
-           combined.contains("drwx") {
-            Ok(combined)
-        } else if !combined.trim().is_empty() {
-            Err(format!("Unexpected SFTP output: {}", combined.trim()))
-        } else {
-            Err("SFTP command failed with no output".to_string())
-        }
+        let sftp = session.sftp().map_err(SftpError::Subsystem)?;
+        info!("Opened SFTP session to {}@{}:{}", self.username, host, self.port);
+        Ok(Connection { sftp, last_used: Instant::now() })
     }
 
-    fn cleanup_temp_files(&self, files: &[&Path]) {
-        for file in files {
-            let _ = fs::remove_file(file);
+    /// The host key the server presents, compared with the known-hosts store.
+    fn host_key(&self) -> Result<PresentedKey, SftpError> {
+        let session = self.handshake()?;
+        Ok(known_hosts::inspect(&session, self.hostname(), self.port)?)
+    }
+
+    /// A pooled session for this config, and whether it was reused.
+    fn checkout(&self, key: &str) -> Result<(Arc<Mutex<Connection>>, bool), SftpError> {
+        {
+            let mut connections = lock(&CONNECTIONS);
+            connections.retain(|_, c| c.try_lock().map(|c| c.last_used.elapsed() < IDLE_TIMEOUT).unwrap_or(true));
+            if let Some(connection) = connections.get(key) {
+                return Ok((connection.clone(), true));
+            }
         }
+
+        let connection = Arc::new(Mutex::new(self.connect()?));
+        lock(&CONNECTIONS).insert(key.to_string(), connection.clone());
+        Ok((connection, false))
     }
-}
 
-#[tauri::command]
-pub async fn run_sftp_command(config: SftpConfig, command: String) -> Result<String, String> {
-    task::spawn_blocking(move || {
-        let (batch_path, ps_path) = config.create_temp_files(&[
-            &command,
-            "exit"
-        ])?;
+    /// Runs `op` on a pooled session. A reused session that turns out to be
+    /// dead is replaced and `op` retried once.
+    pub(crate) fn with_sftp<T, F>(&self, mut op: F) -> Result<T, SftpError>
+    where
+        F: FnMut(&Sftp) -> Result<T, SftpError>,
+    {
+        let key = self.connection_key();
+        let (connection, reused) = self.checkout(&key)?;
+        let result = {
+            let mut connection = lock(&connection);
+            connection.last_used = Instant::now();
+            op(&connection.sftp)
+        };
 
-        let result = config.run_sftp_command(&batch_path, &ps_path);
+        match result {
+            Err(e) if e.is_connection_lost() => {
+                lock(&CONNECTIONS).remove(&key);
+                if !reused {
+                    return Err(e);
+                }
+                let (connection, _) = self.checkout(&key)?;
+                let mut connection = lock(&connection);
+                connection.last_used = Instant::now();
+                op(&connection.sftp)
+            },
+            result => result,
+        }
+    }
 
-        config.cleanup_temp_files(&[&batch_path, &ps_path]);
+    /// Resolves `path` against `remote_path` unless it is absolute.
+    pub(crate) fn resolve(&self, path: &str) -> String {
+        let path = path.trim();
+        if path.starts_with('/') {
+            return path.to_string();
+        }
+        let base = self.remote_path.trim();
+        match (base.is_empty(), path.is_empty()) {
+            (true, true) => ".".to_string(),
+            (true, false) => path.to_string(),
+            (false, true) => base.to_string(),
+            (false, false) => format!("{}/{}", base.trim_end_matches('/'), path),
+        }
+    }
 
-        result
-    }).await.map_err(|e| format!("Task join error: {}", e))?
-}
+    pub fn working_directory(&self) -> Result<String, SftpError> {
+        let path = self.resolve("");
+        self.with_sftp(|sftp| {
+            sftp.realpath(Path::new(&path))
+                .map(|p| p.to_string_lossy().into_owned())
+                .map_err(|e| SftpError::remote(&path, e))
+        })
+    }
 
-#[tauri::command]
-pub async fn test_sftp_connection(config: SftpConfig) -> Result<bool, String> {
-    task::spawn_blocking(move || {
-        let (batch_path, ps_path) = config.create_temp_files(&["pwd", "exit"])?;
+    /// Entries of a remote directory, without `.` and `..`, sorted by name.
+    pub fn list(&self, path: &str) -> Result<Vec<RemoteEntry>, SftpError> {
+        let path = self.resolve(path);
+        self.with_sftp(|sftp| {
+            let mut entries: Vec<RemoteEntry> = sftp.readdir(Path::new(&path))
+                .map_err(|e| SftpError::remote(&path, e))?
+                .into_iter()
+                .filter_map(|(entry, stat)| {
+                    let name = entry.file_name()?.to_string_lossy().into_owned();
+                    (name != "." && name != "..").then(|| remote_entry(sftp, &path, name, &stat))
+                })
+                .collect();
+            entries.sort_by(|a, b| a.name.cmp(&b.name));
+            Ok(entries)
+        })
+    }
 
-        let result = config.run_sftp_command(&batch_path, &ps_path);
+    pub fn mkdir(&self, path: &str) -> Result<String, SftpError> {
+        let path = self.resolve(path);
+        self.with_sftp(|sftp| sftp.mkdir(Path::new(&path), 0o755).map_err(|e| SftpError::remote(&path, e)))?;
+        Ok(path)
+    }
 
-        config.cleanup_temp_files(&[&batch_path, &ps_path]);
+    pub fn rename(&self, from: &str, to: &str) -> Result<(String, String), SftpError> {
+        let (from, to) = (self.resolve(from), self.resolve(to));
+        self.with_sftp(|sftp| {
+            sftp.rename(Path::new(&from), Path::new(&to), None).map_err(|e| SftpError::remote(&from, e))
+        })?;
+        Ok((from, to))
+    }
 
-        match result {
-            Ok(_) => Ok(true),
-            Err(e) => Err(e),
+    /// Deletes a file, symlink or empty directory, or a whole tree when
+    /// `recursive` is set. Symlinks are removed, never followed.
+    pub fn remove(&self, path: &str, recursive: bool) -> Result<String, SftpError> {
+        fn remove_tree(sftp: &Sftp, path: &str) -> Result<(), SftpError> {
+            for (entry, stat) in sftp.readdir(Path::new(path)).map_err(|e| SftpError::remote(path, e))? {
+                let Some(name) = entry.file_name().map(|n| n.to_string_lossy().into_owned()) else {
+                    continue;
+                };
+                if name == "." || name == ".." {
+                    continue;
+                }
+                let child = format!("{}/{}", path.trim_end_matches('/'), name);
+                if stat.is_dir() {
+                    remove_tree(sftp, &child)?;
+                } else {
+                    sftp.unlink(Path::new(&child)).map_err(|e| SftpError::remote(&child, e))?;
+                }
+            }
+            sftp.rmdir(Path::new(path)).map_err(|e| SftpError::remote(path, e))
         }
-    }).await.map_err(|e| format!("Task join error: {}", e))?
-}
 
-#[tauri::command]
-pub async fn upload_file_sftp(config: SftpConfig, local_path: String, remote_path: String) -> Result<bool, String> {
-    task::spawn_blocking(move || {
-        let (batch_path, ps_path) = config.create_temp_files(&[
-            &format!("put \"{}\" \"{}\"", local_path, remote_path),
-            "exit"
-        ])?;
+        let path = self.resolve(path);
+        if path.trim_end_matches('/').is_empty() {
+            return Err(SftpError::InvalidCommand("refusing to delete /".to_string()));
+        }
+        self.with_sftp(|sftp| {
+            let stat = sftp.lstat(Path::new(&path)).map_err(|e| SftpError::remote(&path, e))?;
+            match (stat.is_dir(), recursive) {
+                (true, true) => remove_tree(sftp, &path),
+                (true, false) => sftp.rmdir(Path::new(&path)).map_err(|e| SftpError::remote(&path, e)),
+                (false, _) => sftp.unlink(Path::new(&path)).map_err(|e| SftpError::remote(&path, e)),
+            }
+        })?;
+        Ok(path)
+    }
 
-        let result = config.run_sftp_command(&batch_path, &ps_path);
+    /// Sets the permission bits of `path` from an octal string like `755`.
+    pub fn chmod(&self, path: &str, mode: &str) -> Result<(String, u32), SftpError> {
+        let mode = u32::from_str_radix(mode.trim(), 8)
+            .ok()
+            .filter(|mode| *mode <= 0o7777)
+            .ok_or_else(|| SftpError::InvalidCommand(format!("invalid mode {}", mode)))?;
+        let path = self.resolve(path);
+        self.with_sftp(|sftp| {
+            let stat = FileStat { size: None, uid: None, gid: None, perm: Some(mode), atime: None, mtime: None };
+            sftp.setstat(Path::new(&path), stat).map_err(|e| SftpError::remote(&path, e))
+        })?;
+        Ok((path, mode))
+    }
 
-        config.cleanup_temp_files(&[&batch_path, &ps_path]);
+    /// Runs one psftp-style command such as `ls`, `put a b` or `rename a b`
+    /// and returns its output.
+    pub fn run_command(&self, command: &str) -> Result<String, SftpError> {
+        let args = split_command(command)?;
+        let Some((name, args)) = args.split_first() else {
+            return Err(SftpError::InvalidCommand("empty command".to_string()));
+        };
+        let expect = |count: std::ops::RangeInclusive<usize>| {
+            if count.contains(&args.len()) {
+                Ok(())
+            } else {
+                Err(SftpError::InvalidCommand(format!("wrong number of arguments for {}", name)))
+            }
+        };
+
+        match name.as_str() {
+            "pwd" => {
+                expect(0..=0)?;
+                Ok(format!("Remote working directory is {}", self.working_directory()?))
+            },
+            "ls" | "dir" => {
+                expect(0..=1)?;
+                let entries = self.list(args.first().map(String::as_str).unwrap_or(""))?;
+                Ok(entries.iter()
+                    .map(|entry| match &entry.symlink_target {
+                        Some(target) => format!("{} {:>12} {} -> {}", entry.permissions, entry.size, entry.name, target),
+                        None => format!("{} {:>12} {}", entry.permissions, entry.size, entry.name),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n"))
+            },
+            "mkdir" => {
+                expect(1..=1)?;
+                Ok(format!("mkdir {}: OK", self.mkdir(&args[0])?))
+            },
+            "rmdir" => {
+                expect(1..=1)?;
+                let path = self.resolve(&args[0]);
+                self.with_sftp(|sftp| sftp.rmdir(Path::new(&path)).map_err(|e| SftpError::remote(&path, e)))?;
+                Ok(format!("rmdir {}: OK", path))
+            },
+            "rm" | "del" => {
+                expect(1..=1)?;
+                let path = self.resolve(&args[0]);
+                self.with_sftp(|sftp| sftp.unlink(Path::new(&path)).map_err(|e| SftpError::remote(&path, e)))?;
+                Ok(format!("rm {}: OK", path))
+            },
+            "mv" | "ren" | "rename" => {
+                expect(2..=2)?;
+                let (from, to) = self.rename(&args[0], &args[1])?;
+                Ok(format!("{} -> {}", from, to))
+            },
+            "chmod" => {
+                expect(2..=2)?;
+                let (path, mode) = self.chmod(&args[1], &args[0])?;
+                Ok(format!("{}: {:o}", path, mode))
+            },
+            "get" => {
+                expect(1..=2)?;
+                let local = args.get(1).cloned().unwrap_or_else(|| file_name(&args[0]));
+                let progress = transfer::transfer_now(self, TransferDirection::Download, &args[0], &local)?;
+                Ok(format!("remote:{} => local:{} ({} bytes)", args[0], local, progress.bytes_total))
+            },
+            "put" => {
+                expect(1..=2)?;
+                let remote = args.get(1).cloned().unwrap_or_else(|| file_name(&args[0]));
+                let progress = transfer::transfer_now(self, TransferDirection::Upload, &args[0], &remote)?;
+                Ok(format!("local:{} => remote:{} ({} bytes)", args[0], remote, progress.bytes_total))
+            },
+            _ => Err(SftpError::UnsupportedCommand(name.clone())),
+        }
+    }
+}
 
-        match result {
-            Ok(output) => {
-                if output.contains("no such file") || 
-                   output.contains("permission denied") || 
-                   output.contains("failure") {
-                    Err(format!("Upload failed: {}", output))
-                } else {
-                    Ok(true)
+/// Splits a command line on whitespace, honouring double quotes.
+fn split_command(command: &str) -> Result<Vec<String>, SftpError> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_quotes = false;
+    let mut has_arg = false;
+
+    for c in command.trim().chars() {
+        match c {
+            '"' => {
+                in_quotes = !in_quotes;
+                has_arg = true;
+            },
+            c if c.is_whitespace() && !in_quotes => {
+                if has_arg {
+                    args.push(std::mem::take(&mut current));
+                    has_arg = false;
                 }
             },
-            Err(e) => Err(e),
+            c => {
+                current.push(c);
+                has_arg = true;
+            },
         }
-    }).await.map_err(|e| format!("Task join error: {}", e))?
+    }
+    if in_quotes {
+        return Err(SftpError::InvalidCommand("unterminated quote".to_string()));
+    }
+    if has_arg {
+        args.push(current);
+    }
+    Ok(args)
 }
 
-#[tauri::command]
-pub async fn download_file_sftp(config: SftpConfig, remote_path: String, local_path: String) -> Result<bool, String> {
-    task::spawn_blocking(move || {
-        let (batch_path, ps_path) = config.create_temp_files(&[
-            &format!("get \"{}\" \"{}\"", remote_path, local_path),
-            "exit"
-        ])?;
+fn file_name(path: &str) -> String {
+    path.rsplit(['/', '\\']).next().unwrap_or(path).to_string()
+}
 
-        let result = config.run_sftp_command(&batch_path, &ps_path);
+fn remote_entry(sftp: &Sftp, dir: &str, name: String, stat: &FileStat) -> RemoteEntry {
+    let path = if dir == "/" { format!("/{}", name) } else { format!("{}/{}", dir.trim_end_matches('/'), name) };
+    let file_type = if stat.file_type().is_symlink() {
+        RemoteFileType::Symlink
+    } else if stat.is_dir() {
+        RemoteFileType::Directory
+    } else if stat.is_file() {
+        RemoteFileType::File
+    } else {
+        RemoteFileType::Other
+    };
+    let symlink_target = (file_type == RemoteFileType::Symlink)
+        .then(|| sftp.readlink(Path::new(&path)).ok())
+        .flatten()
+        .map(|target| target.to_string_lossy().into_owned());
+
+    RemoteEntry {
+        name,
+        file_type,
+        size: stat.size.unwrap_or(0),
+        permissions: permissions(stat),
+        mode: stat.perm.unwrap_or(0) & 0o7777,
+        modified: stat.mtime.and_then(|mtime| Utc.timestamp_opt(mtime as i64, 0).single()),
+        symlink_target,
+        path,
+    }
+}
 
-        config.cleanup_temp_files(&[&batch_path, &ps_path]);
+/// `ls -l` style permission string.
+fn permissions(stat: &FileStat) -> String {
+    let mode = stat.perm.unwrap_or(0);
+    let kind = if stat.is_dir() { 'd' } else if stat.file_type().is_symlink() { 'l' } else { '-' };
+    let mut text = String::with_capacity(10);
+    text.push(kind);
+    for shift in [6, 3, 0] {
+        let bits = (mode >> shift) & 0o7;
+        text.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        text.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        text.push(if bits & 0o1 != 0 { 'x' } else { '-' });
+    }
+    text
+}
 
-        match result {
-            Ok(_) => Ok(true),
-            Err(e) => Err(e),
-        }
-    }).await.map_err(|e| format!("Task join error: {}", e))?
+#[tauri::command]
+pub async fn run_sftp_command(config: SftpConfig, command: String) -> Result<String, String> {
+    task::spawn_blocking(move || config.run_command(&command).map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+/// Shows the fingerprint of the server's host key so the user can accept it
+/// with `trust_host_key` before the first connection.
+#[tauri::command]
+pub async fn get_sftp_host_key(config: SftpConfig) -> Result<PresentedKey, String> {
+    task::spawn_blocking(move || config.host_key().map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
 }
 
 #[tauri::command]
-pub async fn list_remote_files(config: SftpConfig, path: String) -> Result<Vec<String>, String> {
+pub async fn test_sftp_connection(config: SftpConfig) -> Result<bool, String> {
     task::spawn_blocking(move || {
-        let (batch_path, ps_path) = config.create_temp_files(&[
-            "ls",
-            "exit"
-        ])?;
+        config.working_directory()
+            .map(|_| true)
+            .map_err(|e| e.to_string())
+    }).await.map_err(|e| format!("Task join error: {}", e))?
+}
 
-        let result = config.run_sftp_command(&batch_path, &ps_path);
+/// Uploads a file or directory through the transfer queue and waits for it.
+#[tauri::command]
+pub async fn upload_file_sftp(app: AppHandle, config: SftpConfig, local_path: String, remote_path: String) -> Result<bool, String> {
+    let (_, done) = transfer::enqueue(config, TransferDirection::Upload, local_path, remote_path, transfer::emit_to(app))?;
+    transfer::wait(done).await
+        .map(|_| true)
+        .map_err(|e| format!("Upload failed: {}", e))
+}
 
-        config.cleanup_temp_files(&[&batch_path, &ps_path]);
+/// Downloads a file or directory through the transfer queue and waits for it.
+#[tauri::command]
+pub async fn download_file_sftp(app: AppHandle, config: SftpConfig, remote_path: String, local_path: String) -> Result<bool, String> {
+    let (_, done) = transfer::enqueue(config, TransferDirection::Download, remote_path, local_path, transfer::emit_to(app))?;
+    transfer::wait(done).await
+        .map(|_| true)
+        .map_err(|e| format!("Download failed: {}", e))
+}
 
-        match result {
-            Ok(output) => {
-                let files: Vec<String> = output.lines()
-                    .filter(|line| {
-                        let line = line.trim();
-                        !line.is_empty() && 
-                        !line.starts_with("Remote working directory") &&
-                        !line.starts_with("Using username") &&
-                        !line.starts_with("mkdir") &&
-                        !line.starts_with("Remote directory") &&
-                        !line.starts_with("Listing directory")
-                    })
-                    .map(|line| {
-                        if line.contains("-rw-") || line.contains("-r--") || line.contains("drwx") || line.contains("-rwx") {
-                            line.split_whitespace().last().unwrap_or(line).to_string()
-                        } else {
-                            line.trim().to_string()
-                        }
-                    })
-                    .collect();
+/// Lists the directory at `path`, resolved against the config's
+/// `remote_path` when relative.
+#[tauri::command]
+pub async fn list_remote_files(config: SftpConfig, path: String) -> Result<Vec<RemoteEntry>, String> {
+    task::spawn_blocking(move || config.list(&path).map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
 
-                Ok(files)
-            },
-            Err(e) => Err(e),
-        }
-    }).await.map_err(|e| format!("Task join error: {}", e))?
-} 
\ No newline at end of file
+#[tauri::command]
+pub async fn create_remote_directory(config: SftpConfig, path: String) -> Result<String, String> {
+    task::spawn_blocking(move || config.mkdir(&path).map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+#[tauri::command]
+pub async fn rename_remote_path(config: SftpConfig, from: String, to: String) -> Result<String, String> {
+    task::spawn_blocking(move || config.rename(&from, &to).map(|(_, to)| to).map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+#[tauri::command]
+pub async fn delete_remote_path(config: SftpConfig, path: String, recursive: Option<bool>) -> Result<String, String> {
+    task::spawn_blocking(move || config.remove(&path, recursive.unwrap_or(false)).map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
+
+/// Sets permissions from an octal string such as `644` or `0755`.
+#[tauri::command]
+pub async fn chmod_remote_path(config: SftpConfig, path: String, mode: String) -> Result<u32, String> {
+    task::spawn_blocking(move || config.chmod(&path, &mode).map(|(_, mode)| mode).map_err(|e| e.to_string()))
+        .await
+        .map_err(|e| format!("Task join error: {}", e))?
+}
@@ -0,0 +1,1661 @@
+//! ServerMint's own SFTP endpoint, so collaborators can edit a server's
+//! files with their usual tools without an account on the host.
+//!
+//! Each account belongs to one server and sees that server's directory as
+//! `/`. Accounts log in with a password or an authorized key and are either
+//! read-only or read-write. Paths matching the server's denylist are hidden
+//! and refused, and every login and change is appended to `sftp-audit.log`.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, TimeZone, Utc};
+use log::{info, warn};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::Ed25519KeyPair;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::SocketAddr;
+use std::num::NonZeroU32;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tauri::State;
+use tokio::task;
+use uuid::Uuid;
+
+use crate::known_hosts;
+use crate::server::ServerManager;
+use crate::ssh_server::{self, Reader, SshHandler, Subsystem, Writer};
+use crate::sync;
+
+const DEFAULT_BIND: &str = "0.0.0.0:2022";
+const MAX_CONNECTIONS: usize = 32;
+const AUDIT_LIST_LIMIT: usize = 500;
+const PASSWORD_ITERATIONS: u32 = 100_000;
+const MIN_PASSWORD_LEN: usize = 8;
+
+const SFTP_VERSION: u32 = 3;
+const MAX_SFTP_PACKET: usize = 1024 * 1024;
+const MAX_READ_LEN: u32 = 256 * 1024;
+const READDIR_BATCH: usize = 100;
+
+const FXP_INIT: u8 = 1;
+const FXP_VERSION: u8 = 2;
+const FXP_OPEN: u8 = 3;
+const FXP_CLOSE: u8 = 4;
+const FXP_READ: u8 = 5;
+const FXP_WRITE: u8 = 6;
+const FXP_LSTAT: u8 = 7;
+const FXP_FSTAT: u8 = 8;
+const FXP_SETSTAT: u8 = 9;
+const FXP_FSETSTAT: u8 = 10;
+const FXP_OPENDIR: u8 = 11;
+const FXP_READDIR: u8 = 12;
+const FXP_REMOVE: u8 = 13;
+const FXP_MKDIR: u8 = 14;
+const FXP_RMDIR: u8 = 15;
+const FXP_REALPATH: u8 = 16;
+const FXP_STAT: u8 = 17;
+const FXP_RENAME: u8 = 18;
+const FXP_STATUS: u8 = 101;
+const FXP_HANDLE: u8 = 102;
+const FXP_DATA: u8 = 103;
+const FXP_NAME: u8 = 104;
+const FXP_ATTRS: u8 = 105;
+
+const FX_OK: u32 = 0;
+const FX_EOF: u32 = 1;
+const FX_NO_SUCH_FILE: u32 = 2;
+const FX_PERMISSION_DENIED: u32 = 3;
+const FX_FAILURE: u32 = 4;
+const FX_BAD_MESSAGE: u32 = 5;
+const FX_OP_UNSUPPORTED: u32 = 8;
+
+const FXF_READ: u32 = 0x01;
+const FXF_WRITE: u32 = 0x02;
+const FXF_APPEND: u32 = 0x04;
+const FXF_CREAT: u32 = 0x08;
+const FXF_TRUNC: u32 = 0x10;
+const FXF_EXCL: u32 = 0x20;
+
+const ATTR_SIZE: u32 = 0x01;
+const ATTR_UIDGID: u32 = 0x02;
+const ATTR_PERMISSIONS: u32 = 0x04;
+const ATTR_ACMODTIME: u32 = 0x08;
+const ATTR_EXTENDED: u32 = 0x8000_0000;
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SftpServerSettings {
+    pub enabled: bool,
+    pub bind: String,
+}
+
+impl Default for SftpServerSettings {
+    fn default() -> Self {
+        SftpServerSettings {
+            enabled: false,
+            bind: DEFAULT_BIND.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SftpAccount {
+    pub id: String,
+    pub username: String,
+    pub server_id: String,
+    pub read_only: bool,
+    /// `pbkdf2-sha256$iterations$salt$hash`, or `None` for key-only accounts.
+    pub password_hash: Option<String>,
+    /// OpenSSH public key lines.
+    #[serde(default)]
+    pub authorized_keys: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The account as the UI gets it: an empty `password_hash` only says that
+/// a password is set.
+fn redacted(account: &SftpAccount) -> SftpAccount {
+    let mut account = account.clone();
+    if let Some(hash) = account.password_hash.as_mut() {
+        hash.clear();
+    }
+    account
+}
+
+/// What the UI sends to create or update an account.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SftpAccountRequest {
+    pub username: String,
+    pub server_id: String,
+    #[serde(default)]
+    pub read_only: bool,
+    /// On update, `None` keeps the current password and an empty string
+    /// removes it.
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub authorized_keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SftpAuditAction {
+    LoginSucceeded,
+    LoginFailed,
+    Write,
+    Remove,
+    CreateDirectory,
+    RemoveDirectory,
+    Rename,
+    SetAttributes,
+    Denied,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SftpAuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub username: String,
+    pub server_id: Option<String>,
+    pub remote_addr: String,
+    pub action: SftpAuditAction,
+    pub path: Option<String>,
+    pub detail: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct SftpServerFile {
+    #[serde(default)]
+    settings: SftpServerSettings,
+    #[serde(default)]
+    accounts: Vec<SftpAccount>,
+    /// Denylist patterns by server id, in the same glob syntax as sync
+    /// excludes.
+    #[serde(default)]
+    denylists: HashMap<String, Vec<String>>,
+}
+
+/// Accounts, denylists and settings for the SFTP endpoint, persisted to
+/// `sftp_server.json`.
+#[derive(Debug)]
+pub struct SftpServerStore {
+    path: PathBuf,
+    audit_path: PathBuf,
+    host_key_path: PathBuf,
+    pub settings: SftpServerSettings,
+    accounts: Vec<SftpAccount>,
+    denylists: HashMap<String, Vec<String>>,
+}
+
+fn hash_password(password: &str) -> Result<String, String> {
+    let mut salt = [0u8; 16];
+    SystemRandom::new().fill(&mut salt)
+        .map_err(|_| "Failed to generate a password salt".to_string())?;
+    let mut hash = [0u8; 32];
+    let iterations = NonZeroU32::new(PASSWORD_ITERATIONS).expect("iterations are non-zero");
+    pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, &salt, password.as_bytes(), &mut hash);
+    Ok(format!(
+        "pbkdf2-sha256${}${}${}",
+        PASSWORD_ITERATIONS,
+        STANDARD.encode(salt),
+        STANDARD.encode(hash)
+    ))
+}
+
+fn verify_password(stored: &str, password: &str) -> bool {
+    let parts: Vec<&str> = stored.split('$').collect();
+    let [scheme, iterations, salt, hash] = parts[..] else { return false };
+    let iterations = iterations.parse().ok().and_then(NonZeroU32::new);
+    match (scheme, iterations, STANDARD.decode(salt), STANDARD.decode(hash)) {
+        ("pbkdf2-sha256", Some(iterations), Ok(salt), Ok(hash)) => {
+            pbkdf2::verify(pbkdf2::PBKDF2_HMAC_SHA256, iterations, &salt, password.as_bytes(), &hash).is_ok()
+        },
+        _ => false,
+    }
+}
+
+fn valid_username(username: &str) -> bool {
+    !username.is_empty()
+        && username.len() <= 32
+        && username.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-'))
+}
+
+fn append_audit(path: &Path, entry: &SftpAuditEntry) {
+    let result = serde_json::to_string(entry)
+        .map_err(|e| e.to_string())
+        .and_then(|line| {
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}", line))
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        warn!("Failed to write SFTP audit entry: {}", e);
+    }
+}
+
+impl SftpServerStore {
+    pub fn new() -> Self {
+        let app_data_dir = std::env::var("APPDATA")
+            .unwrap_or_else(|_| std::env::var("HOME").unwrap_or_else(|_| ".".to_string()));
+        let base = PathBuf::from(format!("{}/ServerMint", app_data_dir));
+
+        let mut store = SftpServerStore {
+            path: base.join("sftp_server.json"),
+            audit_path: base.join("sftp-audit.log"),
+            host_key_path: base.join("sftp_host_ed25519.der"),
+            settings: SftpServerSettings::default(),
+            accounts: Vec::new(),
+            denylists: HashMap::new(),
+        };
+
+        if let Err(e) = store.load() {
+            println!("Warning: Failed to load SFTP server accounts: {}", e);
+        }
+
+        store
+    }
+
+    fn load(&mut self) -> Result<(), String> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+
+        let contents = fs::read_to_string(&self.path)
+            .map_err(|e| format!("Failed to read {}: {}", self.path.display(), e))?;
+        let file: SftpServerFile = serde_json::from_str(&contents)
+            .map_err(|e| format!("Failed to parse {}: {}", self.path.display(), e))?;
+
+        self.settings = file.settings;
+        self.accounts = file.accounts;
+        self.denylists = file.denylists;
+        Ok(())
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+
+        let file = SftpServerFile {
+            settings: self.settings.clone(),
+            accounts: self.accounts.clone(),
+            denylists: self.denylists.clone(),
+        };
+        let json = serde_json::to_string_pretty(&file)
+            .map_err(|e| format!("Failed to serialize SFTP server accounts: {}", e))?;
+
+        let tmp_path = self.path.with_extension("json.tmp");
+        fs::write(&tmp_path, json)
+            .map_err(|e| format!("Failed to write SFTP server accounts: {}", e))?;
+        fs::rename(&tmp_path, &self.path)
+            .map_err(|e| format!("Failed to replace SFTP server accounts: {}", e))
+    }
+
+    /// Loads the endpoint's host key, generating it on first use.
+    pub fn host_key(&self) -> Result<Ed25519KeyPair, String> {
+        if self.host_key_path.exists() {
+            let pkcs8 = fs::read(&self.host_key_path)
+                .map_err(|e| format!("Failed to read SFTP host key: {}", e))?;
+            return Ed25519KeyPair::from_pkcs8(&pkcs8)
+                .map_err(|e| format!("Failed to parse SFTP host key: {}", e));
+        }
+
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|e| format!("Failed to generate SFTP host key: {}", e))?;
+        if let Some(parent) = self.host_key_path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create directory: {}", e))?;
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options.open(&self.host_key_path)
+            .and_then(|mut file| file.write_all(pkcs8.as_ref()))
+            .map_err(|e| format!("Failed to write SFTP host key: {}", e))?;
+
+        Ed25519KeyPair::from_pkcs8(pkcs8.as_ref())
+            .map_err(|e| format!("Failed to parse SFTP host key: {}", e))
+    }
+
+    /// Accounts as returned here and by `create_account`/`update_account`
+    /// go to the UI, so they're [`redacted`].
+    pub fn list_accounts(&self) -> Vec<SftpAccount> {
+        self.accounts.iter().map(redacted).collect()
+    }
+
+    fn find_account(&self, username: &str) -> Option<&SftpAccount> {
+        self.accounts.iter().find(|a| a.username == username)
+    }
+
+    /// Validates `request` and builds the account it describes. `existing`
+    /// is the account being updated.
+    fn build_account(&self, request: SftpAccountRequest, existing: Option<&SftpAccount>) -> Result<SftpAccount, String> {
+        let username = request.username.trim().to_string();
+        if !valid_username(&username) {
+            return Err("Usernames need 1 to 32 letters, digits, dots, dashes or underscores".to_string());
+        }
+        if self.accounts.iter().any(|a| a.username == username && Some(&a.id) != existing.map(|e| &e.id)) {
+            return Err(format!("An SFTP account named {} already exists", username));
+        }
+
+        let authorized_keys = request.authorized_keys.iter()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| ssh_server::parse_authorized_key(line).map(|_| line.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let password_hash = match request.password.as_deref() {
+            None => existing.and_then(|e| e.password_hash.clone()),
+            Some("") => None,
+            Some(password) if password.len() < MIN_PASSWORD_LEN => {
+                return Err(format!("Passwords need at least {} characters", MIN_PASSWORD_LEN));
+            },
+            Some(password) => Some(hash_password(password)?),
+        };
+        if password_hash.is_none() && authorized_keys.is_empty() {
+            return Err("An SFTP account needs a password or at least one public key".to_string());
+        }
+
+        Ok(SftpAccount {
+            id: existing.map(|e| e.id.clone()).unwrap_or_else(|| Uuid::new_v4().to_string()),
+            username,
+            server_id: request.server_id,
+            read_only: request.read_only,
+            password_hash,
+            authorized_keys,
+            created_at: existing.map(|e| e.created_at).unwrap_or_else(Utc::now),
+        })
+    }
+
+    pub fn create_account(&mut self, request: SftpAccountRequest) -> Result<SftpAccount, String> {
+        let account = self.build_account(request, None)?;
+        self.accounts.push(account.clone());
+        self.save()?;
+        Ok(redacted(&account))
+    }
+
+    pub fn update_account(&mut self, id: &str, request: SftpAccountRequest) -> Result<SftpAccount, String> {
+        let index = self.accounts.iter().position(|a| a.id == id)
+            .ok_or_else(|| format!("SFTP account {} not found", id))?;
+        let account = self.build_account(request, Some(&self.accounts[index]))?;
+        self.accounts[index] = account.clone();
+        self.save()?;
+        Ok(redacted(&account))
+    }
+
+    pub fn delete_account(&mut self, id: &str) -> Result<(), String> {
+        let before = self.accounts.len();
+        self.accounts.retain(|a| a.id != id);
+        if self.accounts.len() == before {
+            return Err(format!("SFTP account {} not found", id));
+        }
+        self.save()
+    }
+
+    pub fn denylist(&self, server_id: &str) -> Vec<String> {
+        self.denylists.get(server_id).cloned().unwrap_or_default()
+    }
+
+    pub fn set_denylist(&mut self, server_id: &str, patterns: Vec<String>) -> Result<(), String> {
+        let patterns: Vec<String> = patterns.into_iter()
+            .map(|p| p.trim().to_string())
+            .filter(|p| !p.is_empty())
+            .collect();
+        if patterns.is_empty() {
+            self.denylists.remove(server_id);
+        } else {
+            self.denylists.insert(server_id.to_string(), patterns);
+        }
+        self.save()
+    }
+
+    /// The most recent audit entries, newest first, optionally for one
+    /// server.
+    pub fn read_audit(&self, server_id: Option<&str>) -> Result<Vec<SftpAuditEntry>, String> {
+        if !self.audit_path.exists() {
+            return Ok(Vec::new());
+        }
+
+        let contents = fs::read_to_string(&self.audit_path)
+            .map_err(|e| format!("Failed to read audit log: {}", e))?;
+        Ok(contents.lines()
+            .rev()
+            .filter_map(|line| serde_json::from_str::<SftpAuditEntry>(line).ok())
+            .filter(|entry| server_id.is_none() || entry.server_id.as_deref() == server_id)
+            .take(AUDIT_LIST_LIMIT)
+            .collect())
+    }
+}
+
+/// An SFTP status reply; also the error type of request handlers.
+#[derive(Debug)]
+struct Status {
+    code: u32,
+    message: String,
+}
+
+impl Status {
+    fn new(code: u32, message: impl Into<String>) -> Self {
+        Status { code, message: message.into() }
+    }
+
+    fn ok() -> Self {
+        Status::new(FX_OK, "Success")
+    }
+
+    fn bad_message() -> Self {
+        Status::new(FX_BAD_MESSAGE, "Malformed request")
+    }
+
+    fn denied(message: impl Into<String>) -> Self {
+        Status::new(FX_PERMISSION_DENIED, message)
+    }
+}
+
+impl From<io::Error> for Status {
+    fn from(e: io::Error) -> Self {
+        let code = match e.kind() {
+            io::ErrorKind::NotFound => FX_NO_SUCH_FILE,
+            io::ErrorKind::PermissionDenied => FX_PERMISSION_DENIED,
+            _ => FX_FAILURE,
+        };
+        Status::new(code, e.to_string())
+    }
+}
+
+/// File attributes as SFTP v3 encodes them.
+#[derive(Debug, Default)]
+struct Attrs {
+    size: Option<u64>,
+    permissions: Option<u32>,
+    /// Access and modification times.
+    times: Option<(u32, u32)>,
+}
+
+fn unix_seconds(time: io::Result<SystemTime>) -> u32 {
+    time.ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as u32)
+        .unwrap_or(0)
+}
+
+#[cfg(unix)]
+fn mode_bits(metadata: &Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode_bits(metadata: &Metadata) -> u32 {
+    let mode = if metadata.is_dir() { 0o755 } else { 0o644 };
+    if metadata.permissions().readonly() {
+        mode & !0o222
+    } else {
+        mode
+    }
+}
+
+impl Attrs {
+    fn read(r: &mut Reader) -> Option<Attrs> {
+        let flags = r.u32()?;
+        let mut attrs = Attrs::default();
+        if flags & ATTR_SIZE != 0 {
+            attrs.size = Some(r.u64()?);
+        }
+        if flags & ATTR_UIDGID != 0 {
+            r.u32()?;
+            r.u32()?;
+        }
+        if flags & ATTR_PERMISSIONS != 0 {
+            attrs.permissions = Some(r.u32()?);
+        }
+        if flags & ATTR_ACMODTIME != 0 {
+            attrs.times = Some((r.u32()?, r.u32()?));
+        }
+        if flags & ATTR_EXTENDED != 0 {
+            for _ in 0..r.u32()? {
+                r.bytes()?;
+                r.bytes()?;
+            }
+        }
+        Some(attrs)
+    }
+
+    fn from_metadata(metadata: &Metadata) -> Attrs {
+        let kind = if metadata.is_symlink() {
+            S_IFLNK
+        } else if metadata.is_dir() {
+            S_IFDIR
+        } else {
+            S_IFREG
+        };
+        Attrs {
+            size: Some(metadata.len()),
+            permissions: Some(kind | mode_bits(metadata)),
+            times: Some((unix_seconds(metadata.accessed()), unix_seconds(metadata.modified()))),
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut flags = 0;
+        if self.size.is_some() {
+            flags |= ATTR_SIZE;
+        }
+        if self.permissions.is_some() {
+            flags |= ATTR_PERMISSIONS;
+        }
+        if self.times.is_some() {
+            flags |= ATTR_ACMODTIME;
+        }
+        out.put_u32(flags);
+        if let Some(size) = self.size {
+            out.put_u64(size);
+        }
+        if let Some(permissions) = self.permissions {
+            out.put_u32(permissions);
+        }
+        if let Some((atime, mtime)) = self.times {
+            out.put_u32(atime);
+            out.put_u32(mtime);
+        }
+    }
+
+    /// An `ls -l` style line, which clients show for SFTP v3 listings.
+    fn long_name(&self, name: &str, owner: &str) -> String {
+        let mode = self.permissions.unwrap_or(0);
+        let kind = match mode & S_IFMT {
+            S_IFDIR => 'd',
+            S_IFLNK => 'l',
+            _ => '-',
+        };
+        let permissions: String = b"rwxrwxrwx".iter().enumerate()
+            .map(|(i, &c)| if mode & (1 << (8 - i)) != 0 { c as char } else { '-' })
+            .collect();
+        let modified = self.times
+            .and_then(|(_, mtime)| Utc.timestamp_opt(mtime as i64, 0).single())
+            .map(|t| t.format("%b %d %H:%M").to_string())
+            .unwrap_or_default();
+        format!(
+            "{}{} 1 {:<8} {:<8} {:>8} {} {}",
+            kind, permissions, owner, owner, self.size.unwrap_or(0), modified, name
+        )
+    }
+}
+
+enum Reply {
+    Status(Status),
+    Handle(String),
+    Data(Vec<u8>),
+    Name(Vec<(String, String, Attrs)>),
+    Attrs(Attrs),
+}
+
+impl Reply {
+    fn encode(self, id: u32) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            Reply::Status(status) => {
+                out.put_u8(FXP_STATUS);
+                out.put_u32(id);
+                out.put_u32(status.code);
+                out.put_str(&status.message);
+                out.put_str("");
+            },
+            Reply::Handle(handle) => {
+                out.put_u8(FXP_HANDLE);
+                out.put_u32(id);
+                out.put_str(&handle);
+            },
+            Reply::Data(data) => {
+                out.put_u8(FXP_DATA);
+                out.put_u32(id);
+                out.put_bytes(&data);
+            },
+            Reply::Name(entries) => {
+                out.put_u8(FXP_NAME);
+                out.put_u32(id);
+                out.put_u32(entries.len() as u32);
+                for (name, long_name, attrs) in entries {
+                    out.put_str(&name);
+                    out.put_str(&long_name);
+                    attrs.write(&mut out);
+                }
+            },
+            Reply::Attrs(attrs) => {
+                out.put_u8(FXP_ATTRS);
+                out.put_u32(id);
+                attrs.write(&mut out);
+            },
+        }
+        out
+    }
+}
+
+type SftpResult = Result<Reply, Status>;
+
+struct OpenFile {
+    file: File,
+    path: String,
+    writable: bool,
+    /// Bytes written, and whether the open created or truncated the file,
+    /// for the audit log.
+    written: u64,
+    changed: bool,
+}
+
+enum Handle {
+    File(OpenFile),
+    /// Entries not listed yet, last first.
+    Directory(Vec<(String, Metadata)>),
+}
+
+/// A path inside the jail, as the client sees it and on disk.
+struct Resolved {
+    display: String,
+    real: PathBuf,
+    /// Relative to the root with links resolved, `/`-separated, as the
+    /// denylist is matched against it.
+    relative: String,
+}
+
+/// Who a session belongs to, for audit entries.
+#[derive(Clone)]
+struct AuditContext {
+    path: PathBuf,
+    username: String,
+    server_id: Option<String>,
+    remote_addr: String,
+}
+
+impl AuditContext {
+    fn record(&self, action: SftpAuditAction, path: Option<&str>, detail: Option<String>) {
+        info!(
+            "SFTP {} from {}: {:?} {}{}",
+            self.username,
+            self.remote_addr,
+            action,
+            path.unwrap_or(""),
+            detail.as_deref().map(|d| format!(" ({})", d)).unwrap_or_default()
+        );
+        append_audit(&self.path, &SftpAuditEntry {
+            timestamp: Utc::now(),
+            username: self.username.clone(),
+            server_id: self.server_id.clone(),
+            remote_addr: self.remote_addr.clone(),
+            action,
+            path: path.map(str::to_string),
+            detail,
+        });
+    }
+}
+
+/// One SFTP session, jailed to a server directory.
+struct SftpSession {
+    root: PathBuf,
+    read_only: bool,
+    /// Lowercased when `case_insensitive`.
+    denylist: Vec<String>,
+    /// Whether the root's filesystem ignores case, so the denylist must too.
+    case_insensitive: bool,
+    audit: AuditContext,
+    input: Vec<u8>,
+    handles: HashMap<String, Handle>,
+    next_handle: u64,
+}
+
+impl Subsystem for SftpSession {
+    fn data(&mut self, data: &[u8]) -> Result<Vec<u8>, String> {
+        self.input.extend_from_slice(data);
+        let mut output = Vec::new();
+        while self.input.len() >= 4 {
+            let len = u32::from_be_bytes([self.input[0], self.input[1], self.input[2], self.input[3]]) as usize;
+            if len == 0 || len > MAX_SFTP_PACKET {
+                return Err(format!("Invalid SFTP packet length {}", len));
+            }
+            if self.input.len() < 4 + len {
+                break;
+            }
+            let packet: Vec<u8> = self.input.drain(..4 + len).skip(4).collect();
+            let reply = self.handle(&packet);
+            output.put_bytes(&reply);
+        }
+        Ok(output)
+    }
+}
+
+impl Drop for SftpSession {
+    fn drop(&mut self) {
+        for (_, handle) in std::mem::take(&mut self.handles) {
+            if let Handle::File(file) = handle {
+                self.record_write(&file);
+            }
+        }
+    }
+}
+
+/// Whether `root` is on a filesystem that ignores case, as Windows and macOS
+/// ones do by default: the root's own name in another case is the same
+/// directory.
+fn is_case_insensitive(root: &Path) -> bool {
+    let Some(name) = root.file_name().map(|n| n.to_string_lossy().into_owned()) else {
+        return cfg!(any(windows, target_os = "macos"));
+    };
+    let swapped: String = name.chars()
+        .map(|c| if c.is_lowercase() { c.to_ascii_uppercase() } else { c.to_ascii_lowercase() })
+        .collect();
+    if swapped == name {
+        return cfg!(any(windows, target_os = "macos"));
+    }
+    root.with_file_name(swapped).canonicalize().is_ok_and(|other| other == root)
+}
+
+/// `/`-separated, as the denylist is written.
+fn relative_string(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl SftpSession {
+    /// A session jailed to `root`, which must be canonical.
+    fn new(root: PathBuf, read_only: bool, denylist: Vec<String>, audit: AuditContext, case_insensitive: bool) -> Self {
+        let denylist = if case_insensitive {
+            denylist.iter().map(|p| p.to_lowercase()).collect()
+        } else {
+            denylist
+        };
+        SftpSession {
+            root,
+            read_only,
+            denylist,
+            case_insensitive,
+            audit,
+            input: Vec::new(),
+            handles: HashMap::new(),
+            next_handle: 0,
+        }
+    }
+
+    /// Whether `relative`, or anything it's inside, is denylisted.
+    fn denied(&self, relative: &str) -> bool {
+        let relative = if self.case_insensitive { relative.to_lowercase() } else { relative.to_string() };
+        let parts: Vec<&str> = relative.split('/').filter(|p| !p.is_empty()).collect();
+        (1..=parts.len()).any(|i| sync::is_excluded(&self.denylist, &parts[..i].join("/")))
+    }
+
+    fn handle(&mut self, packet: &[u8]) -> Vec<u8> {
+        let mut r = Reader::new(&packet[1..]);
+        if packet[0] == FXP_INIT {
+            let mut version = vec![FXP_VERSION];
+            version.put_u32(SFTP_VERSION);
+            return version;
+        }
+        let Some(id) = r.u32() else {
+            return Reply::Status(Status::bad_message()).encode(0);
+        };
+
+        let result = match packet[0] {
+            FXP_OPEN => self.open(&mut r),
+            FXP_CLOSE => self.close(&mut r),
+            FXP_READ => self.read(&mut r),
+            FXP_WRITE => self.write(&mut r),
+            FXP_LSTAT => self.stat(&mut r, false),
+            FXP_STAT => self.stat(&mut r, true),
+            FXP_FSTAT => self.fstat(&mut r),
+            FXP_SETSTAT => self.setstat(&mut r),
+            FXP_FSETSTAT => self.fsetstat(&mut r),
+            FXP_OPENDIR => self.opendir(&mut r),
+            FXP_READDIR => self.readdir(&mut r),
+            FXP_REMOVE => self.remove(&mut r),
+            FXP_MKDIR => self.mkdir(&mut r),
+            FXP_RMDIR => self.rmdir(&mut r),
+            FXP_REALPATH => self.realpath(&mut r),
+            FXP_RENAME => self.rename(&mut r),
+            _ => Err(Status::new(FX_OP_UNSUPPORTED, "Operation not supported")),
+        };
+        match result {
+            Ok(reply) => reply.encode(id),
+            Err(status) => Reply::Status(status).encode(id),
+        }
+    }
+
+    /// Clamps `path` to the jail and returns its components.
+    fn normalize(path: &str) -> Result<Vec<String>, Status> {
+        let mut parts: Vec<String> = Vec::new();
+        for part in path.split(['/', '\\']) {
+            match part {
+                "" | "." => {},
+                ".." => {
+                    parts.pop();
+                },
+                part if cfg!(windows) && part.contains(':') => {
+                    return Err(Status::new(FX_NO_SUCH_FILE, "Invalid file name"));
+                },
+                part => parts.push(part.to_string()),
+            }
+        }
+        Ok(parts)
+    }
+
+    fn resolve(&self, path: &str, operation: &str) -> Result<Resolved, Status> {
+        let parts = Self::normalize(path)?;
+        let display = format!("/{}", parts.join("/"));
+        let real = parts.iter().fold(self.root.clone(), |path, part| path.join(part));
+
+        // Symlinks must not lead out of the jail. Only the deepest existing
+        // ancestor can be one, as everything below it doesn't exist yet.
+        let mut existing = real.as_path();
+        let canonical = loop {
+            if let Ok(metadata) = fs::symlink_metadata(existing) {
+                let target = existing.canonicalize().ok();
+                let inside = target.as_ref()
+                    .map(|target| target.starts_with(&self.root))
+                    .unwrap_or(!metadata.is_symlink());
+                if !inside {
+                    self.audit.record(SftpAuditAction::Denied, Some(&display), Some(format!("{} through a link leaving the server directory", operation)));
+                    return Err(Status::denied("This path leads outside the server directory"));
+                }
+                let rest = real.strip_prefix(existing).unwrap_or(Path::new(""));
+                break target.map(|target| target.join(rest));
+            }
+            match existing.parent() {
+                Some(parent) => existing = parent,
+                None => break None,
+            }
+        };
+
+        // The path as given and the path on disk both have to pass, so
+        // neither a link nor another spelling gets past a pattern.
+        let relative = canonical.as_deref()
+            .and_then(|canonical| canonical.strip_prefix(&self.root).ok())
+            .map(relative_string)
+            .unwrap_or_else(|| parts.join("/"));
+        if self.denied(&parts.join("/")) || self.denied(&relative) {
+            self.audit.record(SftpAuditAction::Denied, Some(&display), Some(format!("{} of a denylisted path", operation)));
+            return Err(Status::denied("This path is not available"));
+        }
+
+        Ok(Resolved { display, real, relative })
+    }
+
+    fn require_write(&self, operation: &str, path: &str) -> Result<(), Status> {
+        if self.read_only {
+            self.audit.record(SftpAuditAction::Denied, Some(path), Some(format!("{} by a read-only account", operation)));
+            return Err(Status::denied("This account is read-only"));
+        }
+        Ok(())
+    }
+
+    fn path_arg(r: &mut Reader) -> Result<String, Status> {
+        r.string().ok_or_else(Status::bad_message)
+    }
+
+    fn handle_arg(r: &mut Reader) -> Result<String, Status> {
+        r.bytes()
+            .map(|b| String::from_utf8_lossy(b).into_owned())
+            .ok_or_else(Status::bad_message)
+    }
+
+    fn add_handle(&mut self, handle: Handle) -> Reply {
+        let id = self.next_handle.to_string();
+        self.next_handle += 1;
+        self.handles.insert(id.clone(), handle);
+        Reply::Handle(id)
+    }
+
+    fn open(&mut self, r: &mut Reader) -> SftpResult {
+        let path = Self::path_arg(r)?;
+        let flags = r.u32().ok_or_else(Status::bad_message)?;
+        Attrs::read(r).ok_or_else(Status::bad_message)?;
+
+        let writable = flags & (FXF_WRITE | FXF_APPEND | FXF_CREAT | FXF_TRUNC) != 0;
+        let resolved = self.resolve(&path, "open")?;
+        if writable {
+            self.require_write("write", &resolved.display)?;
+        }
+        if resolved.real.is_dir() {
+            return Err(Status::new(FX_FAILURE, "Is a directory"));
+        }
+
+        let existed = resolved.real.exists();
+        let mut options = OpenOptions::new();
+        options
+            .read(flags & FXF_READ != 0 || !writable)
+            .write(flags & FXF_WRITE != 0)
+            .append(flags & FXF_APPEND != 0)
+            .truncate(flags & FXF_TRUNC != 0);
+        if flags & FXF_CREAT != 0 {
+            if flags & FXF_EXCL != 0 {
+                options.create_new(true);
+            } else {
+                options.create(true);
+            }
+        }
+        let file = options.open(&resolved.real)?;
+
+        let changed = (flags & FXF_CREAT != 0 && !existed) || flags & FXF_TRUNC != 0;
+        Ok(self.add_handle(Handle::File(OpenFile {
+            file,
+            path: resolved.display,
+            writable,
+            written: 0,
+            changed,
+        })))
+    }
+
+    fn record_write(&self, file: &OpenFile) {
+        if file.changed || file.written > 0 {
+            self.audit.record(SftpAuditAction::Write, Some(&file.path), Some(format!("{} bytes written", file.written)));
+        }
+    }
+
+    fn close(&mut self, r: &mut Reader) -> SftpResult {
+        let handle = Self::handle_arg(r)?;
+        match self.handles.remove(&handle) {
+            Some(Handle::File(file)) => {
+                self.record_write(&file);
+                Ok(Reply::Status(Status::ok()))
+            },
+            Some(Handle::Directory(_)) => Ok(Reply::Status(Status::ok())),
+            None => Err(Status::new(FX_FAILURE, "Invalid handle")),
+        }
+    }
+
+    fn file(&mut self, handle: &str) -> Result<&mut OpenFile, Status> {
+        match self.handles.get_mut(handle) {
+            Some(Handle::File(file)) => Ok(file),
+            _ => Err(Status::new(FX_FAILURE, "Invalid handle")),
+        }
+    }
+
+    fn read(&mut self, r: &mut Reader) -> SftpResult {
+        let handle = Self::handle_arg(r)?;
+        let (offset, len) = r.u64().zip(r.u32()).ok_or_else(Status::bad_message)?;
+        let file = &mut self.file(&handle)?.file;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut data = vec![0u8; len.min(MAX_READ_LEN) as usize];
+        let read = file.read(&mut data)?;
+        if read == 0 && !data.is_empty() {
+            return Err(Status::new(FX_EOF, "End of file"));
+        }
+        data.truncate(read);
+        Ok(Reply::Data(data))
+    }
+
+    fn write(&mut self, r: &mut Reader) -> SftpResult {
+        let handle = Self::handle_arg(r)?;
+        let offset = r.u64().ok_or_else(Status::bad_message)?;
+        let data = r.bytes().ok_or_else(Status::bad_message)?;
+        let file = self.file(&handle)?;
+        if !file.writable {
+            return Err(Status::denied("The file was not opened for writing"));
+        }
+
+        file.file.seek(SeekFrom::Start(offset))?;
+        file.file.write_all(data)?;
+        file.written += data.len() as u64;
+        Ok(Reply::Status(Status::ok()))
+    }
+
+    fn stat(&mut self, r: &mut Reader, follow: bool) -> SftpResult {
+        let resolved = self.resolve(&Self::path_arg(r)?, "stat")?;
+        let metadata = if follow {
+            fs::metadata(&resolved.real)?
+        } else {
+            fs::symlink_metadata(&resolved.real)?
+        };
+        Ok(Reply::Attrs(Attrs::from_metadata(&metadata)))
+    }
+
+    fn fstat(&mut self, r: &mut Reader) -> SftpResult {
+        let handle = Self::handle_arg(r)?;
+        let metadata = self.file(&handle)?.file.metadata()?;
+        Ok(Reply::Attrs(Attrs::from_metadata(&metadata)))
+    }
+
+    /// Applies size, permissions and times. Owners can't be changed.
+    fn apply_attrs(path: &Path, file: Option<&File>, attrs: &Attrs) -> Result<Vec<String>, Status> {
+        let mut changes = Vec::new();
+        if let Some(size) = attrs.size {
+            match file {
+                Some(file) => file.set_len(size)?,
+                None => OpenOptions::new().write(true).open(path)?.set_len(size)?,
+            }
+            changes.push(format!("size {}", size));
+        }
+        if let Some(mode) = attrs.permissions {
+            let mut permissions = fs::metadata(path)?.permissions();
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                permissions.set_mode(mode & 0o7777);
+            }
+            #[cfg(not(unix))]
+            permissions.set_readonly(mode & 0o200 == 0);
+            fs::set_permissions(path, permissions)?;
+            changes.push(format!("mode {:o}", mode & 0o7777));
+        }
+        if let Some((_, mtime)) = attrs.times {
+            // Directories can't be opened for writing on every platform, and
+            // their times don't matter to anyone.
+            if !path.is_dir() {
+                let modified = UNIX_EPOCH + Duration::from_secs(mtime as u64);
+                match file {
+                    Some(file) => file.set_modified(modified)?,
+                    None => OpenOptions::new().write(true).open(path)?.set_modified(modified)?,
+                }
+            }
+        }
+        Ok(changes)
+    }
+
+    fn record_attrs(&self, path: &str, changes: Vec<String>) {
+        if !changes.is_empty() {
+            self.audit.record(SftpAuditAction::SetAttributes, Some(path), Some(changes.join(", ")));
+        }
+    }
+
+    fn setstat(&mut self, r: &mut Reader) -> SftpResult {
+        let path = Self::path_arg(r)?;
+        let attrs = Attrs::read(r).ok_or_else(Status::bad_message)?;
+        let resolved = self.resolve(&path, "setstat")?;
+        self.require_write("setstat", &resolved.display)?;
+
+        let changes = Self::apply_attrs(&resolved.real, None, &attrs)?;
+        self.record_attrs(&resolved.display, changes);
+        Ok(Reply::Status(Status::ok()))
+    }
+
+    fn fsetstat(&mut self, r: &mut Reader) -> SftpResult {
+        let handle = Self::handle_arg(r)?;
+        let attrs = Attrs::read(r).ok_or_else(Status::bad_message)?;
+        let root = self.root.clone();
+        let file = self.file(&handle)?;
+        if !file.writable {
+            return Err(Status::denied("The file was not opened for writing"));
+        }
+
+        let display = file.path.clone();
+        let real = Self::normalize(&display)?.iter().fold(root, |path, part| path.join(part));
+        let changes = Self::apply_attrs(&real, Some(&file.file), &attrs)?;
+        self.record_attrs(&display, changes);
+        Ok(Reply::Status(Status::ok()))
+    }
+
+    fn opendir(&mut self, r: &mut Reader) -> SftpResult {
+        let resolved = self.resolve(&Self::path_arg(r)?, "list")?;
+        let prefix = resolved.relative.clone();
+
+        let mut entries = Vec::new();
+        for entry in fs::read_dir(&resolved.real)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let relative = if prefix.is_empty() { name.clone() } else { format!("{}/{}", prefix, name) };
+            if self.denied(&relative) {
+                continue;
+            }
+            if let Ok(metadata) = fs::symlink_metadata(entry.path()) {
+                entries.push((name, metadata));
+            }
+        }
+        entries.sort_by(|a, b| b.0.cmp(&a.0));
+        Ok(self.add_handle(Handle::Directory(entries)))
+    }
+
+    fn readdir(&mut self, r: &mut Reader) -> SftpResult {
+        let handle = Self::handle_arg(r)?;
+        let Some(Handle::Directory(entries)) = self.handles.get_mut(&handle) else {
+            return Err(Status::new(FX_FAILURE, "Invalid handle"));
+        };
+        if entries.is_empty() {
+            return Err(Status::new(FX_EOF, "End of directory"));
+        }
+
+        let batch = entries.split_off(entries.len().saturating_sub(READDIR_BATCH));
+        let names = batch.into_iter().rev()
+            .map(|(name, metadata)| {
+                let attrs = Attrs::from_metadata(&metadata);
+                let long_name = attrs.long_name(&name, &self.audit.username);
+                (name, long_name, attrs)
+            })
+            .collect();
+        Ok(Reply::Name(names))
+    }
+
+    fn remove(&mut self, r: &mut Reader) -> SftpResult {
+        let resolved = self.resolve(&Self::path_arg(r)?, "remove")?;
+        self.require_write("remove", &resolved.display)?;
+        if fs::symlink_metadata(&resolved.real)?.is_dir() {
+            return Err(Status::new(FX_FAILURE, "Is a directory"));
+        }
+
+        fs::remove_file(&resolved.real)?;
+        self.audit.record(SftpAuditAction::Remove, Some(&resolved.display), None);
+        Ok(Reply::Status(Status::ok()))
+    }
+
+    fn mkdir(&mut self, r: &mut Reader) -> SftpResult {
+        let path = Self::path_arg(r)?;
+        Attrs::read(r).ok_or_else(Status::bad_message)?;
+        let resolved = self.resolve(&path, "mkdir")?;
+        self.require_write("mkdir", &resolved.display)?;
+
+        fs::create_dir(&resolved.real)?;
+        self.audit.record(SftpAuditAction::CreateDirectory, Some(&resolved.display), None);
+        Ok(Reply::Status(Status::ok()))
+    }
+
+    fn rmdir(&mut self, r: &mut Reader) -> SftpResult {
+        let resolved = self.resolve(&Self::path_arg(r)?, "rmdir")?;
+        self.require_write("rmdir", &resolved.display)?;
+        if resolved.display == "/" {
+            return Err(Status::denied("The server directory can't be removed"));
+        }
+
+        fs::remove_dir(&resolved.real)?;
+        self.audit.record(SftpAuditAction::RemoveDirectory, Some(&resolved.display), None);
+        Ok(Reply::Status(Status::ok()))
+    }
+
+    fn realpath(&mut self, r: &mut Reader) -> SftpResult {
+        let parts = Self::normalize(&Self::path_arg(r)?)?;
+        let path = format!("/{}", parts.join("/"));
+        Ok(Reply::Name(vec![(path.clone(), path, Attrs::default())]))
+    }
+
+    /// The first path inside `dir` that the denylist treats differently
+    /// once `dir` is renamed from `from` to `to`, both relative to the root.
+    fn denylist_change(&self, dir: &Path, from: &str, to: &str) -> io::Result<Option<String>> {
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let (from, to) = (format!("{}/{}", from, name), format!("{}/{}", to, name));
+            if self.denied(&from) != self.denied(&to) {
+                return Ok(Some(from));
+            }
+            if entry.file_type()?.is_dir() {
+                if let Some(path) = self.denylist_change(&entry.path(), &from, &to)? {
+                    return Ok(Some(path));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    fn rename(&mut self, r: &mut Reader) -> SftpResult {
+        let from = Self::path_arg(r)?;
+        let to = Self::path_arg(r)?;
+        let from = self.resolve(&from, "rename")?;
+        let to = self.resolve(&to, "rename")?;
+        self.require_write("rename", &from.display)?;
+        if from.display == "/" || to.display == "/" {
+            return Err(Status::denied("The server directory can't be renamed"));
+        }
+        if !self.denylist.is_empty() && fs::symlink_metadata(&from.real)?.is_dir() {
+            let changed = self.denylist_change(&from.real, &from.relative, &to.relative)?;
+            if let Some(path) = changed {
+                self.audit.record(SftpAuditAction::Denied, Some(&from.display), Some(format!("rename to {}, which would change whether /{} is denylisted", to.display, path)));
+                return Err(Status::denied("Renaming this directory would expose or hide paths that are not available"));
+            }
+        }
+        if fs::symlink_metadata(&to.real).is_ok() {
+            return Err(Status::new(FX_FAILURE, "The target already exists"));
+        }
+
+        fs::rename(&from.real, &to.real)?;
+        self.audit.record(SftpAuditAction::Rename, Some(&from.display), Some(format!("to {}", to.display)));
+        Ok(Reply::Status(Status::ok()))
+    }
+}
+
+#[derive(Clone)]
+pub struct SftpServerContext {
+    pub server_manager: Arc<Mutex<ServerManager>>,
+    pub store: Arc<Mutex<SftpServerStore>>,
+}
+
+/// Authenticates one connection against the account store and starts its
+/// SFTP session.
+struct ConnectionHandler {
+    ctx: SftpServerContext,
+    remote_addr: String,
+    account: Option<SftpAccount>,
+}
+
+impl ConnectionHandler {
+    fn find_account(&self, username: &str) -> Option<SftpAccount> {
+        self.ctx.store.lock().ok()?.find_account(username).cloned()
+    }
+
+    fn audit(&self, username: &str, server_id: Option<String>) -> Option<AuditContext> {
+        let path = self.ctx.store.lock().ok()?.audit_path.clone();
+        Some(AuditContext {
+            path,
+            username: username.to_string(),
+            server_id,
+            remote_addr: self.remote_addr.clone(),
+        })
+    }
+}
+
+impl SshHandler for ConnectionHandler {
+    fn check_password(&mut self, user: &str, password: &str) -> bool {
+        let account = self.find_account(user)
+            .filter(|a| a.password_hash.as_deref().is_some_and(|hash| verify_password(hash, password)));
+        let success = account.is_some();
+        if success {
+            self.account = account;
+        }
+        success
+    }
+
+    fn check_key(&mut self, user: &str, key: &[u8]) -> bool {
+        let account = self.find_account(user).filter(|a| {
+            a.authorized_keys.iter()
+                .filter_map(|line| ssh_server::parse_authorized_key(line).ok())
+                .any(|(_, blob)| blob == key)
+        });
+        let success = account.is_some();
+        if success {
+            self.account = account;
+        }
+        success
+    }
+
+    fn auth_finished(&mut self, user: &str, method: &str, success: bool) {
+        let server_id = self.find_account(user).map(|a| a.server_id);
+        let action = if success { SftpAuditAction::LoginSucceeded } else { SftpAuditAction::LoginFailed };
+        if let Some(audit) = self.audit(user, server_id) {
+            audit.record(action, None, Some(method.to_string()));
+        }
+    }
+
+    fn subsystem(&mut self, user: &str, name: &str) -> Option<Box<dyn Subsystem>> {
+        if name != "sftp" {
+            return None;
+        }
+        let account = self.account.clone().filter(|a| a.username == user)?;
+
+        let root = self.ctx.server_manager.lock().ok()?
+            .get_server_info(&account.server_id)
+            .ok()?
+            .config
+            .path;
+        let root = match Path::new(&root).canonicalize() {
+            Ok(root) => root,
+            Err(e) => {
+                warn!("SFTP account {} has no usable server directory {}: {}", account.username, root, e);
+                return None;
+            },
+        };
+        let denylist = self.ctx.store.lock().ok()?.denylist(&account.server_id);
+
+        let audit = self.audit(user, Some(account.server_id.clone()))?;
+        let case_insensitive = is_case_insensitive(&root);
+        Some(Box::new(SftpSession::new(root, account.read_only, denylist, audit, case_insensitive)))
+    }
+}
+
+/// Accepts SFTP connections on `bind` until the listener fails. Each
+/// connection runs on its own blocking thread.
+pub async fn serve(ctx: SftpServerContext, bind: String) -> Result<(), String> {
+    let host_key = ctx.store.lock()
+        .map_err(|_| "Failed to lock SFTP server store")?
+        .host_key()?;
+    let fingerprint = known_hosts::fingerprint(&ssh_server::host_key_blob(&host_key));
+    let host_key = Arc::new(host_key);
+
+    let listener = tokio::net::TcpListener::bind(&bind).await
+        .map_err(|e| format!("Failed to bind SFTP server on {}: {}", bind, e))?;
+    info!("SFTP server listening on {} with host key {}", bind, fingerprint);
+
+    let active = Arc::new(AtomicUsize::new(0));
+    loop {
+        let (stream, addr) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                warn!("Failed to accept SFTP connection: {}", e);
+                tokio::time::sleep(Duration::from_millis(100)).await;
+                continue;
+            },
+        };
+        if active.load(Ordering::SeqCst) >= MAX_CONNECTIONS {
+            warn!("Refusing SFTP connection from {}: too many connections", addr);
+            continue;
+        }
+        let stream = match stream.into_std().and_then(|s| s.set_nonblocking(false).map(|_| s)) {
+            Ok(stream) => stream,
+            Err(e) => {
+                warn!("Failed to set up SFTP connection from {}: {}", addr, e);
+                continue;
+            },
+        };
+
+        let ctx = ctx.clone();
+        let host_key = host_key.clone();
+        let active = active.clone();
+        active.fetch_add(1, Ordering::SeqCst);
+        task::spawn_blocking(move || {
+            handle_connection(ctx, &host_key, stream, addr);
+            active.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+fn handle_connection(ctx: SftpServerContext, host_key: &Ed25519KeyPair, stream: std::net::TcpStream, addr: SocketAddr) {
+    let handler = ConnectionHandler {
+        ctx,
+        remote_addr: addr.to_string(),
+        account: None,
+    };
+    if let Err(e) = ssh_server::serve_connection(stream, host_key, handler) {
+        info!("SFTP connection from {} ended: {}", addr, e);
+    }
+}
+
+type SftpServerStoreState<'a> = State<'a, Arc<Mutex<SftpServerStore>>>;
+type ServerManagerState<'a> = State<'a, Arc<Mutex<ServerManager>>>;
+
+fn ensure_server_exists(servers: &ServerManagerState, server_id: &str) -> Result<(), String> {
+    servers.lock()
+        .map_err(|_| "Failed to lock server manager")?
+        .get_server_info(server_id)
+        .map(|_| ())
+}
+
+#[tauri::command]
+pub fn list_sftp_accounts(state: SftpServerStoreState) -> Result<Vec<SftpAccount>, String> {
+    let store = state.lock().map_err(|_| "Failed to lock SFTP server store")?;
+    Ok(store.list_accounts())
+}
+
+#[tauri::command]
+pub fn create_sftp_account(state: SftpServerStoreState, servers: ServerManagerState, account: SftpAccountRequest) -> Result<SftpAccount, String> {
+    ensure_server_exists(&servers, &account.server_id)?;
+    let mut store = state.lock().map_err(|_| "Failed to lock SFTP server store")?;
+    store.create_account(account)
+}
+
+#[tauri::command]
+pub fn update_sftp_account(state: SftpServerStoreState, servers: ServerManagerState, id: String, account: SftpAccountRequest) -> Result<SftpAccount, String> {
+    ensure_server_exists(&servers, &account.server_id)?;
+    let mut store = state.lock().map_err(|_| "Failed to lock SFTP server store")?;
+    store.update_account(&id, account)
+}
+
+#[tauri::command]
+pub fn delete_sftp_account(state: SftpServerStoreState, id: String) -> Result<(), String> {
+    let mut store = state.lock().map_err(|_| "Failed to lock SFTP server store")?;
+    store.delete_account(&id)
+}
+
+#[tauri::command]
+pub fn get_sftp_denylist(state: SftpServerStoreState, server_id: String) -> Result<Vec<String>, String> {
+    let store = state.lock().map_err(|_| "Failed to lock SFTP server store")?;
+    Ok(store.denylist(&server_id))
+}
+
+/// Applies to sessions started after the change.
+#[tauri::command]
+pub fn set_sftp_denylist(state: SftpServerStoreState, server_id: String, patterns: Vec<String>) -> Result<(), String> {
+    let mut store = state.lock().map_err(|_| "Failed to lock SFTP server store")?;
+    store.set_denylist(&server_id, patterns)
+}
+
+#[tauri::command]
+pub fn get_sftp_server_settings(state: SftpServerStoreState) -> Result<SftpServerSettings, String> {
+    let store = state.lock().map_err(|_| "Failed to lock SFTP server store")?;
+    Ok(store.settings.clone())
+}
+
+/// Takes effect the next time ServerMint starts.
+#[tauri::command]
+pub fn update_sftp_server_settings(state: SftpServerStoreState, settings: SftpServerSettings) -> Result<(), String> {
+    let mut store = state.lock().map_err(|_| "Failed to lock SFTP server store")?;
+    store.settings = settings;
+    store.save()
+}
+
+/// The host key fingerprint collaborators should see on first connect.
+#[tauri::command]
+pub fn get_sftp_server_fingerprint(state: SftpServerStoreState) -> Result<String, String> {
+    let store = state.lock().map_err(|_| "Failed to lock SFTP server store")?;
+    Ok(known_hosts::fingerprint(&ssh_server::host_key_blob(&store.host_key()?)))
+}
+
+#[tauri::command]
+pub fn read_sftp_audit_log(state: SftpServerStoreState, server_id: Option<String>) -> Result<Vec<SftpAuditEntry>, String> {
+    let store = state.lock().map_err(|_| "Failed to lock SFTP server store")?;
+    store.read_audit(server_id.as_deref())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    const USER: &str = "alice";
+    const PASSWORD: &str = "correct horse";
+
+    /// Logs `USER` in with `PASSWORD` and jails it to `root`.
+    struct JailHandler {
+        root: PathBuf,
+        denylist: Vec<String>,
+        case_insensitive: bool,
+    }
+
+    impl JailHandler {
+        fn new(root: &Path, denylist: &[&str]) -> Self {
+            JailHandler {
+                root: root.canonicalize().unwrap(),
+                denylist: denylist.iter().map(|p| p.to_string()).collect(),
+                case_insensitive: false,
+            }
+        }
+    }
+
+    impl SshHandler for JailHandler {
+        fn check_password(&mut self, user: &str, password: &str) -> bool {
+            user == USER && password == PASSWORD
+        }
+
+        fn check_key(&mut self, _user: &str, _key: &[u8]) -> bool {
+            false
+        }
+
+        fn auth_finished(&mut self, _user: &str, _method: &str, _success: bool) {}
+
+        fn subsystem(&mut self, user: &str, name: &str) -> Option<Box<dyn Subsystem>> {
+            let audit = AuditContext {
+                path: self.root.with_extension("audit.log"),
+                username: user.to_string(),
+                server_id: None,
+                remote_addr: "127.0.0.1".to_string(),
+            };
+            (name == "sftp").then(|| {
+                Box::new(SftpSession::new(self.root.clone(), false, self.denylist.clone(), audit, self.case_insensitive)) as Box<dyn Subsystem>
+            })
+        }
+    }
+
+    /// Serves one SFTP connection jailed to `root` and logs in to it.
+    fn connect(root: &Path, denylist: &[&str]) -> (ssh2::Session, ssh2::Sftp) {
+        connect_to(JailHandler::new(root, denylist))
+    }
+
+    fn connect_to(handler: JailHandler) -> (ssh2::Session, ssh2::Sftp) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+            let host_key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            let _ = ssh_server::serve_connection(stream, &host_key, handler);
+        });
+
+        let mut session = ssh2::Session::new().unwrap();
+        session.set_tcp_stream(TcpStream::connect(("127.0.0.1", port)).unwrap());
+        session.set_timeout(10_000);
+        session.handshake().unwrap();
+        session.userauth_password(USER, PASSWORD).unwrap();
+        let sftp = session.sftp().unwrap();
+        (session, sftp)
+    }
+
+    fn put(sftp: &ssh2::Sftp, path: &str, contents: &str) -> io::Result<()> {
+        sftp.create(Path::new(path))?.write_all(contents.as_bytes())
+    }
+
+    #[test]
+    fn dot_dot_is_clamped_to_the_server_directory() {
+        let dir = scratch_dir("sftp-dotdot");
+        let root = dir.join("server");
+        fs::create_dir_all(&root).unwrap();
+        let (_session, sftp) = connect(&root, &[]);
+
+        assert_eq!(sftp.realpath(Path::new("/../../..")).unwrap(), Path::new("/"));
+        put(&sftp, "../../escaped.txt", "hello").unwrap();
+        assert_eq!(fs::read_to_string(root.join("escaped.txt")).unwrap(), "hello");
+        assert!(!dir.join("escaped.txt").exists());
+    }
+
+    #[test]
+    fn symlinks_cannot_lead_out_of_the_server_directory() {
+        let dir = scratch_dir("sftp-symlink");
+        let root = dir.join("server");
+        let outside = dir.join("outside");
+        fs::create_dir_all(&root).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        fs::write(outside.join("secret.txt"), "secret").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("escape")).unwrap();
+        std::os::unix::fs::symlink(outside.join("secret.txt"), root.join("secret-link")).unwrap();
+        let (_session, sftp) = connect(&root, &[]);
+
+        assert!(sftp.open(Path::new("/escape/secret.txt")).is_err());
+        assert!(sftp.open(Path::new("/secret-link")).is_err());
+        assert!(sftp.readdir(Path::new("/escape")).is_err());
+        assert!(put(&sftp, "/escape/planted.txt", "x").is_err());
+        assert!(!outside.join("planted.txt").exists());
+    }
+
+    #[test]
+    fn renames_cannot_move_denylisted_paths() {
+        let dir = scratch_dir("sftp-rename");
+        let root = dir.join("server");
+        fs::create_dir_all(root.join("config")).unwrap();
+        fs::create_dir_all(root.join("staging")).unwrap();
+        fs::create_dir_all(root.join("plugins")).unwrap();
+        fs::write(root.join("config/secret.txt"), "secret").unwrap();
+        fs::write(root.join("staging/secret.txt"), "visible").unwrap();
+        fs::write(root.join("plugins/secret.txt"), "visible").unwrap();
+        let (_session, sftp) = connect(&root, &["config/secret.txt"]);
+
+        assert!(sftp.open(Path::new("/config/secret.txt")).is_err());
+        // Renaming the parent would expose the file under a new name...
+        assert!(sftp.rename(Path::new("/config"), Path::new("/exposed"), None).is_err());
+        assert!(root.join("config/secret.txt").exists());
+        // ...and renaming another directory onto the pattern would hide one.
+        assert!(sftp.rename(Path::new("/staging"), Path::new("/config2/../config"), None).is_err());
+        assert!(root.join("staging/secret.txt").exists());
+
+        sftp.rename(Path::new("/plugins"), Path::new("/mods"), None).unwrap();
+        assert!(root.join("mods/secret.txt").exists());
+    }
+
+    #[test]
+    fn links_to_denylisted_paths_are_denied() {
+        let dir = scratch_dir("sftp-deny-link");
+        let root = dir.join("server");
+        fs::create_dir_all(root.join("config")).unwrap();
+        fs::write(root.join("server.properties"), "rcon.password=hunter2").unwrap();
+        fs::write(root.join("config/ops.json"), "[]").unwrap();
+        std::os::unix::fs::symlink(root.join("server.properties"), root.join("props")).unwrap();
+        std::os::unix::fs::symlink(root.join("config"), root.join("cfg")).unwrap();
+        let (_session, sftp) = connect(&root, &["server.properties", "config"]);
+
+        assert!(sftp.open(Path::new("/props")).is_err());
+        assert!(sftp.open(Path::new("/cfg/ops.json")).is_err());
+        assert!(sftp.readdir(Path::new("/cfg")).is_err());
+    }
+
+    #[test]
+    fn case_insensitive_filesystems_match_the_denylist_in_any_case() {
+        let dir = scratch_dir("sftp-deny-case");
+        let root = dir.join("server");
+        fs::create_dir_all(root.join("config")).unwrap();
+        fs::write(root.join("Server.Properties"), "rcon.password=hunter2").unwrap();
+        fs::write(root.join("config/OPS.JSON"), "[]").unwrap();
+        fs::write(root.join("config/whitelist.json"), "[]").unwrap();
+        let mut handler = JailHandler::new(&root, &["server.properties", "config/ops.json"]);
+        handler.case_insensitive = true;
+        let (_session, sftp) = connect_to(handler);
+
+        assert!(sftp.open(Path::new("/Server.Properties")).is_err());
+        assert!(sftp.open(Path::new("/SERVER.properties")).is_err());
+        assert!(sftp.open(Path::new("/Config/Ops.Json")).is_err());
+        let listed: Vec<PathBuf> = sftp.readdir(Path::new("/config")).unwrap().into_iter().map(|(path, _)| path).collect();
+        assert_eq!(listed, vec![PathBuf::from("/config/whitelist.json")]);
+        assert!(sftp.open(Path::new("/config/whitelist.json")).is_ok());
+    }
+
+    #[test]
+    fn case_sensitivity_is_probed_from_the_root() {
+        let dir = scratch_dir("sftp-case-probe");
+        let root = dir.join("Server");
+        fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+        let folded = dir.join("sERVER").canonicalize().is_ok_and(|other| other == root);
+        assert_eq!(is_case_insensitive(&root), folded);
+    }
+
+    #[test]
+    fn accounts_reach_the_ui_without_password_hashes() {
+        let dir = scratch_dir("sftp-redacted");
+        let mut store = SftpServerStore {
+            path: dir.join("sftp_server.json"),
+            audit_path: dir.join("sftp-audit.log"),
+            host_key_path: dir.join("sftp_host_ed25519.der"),
+            settings: SftpServerSettings::default(),
+            accounts: Vec::new(),
+            denylists: HashMap::new(),
+        };
+        let request = |password: Option<&str>| SftpAccountRequest {
+            username: USER.to_string(),
+            server_id: "survival".to_string(),
+            read_only: false,
+            password: password.map(str::to_string),
+            authorized_keys: Vec::new(),
+        };
+
+        let created = store.create_account(request(Some(PASSWORD))).unwrap();
+        assert_eq!(created.password_hash.as_deref(), Some(""));
+        let updated = store.update_account(&created.id, request(None)).unwrap();
+        assert_eq!(updated.password_hash.as_deref(), Some(""));
+        assert!(store.list_accounts().iter().all(|a| a.password_hash.as_deref() == Some("")));
+
+        // Only what's returned is redacted.
+        let stored = store.find_account(USER).unwrap().password_hash.clone().unwrap();
+        assert!(verify_password(&stored, PASSWORD));
+        assert!(fs::read_to_string(&store.path).unwrap().contains(&stored));
+    }
+}
@@ -0,0 +1,1480 @@
+//! A small SSH server, just enough to carry the embedded SFTP endpoint in
+//! `sftp_server`.
+//!
+//! It speaks curve25519 key exchange with an Ed25519 host key, the
+//! chacha20-poly1305 and AES-256-GCM ciphers, password and public key
+//! authentication, and session channels that run a subsystem, or a command
+//! if the handler allows exec. Shells, port forwarding and agent forwarding
+//! are refused.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::{debug, warn};
+use ring::aead::chacha20_poly1305_openssh as chacha;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM};
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, X25519};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{self, Ed25519KeyPair, KeyPair, RsaPublicKeyComponents};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+const SERVER_VERSION: &str = "SSH-2.0-ServerMint_1.0";
+/// How long a client has from connecting to authenticating, and the timeout
+/// of each read until then.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(60);
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+const MAX_PACKET_LEN: usize = 256 * 1024;
+const MAX_AUTH_ATTEMPTS: u32 = 6;
+const FAILED_AUTH_DELAY: Duration = Duration::from_millis(500);
+const MAX_CHANNELS: usize = 8;
+const LOCAL_WINDOW: u32 = 2 * 1024 * 1024;
+/// Output queued for a client beyond which its input is no longer processed
+/// and its window no longer reopened, until it reads.
+const MAX_PENDING: usize = 1024 * 1024;
+/// Client data goes to the subsystem in pieces of at most this size, so one
+/// piece can't queue much output past `MAX_PENDING`.
+const FEED_SIZE: usize = 1024;
+const LOCAL_MAX_PACKET: u32 = 32 * 1024;
+
+const KEX_ALGORITHMS: &[&str] = &["curve25519-sha256", "curve25519-sha256@libssh.org"];
+const HOST_KEY_ALGORITHM: &str = "ssh-ed25519";
+const MAC_ALGORITHMS: &[&str] = &["hmac-sha2-256", "hmac-sha2-512"];
+const STRICT_KEX_SERVER: &str = "kex-strict-s-v00@openssh.com";
+const STRICT_KEX_CLIENT: &str = "kex-strict-c-v00@openssh.com";
+const EXT_INFO_CLIENT: &str = "ext-info-c";
+const SIGNATURE_ALGORITHMS: &[&str] = &["ssh-ed25519", "rsa-sha2-256", "rsa-sha2-512", "ecdsa-sha2-nistp256"];
+const AUTH_METHODS: &str = "publickey,password";
+
+const MSG_DISCONNECT: u8 = 1;
+const MSG_IGNORE: u8 = 2;
+const MSG_UNIMPLEMENTED: u8 = 3;
+const MSG_DEBUG: u8 = 4;
+const MSG_SERVICE_REQUEST: u8 = 5;
+const MSG_SERVICE_ACCEPT: u8 = 6;
+const MSG_EXT_INFO: u8 = 7;
+const MSG_KEXINIT: u8 = 20;
+const MSG_NEWKEYS: u8 = 21;
+const MSG_KEX_ECDH_INIT: u8 = 30;
+const MSG_KEX_ECDH_REPLY: u8 = 31;
+const MSG_USERAUTH_REQUEST: u8 = 50;
+const MSG_USERAUTH_FAILURE: u8 = 51;
+const MSG_USERAUTH_SUCCESS: u8 = 52;
+const MSG_USERAUTH_PK_OK: u8 = 60;
+const MSG_GLOBAL_REQUEST: u8 = 80;
+const MSG_REQUEST_FAILURE: u8 = 82;
+const MSG_CHANNEL_OPEN: u8 = 90;
+const MSG_CHANNEL_OPEN_CONFIRMATION: u8 = 91;
+const MSG_CHANNEL_OPEN_FAILURE: u8 = 92;
+const MSG_CHANNEL_WINDOW_ADJUST: u8 = 93;
+const MSG_CHANNEL_DATA: u8 = 94;
+const MSG_CHANNEL_EXTENDED_DATA: u8 = 95;
+const MSG_CHANNEL_EOF: u8 = 96;
+const MSG_CHANNEL_CLOSE: u8 = 97;
+const MSG_CHANNEL_REQUEST: u8 = 98;
+const MSG_CHANNEL_SUCCESS: u8 = 99;
+const MSG_CHANNEL_FAILURE: u8 = 100;
+
+const DISCONNECT_PROTOCOL_ERROR: u32 = 2;
+const DISCONNECT_SERVICE_NOT_AVAILABLE: u32 = 7;
+const DISCONNECT_NO_MORE_AUTH_METHODS: u32 = 14;
+const OPEN_ADMINISTRATIVELY_PROHIBITED: u32 = 1;
+const OPEN_UNKNOWN_CHANNEL_TYPE: u32 = 3;
+
+#[derive(Debug, Error)]
+pub(crate) enum SshError {
+    #[error("Connection error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Protocol error: {0}")]
+    Protocol(String),
+    #[error("Key exchange failed: {0}")]
+    KeyExchange(String),
+    #[error("A packet failed its integrity check")]
+    Integrity,
+    #[error("Too many failed authentication attempts")]
+    TooManyAttempts,
+    #[error("The client disconnected")]
+    Disconnected,
+}
+
+fn malformed(what: &str) -> SshError {
+    SshError::Protocol(format!("malformed {}", what))
+}
+
+/// Decides who may log in and what they get once they have.
+pub(crate) trait SshHandler {
+    fn check_password(&mut self, user: &str, password: &str) -> bool;
+    /// Whether the public key blob `key` may be used to log in as `user`.
+    /// Signatures are verified by the transport.
+    fn check_key(&mut self, user: &str, key: &[u8]) -> bool;
+    /// Called once a password or signed key attempt has been decided.
+    fn auth_finished(&mut self, user: &str, method: &str, success: bool);
+    fn subsystem(&mut self, user: &str, name: &str) -> Option<Box<dyn Subsystem>>;
+    /// Runs `command` for an exec request. Refused unless a handler
+    /// overrides it.
+    fn exec(&mut self, _user: &str, _command: &str) -> Option<Box<dyn Subsystem>> {
+        None
+    }
+}
+
+/// A subsystem running on a session channel, such as `sftp`.
+pub(crate) trait Subsystem {
+    /// Handles bytes the client sent on the channel and returns the reply.
+    /// An error closes the channel.
+    fn data(&mut self, data: &[u8]) -> Result<Vec<u8>, String>;
+    /// Called when the client sends EOF. Returns the last output and the
+    /// exit status reported before the channel closes.
+    fn eof(&mut self) -> (Vec<u8>, u32) {
+        (Vec::new(), 0)
+    }
+}
+
+/// Reads SSH wire-format values (RFC 4251 section 5). SFTP uses the same
+/// encoding.
+pub(crate) struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(head)
+    }
+
+    pub(crate) fn u8(&mut self) -> Option<u8> {
+        self.take(1).map(|b| b[0])
+    }
+
+    pub(crate) fn bool(&mut self) -> Option<bool> {
+        self.u8().map(|b| b != 0)
+    }
+
+    pub(crate) fn u32(&mut self) -> Option<u32> {
+        self.take(4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    pub(crate) fn u64(&mut self) -> Option<u64> {
+        let high = self.u32()? as u64;
+        let low = self.u32()? as u64;
+        Some(high << 32 | low)
+    }
+
+    pub(crate) fn bytes(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    pub(crate) fn string(&mut self) -> Option<String> {
+        self.bytes().and_then(|b| String::from_utf8(b.to_vec()).ok())
+    }
+
+    fn name_list(&mut self) -> Option<Vec<String>> {
+        let names = self.string()?;
+        Some(names.split(',').filter(|n| !n.is_empty()).map(str::to_string).collect())
+    }
+}
+
+/// Appends SSH wire-format values.
+pub(crate) trait Writer {
+    fn put_u8(&mut self, value: u8);
+    fn put_bool(&mut self, value: bool);
+    fn put_u32(&mut self, value: u32);
+    fn put_u64(&mut self, value: u64);
+    fn put_bytes(&mut self, value: &[u8]);
+    fn put_str(&mut self, value: &str);
+    fn put_mpint(&mut self, value: &[u8]);
+}
+
+impl Writer for Vec<u8> {
+    fn put_u8(&mut self, value: u8) {
+        self.push(value);
+    }
+
+    fn put_bool(&mut self, value: bool) {
+        self.push(value as u8);
+    }
+
+    fn put_u32(&mut self, value: u32) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn put_u64(&mut self, value: u64) {
+        self.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn put_bytes(&mut self, value: &[u8]) {
+        self.put_u32(value.len() as u32);
+        self.extend_from_slice(value);
+    }
+
+    fn put_str(&mut self, value: &str) {
+        self.put_bytes(value.as_bytes());
+    }
+
+    /// Writes an unsigned big-endian integer as an mpint.
+    fn put_mpint(&mut self, value: &[u8]) {
+        let value = strip_leading_zeros(value);
+        if value.first().is_some_and(|b| b & 0x80 != 0) {
+            self.put_u32(value.len() as u32 + 1);
+            self.push(0);
+            self.extend_from_slice(value);
+        } else {
+            self.put_bytes(value);
+        }
+    }
+}
+
+fn strip_leading_zeros(value: &[u8]) -> &[u8] {
+    let start = value.iter().position(|&b| b != 0).unwrap_or(value.len());
+    &value[start..]
+}
+
+/// The key types accepted in authorized keys, by their OpenSSH names.
+const KEY_TYPES: &[&str] = &["ssh-ed25519", "ssh-rsa", "ecdsa-sha2-nistp256"];
+
+/// Parses an OpenSSH public key line (`type base64 [comment]`) and returns
+/// the key type and blob.
+pub(crate) fn parse_authorized_key(line: &str) -> Result<(String, Vec<u8>), String> {
+    let mut parts = line.split_whitespace();
+    let key_type = parts.next().ok_or("The public key is empty")?;
+    if !KEY_TYPES.contains(&key_type) {
+        return Err(format!(
+            "Unsupported key type {}, use one of {}",
+            key_type,
+            KEY_TYPES.join(", ")
+        ));
+    }
+    let blob = parts.next()
+        .and_then(|encoded| STANDARD.decode(encoded).ok())
+        .ok_or_else(|| format!("The {} key is not valid base64", key_type))?;
+    if Reader::new(&blob).string().as_deref() != Some(key_type) {
+        return Err(format!("The key data does not match its type {}", key_type));
+    }
+    Ok((key_type.to_string(), blob))
+}
+
+pub(crate) fn host_key_blob(key: &Ed25519KeyPair) -> Vec<u8> {
+    let mut blob = Vec::new();
+    blob.put_str(HOST_KEY_ALGORITHM);
+    blob.put_bytes(key.public_key().as_ref());
+    blob
+}
+
+/// Pads or trims an unsigned big-endian integer to `len` bytes.
+fn fixed_width(value: &[u8], len: usize) -> Option<Vec<u8>> {
+    let value = strip_leading_zeros(value);
+    if value.len() > len {
+        return None;
+    }
+    let mut out = vec![0; len - value.len()];
+    out.extend_from_slice(value);
+    Some(out)
+}
+
+/// Checks a user authentication signature made with `algorithm`.
+fn verify_signature(algorithm: &str, key: &[u8], message: &[u8], signature: &[u8]) -> bool {
+    let mut sig = Reader::new(signature);
+    if sig.string().as_deref() != Some(algorithm) {
+        return false;
+    }
+    let Some(sig) = sig.bytes() else { return false };
+    let mut key = Reader::new(key);
+    let key_type = key.string();
+
+    match (algorithm, key_type.as_deref()) {
+        ("ssh-ed25519", Some("ssh-ed25519")) => key.bytes().is_some_and(|public| {
+            signature::UnparsedPublicKey::new(&signature::ED25519, public)
+                .verify(message, sig)
+                .is_ok()
+        }),
+        ("rsa-sha2-256" | "rsa-sha2-512", Some("ssh-rsa")) => {
+            let (Some(e), Some(n)) = (key.bytes(), key.bytes()) else { return false };
+            let params = if algorithm == "rsa-sha2-256" {
+                &signature::RSA_PKCS1_2048_8192_SHA256
+            } else {
+                &signature::RSA_PKCS1_2048_8192_SHA512
+            };
+            RsaPublicKeyComponents { n: strip_leading_zeros(n), e: strip_leading_zeros(e) }
+                .verify(params, message, sig)
+                .is_ok()
+        },
+        ("ecdsa-sha2-nistp256", Some("ecdsa-sha2-nistp256")) => {
+            let (Some(_curve), Some(point)) = (key.bytes(), key.bytes()) else { return false };
+            let mut parts = Reader::new(sig);
+            let fixed = parts.bytes()
+                .and_then(|r| fixed_width(r, 32))
+                .zip(parts.bytes().and_then(|s| fixed_width(s, 32)));
+            fixed.is_some_and(|(mut r, s)| {
+                r.extend(s);
+                signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point)
+                    .verify(message, &r)
+                    .is_ok()
+            })
+        },
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Cipher {
+    ChaCha20Poly1305,
+    Aes256Gcm,
+}
+
+impl Cipher {
+    const ALL: [Cipher; 2] = [Cipher::ChaCha20Poly1305, Cipher::Aes256Gcm];
+
+    fn name(self) -> &'static str {
+        match self {
+            Cipher::ChaCha20Poly1305 => "chacha20-poly1305@openssh.com",
+            Cipher::Aes256Gcm => "aes256-gcm@openssh.com",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Cipher> {
+        Cipher::ALL.into_iter().find(|c| c.name() == name)
+    }
+
+    fn key_len(self) -> usize {
+        match self {
+            Cipher::ChaCha20Poly1305 => chacha::KEY_LEN,
+            Cipher::Aes256Gcm => 32,
+        }
+    }
+
+    fn iv_len(self) -> usize {
+        match self {
+            Cipher::ChaCha20Poly1305 => 0,
+            Cipher::Aes256Gcm => 12,
+        }
+    }
+}
+
+const TAG_LEN: usize = 16;
+
+fn gcm_key(key: &[u8], iv: &[u8]) -> Result<(Box<LessSafeKey>, [u8; 12]), SshError> {
+    let key = UnboundKey::new(&AES_256_GCM, key)
+        .map_err(|_| SshError::KeyExchange("invalid AES-GCM key".to_string()))?;
+    let iv = iv.try_into()
+        .map_err(|_| SshError::KeyExchange("invalid AES-GCM IV".to_string()))?;
+    Ok((Box::new(LessSafeKey::new(key)), iv))
+}
+
+fn chacha_key(key: &[u8]) -> Result<[u8; chacha::KEY_LEN], SshError> {
+    key.try_into()
+        .map_err(|_| SshError::KeyExchange("invalid ChaCha20-Poly1305 key".to_string()))
+}
+
+/// Returns the nonce for the next AES-GCM packet and advances the invocation
+/// counter in the IV's last eight bytes (RFC 5647).
+fn next_nonce(iv: &mut [u8; 12]) -> Nonce {
+    let nonce = Nonce::assume_unique_for_key(*iv);
+    let mut counter = [0u8; 8];
+    counter.copy_from_slice(&iv[4..]);
+    iv[4..].copy_from_slice(&u64::from_be_bytes(counter).wrapping_add(1).to_be_bytes());
+    nonce
+}
+
+enum Opening {
+    Plain,
+    ChaCha(Box<chacha::OpeningKey>),
+    Gcm(Box<LessSafeKey>, [u8; 12]),
+}
+
+impl Opening {
+    fn new(cipher: Cipher, iv: &[u8], key: &[u8]) -> Result<Self, SshError> {
+        Ok(match cipher {
+            Cipher::ChaCha20Poly1305 => Opening::ChaCha(Box::new(chacha::OpeningKey::new(&chacha_key(key)?))),
+            Cipher::Aes256Gcm => {
+                let (key, iv) = gcm_key(key, iv)?;
+                Opening::Gcm(key, iv)
+            },
+        })
+    }
+}
+
+enum Sealing {
+    Plain,
+    ChaCha(Box<chacha::SealingKey>),
+    Gcm(Box<LessSafeKey>, [u8; 12]),
+}
+
+impl Sealing {
+    fn new(cipher: Cipher, iv: &[u8], key: &[u8]) -> Result<Self, SshError> {
+        Ok(match cipher {
+            Cipher::ChaCha20Poly1305 => Sealing::ChaCha(Box::new(chacha::SealingKey::new(&chacha_key(key)?))),
+            Cipher::Aes256Gcm => {
+                let (key, iv) = gcm_key(key, iv)?;
+                Sealing::Gcm(key, iv)
+            },
+        })
+    }
+}
+
+/// The binary packet protocol (RFC 4253 section 6).
+struct Transport {
+    stream: TcpStream,
+    rng: SystemRandom,
+    opening: Opening,
+    sealing: Sealing,
+    recv_seq: u32,
+    send_seq: u32,
+}
+
+impl Transport {
+    fn send(&mut self, payload: &[u8]) -> Result<(), SshError> {
+        // AEAD ciphers leave the length field out of the padding alignment.
+        let (block, aad_len) = match self.sealing {
+            Sealing::Plain => (8, 0),
+            Sealing::ChaCha(_) => (8, 4),
+            Sealing::Gcm(..) => (16, 4),
+        };
+        let mut padding = block - (5 + payload.len() - aad_len) % block;
+        if padding < 4 {
+            padding += block;
+        }
+
+        let mut packet = Vec::with_capacity(5 + payload.len() + padding + TAG_LEN);
+        packet.put_u32((1 + payload.len() + padding) as u32);
+        packet.put_u8(padding as u8);
+        packet.extend_from_slice(payload);
+        let padding_start = packet.len();
+        packet.resize(padding_start + padding, 0);
+        self.rng.fill(&mut packet[padding_start..])
+            .map_err(|_| SshError::Protocol("failed to generate padding".to_string()))?;
+
+        match &mut self.sealing {
+            Sealing::Plain => {},
+            Sealing::ChaCha(key) => {
+                let mut tag = [0u8; chacha::TAG_LEN];
+                key.seal_in_place(self.send_seq, &mut packet, &mut tag);
+                packet.extend_from_slice(&tag);
+            },
+            Sealing::Gcm(key, iv) => {
+                let (length, body) = packet.split_at_mut(4);
+                let tag = key.seal_in_place_separate_tag(next_nonce(iv), Aad::from(&*length), body)
+                    .map_err(|_| SshError::Integrity)?;
+                packet.extend_from_slice(tag.as_ref());
+            },
+        }
+
+        self.stream.write_all(&packet)?;
+        self.send_seq = self.send_seq.wrapping_add(1);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Vec<u8>, SshError> {
+        let mut length = [0u8; 4];
+        self.stream.read_exact(&mut length)?;
+        let len = match &self.opening {
+            Opening::ChaCha(key) => u32::from_be_bytes(key.decrypt_packet_length(self.recv_seq, length)),
+            _ => u32::from_be_bytes(length),
+        } as usize;
+        if !(5..=MAX_PACKET_LEN).contains(&len) {
+            return Err(SshError::Protocol(format!("invalid packet length {}", len)));
+        }
+
+        let tag_len = if matches!(self.opening, Opening::Plain) { 0 } else { TAG_LEN };
+        let mut packet = vec![0u8; 4 + len + tag_len];
+        packet[..4].copy_from_slice(&length);
+        self.stream.read_exact(&mut packet[4..])?;
+
+        let plain: &[u8] = match &mut self.opening {
+            Opening::Plain => &packet[4..],
+            Opening::ChaCha(key) => {
+                let (data, tag) = packet.split_at_mut(4 + len);
+                let tag: [u8; chacha::TAG_LEN] = (&*tag).try_into().map_err(|_| SshError::Integrity)?;
+                key.open_in_place(self.recv_seq, data, &tag).map_err(|_| SshError::Integrity)?
+            },
+            Opening::Gcm(key, iv) => {
+                key.open_in_place(next_nonce(iv), Aad::from(length), &mut packet[4..])
+                    .map_err(|_| SshError::Integrity)?
+            },
+        };
+
+        let padding = plain[0] as usize;
+        if padding < 4 || padding + 1 >= plain.len() {
+            return Err(SshError::Protocol(format!("invalid padding length {}", padding)));
+        }
+        let payload = plain[1..plain.len() - padding].to_vec();
+        self.recv_seq = self.recv_seq.wrapping_add(1);
+        Ok(payload)
+    }
+}
+
+/// The algorithm lists from a KEXINIT message.
+struct Proposal {
+    kex: Vec<String>,
+    host_key: Vec<String>,
+    ciphers_c2s: Vec<String>,
+    ciphers_s2c: Vec<String>,
+    compression_c2s: Vec<String>,
+    compression_s2c: Vec<String>,
+    first_kex_follows: bool,
+}
+
+impl Proposal {
+    fn parse(payload: &[u8]) -> Option<Proposal> {
+        let mut r = Reader::new(payload.get(17..)?);
+        let kex = r.name_list()?;
+        let host_key = r.name_list()?;
+        let ciphers_c2s = r.name_list()?;
+        let ciphers_s2c = r.name_list()?;
+        let _macs_c2s = r.name_list()?;
+        let _macs_s2c = r.name_list()?;
+        let compression_c2s = r.name_list()?;
+        let compression_s2c = r.name_list()?;
+        let _languages_c2s = r.name_list()?;
+        let _languages_s2c = r.name_list()?;
+        let first_kex_follows = r.bool()?;
+        Some(Proposal { kex, host_key, ciphers_c2s, ciphers_s2c, compression_c2s, compression_s2c, first_kex_follows })
+    }
+}
+
+/// The first of the client's algorithms that the server supports.
+fn negotiate<'a>(client: &'a [String], supported: &[&str]) -> Option<&'a str> {
+    client.iter().map(String::as_str).find(|name| supported.contains(name))
+}
+
+/// Derives a session key from the shared secret (RFC 4253 section 7.2).
+fn derive_key(shared: &[u8], hash: &[u8], letter: u8, session_id: &[u8], len: usize) -> Vec<u8> {
+    let mut key = Sha256::new()
+        .chain_update(shared)
+        .chain_update(hash)
+        .chain_update([letter])
+        .chain_update(session_id)
+        .finalize()
+        .to_vec();
+    while key.len() < len {
+        let more = Sha256::new()
+            .chain_update(shared)
+            .chain_update(hash)
+            .chain_update(&key)
+            .finalize();
+        key.extend_from_slice(&more);
+    }
+    key.truncate(len);
+    key
+}
+
+struct Channel {
+    remote_id: u32,
+    remote_window: u32,
+    remote_max_packet: u32,
+    local_window: u32,
+    subsystem: Option<Box<dyn Subsystem>>,
+    /// Client data waiting for the client to read enough of `pending`.
+    unprocessed: Vec<u8>,
+    /// Subsystem output waiting for the client to open its window.
+    pending: Vec<u8>,
+    eof_received: bool,
+    /// Whether the subsystem has seen the EOF, after all client data.
+    eof_delivered: bool,
+    exit_status: u32,
+    /// Whether we sent CHANNEL_CLOSE.
+    closed: bool,
+}
+
+struct Connection<'a, H: SshHandler> {
+    transport: Transport,
+    host_key: &'a Ed25519KeyPair,
+    handler: H,
+    client_version: Vec<u8>,
+    session_id: Option<Vec<u8>>,
+    strict_kex: bool,
+    channels: HashMap<u32, Channel>,
+    next_channel: u32,
+}
+
+/// Runs one client connection to completion. Blocks the calling thread.
+pub(crate) fn serve_connection<H: SshHandler>(stream: TcpStream, host_key: &Ed25519KeyPair, handler: H) -> Result<(), SshError> {
+    serve_with_deadline(stream, host_key, handler, HANDSHAKE_TIMEOUT)
+}
+
+/// Closes `stream` unless the returned sender is dropped within `deadline`,
+/// so a client can't stay connected unauthenticated by sending slowly.
+fn auth_watchdog(stream: &TcpStream, deadline: Duration) -> Result<mpsc::Sender<()>, SshError> {
+    let watched = stream.try_clone()?;
+    let (authenticated, done) = mpsc::channel::<()>();
+    thread::spawn(move || {
+        if let Err(RecvTimeoutError::Timeout) = done.recv_timeout(deadline) {
+            debug!("Closing a connection that did not authenticate in time");
+            let _ = watched.shutdown(Shutdown::Both);
+        }
+    });
+    Ok(authenticated)
+}
+
+fn serve_with_deadline<H: SshHandler>(stream: TcpStream, host_key: &Ed25519KeyPair, handler: H, deadline: Duration) -> Result<(), SshError> {
+    let authenticated = auth_watchdog(&stream, deadline)?;
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+
+    let mut connection = Connection {
+        transport: Transport {
+            stream,
+            rng: SystemRandom::new(),
+            opening: Opening::Plain,
+            sealing: Sealing::Plain,
+            recv_seq: 0,
+            send_seq: 0,
+        },
+        host_key,
+        handler,
+        client_version: Vec::new(),
+        session_id: None,
+        strict_kex: false,
+        channels: HashMap::new(),
+        next_channel: 0,
+    };
+
+    let result = connection.run(authenticated);
+    match &result {
+        Err(SshError::Protocol(message)) | Err(SshError::KeyExchange(message)) => {
+            connection.disconnect(DISCONNECT_PROTOCOL_ERROR, message);
+        },
+        Err(SshError::TooManyAttempts) => {
+            connection.disconnect(DISCONNECT_NO_MORE_AUTH_METHODS, "Too many authentication failures");
+        },
+        _ => {},
+    }
+    match result {
+        Err(SshError::Disconnected) => Ok(()),
+        result => result,
+    }
+}
+
+impl<'a, H: SshHandler> Connection<'a, H> {
+    fn run(&mut self, authenticated: mpsc::Sender<()>) -> Result<(), SshError> {
+        self.exchange_versions()?;
+        self.key_exchange(None)?;
+        let user = self.authenticate()?;
+        drop(authenticated);
+
+        self.transport.stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+        self.serve_channels(&user)
+    }
+
+    fn disconnect(&mut self, reason: u32, message: &str) {
+        let mut payload = vec![MSG_DISCONNECT];
+        payload.put_u32(reason);
+        payload.put_str(message);
+        payload.put_str("");
+        let _ = self.transport.send(&payload);
+    }
+
+    fn exchange_versions(&mut self) -> Result<(), SshError> {
+        self.transport.stream.write_all(format!("{}\r\n", SERVER_VERSION).as_bytes())?;
+
+        // Clients may send other lines before the version (RFC 4253 4.2).
+        // Read a byte at a time so nothing after the version is consumed.
+        for _ in 0..32 {
+            let mut line = Vec::new();
+            let mut byte = [0u8; 1];
+            while line.len() < 255 {
+                self.transport.stream.read_exact(&mut byte)?;
+                if byte[0] == b'\n' {
+                    break;
+                }
+                line.push(byte[0]);
+            }
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            if line.starts_with(b"SSH-2.0-") || line.starts_with(b"SSH-1.99-") {
+                self.client_version = line;
+                return Ok(());
+            }
+            if line.starts_with(b"SSH-") {
+                return Err(SshError::Protocol("only SSH protocol 2.0 is supported".to_string()));
+            }
+        }
+        Err(SshError::Protocol("no SSH version string received".to_string()))
+    }
+
+    fn kexinit_payload(&mut self, first: bool) -> Result<Vec<u8>, SshError> {
+        let mut cookie = [0u8; 16];
+        self.transport.rng.fill(&mut cookie)
+            .map_err(|_| SshError::KeyExchange("failed to generate cookie".to_string()))?;
+
+        let mut kex = KEX_ALGORITHMS.join(",");
+        if first {
+            kex.push(',');
+            kex.push_str(STRICT_KEX_SERVER);
+        }
+        let ciphers = Cipher::ALL.map(Cipher::name).join(",");
+        let macs = MAC_ALGORITHMS.join(",");
+
+        let mut payload = vec![MSG_KEXINIT];
+        payload.extend_from_slice(&cookie);
+        for list in [kex.as_str(), HOST_KEY_ALGORITHM, &ciphers, &ciphers, &macs, &macs, "none", "none", "", ""] {
+            payload.put_str(list);
+        }
+        payload.put_bool(false);
+        payload.put_u32(0);
+        Ok(payload)
+    }
+
+    /// Reads the next key exchange message. Strict key exchange forbids
+    /// anything else during the first exchange.
+    fn receive_kex(&mut self, expected: u8, strict: bool) -> Result<Vec<u8>, SshError> {
+        loop {
+            let payload = self.transport.receive()?;
+            match payload[0] {
+                kind if kind == expected => return Ok(payload),
+                MSG_DISCONNECT => return Err(SshError::Disconnected),
+                MSG_IGNORE | MSG_DEBUG | MSG_UNIMPLEMENTED if !strict => continue,
+                kind => return Err(SshError::KeyExchange(format!("unexpected message {} during key exchange", kind))),
+            }
+        }
+    }
+
+    /// Runs a key exchange. `client_init` is the client's KEXINIT when the
+    /// client started a re-exchange.
+    fn key_exchange(&mut self, client_init: Option<Vec<u8>>) -> Result<(), SshError> {
+        let first = self.session_id.is_none();
+        let server_init = self.kexinit_payload(first)?;
+        self.transport.send(&server_init)?;
+
+        let client_init = match client_init {
+            Some(payload) => payload,
+            None => self.receive_kex(MSG_KEXINIT, false)?,
+        };
+        let proposal = Proposal::parse(&client_init).ok_or_else(|| malformed("KEXINIT"))?;
+        let no_match = |what: &str| SshError::KeyExchange(format!("no common {} algorithm", what));
+
+        let kex = negotiate(&proposal.kex, KEX_ALGORITHMS).ok_or_else(|| no_match("key exchange"))?;
+        let host_key = negotiate(&proposal.host_key, &[HOST_KEY_ALGORITHM]).ok_or_else(|| no_match("host key"))?;
+        let cipher_names = Cipher::ALL.map(Cipher::name);
+        let cipher_c2s = negotiate(&proposal.ciphers_c2s, &cipher_names)
+            .and_then(Cipher::from_name)
+            .ok_or_else(|| no_match("cipher"))?;
+        let cipher_s2c = negotiate(&proposal.ciphers_s2c, &cipher_names)
+            .and_then(Cipher::from_name)
+            .ok_or_else(|| no_match("cipher"))?;
+        // Both ciphers are AEAD, so the MAC lists don't matter.
+        if negotiate(&proposal.compression_c2s, &["none"]).is_none()
+            || negotiate(&proposal.compression_s2c, &["none"]).is_none() {
+            return Err(no_match("compression"));
+        }
+
+        let ext_info = first && proposal.kex.iter().any(|k| k == EXT_INFO_CLIENT);
+        if first {
+            self.strict_kex = proposal.kex.iter().any(|k| k == STRICT_KEX_CLIENT);
+        }
+        let strict = first && self.strict_kex;
+
+        let guessed_wrong = proposal.kex.first().map(String::as_str) != Some(kex)
+            || proposal.host_key.first().map(String::as_str) != Some(host_key);
+        if proposal.first_kex_follows && guessed_wrong {
+            self.transport.receive()?;
+        }
+
+        let ecdh_init = self.receive_kex(MSG_KEX_ECDH_INIT, strict)?;
+        let client_public = Reader::new(&ecdh_init[1..]).bytes()
+            .ok_or_else(|| malformed("KEX_ECDH_INIT"))?
+            .to_vec();
+
+        let private = EphemeralPrivateKey::generate(&X25519, &self.transport.rng)
+            .map_err(|_| SshError::KeyExchange("failed to generate an X25519 key".to_string()))?;
+        let server_public = private.compute_public_key()
+            .map_err(|_| SshError::KeyExchange("failed to compute the X25519 public key".to_string()))?;
+        let shared = agreement::agree_ephemeral(private, &UnparsedPublicKey::new(&X25519, &client_public), |secret| {
+            let mut encoded = Vec::new();
+            encoded.put_mpint(secret);
+            encoded
+        }).map_err(|_| SshError::KeyExchange("invalid X25519 public key".to_string()))?;
+
+        let host_key_blob = host_key_blob(self.host_key);
+        let mut hash_input = Vec::new();
+        hash_input.put_bytes(&self.client_version);
+        hash_input.put_str(SERVER_VERSION);
+        hash_input.put_bytes(&client_init);
+        hash_input.put_bytes(&server_init);
+        hash_input.put_bytes(&host_key_blob);
+        hash_input.put_bytes(&client_public);
+        hash_input.put_bytes(server_public.as_ref());
+        hash_input.extend_from_slice(&shared);
+        let exchange_hash = Sha256::digest(&hash_input).to_vec();
+        let session_id = self.session_id.get_or_insert_with(|| exchange_hash.clone()).clone();
+
+        let mut signature = Vec::new();
+        signature.put_str(HOST_KEY_ALGORITHM);
+        signature.put_bytes(self.host_key.sign(&exchange_hash).as_ref());
+        let mut reply = vec![MSG_KEX_ECDH_REPLY];
+        reply.put_bytes(&host_key_blob);
+        reply.put_bytes(server_public.as_ref());
+        reply.put_bytes(&signature);
+        self.transport.send(&reply)?;
+
+        let key = |letter: u8, len: usize| derive_key(&shared, &exchange_hash, letter, &session_id, len);
+
+        self.transport.send(&[MSG_NEWKEYS])?;
+        self.transport.sealing = Sealing::new(
+            cipher_s2c,
+            &key(b'B', cipher_s2c.iv_len()),
+            &key(b'D', cipher_s2c.key_len()),
+        )?;
+        if self.strict_kex {
+            self.transport.send_seq = 0;
+        }
+
+        self.receive_kex(MSG_NEWKEYS, strict)?;
+        self.transport.opening = Opening::new(
+            cipher_c2s,
+            &key(b'A', cipher_c2s.iv_len()),
+            &key(b'C', cipher_c2s.key_len()),
+        )?;
+        if self.strict_kex {
+            self.transport.recv_seq = 0;
+        }
+        debug!("SSH key exchange done with {} and {}/{}", kex, cipher_c2s.name(), cipher_s2c.name());
+
+        if ext_info {
+            let mut payload = vec![MSG_EXT_INFO];
+            payload.put_u32(1);
+            payload.put_str("server-sig-algs");
+            payload.put_str(&SIGNATURE_ALGORITHMS.join(","));
+            self.transport.send(&payload)?;
+        }
+        Ok(())
+    }
+
+    /// Reads the next message that isn't transport housekeeping, running a
+    /// key re-exchange if the client asks for one.
+    fn receive(&mut self) -> Result<Vec<u8>, SshError> {
+        loop {
+            let payload = self.transport.receive()?;
+            match payload[0] {
+                MSG_DISCONNECT => return Err(SshError::Disconnected),
+                MSG_IGNORE | MSG_DEBUG | MSG_UNIMPLEMENTED => continue,
+                MSG_KEXINIT => self.key_exchange(Some(payload))?,
+                _ => return Ok(payload),
+            }
+        }
+    }
+
+    fn authenticate(&mut self) -> Result<String, SshError> {
+        let request = self.receive()?;
+        let mut r = Reader::new(&request[1..]);
+        if request[0] != MSG_SERVICE_REQUEST || r.string().as_deref() != Some("ssh-userauth") {
+            self.disconnect(DISCONNECT_SERVICE_NOT_AVAILABLE, "Only ssh-userauth is available");
+            return Err(SshError::Protocol("expected a ssh-userauth service request".to_string()));
+        }
+        let mut accept = vec![MSG_SERVICE_ACCEPT];
+        accept.put_str("ssh-userauth");
+        self.transport.send(&accept)?;
+
+        let mut failures = 0;
+        loop {
+            let request = self.receive()?;
+            if request[0] != MSG_USERAUTH_REQUEST {
+                return Err(SshError::Protocol(format!("unexpected message {} during authentication", request[0])));
+            }
+            let mut r = Reader::new(&request[1..]);
+            let (user, service, method) = match (r.string(), r.string(), r.string()) {
+                (Some(user), Some(service), Some(method)) => (user, service, method),
+                _ => return Err(malformed("USERAUTH_REQUEST")),
+            };
+            if service != "ssh-connection" {
+                self.disconnect(DISCONNECT_SERVICE_NOT_AVAILABLE, "Only ssh-connection is available");
+                return Err(SshError::Protocol(format!("unknown service {}", service)));
+            }
+
+            let success = match method.as_str() {
+                "password" => {
+                    let change = r.bool().ok_or_else(|| malformed("password request"))?;
+                    let password = r.string().ok_or_else(|| malformed("password request"))?;
+                    let success = !change && self.handler.check_password(&user, &password);
+                    self.handler.auth_finished(&user, "password", success);
+                    Some(success)
+                },
+                "publickey" => self.public_key_auth(&user, &mut r)?,
+                _ => None,
+            };
+
+            if success == Some(true) {
+                self.transport.send(&[MSG_USERAUTH_SUCCESS])?;
+                return Ok(user);
+            }
+            // Key queries and unsupported methods count too, or a client
+            // could keep asking forever.
+            failures += 1;
+            if failures >= MAX_AUTH_ATTEMPTS {
+                return Err(SshError::TooManyAttempts);
+            }
+            match success {
+                Some(false) if method == "password" => thread::sleep(FAILED_AUTH_DELAY),
+                // A key query that was answered with PK_OK.
+                None if method == "publickey" => continue,
+                _ => {},
+            }
+
+            let mut failure = vec![MSG_USERAUTH_FAILURE];
+            failure.put_str(AUTH_METHODS);
+            failure.put_bool(false);
+            self.transport.send(&failure)?;
+        }
+    }
+
+    /// Returns `None` after answering a query for an acceptable key, and
+    /// whether the attempt succeeded otherwise.
+    fn public_key_auth(&mut self, user: &str, r: &mut Reader) -> Result<Option<bool>, SshError> {
+        let (signed, algorithm, key) = match (r.bool(), r.string(), r.bytes()) {
+            (Some(signed), Some(algorithm), Some(key)) => (signed, algorithm, key),
+            _ => return Err(malformed("publickey request")),
+        };
+        if !SIGNATURE_ALGORITHMS.contains(&algorithm.as_str()) || !self.handler.check_key(user, key) {
+            return Ok(Some(false));
+        }
+
+        if !signed {
+            let mut ok = vec![MSG_USERAUTH_PK_OK];
+            ok.put_str(&algorithm);
+            ok.put_bytes(key);
+            self.transport.send(&ok)?;
+            return Ok(None);
+        }
+
+        let signature = r.bytes().ok_or_else(|| malformed("publickey signature"))?;
+        let mut message = Vec::new();
+        message.put_bytes(self.session_id.as_deref().unwrap_or_default());
+        message.put_u8(MSG_USERAUTH_REQUEST);
+        message.put_str(user);
+        message.put_str("ssh-connection");
+        message.put_str("publickey");
+        message.put_bool(true);
+        message.put_str(&algorithm);
+        message.put_bytes(key);
+
+        let success = verify_signature(&algorithm, key, &message, signature);
+        self.handler.auth_finished(user, "publickey", success);
+        Ok(Some(success))
+    }
+
+    fn serve_channels(&mut self, user: &str) -> Result<(), SshError> {
+        loop {
+            let payload = self.receive()?;
+            let mut r = Reader::new(&payload[1..]);
+            match payload[0] {
+                MSG_GLOBAL_REQUEST => {
+                    let _name = r.string();
+                    if r.bool() == Some(true) {
+                        self.transport.send(&[MSG_REQUEST_FAILURE])?;
+                    }
+                },
+                MSG_CHANNEL_OPEN => self.open_channel(&mut r)?,
+                MSG_CHANNEL_REQUEST => self.channel_request(user, &mut r)?,
+                MSG_CHANNEL_DATA => self.channel_data(&mut r, false)?,
+                MSG_CHANNEL_EXTENDED_DATA => self.channel_data(&mut r, true)?,
+                MSG_CHANNEL_WINDOW_ADJUST => {
+                    let (Some(id), Some(bytes)) = (r.u32(), r.u32()) else { return Err(malformed("WINDOW_ADJUST")) };
+                    let channel = self.channel(id)?;
+                    channel.remote_window = channel.remote_window.saturating_add(bytes);
+                },
+                MSG_CHANNEL_EOF => {
+                    let id = r.u32().ok_or_else(|| malformed("CHANNEL_EOF"))?;
+                    self.channel(id)?.eof_received = true;
+                },
+                MSG_CHANNEL_CLOSE => {
+                    let id = r.u32().ok_or_else(|| malformed("CHANNEL_CLOSE"))?;
+                    let channel = self.channels.remove(&id)
+                        .ok_or_else(|| SshError::Protocol(format!("unknown channel {}", id)))?;
+                    if !channel.closed {
+                        let mut close = vec![MSG_CHANNEL_CLOSE];
+                        close.put_u32(channel.remote_id);
+                        self.transport.send(&close)?;
+                    }
+                },
+                // Further authentication requests are ignored (RFC 4252 5.1).
+                MSG_USERAUTH_REQUEST => {},
+                _ => {
+                    let mut unimplemented = vec![MSG_UNIMPLEMENTED];
+                    unimplemented.put_u32(self.transport.recv_seq.wrapping_sub(1));
+                    self.transport.send(&unimplemented)?;
+                },
+            }
+            self.flush_channels()?;
+        }
+    }
+
+    fn channel(&mut self, id: u32) -> Result<&mut Channel, SshError> {
+        self.channels.get_mut(&id)
+            .ok_or_else(|| SshError::Protocol(format!("unknown channel {}", id)))
+    }
+
+    fn open_channel(&mut self, r: &mut Reader) -> Result<(), SshError> {
+        let (kind, remote_id, window, max_packet) = match (r.string(), r.u32(), r.u32(), r.u32()) {
+            (Some(kind), Some(id), Some(window), Some(max_packet)) => (kind, id, window, max_packet),
+            _ => return Err(malformed("CHANNEL_OPEN")),
+        };
+
+        let refusal = if kind != "session" {
+            Some((OPEN_UNKNOWN_CHANNEL_TYPE, "Only session channels are supported"))
+        } else if self.channels.len() >= MAX_CHANNELS {
+            Some((OPEN_ADMINISTRATIVELY_PROHIBITED, "Too many open channels"))
+        } else {
+            None
+        };
+        if let Some((reason, message)) = refusal {
+            let mut failure = vec![MSG_CHANNEL_OPEN_FAILURE];
+            failure.put_u32(remote_id);
+            failure.put_u32(reason);
+            failure.put_str(message);
+            failure.put_str("");
+            return self.transport.send(&failure);
+        }
+
+        let id = self.next_channel;
+        self.next_channel = self.next_channel.wrapping_add(1);
+        self.channels.insert(id, Channel {
+            remote_id,
+            remote_window: window,
+            remote_max_packet: max_packet.max(1),
+            local_window: LOCAL_WINDOW,
+            subsystem: None,
+            unprocessed: Vec::new(),
+            pending: Vec::new(),
+            eof_received: false,
+            eof_delivered: false,
+            exit_status: 0,
+            closed: false,
+        });
+
+        let mut confirmation = vec![MSG_CHANNEL_OPEN_CONFIRMATION];
+        confirmation.put_u32(remote_id);
+        confirmation.put_u32(id);
+        confirmation.put_u32(LOCAL_WINDOW);
+        confirmation.put_u32(LOCAL_MAX_PACKET);
+        self.transport.send(&confirmation)
+    }
+
+    fn channel_request(&mut self, user: &str, r: &mut Reader) -> Result<(), SshError> {
+        let (id, kind, want_reply) = match (r.u32(), r.string(), r.bool()) {
+            (Some(id), Some(kind), Some(want_reply)) => (id, kind, want_reply),
+            _ => return Err(malformed("CHANNEL_REQUEST")),
+        };
+
+        let accepted = match kind.as_str() {
+            "subsystem" if self.channel(id)?.subsystem.is_none() => {
+                let name = r.string().ok_or_else(|| malformed("subsystem request"))?;
+                match self.handler.subsystem(user, &name) {
+                    Some(subsystem) => {
+                        self.channel(id)?.subsystem = Some(subsystem);
+                        true
+                    },
+                    None => false,
+                }
+            },
+            "exec" if self.channel(id)?.subsystem.is_none() => {
+                let command = r.string().ok_or_else(|| malformed("exec request"))?;
+                match self.handler.exec(user, &command) {
+                    Some(process) => {
+                        self.channel(id)?.subsystem = Some(process);
+                        true
+                    },
+                    None => false,
+                }
+            },
+            _ => {
+                debug!("Refusing {} request on SSH channel {}", kind, id);
+                false
+            },
+        };
+
+        if want_reply {
+            let channel = self.channel(id)?;
+            let mut reply = vec![if accepted { MSG_CHANNEL_SUCCESS } else { MSG_CHANNEL_FAILURE }];
+            reply.put_u32(channel.remote_id);
+            self.transport.send(&reply)?;
+        }
+        Ok(())
+    }
+
+    fn channel_data(&mut self, r: &mut Reader, extended: bool) -> Result<(), SshError> {
+        let id = r.u32().ok_or_else(|| malformed("CHANNEL_DATA"))?;
+        if extended {
+            r.u32().ok_or_else(|| malformed("CHANNEL_EXTENDED_DATA"))?;
+        }
+        let data = r.bytes().ok_or_else(|| malformed("CHANNEL_DATA"))?;
+
+        let channel = self.channels.get_mut(&id)
+            .ok_or_else(|| SshError::Protocol(format!("unknown channel {}", id)))?;
+        channel.local_window = channel.local_window.checked_sub(data.len() as u32)
+            .ok_or_else(|| SshError::Protocol(format!("channel {} overran its window", id)))?;
+
+        // Processed in `flush_channels`, as fast as the client reads the
+        // replies. The window bounds what can wait here.
+        if !extended && !channel.closed {
+            channel.unprocessed.extend_from_slice(data);
+        }
+        Ok(())
+    }
+
+    /// Hands the subsystem its next piece of client data, or the EOF once
+    /// all data is in. Returns whether there was anything to hand over.
+    fn feed(transport: &mut Transport, id: u32, channel: &mut Channel) -> Result<bool, SshError> {
+        let result = if !channel.unprocessed.is_empty() {
+            let piece: Vec<u8> = channel.unprocessed.drain(..channel.unprocessed.len().min(FEED_SIZE)).collect();
+            match channel.subsystem.as_mut() {
+                Some(subsystem) => subsystem.data(&piece),
+                None => Err("no subsystem was started".to_string()),
+            }
+        } else if channel.eof_received && !channel.eof_delivered {
+            channel.eof_delivered = true;
+            if let Some(subsystem) = channel.subsystem.as_mut() {
+                let (output, status) = subsystem.eof();
+                channel.exit_status = status;
+                Ok(output)
+            } else {
+                Ok(Vec::new())
+            }
+        } else {
+            return Ok(false);
+        };
+
+        match result {
+            Ok(reply) => channel.pending.extend_from_slice(&reply),
+            Err(e) => {
+                warn!("Closing SSH channel {}: {}", id, e);
+                channel.subsystem = None;
+                channel.unprocessed.clear();
+                channel.closed = true;
+                for kind in [MSG_CHANNEL_EOF, MSG_CHANNEL_CLOSE] {
+                    let mut message = vec![kind];
+                    message.put_u32(channel.remote_id);
+                    transport.send(&message)?;
+                }
+            },
+        }
+        Ok(true)
+    }
+
+    /// Processes client data and sends its output as far as the clients'
+    /// windows allow, reopens windows once output has drained, and closes
+    /// channels the client has finished with once their output is out.
+    fn flush_channels(&mut self) -> Result<(), SshError> {
+        for (&id, channel) in self.channels.iter_mut() {
+            loop {
+                while !channel.pending.is_empty() && channel.remote_window > 0 && !channel.closed {
+                    let len = channel.pending.len()
+                        .min(channel.remote_window as usize)
+                        .min(channel.remote_max_packet as usize)
+                        .min(LOCAL_MAX_PACKET as usize);
+                    let mut message = Vec::with_capacity(len + 9);
+                    message.put_u8(MSG_CHANNEL_DATA);
+                    message.put_u32(channel.remote_id);
+                    message.put_bytes(&channel.pending[..len]);
+                    self.transport.send(&message)?;
+                    channel.pending.drain(..len);
+                    channel.remote_window -= len as u32;
+                }
+                if channel.closed || channel.pending.len() >= MAX_PENDING || !Self::feed(&mut self.transport, id, channel)? {
+                    break;
+                }
+            }
+
+            // A client that doesn't read gets no more window, so what it can
+            // make us queue stays bounded.
+            let drained = channel.unprocessed.is_empty() && channel.pending.len() < MAX_PENDING;
+            if drained && !channel.closed && channel.local_window < LOCAL_WINDOW / 2 {
+                let mut adjust = vec![MSG_CHANNEL_WINDOW_ADJUST];
+                adjust.put_u32(channel.remote_id);
+                adjust.put_u32(LOCAL_WINDOW - channel.local_window);
+                self.transport.send(&adjust)?;
+                channel.local_window = LOCAL_WINDOW;
+            }
+
+            if channel.eof_delivered && channel.pending.is_empty() && !channel.closed {
+                channel.subsystem = None;
+                channel.closed = true;
+                let mut exit_status = vec![MSG_CHANNEL_REQUEST];
+                exit_status.put_u32(channel.remote_id);
+                exit_status.put_str("exit-status");
+                exit_status.put_bool(false);
+                exit_status.put_u32(channel.exit_status);
+                self.transport.send(&exit_status)?;
+                for kind in [MSG_CHANNEL_EOF, MSG_CHANNEL_CLOSE] {
+                    let mut message = vec![kind];
+                    message.put_u32(channel.remote_id);
+                    self.transport.send(&message)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread::JoinHandle;
+    use std::time::Instant;
+
+    const USER: &str = "alice";
+    const PASSWORD: &str = "correct horse";
+
+    struct PasswordHandler;
+
+    impl SshHandler for PasswordHandler {
+        fn check_password(&mut self, user: &str, password: &str) -> bool {
+            user == USER && password == PASSWORD
+        }
+
+        fn check_key(&mut self, _user: &str, _key: &[u8]) -> bool {
+            false
+        }
+
+        fn auth_finished(&mut self, _user: &str, _method: &str, _success: bool) {}
+
+        fn subsystem(&mut self, _user: &str, _name: &str) -> Option<Box<dyn Subsystem>> {
+            None
+        }
+    }
+
+    /// Serves one connection on a free local port.
+    fn serve_once() -> (u16, JoinHandle<Result<(), SshError>>) {
+        serve_once_within(HANDSHAKE_TIMEOUT)
+    }
+
+    fn serve_once_within(deadline: Duration) -> (u16, JoinHandle<Result<(), SshError>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = thread::spawn(move || {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+            let host_key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+            let (stream, _) = listener.accept()?;
+            serve_with_deadline(stream, &host_key, PasswordHandler, deadline)
+        });
+        (port, server)
+    }
+
+    /// Writes an unencrypted packet, as sent before the first key exchange
+    /// completes.
+    fn send_plain(stream: &mut TcpStream, payload: &[u8]) {
+        let padding = 8 - (payload.len() + 5) % 8 + 4;
+        let mut packet = Vec::new();
+        packet.put_u32((1 + payload.len() + padding) as u32);
+        packet.put_u8(padding as u8);
+        packet.extend_from_slice(payload);
+        packet.extend(std::iter::repeat(0).take(padding));
+        stream.write_all(&packet).unwrap();
+    }
+
+    fn receive_plain(stream: &mut TcpStream) -> Vec<u8> {
+        let mut length = [0u8; 4];
+        stream.read_exact(&mut length).unwrap();
+        let mut packet = vec![0u8; u32::from_be_bytes(length) as usize];
+        stream.read_exact(&mut packet).unwrap();
+        packet[1..packet.len() - packet[0] as usize].to_vec()
+    }
+
+    /// Opens a raw connection and sends a KEXINIT offering `kex` followed by
+    /// an IGNORE message.
+    fn kexinit_then_ignore(port: u16, kex: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+        stream.write_all(b"SSH-2.0-Test_1.0\r\n").unwrap();
+
+        let mut version = Vec::new();
+        let mut byte = [0u8; 1];
+        while byte[0] != b'\n' {
+            stream.read_exact(&mut byte).unwrap();
+            version.push(byte[0]);
+        }
+        assert!(version.starts_with(SERVER_VERSION.as_bytes()));
+        assert_eq!(receive_plain(&mut stream)[0], MSG_KEXINIT);
+
+        let ciphers = Cipher::ALL.map(Cipher::name).join(",");
+        let mut kexinit = vec![MSG_KEXINIT];
+        kexinit.extend_from_slice(&[7u8; 16]);
+        for list in [kex, HOST_KEY_ALGORITHM, &ciphers, &ciphers, "", "", "none", "none", "", ""] {
+            kexinit.put_str(list);
+        }
+        kexinit.put_bool(false);
+        kexinit.put_u32(0);
+        send_plain(&mut stream, &kexinit);
+
+        let mut ignore = vec![MSG_IGNORE];
+        ignore.put_str("padding");
+        send_plain(&mut stream, &ignore);
+        stream
+    }
+
+    #[test]
+    fn strict_kex_refuses_other_messages_during_the_first_exchange() {
+        let (port, server) = serve_once();
+        let mut stream = kexinit_then_ignore(port, &format!("curve25519-sha256,{}", STRICT_KEX_CLIENT));
+
+        let disconnect = receive_plain(&mut stream);
+        assert_eq!(disconnect[0], MSG_DISCONNECT);
+        assert_eq!(Reader::new(&disconnect[1..]).u32(), Some(DISCONNECT_PROTOCOL_ERROR));
+        assert!(matches!(server.join().unwrap(), Err(SshError::KeyExchange(_))));
+    }
+
+    #[test]
+    fn kex_without_strict_mode_skips_ignore_messages() {
+        let (port, server) = serve_once();
+        let stream = kexinit_then_ignore(port, "curve25519-sha256");
+
+        // The server is still waiting for KEX_ECDH_INIT when the client hangs
+        // up, rather than failing the exchange.
+        drop(stream);
+        assert!(matches!(server.join().unwrap(), Err(SshError::Io(_))));
+    }
+
+    #[test]
+    fn connections_end_after_too_many_failed_logins() {
+        let (port, server) = serve_once();
+        let mut session = ssh2::Session::new().unwrap();
+        session.set_tcp_stream(TcpStream::connect(("127.0.0.1", port)).unwrap());
+        session.set_timeout(10_000);
+        session.handshake().unwrap();
+
+        for _ in 0..MAX_AUTH_ATTEMPTS {
+            assert!(session.userauth_password(USER, "wrong password").is_err());
+        }
+        assert!(session.userauth_password(USER, PASSWORD).is_err());
+        assert!(!session.authenticated());
+        assert!(matches!(server.join().unwrap(), Err(SshError::TooManyAttempts)));
+    }
+
+    #[test]
+    fn the_right_password_logs_in() {
+        let (port, server) = serve_once();
+        let mut session = ssh2::Session::new().unwrap();
+        session.set_tcp_stream(TcpStream::connect(("127.0.0.1", port)).unwrap());
+        session.set_timeout(10_000);
+        session.handshake().unwrap();
+
+        assert!(session.userauth_password(USER, "wrong password").is_err());
+        session.userauth_password(USER, PASSWORD).unwrap();
+        assert!(session.authenticated());
+        session.disconnect(None, "done", None).unwrap();
+        drop(session);
+        server.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn unsupported_methods_count_toward_the_limit() {
+        let (port, server) = serve_once();
+        let mut session = ssh2::Session::new().unwrap();
+        session.set_tcp_stream(TcpStream::connect(("127.0.0.1", port)).unwrap());
+        session.set_timeout(10_000);
+        session.handshake().unwrap();
+
+        // Each listing is a "none" request.
+        for _ in 0..MAX_AUTH_ATTEMPTS {
+            let _ = session.auth_methods(USER);
+        }
+        assert!(session.userauth_password(USER, PASSWORD).is_err());
+        assert!(matches!(server.join().unwrap(), Err(SshError::TooManyAttempts)));
+    }
+
+    #[test]
+    fn clients_that_never_authenticate_are_disconnected_at_the_deadline() {
+        let (port, server) = serve_once_within(Duration::from_secs(1));
+        let started = Instant::now();
+        let mut stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        // Sends part of a version line and then nothing, well within the
+        // per-read timeout.
+        stream.write_all(b"SSH-2.0-").unwrap();
+
+        assert!(matches!(server.join().unwrap(), Err(SshError::Io(_))));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    /// Replies to every byte with `FLOOD_FACTOR` bytes, counting its input.
+    struct Flood(Arc<AtomicUsize>);
+
+    const FLOOD_FACTOR: usize = 64;
+
+    impl Subsystem for Flood {
+        fn data(&mut self, data: &[u8]) -> Result<Vec<u8>, String> {
+            self.0.fetch_add(data.len(), Ordering::SeqCst);
+            Ok(vec![b'x'; data.len() * FLOOD_FACTOR])
+        }
+    }
+
+    struct FloodHandler(Arc<AtomicUsize>);
+
+    impl SshHandler for FloodHandler {
+        fn check_password(&mut self, user: &str, password: &str) -> bool {
+            user == USER && password == PASSWORD
+        }
+
+        fn check_key(&mut self, _user: &str, _key: &[u8]) -> bool {
+            false
+        }
+
+        fn auth_finished(&mut self, _user: &str, _method: &str, _success: bool) {}
+
+        fn subsystem(&mut self, _user: &str, _name: &str) -> Option<Box<dyn Subsystem>> {
+            Some(Box::new(Flood(self.0.clone())))
+        }
+    }
+
+    #[test]
+    fn clients_that_stop_reading_stop_getting_window() {
+        let processed = Arc::new(AtomicUsize::new(0));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let handler = FloodHandler(processed.clone());
+        thread::spawn(move || {
+            let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+            let host_key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            let _ = serve_connection(stream, &host_key, handler);
+        });
+
+        let mut session = ssh2::Session::new().unwrap();
+        session.set_tcp_stream(TcpStream::connect(("127.0.0.1", port)).unwrap());
+        session.set_timeout(10_000);
+        session.handshake().unwrap();
+        session.userauth_password(USER, PASSWORD).unwrap();
+        let mut channel = session.channel_session().unwrap();
+        channel.subsystem("flood").unwrap();
+
+        // Without backpressure all of this would be taken in, queueing
+        // 512 MiB of replies.
+        session.set_timeout(2_000);
+        let block = vec![0u8; 64 * 1024];
+        let mut written = 0;
+        while written < 8 * 1024 * 1024 {
+            match channel.write(&block) {
+                Ok(n) => written += n,
+                Err(_) => break,
+            }
+        }
+        assert!(written <= LOCAL_WINDOW as usize * 2, "wrote {} bytes", written);
+        let queued = processed.load(Ordering::SeqCst) * FLOOD_FACTOR;
+        assert!(queued < MAX_PENDING + FEED_SIZE * FLOOD_FACTOR + 4 * 1024 * 1024, "queued {} bytes", queued);
+
+        // Reading lets the rest through.
+        session.set_timeout(10_000);
+        let before = processed.load(Ordering::SeqCst);
+        let mut output = vec![0u8; 64 * 1024];
+        let mut read = 0;
+        while read < 4 * 1024 * 1024 {
+            read += channel.read(&mut output).unwrap();
+        }
+        assert!(processed.load(Ordering::SeqCst) > before);
+    }
+}
@@ -0,0 +1,472 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+#[cfg(target_os = "windows")]
+use std::os::windows::process::CommandExt;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt as UnixCommandExt;
+
+pub const SUPERVISE_FLAG: &str = "--supervise";
+
+/// What the supervisor should launch. Written next to the state file and
+/// handed to the supervisor process by path.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct LaunchSpec {
+    pub server_id: String,
+    pub program: String,
+    pub args: Vec<String>,
+    pub working_dir: String,
+}
+
+/// Written by a live supervisor so a restarted app can find and reattach to it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SupervisorState {
+    pub server_id: String,
+    pub supervisor_pid: u32,
+    pub pid: u32,
+    pub control_port: u16,
+    pub control_token: String,
+    pub console_log: String,
+    pub started_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum ControlOp {
+    Ping,
+    Command { line: String },
+    Stop,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ControlRequest {
+    token: String,
+    #[serde(flatten)]
+    op: ControlOp,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ControlResponse {
+    ok: bool,
+    error: Option<String>,
+}
+
+pub fn run_dir() -> PathBuf {
+    let app_data_dir = std::env::var("APPDATA")
+        .unwrap_or_else(|_| std::env::var("HOME").unwrap_or_else(|_| ".".to_string()));
+    PathBuf::from(format!("{}/ServerMint/run", app_data_dir))
+}
+
+fn state_path(server_id: &str) -> PathBuf {
+    run_dir().join(format!("{}.json", server_id))
+}
+
+fn spec_path(server_id: &str) -> PathBuf {
+    run_dir().join(format!("{}.spec.json", server_id))
+}
+
+pub fn console_log_path(server_id: &str) -> PathBuf {
+    run_dir().join(format!("{}.log", server_id))
+}
+
+/// The state holds the control token, so these files are created readable
+/// by their owner only.
+fn write_json_atomic<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(value)
+        .map_err(|e| format!("Failed to serialize {}: {}", path.display(), e))?;
+    let tmp_path = path.with_extension("json.tmp");
+    // A leftover file would keep its permissions, so start from a new one.
+    let _ = fs::remove_file(&tmp_path);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(&tmp_path)
+        .and_then(|mut file| file.write_all(json.as_bytes()))
+        .map_err(|e| format!("Failed to write {}: {}", tmp_path.display(), e))?;
+    fs::rename(&tmp_path, path)
+        .map_err(|e| format!("Failed to replace {}: {}", path.display(), e))
+}
+
+pub fn read_state(server_id: &str) -> Option<SupervisorState> {
+    let contents = fs::read_to_string(state_path(server_id)).ok()?;
+    serde_json::from_str(&contents).ok()
+}
+
+/// Returns the supervisor state for `server_id` if its supervisor is still
+/// answering on its control socket. Stale state files are removed.
+pub fn find_running(server_id: &str) -> Option<SupervisorState> {
+    let state = read_state(server_id)?;
+    match send_request(&state, ControlOp::Ping) {
+        Ok(_) => Some(state),
+        Err(e) => {
+            println!("Removing stale supervisor state for {}: {}", server_id, e);
+            let _ = fs::remove_file(state_path(server_id));
+            None
+        }
+    }
+}
+
+fn send_request(state: &SupervisorState, op: ControlOp) -> Result<(), String> {
+    let mut stream = TcpStream::connect_timeout(
+        &([127, 0, 0, 1], state.control_port).into(),
+        Duration::from_secs(2),
+    ).map_err(|e| format!("Failed to connect to supervisor: {}", e))?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))
+        .map_err(|e| format!("Failed to configure supervisor connection: {}", e))?;
+
+    let request = ControlRequest { token: state.control_token.clone(), op };
+    let mut line = serde_json::to_string(&request)
+        .map_err(|e| format!("Failed to serialize supervisor request: {}", e))?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+        .map_err(|e| format!("Failed to send supervisor request: {}", e))?;
+
+    let mut reply = String::new();
+    BufReader::new(&stream).read_line(&mut reply)
+        .map_err(|e| format!("Failed to read supervisor reply: {}", e))?;
+    let response: ControlResponse = serde_json::from_str(&reply)
+        .map_err(|e| format!("Invalid supervisor reply: {}", e))?;
+
+    if response.ok {
+        Ok(())
+    } else {
+        Err(response.error.unwrap_or_else(|| "Supervisor rejected request".to_string()))
+    }
+}
+
+/// Handle held by `ServerManager` for a server running under a supervisor.
+/// Console output is followed from the supervisor's log file, so the same
+/// handle works for freshly launched and reattached servers.
+#[derive(Debug)]
+pub struct SupervisorHandle {
+    pub state: SupervisorState,
+    tail_stop: Arc<AtomicBool>,
+}
+
+impl SupervisorHandle {
+    pub fn launch(spec: &LaunchSpec) -> Result<SupervisorState, String> {
+        let dir = run_dir();
+        fs::create_dir_all(&dir)
+            .map_err(|e| format!("Failed to create run directory: {}", e))?;
+
+        let _ = fs::remove_file(state_path(&spec.server_id));
+        write_json_atomic(&spec_path(&spec.server_id), spec)?;
+
+        let exe = std::env::current_exe()
+            .map_err(|e| format!("Failed to locate ServerMint executable: {}", e))?;
+
+        let mut command = Command::new(exe);
+        command.arg(SUPERVISE_FLAG);
+        command.arg(spec_path(&spec.server_id));
+        command.stdin(Stdio::null());
+        command.stdout(Stdio::null());
+        command.stderr(Stdio::null());
+
+        // Detach from our console/process group so the server outlives the app.
+        #[cfg(target_os = "windows")]
+        command.creation_flags(0x08000000 | 0x00000200);
+        #[cfg(unix)]
+        command.process_group(0);
+
+        let mut supervisor = command.spawn()
+            .map_err(|e| format!("Failed to start supervisor: {}", e))?;
+        let supervisor_pid = supervisor.id();
+
+        let deadline = Instant::now() + Duration::from_secs(15);
+        loop {
+            if let Some(state) = read_state(&spec.server_id) {
+                if state.supervisor_pid == supervisor_pid {
+                    // Reap the supervisor when it eventually exits.
+                    thread::spawn(move || {
+                        let _ = supervisor.wait();
+                    });
+                    return Ok(state);
+                }
+            }
+
+            if let Ok(Some(status)) = supervisor.try_wait() {
+                let log = fs::read_to_string(console_log_path(&spec.server_id)).unwrap_or_default();
+                return Err(format!("Supervisor exited with {} before the server started: {}", status, log.trim()));
+            }
+
+            if Instant::now() > deadline {
+                let _ = supervisor.kill();
+                return Err("Timed out waiting for supervisor to start".to_string());
+            }
+
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    pub fn attach(state: SupervisorState, output: Arc<Mutex<Vec<String>>>) -> Self {
+        let tail_stop = Arc::new(AtomicBool::new(false));
+        let stop = tail_stop.clone();
+        let log_path = PathBuf::from(&state.console_log);
+        let server_id = state.server_id.clone();
+
+        thread::spawn(move || {
+            let file = match File::open(&log_path) {
+                Ok(file) => file,
+                Err(e) => {
+                    println!("Failed to open console log for {}: {}", server_id, e);
+                    return;
+                }
+            };
+
+            let mut reader = BufReader::new(file);
+            let mut pending = String::new();
+            loop {
+                match reader.read_line(&mut pending) {
+                    Ok(0) | Err(_) => {
+                        if stop.load(Ordering::Relaxed) || !state_path(&server_id).exists() {
+                            break;
+                        }
+                        thread::sleep(Duration::from_millis(250));
+                    },
+                    Ok(_) => {
+                        if pending.ends_with('\n') {
+                            let line = pending.trim_end_matches(['\r', '\n']).to_string();
+                            pending.clear();
+                            if let Ok(mut output) = output.lock() {
+                                output.push(line);
+                            }
+                        }
+                    },
+                }
+            }
+        });
+
+        SupervisorHandle { state, tail_stop }
+    }
+
+    pub fn is_alive(&self) -> bool {
+        read_state(&self.state.server_id)
+            .map(|state| state.supervisor_pid == self.state.supervisor_pid)
+            .unwrap_or(false)
+    }
+
+    pub fn send_command(&self, line: &str) -> Result<(), String> {
+        send_request(&self.state, ControlOp::Command { line: line.to_string() })
+    }
+
+    pub fn stop(&self) -> Result<(), String> {
+        let result = send_request(&self.state, ControlOp::Stop);
+        self.tail_stop.store(true, Ordering::Relaxed);
+        result
+    }
+}
+
+impl Drop for SupervisorHandle {
+    fn drop(&mut self) {
+        self.tail_stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Returns the launch spec path when this process was started as a supervisor.
+pub fn spec_from_args() -> Option<PathBuf> {
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some(SUPERVISE_FLAG) {
+        args.next().map(PathBuf::from)
+    } else {
+        None
+    }
+}
+
+/// Entry point of the supervisor process. Owns the server's `Child`, appends
+/// its output to the console log and serves the control socket until the
+/// server exits.
+/// Copies the server's output to the console log line by line until the
+/// pipe closes. Lines that aren't UTF-8 are copied lossily rather than
+/// ending the copy, which would leave the pipe full and the server blocked.
+fn copy_lines(mut reader: impl BufRead, log: &Mutex<impl Write>) {
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => return,
+            Ok(_) => {},
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => return,
+        }
+        let text = String::from_utf8_lossy(&line);
+        if let Ok(mut log) = log.lock() {
+            let _ = writeln!(log, "{}", text.trim_end_matches(['\n', '\r']));
+            let _ = log.flush();
+        }
+    }
+}
+
+pub fn run(spec_file: &Path) -> Result<(), String> {
+    let contents = fs::read_to_string(spec_file)
+        .map_err(|e| format!("Failed to read launch spec: {}", e))?;
+    let spec: LaunchSpec = serde_json::from_str(&contents)
+        .map_err(|e| format!("Failed to parse launch spec: {}", e))?;
+    let _ = fs::remove_file(spec_file);
+
+    let log_path = console_log_path(&spec.server_id);
+    let log = Arc::new(Mutex::new(
+        File::create(&log_path).map_err(|e| format!("Failed to create console log: {}", e))?,
+    ));
+
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .map_err(|e| format!("Failed to bind control socket: {}", e))?;
+    let control_port = listener.local_addr()
+        .map_err(|e| format!("Failed to read control socket address: {}", e))?
+        .port();
+
+    let mut command = Command::new(&spec.program);
+    command.args(&spec.args);
+    command.current_dir(&spec.working_dir);
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+    command.stdin(Stdio::piped());
+
+    #[cfg(target_os = "windows")]
+    command.creation_flags(0x08000000);
+
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            if let Ok(mut log) = log.lock() {
+                let _ = writeln!(log, "[ServerMint] Failed to start {}: {}", spec.program, e);
+            }
+            return Err(format!("Failed to start server process: {}", e));
+        }
+    };
+
+    let stdout = child.stdout.take().expect("Failed to capture stdout");
+    let stderr = child.stderr.take().expect("Failed to capture stderr");
+    let stdin = Arc::new(Mutex::new(child.stdin.take().expect("Failed to capture stdin")));
+
+    for stream in [Box::new(stdout) as Box<dyn std::io::Read + Send>, Box::new(stderr)] {
+        let log = log.clone();
+        thread::spawn(move || copy_lines(BufReader::new(stream), &log));
+    }
+
+    let state = SupervisorState {
+        server_id: spec.server_id.clone(),
+        supervisor_pid: std::process::id(),
+        pid: child.id(),
+        control_port,
+        control_token: Uuid::new_v4().simple().to_string(),
+        console_log: log_path.to_string_lossy().to_string(),
+        started_at: Utc::now(),
+    };
+    write_json_atomic(&state_path(&spec.server_id), &state)?;
+
+    let child = Arc::new(Mutex::new(child));
+    {
+        let child = child.clone();
+        let token = state.control_token.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                handle_control_connection(stream, &token, &child, &stdin);
+            }
+        });
+    }
+
+    let status = loop {
+        if let Ok(mut child) = child.lock() {
+            match child.try_wait() {
+                Ok(Some(status)) => break status.to_string(),
+                Ok(None) => {},
+                Err(e) => break format!("unknown status ({})", e),
+            }
+        }
+        thread::sleep(Duration::from_millis(500));
+    };
+
+    if let Ok(mut log) = log.lock() {
+        let _ = writeln!(log, "[ServerMint] Server process exited with {}", status);
+    }
+    // A restart may already have launched a new supervisor for this server;
+    // only clear the state file if it is still ours.
+    if read_state(&spec.server_id).map(|s| s.supervisor_pid) == Some(state.supervisor_pid) {
+        let _ = fs::remove_file(state_path(&spec.server_id));
+    }
+    Ok(())
+}
+
+fn handle_control_connection(
+    stream: TcpStream,
+    token: &str,
+    child: &Arc<Mutex<Child>>,
+    stdin: &Arc<Mutex<ChildStdin>>,
+) {
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+    let mut line = String::new();
+    if BufReader::new(&stream).read_line(&mut line).is_err() {
+        return;
+    }
+
+    let result = match serde_json::from_str::<ControlRequest>(&line) {
+        Err(e) => Err(format!("Invalid request: {}", e)),
+        Ok(request) if request.token != token => Err("Invalid control token".to_string()),
+        Ok(request) => match request.op {
+            ControlOp::Ping => Ok(()),
+            ControlOp::Command { line } => match stdin.lock() {
+                Ok(mut stdin) => stdin.write_all(format!("{}\n", line).as_bytes())
+                    .and_then(|_| stdin.flush())
+                    .map_err(|e| format!("Failed to send command: {}", e)),
+                Err(_) => Err("Failed to lock server stdin".to_string()),
+            },
+            ControlOp::Stop => match child.lock() {
+                Ok(mut child) => child.kill().map_err(|e| format!("Failed to stop server: {}", e)),
+                Err(_) => Err("Failed to lock server process".to_string()),
+            },
+        },
+    };
+
+    let response = match result {
+        Ok(()) => ControlResponse { ok: true, error: None },
+        Err(e) => ControlResponse { ok: false, error: Some(e) },
+    };
+    if let Ok(mut reply) = serde_json::to_string(&response) {
+        reply.push('\n');
+        let mut stream = stream;
+        let _ = stream.write_all(reply.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_that_is_not_utf8_is_copied_lossily() {
+        let output: &[u8] = b"Starting server\n\xff\xfe broken \xc3\r\nDone (1.2s)!\npartial";
+        let log = Mutex::new(Vec::new());
+        copy_lines(output, &log);
+        assert_eq!(
+            String::from_utf8(log.into_inner().unwrap()).unwrap(),
+            "Starting server\n\u{fffd}\u{fffd} broken \u{fffd}\nDone (1.2s)!\npartial\n"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn state_files_are_readable_by_their_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = crate::test_support::scratch_dir("supervisor-state").join("server-1.json");
+        fs::write(path.with_extension("json.tmp"), "stale").unwrap();
+        fs::set_permissions(path.with_extension("json.tmp"), fs::Permissions::from_mode(0o644)).unwrap();
+
+        write_json_atomic(&path, &serde_json::json!({ "control_token": "secret" })).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(fs::read_to_string(&path).unwrap().contains("secret"));
+    }
+}
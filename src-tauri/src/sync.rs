@@ -0,0 +1,602 @@
+//! One-way mirroring between a server directory and a remote SFTP path.
+//!
+//! Both trees are scanned and compared by size and modification time, or by
+//! SHA-256 when asked, and only the differences go through the transfer
+//! queue. Transfers copy modification times across, so an unchanged file
+//! compares equal on the next run. Leftover `.part` files are ignored on both
+//! sides so an interrupted mirror can still resume.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use log::info;
+use ssh2::Sftp;
+use tauri::{AppHandle, State};
+use tokio::task;
+
+use crate::server::ServerManager;
+use crate::sftp::{SftpConfig, SftpError};
+use crate::transfer::{self, FileItem, Plan, TransferDirection, TransferProgress, PARTIAL_SUFFIX};
+
+/// Modification times closer than this count as equal, to absorb
+/// filesystems that store them coarsely.
+const MTIME_TOLERANCE_SECS: u64 = 2;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SyncOptions {
+    /// `upload` mirrors the server to the remote path, `download` the reverse.
+    pub direction: TransferDirection,
+    pub remote_path: String,
+    /// Deletes destination files that are not in the source.
+    #[serde(default)]
+    pub delete_extraneous: bool,
+    /// Globs such as `logs`, `*.log` or `world/region`. Patterns without a
+    /// `/` match names at any depth; an excluded directory is skipped whole
+    /// and never deleted at the destination.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Compares contents instead of modification times. Reads every
+    /// same-sized file in full on both sides.
+    #[serde(default)]
+    pub compare_hashes: bool,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncChange {
+    Create,
+    Update,
+    Delete,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncAction {
+    pub change: SyncChange,
+    /// Relative to the synced directories, `/`-separated.
+    pub path: String,
+    pub directory: bool,
+    pub size: u64,
+    pub reason: &'static str,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncReport {
+    pub direction: TransferDirection,
+    pub local_path: String,
+    pub remote_path: String,
+    pub dry_run: bool,
+    pub actions: Vec<SyncAction>,
+    pub files_compared: u64,
+    pub bytes_to_transfer: u64,
+    pub transfer: Option<TransferProgress>,
+}
+
+#[derive(Debug, Clone)]
+struct TreeEntry {
+    directory: bool,
+    size: u64,
+    modified: Option<u64>,
+}
+
+/// Entries by relative path. Sorted, so parents come before children.
+type Tree = BTreeMap<String, TreeEntry>;
+
+/// Matches a `/`-separated path against a glob where `*` and `?` stay
+/// within one segment and `**` spans any number of them.
+fn glob_match(pattern: &[u8], path: &[u8]) -> bool {
+    match (pattern.first(), path.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) if pattern.starts_with(b"**") => {
+            let rest = &pattern[2..];
+            match rest.strip_prefix(b"/") {
+                Some(rest) => (0..=path.len())
+                    .filter(|&i| i == 0 || path[i - 1] == b'/')
+                    .any(|i| glob_match(rest, &path[i..])),
+                None => (0..=path.len()).any(|i| glob_match(rest, &path[i..])),
+            }
+        },
+        (Some(b'*'), _) => (0..=path.len())
+            .take_while(|&i| !path[..i].contains(&b'/'))
+            .any(|i| glob_match(&pattern[1..], &path[i..])),
+        (Some(b'?'), Some(c)) if *c != b'/' => glob_match(&pattern[1..], &path[1..]),
+        (Some(p), Some(c)) if p == c => glob_match(&pattern[1..], &path[1..]),
+        _ => false,
+    }
+}
+
+pub(crate) fn is_excluded(exclude: &[String], relative: &str) -> bool {
+    let name = relative.rsplit('/').next().unwrap_or(relative);
+    exclude.iter().any(|pattern| {
+        let pattern = pattern.trim().trim_matches('/');
+        if pattern.is_empty() {
+            false
+        } else if pattern.contains('/') {
+            glob_match(pattern.as_bytes(), relative.as_bytes())
+        } else {
+            glob_match(pattern.as_bytes(), name.as_bytes())
+        }
+    })
+}
+
+fn join_relative(parent: &str, name: &str) -> String {
+    if parent.is_empty() { name.to_string() } else { format!("{}/{}", parent, name) }
+}
+
+fn remote_path(root: &str, relative: &str) -> String {
+    format!("{}/{}", root.trim_end_matches('/'), relative)
+}
+
+/// Scans the local tree. A missing root is an empty tree unless `required`,
+/// as it must be for the source of a sync.
+fn scan_local(root: &Path, exclude: &[String], required: bool) -> Result<Tree, SftpError> {
+    fn walk(dir: &Path, relative: &str, exclude: &[String], tree: &mut Tree) -> Result<(), SftpError> {
+        let local_error = |source| SftpError::Local { path: dir.display().to_string(), source };
+        for entry in fs::read_dir(dir).map_err(local_error)? {
+            let entry = entry.map_err(local_error)?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = join_relative(relative, &name);
+            let metadata = entry.metadata().map_err(local_error)?;
+            if name.ends_with(PARTIAL_SUFFIX) || is_excluded(exclude, &path) {
+                continue;
+            }
+            if metadata.is_dir() {
+                tree.insert(path.clone(), TreeEntry { directory: true, size: 0, modified: None });
+                walk(&entry.path(), &path, exclude, tree)?;
+            } else if metadata.is_file() {
+                tree.insert(path, TreeEntry {
+                    directory: false,
+                    size: metadata.len(),
+                    modified: transfer::unix_time(&metadata),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    let mut tree = Tree::new();
+    match fs::metadata(root) {
+        Ok(metadata) if metadata.is_dir() => walk(root, "", exclude, &mut tree)?,
+        Ok(_) => return Err(SftpError::InvalidCommand(format!("{} is not a directory", root.display()))),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {},
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Err(SftpError::NotFound(root.display().to_string())),
+        Err(source) => return Err(SftpError::Local { path: root.display().to_string(), source }),
+    }
+    Ok(tree)
+}
+
+/// Scans the remote tree, with a missing root handled as in `scan_local`.
+fn scan_remote(sftp: &Sftp, root: &str, exclude: &[String], required: bool) -> Result<Tree, SftpError> {
+    fn walk(sftp: &Sftp, dir: &str, relative: &str, exclude: &[String], tree: &mut Tree) -> Result<(), SftpError> {
+        for (entry, stat) in sftp.readdir(Path::new(dir)).map_err(|e| SftpError::remote(dir, e))? {
+            let Some(name) = entry.file_name().map(|n| n.to_string_lossy().into_owned()) else {
+                continue;
+            };
+            let path = join_relative(relative, &name);
+            if name == "." || name == ".." || name.ends_with(PARTIAL_SUFFIX) || is_excluded(exclude, &path) {
+                continue;
+            }
+            if stat.is_dir() {
+                tree.insert(path.clone(), TreeEntry { directory: true, size: 0, modified: None });
+                walk(sftp, &format!("{}/{}", dir.trim_end_matches('/'), name), &path, exclude, tree)?;
+            } else if stat.is_file() {
+                tree.insert(path, TreeEntry { directory: false, size: stat.size.unwrap_or(0), modified: stat.mtime });
+            }
+        }
+        Ok(())
+    }
+
+    let mut tree = Tree::new();
+    match sftp.stat(Path::new(root)) {
+        Ok(stat) if stat.is_dir() => walk(sftp, root, "", exclude, &mut tree)?,
+        Ok(_) => return Err(SftpError::InvalidCommand(format!("{} is not a directory", root))),
+        Err(e) => match SftpError::remote(root, e) {
+            SftpError::NotFound(_) if !required => {},
+            e => return Err(e),
+        },
+    }
+    Ok(tree)
+}
+
+fn hash_reader(mut reader: impl Read) -> io::Result<[u8; 32]> {
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let read = reader.read(&mut buffer)?;
+        if read == 0 {
+            return Ok(hasher.finalize().into());
+        }
+        hasher.update(&buffer[..read]);
+    }
+}
+
+fn hash_local(path: &Path) -> Result<[u8; 32], SftpError> {
+    fs::File::open(path)
+        .and_then(hash_reader)
+        .map_err(|source| SftpError::Local { path: path.display().to_string(), source })
+}
+
+fn hash_remote(config: &SftpConfig, path: &str) -> Result<[u8; 32], SftpError> {
+    config.with_sftp(|sftp| {
+        let file = sftp.open(Path::new(path)).map_err(|e| SftpError::remote(path, e))?;
+        hash_reader(file).map_err(|source| SftpError::Transfer { path: path.to_string(), source })
+    })
+}
+
+/// Everything a sync would change, with source and destination trees.
+struct SyncPlan {
+    actions: Vec<SyncAction>,
+    files_compared: u64,
+    source: Tree,
+}
+
+fn plan_sync(config: &SftpConfig, local_root: &Path, remote_root: &str, options: &SyncOptions) -> Result<SyncPlan, SftpError> {
+    // A source that doesn't exist must not read as empty, or deleting
+    // extraneous files would empty the destination.
+    let upload = options.direction == TransferDirection::Upload;
+    let local = scan_local(local_root, &options.exclude, upload)?;
+    let remote = config.with_sftp(|sftp| scan_remote(sftp, remote_root, &options.exclude, !upload))?;
+    let (source, destination) = match options.direction {
+        TransferDirection::Upload => (local, remote),
+        TransferDirection::Download => (remote, local),
+    };
+
+    let (actions, files_compared) = diff_trees(&source, &destination, options, |path| {
+        let local_hash = hash_local(&local_root.join(path))?;
+        let remote_hash = hash_remote(config, &remote_path(remote_root, path))?;
+        Ok(local_hash != remote_hash)
+    })?;
+    Ok(SyncPlan { actions, files_compared, source })
+}
+
+/// The changes that make `destination` match `source`, and how many files
+/// were compared. `content_differs` compares a same-sized file's contents
+/// when `compare_hashes` is set.
+fn diff_trees(
+    source: &Tree,
+    destination: &Tree,
+    options: &SyncOptions,
+    mut content_differs: impl FnMut(&str) -> Result<bool, SftpError>,
+) -> Result<(Vec<SyncAction>, u64), SftpError> {
+    let mut actions = Vec::new();
+    let mut files_compared = 0;
+    for (path, entry) in source {
+        let change = match destination.get(path) {
+            None => Some((SyncChange::Create, "new")),
+            Some(existing) if existing.directory != entry.directory => Some((SyncChange::Update, "type changed")),
+            Some(_) if entry.directory => None,
+            Some(existing) => {
+                files_compared += 1;
+                if existing.size != entry.size {
+                    Some((SyncChange::Update, "size changed"))
+                } else if options.compare_hashes {
+                    content_differs(path)?.then_some((SyncChange::Update, "content changed"))
+                } else {
+                    let differs = match (entry.modified, existing.modified) {
+                        (Some(a), Some(b)) => a.abs_diff(b) > MTIME_TOLERANCE_SECS,
+                        _ => true,
+                    };
+                    differs.then_some((SyncChange::Update, "modified"))
+                }
+            },
+        };
+        if let Some((change, reason)) = change {
+            actions.push(SyncAction { change, path: path.clone(), directory: entry.directory, size: entry.size, reason });
+        }
+    }
+
+    if options.delete_extraneous {
+        for (path, entry) in destination {
+            if !source.contains_key(path) {
+                actions.push(SyncAction {
+                    change: SyncChange::Delete,
+                    path: path.clone(),
+                    directory: entry.directory,
+                    size: entry.size,
+                    reason: "not in source",
+                });
+            }
+        }
+    }
+
+    Ok((actions, files_compared))
+}
+
+/// Removes a destination entry, a directory with everything below it.
+fn delete_destination(config: &SftpConfig, direction: TransferDirection, local_root: &Path, remote_root: &str, path: &str) -> Result<(), SftpError> {
+    match direction {
+        TransferDirection::Upload => config.remove(&remote_path(remote_root, path), true).map(|_| ()),
+        TransferDirection::Download => {
+            let target = local_root.join(path);
+            let removed = if target.is_dir() { fs::remove_dir_all(&target) } else { fs::remove_file(&target) };
+            match removed {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    Err(SftpError::Local { path: target.display().to_string(), source: e })
+                },
+                _ => Ok(()),
+            }
+        },
+    }
+}
+
+/// The transfer plan for the created and updated entries.
+fn transfer_plan(plan: &SyncPlan, direction: TransferDirection, local_root: &Path, remote_root: &str) -> Plan {
+    let mut transfer = Plan::default();
+    let local = |path: &str| local_root.join(path).to_string_lossy().into_owned();
+    let remote = |path: &str| remote_path(remote_root, path);
+
+    transfer.directories.push(match direction {
+        TransferDirection::Upload => remote_root.to_string(),
+        TransferDirection::Download => local_root.to_string_lossy().into_owned(),
+    });
+    for action in plan.actions.iter().filter(|a| a.change != SyncChange::Delete) {
+        let (source, destination) = match direction {
+            TransferDirection::Upload => (local(&action.path), remote(&action.path)),
+            TransferDirection::Download => (remote(&action.path), local(&action.path)),
+        };
+        if action.directory {
+            transfer.directories.push(destination);
+        } else {
+            transfer.files.push(FileItem {
+                source,
+                destination,
+                size: action.size,
+                modified: plan.source.get(&action.path).and_then(|entry| entry.modified),
+            });
+        }
+    }
+    transfer
+}
+
+/// Outermost paths only, so deleting a directory covers what is below it.
+fn outermost(paths: Vec<&str>) -> Vec<&str> {
+    let mut kept: Vec<&str> = Vec::new();
+    for path in paths {
+        if !kept.iter().any(|parent| path.starts_with(&format!("{}/", parent))) {
+            kept.push(path);
+        }
+    }
+    kept
+}
+
+/// Mirrors a local server directory to or from a remote path. Changed files
+/// go through the transfer queue, so progress arrives as
+/// `sftp-transfer-progress` events and the sync can be cancelled there.
+pub async fn sync(
+    config: SftpConfig,
+    local_path: String,
+    options: SyncOptions,
+    on_progress: transfer::ProgressCallback,
+) -> Result<SyncReport, String> {
+    let remote_root = config.resolve(&options.remote_path);
+    let plan = {
+        let (config, local_path, remote_root, options) = (config.clone(), local_path.clone(), remote_root.clone(), options.clone());
+        task::spawn_blocking(move || plan_sync(&config, Path::new(&local_path), &remote_root, &options))
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())?
+    };
+
+    let mut report = SyncReport {
+        direction: options.direction,
+        local_path: local_path.clone(),
+        remote_path: remote_root.clone(),
+        dry_run: options.dry_run,
+        bytes_to_transfer: plan.actions.iter()
+            .filter(|a| a.change != SyncChange::Delete)
+            .map(|a| a.size)
+            .sum(),
+        files_compared: plan.files_compared,
+        actions: plan.actions.clone(),
+        transfer: None,
+    };
+    if options.dry_run || report.actions.is_empty() {
+        return Ok(report);
+    }
+
+    let direction = options.direction;
+    let local_root = PathBuf::from(&local_path);
+    let retyped: Vec<String> = plan.actions.iter()
+        .filter(|a| a.reason == "type changed")
+        .map(|a| a.path.clone())
+        .collect();
+    if !retyped.is_empty() {
+        let (config, local_root, remote_root) = (config.clone(), local_root.clone(), remote_root.clone());
+        task::spawn_blocking(move || {
+            retyped.iter().try_for_each(|path| delete_destination(&config, direction, &local_root, &remote_root, path))
+        })
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())?;
+    }
+
+    let transfer_plan = transfer_plan(&plan, direction, &local_root, &remote_root);
+    let (source, destination) = match direction {
+        TransferDirection::Upload => (local_path.clone(), remote_root.clone()),
+        TransferDirection::Download => (remote_root.clone(), local_path.clone()),
+    };
+    let (_, done) = transfer::enqueue_plan(config.clone(), direction, source, destination, transfer_plan, on_progress)?;
+    report.transfer = Some(transfer::wait(done).await?);
+
+    let deletions: Vec<String> = outermost(
+        plan.actions.iter()
+            .filter(|a| a.change == SyncChange::Delete)
+            .map(|a| a.path.as_str())
+            .collect(),
+    ).into_iter().map(str::to_string).collect();
+    if !deletions.is_empty() {
+        let (config, local_root, remote_root) = (config, local_root, remote_root.clone());
+        task::spawn_blocking(move || {
+            deletions.iter().try_for_each(|path| delete_destination(&config, direction, &local_root, &remote_root, path))
+        })
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| e.to_string())?;
+    }
+
+    info!(
+        "Synced {} with {}: {} change(s), {} bytes",
+        local_path, remote_root, report.actions.len(), report.bytes_to_transfer
+    );
+    Ok(report)
+}
+
+/// Mirrors a server's directory to or from an SFTP path. With `dry_run` set
+/// only the planned changes are returned.
+#[tauri::command]
+pub async fn sync_server_sftp(
+    app: AppHandle,
+    state: State<'_, Arc<Mutex<ServerManager>>>,
+    server_id: String,
+    config: SftpConfig,
+    options: SyncOptions,
+) -> Result<SyncReport, String> {
+    let local_path = state.lock()
+        .map_err(|_| "Failed to lock server manager")?
+        .get_server_info(&server_id)?
+        .config
+        .path;
+    sync(config, local_path, options, transfer::emit_to(app)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::scratch_dir;
+
+    fn options(delete_extraneous: bool, compare_hashes: bool) -> SyncOptions {
+        SyncOptions {
+            direction: TransferDirection::Upload,
+            remote_path: "/backup".to_string(),
+            delete_extraneous,
+            exclude: Vec::new(),
+            compare_hashes,
+            dry_run: true,
+        }
+    }
+
+    fn tree(entries: &[(&str, Option<(u64, u64)>)]) -> Tree {
+        entries.iter()
+            .map(|(path, file)| {
+                let entry = match file {
+                    Some((size, modified)) => TreeEntry { directory: false, size: *size, modified: Some(*modified) },
+                    None => TreeEntry { directory: true, size: 0, modified: None },
+                };
+                (path.to_string(), entry)
+            })
+            .collect()
+    }
+
+    fn changes(actions: &[SyncAction]) -> Vec<(SyncChange, &str, &str)> {
+        actions.iter().map(|a| (a.change, a.path.as_str(), a.reason)).collect()
+    }
+
+    #[test]
+    fn glob_match_keeps_single_stars_within_a_segment() {
+        let cases: &[(&str, &str, bool)] = &[
+            ("*.log", "latest.log", true),
+            ("*.log", "logs/latest.log", false),
+            ("world/region", "world/region", true),
+            ("world/*", "world/region", true),
+            ("world/*", "world/region/r.0.0.mca", false),
+            ("world/**", "world/region/r.0.0.mca", true),
+            ("**/session.lock", "session.lock", true),
+            ("**/session.lock", "world/session.lock", true),
+            ("**/session.lock", "world/old_session.lock", false),
+            ("a/**/b", "a/b", true),
+            ("a/**/b", "a/x/y/b", true),
+            ("config?.yml", "config1.yml", true),
+            ("config?.yml", "config/.yml", false),
+            ("ops.json", "ops.json.bak", false),
+        ];
+        for (pattern, path, expected) in cases {
+            assert_eq!(glob_match(pattern.as_bytes(), path.as_bytes()), *expected, "{} against {}", pattern, path);
+        }
+    }
+
+    #[test]
+    fn is_excluded_matches_names_at_any_depth_and_paths_from_the_root() {
+        let exclude = vec!["*.log".to_string(), " /world/region/ ".to_string(), "".to_string()];
+        assert!(is_excluded(&exclude, "latest.log"));
+        assert!(is_excluded(&exclude, "logs/2024-01-01.log"));
+        assert!(is_excluded(&exclude, "world/region"));
+        assert!(!is_excluded(&exclude, "backup/world/region"));
+        assert!(!is_excluded(&exclude, "world"));
+        assert!(!is_excluded(&exclude, "server.properties"));
+        assert!(!is_excluded(&[], "anything"));
+    }
+
+    #[test]
+    fn diff_trees_creates_updates_and_deletes() {
+        let source = tree(&[
+            ("config", None),
+            ("config/a.yml", Some((10, 1000))),
+            ("same.txt", Some((5, 1000))),
+            ("touched.txt", Some((5, 1000))),
+            ("grown.txt", Some((6, 1000))),
+            ("retyped", Some((1, 1000))),
+        ]);
+        let destination = tree(&[
+            ("same.txt", Some((5, 1001))),
+            ("touched.txt", Some((5, 900))),
+            ("grown.txt", Some((5, 1000))),
+            ("retyped", None),
+            ("old", None),
+            ("old/file.txt", Some((3, 1000))),
+        ]);
+
+        let (actions, compared) = diff_trees(&source, &destination, &options(false, false), |_| unreachable!()).unwrap();
+        assert_eq!(compared, 3);
+        assert_eq!(changes(&actions), [
+            (SyncChange::Create, "config", "new"),
+            (SyncChange::Create, "config/a.yml", "new"),
+            (SyncChange::Update, "grown.txt", "size changed"),
+            (SyncChange::Update, "retyped", "type changed"),
+            (SyncChange::Update, "touched.txt", "modified"),
+        ]);
+
+        let (actions, _) = diff_trees(&source, &destination, &options(true, false), |_| unreachable!()).unwrap();
+        let deleted: Vec<&str> = actions.iter().filter(|a| a.change == SyncChange::Delete).map(|a| a.path.as_str()).collect();
+        assert_eq!(deleted, ["old", "old/file.txt"]);
+    }
+
+    #[test]
+    fn diff_trees_compares_hashes_of_same_sized_files_only() {
+        let source = tree(&[("a", Some((5, 1))), ("b", Some((5, 1))), ("c", Some((6, 1)))]);
+        let destination = tree(&[("a", Some((5, 9999))), ("b", Some((5, 1))), ("c", Some((5, 1)))]);
+
+        let mut hashed = Vec::new();
+        let (actions, _) = diff_trees(&source, &destination, &options(false, true), |path| {
+            hashed.push(path.to_string());
+            Ok(path == "b")
+        }).unwrap();
+        assert_eq!(hashed, ["a", "b"]);
+        assert_eq!(changes(&actions), [
+            (SyncChange::Update, "b", "content changed"),
+            (SyncChange::Update, "c", "size changed"),
+        ]);
+    }
+
+    #[test]
+    fn a_missing_source_root_is_an_error_not_an_empty_tree() {
+        let dir = scratch_dir("sync-scan");
+        let missing = dir.join("missing");
+        assert!(matches!(scan_local(&missing, &[], true), Err(SftpError::NotFound(_))));
+        assert!(scan_local(&missing, &[], false).unwrap().is_empty());
+
+        fs::write(dir.join("file"), "x").unwrap();
+        assert!(scan_local(&dir.join("file"), &[], false).is_err());
+
+        fs::create_dir_all(dir.join("root/logs")).unwrap();
+        fs::write(dir.join("root/logs/latest.log"), "x").unwrap();
+        fs::write(dir.join("root/world.dat.part"), "x").unwrap();
+        fs::write(dir.join("root/ops.json"), "[]").unwrap();
+        let scanned = scan_local(&dir.join("root"), &["*.log".to_string()], true).unwrap();
+        assert_eq!(scanned.keys().collect::<Vec<_>>(), ["logs", "ops.json"]);
+    }
+}
@@ -0,0 +1,333 @@
+//! Helpers shared by the unit tests.
+
+use ring::hmac;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, Once};
+use std::thread;
+
+use crate::s3::S3Config;
+
+static APP_DATA: Once = Once::new();
+
+fn root() -> PathBuf {
+    std::env::temp_dir().join(format!("servermint-test-{}", std::process::id()))
+}
+
+/// A new empty directory for one test. The first call also points `APPDATA`
+/// into the test root, so the stores the code under test opens never touch
+/// real ones.
+pub fn scratch_dir(name: &str) -> PathBuf {
+    APP_DATA.call_once(|| std::env::set_var("APPDATA", root().join("appdata")));
+    let dir = root().join(format!("{}-{}", name, uuid::Uuid::new_v4().simple()));
+    std::fs::create_dir_all(&dir).expect("Failed to create test directory");
+    dir
+}
+
+pub const S3_BUCKET: &str = "backups";
+pub const S3_REGION: &str = "eu-test-1";
+pub const S3_ACCESS_KEY: &str = "AKIDSERVERMINT";
+pub const S3_SECRET_KEY: &str = "stub-secret-key";
+/// Objects a listing returns per page, small so paging gets exercised.
+const S3_PAGE_SIZE: usize = 2;
+
+#[derive(Default)]
+struct S3Bucket {
+    objects: BTreeMap<String, Vec<u8>>,
+    /// Parts of unfinished multipart uploads, by upload id and part number.
+    uploads: HashMap<String, BTreeMap<u32, Vec<u8>>>,
+    /// `METHOD key?query` of every request that passed the signature check.
+    requests: Vec<String>,
+}
+
+/// A path-style S3 bucket on a local port, holding objects in memory. It
+/// checks each request's Signature Version 4 the way S3 does and serves the
+/// calls `S3Client` makes.
+pub struct S3Stub {
+    pub endpoint: String,
+    bucket: Arc<Mutex<S3Bucket>>,
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: Vec<(String, String)>,
+    raw_query: String,
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+struct HttpResponse {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn ok(body: impl Into<Vec<u8>>) -> Self {
+        HttpResponse { status: 200, headers: Vec::new(), body: body.into() }
+    }
+
+    fn error(status: u16, code: &str) -> Self {
+        let body = format!("<Error><Code>{}</Code><Message>Refused by the stub</Message></Error>", code);
+        HttpResponse { status, headers: Vec::new(), body: body.into_bytes() }
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], value.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, key), data.as_bytes()).as_ref().to_vec()
+}
+
+fn read_request(stream: &mut TcpStream) -> Option<HttpRequest> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let mut parts = line.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?.to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).ok()?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':')?;
+        headers.insert(name.trim().to_lowercase(), value.trim().to_string());
+    }
+    let length = headers.get("content-length").and_then(|l| l.parse().ok()).unwrap_or(0);
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).ok()?;
+
+    let (path, raw_query) = target.split_once('?').unwrap_or((&target, ""));
+    let query = raw_query.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (percent_decode(name), percent_decode(value))
+        })
+        .collect();
+    Some(HttpRequest {
+        method,
+        path: path.to_string(),
+        query,
+        raw_query: raw_query.to_string(),
+        headers,
+        body,
+    })
+}
+
+/// Recomputes the request's signature from what arrived on the wire.
+fn signature_matches(request: &HttpRequest) -> bool {
+    let header = |name: &str| request.headers.get(name).map(String::as_str).unwrap_or("");
+    let Some(credentials) = header("authorization").strip_prefix("AWS4-HMAC-SHA256 ") else { return false };
+    let fields: HashMap<&str, &str> = credentials.split(", ").filter_map(|f| f.split_once('=')).collect();
+    let amz_date = header("x-amz-date");
+    let date = amz_date.get(..8).unwrap_or("");
+    let scope = format!("{}/{}/s3/aws4_request", date, S3_REGION);
+    if fields.get("Credential") != Some(&format!("{}/{}", S3_ACCESS_KEY, scope).as_str()) {
+        return false;
+    }
+
+    let mut query: Vec<&str> = request.raw_query.split('&').filter(|p| !p.is_empty()).collect();
+    query.sort();
+    let signed_headers = fields.get("SignedHeaders").copied().unwrap_or("");
+    let canonical_headers: String = signed_headers.split(';')
+        .map(|name| format!("{}:{}\n", name, header(name)))
+        .collect();
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        request.method,
+        request.path,
+        query.join("&"),
+        canonical_headers,
+        signed_headers,
+        header("x-amz-content-sha256")
+    );
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let mut key = hmac_sha256(format!("AWS4{}", S3_SECRET_KEY).as_bytes(), date);
+    for part in [S3_REGION, "s3", "aws4_request"] {
+        key = hmac_sha256(&key, part);
+    }
+    fields.get("Signature") == Some(&hex::encode(hmac_sha256(&key, &string_to_sign)).as_str())
+}
+
+fn etag(data: &[u8]) -> String {
+    format!("\"{}\"", hex::encode(&Sha256::digest(data)[..16]))
+}
+
+impl S3Bucket {
+    fn handle(&mut self, request: &HttpRequest) -> HttpResponse {
+        if !signature_matches(request) {
+            return HttpResponse::error(403, "SignatureDoesNotMatch");
+        }
+        if request.headers.get("x-amz-content-sha256") != Some(&hex::encode(Sha256::digest(&request.body))) {
+            return HttpResponse::error(400, "XAmzContentSHA256Mismatch");
+        }
+        let Some(rest) = request.path.strip_prefix(&format!("/{}", S3_BUCKET)) else {
+            return HttpResponse::error(404, "NoSuchBucket");
+        };
+        let key = percent_decode(rest.trim_start_matches('/'));
+        let query: HashMap<&str, &str> = request.query.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        self.requests.push(format!("{} {}?{}", request.method, key, request.raw_query));
+
+        match (request.method.as_str(), key.is_empty()) {
+            ("GET", true) => self.list(&query),
+            ("PUT", false) => match (query.get("partNumber"), query.get("uploadId")) {
+                (Some(number), Some(upload_id)) => match self.uploads.get_mut(*upload_id) {
+                    Some(parts) => {
+                        parts.insert(number.parse().unwrap_or(0), request.body.clone());
+                        HttpResponse { status: 200, headers: vec![("ETag", etag(&request.body))], body: Vec::new() }
+                    },
+                    None => HttpResponse::error(404, "NoSuchUpload"),
+                },
+                _ => {
+                    self.objects.insert(key, request.body.clone());
+                    HttpResponse { status: 200, headers: vec![("ETag", etag(&request.body))], body: Vec::new() }
+                },
+            },
+            ("POST", false) if query.contains_key("uploads") => {
+                let upload_id = format!("upload-{}", uuid::Uuid::new_v4().simple());
+                self.uploads.insert(upload_id.clone(), BTreeMap::new());
+                HttpResponse::ok(format!(
+                    "<InitiateMultipartUploadResult><Bucket>{}</Bucket><Key>{}</Key><UploadId>{}</UploadId></InitiateMultipartUploadResult>",
+                    S3_BUCKET, key, upload_id
+                ))
+            },
+            ("POST", false) => {
+                let Some(parts) = query.get("uploadId").and_then(|id| self.uploads.remove(*id)) else {
+                    return HttpResponse::error(404, "NoSuchUpload");
+                };
+                let listed = String::from_utf8_lossy(&request.body).into_owned();
+                let expected: String = parts.iter()
+                    .map(|(number, data)| format!("<Part><PartNumber>{}</PartNumber><ETag>{}</ETag></Part>", number, etag(data)))
+                    .collect();
+                if listed != format!("<CompleteMultipartUpload>{}</CompleteMultipartUpload>", expected) {
+                    return HttpResponse::error(400, "InvalidPart");
+                }
+                self.objects.insert(key.clone(), parts.into_values().flatten().collect());
+                HttpResponse::ok(format!("<CompleteMultipartUploadResult><Key>{}</Key></CompleteMultipartUploadResult>", key))
+            },
+            ("GET", false) => match self.objects.get(&key) {
+                Some(data) => HttpResponse::ok(data.clone()),
+                None => HttpResponse::error(404, "NoSuchKey"),
+            },
+            ("DELETE", false) => {
+                match query.get("uploadId") {
+                    Some(upload_id) => self.uploads.remove(*upload_id).map(|_| ()),
+                    None => self.objects.remove(&key).map(|_| ()),
+                };
+                HttpResponse { status: 204, headers: Vec::new(), body: Vec::new() }
+            },
+            _ => HttpResponse::error(405, "MethodNotAllowed"),
+        }
+    }
+
+    fn list(&self, query: &HashMap<&str, &str>) -> HttpResponse {
+        if query.get("list-type") != Some(&"2") {
+            return HttpResponse::error(400, "InvalidArgument");
+        }
+        let prefix = query.get("prefix").copied().unwrap_or("");
+        let after = query.get("continuation-token").copied().unwrap_or("");
+        let matching: Vec<(&String, &Vec<u8>)> = self.objects.iter()
+            .filter(|(key, _)| key.starts_with(prefix) && key.as_str() > after)
+            .collect();
+        let page = &matching[..matching.len().min(S3_PAGE_SIZE)];
+        let truncated = matching.len() > page.len();
+
+        let mut body = String::from("<ListBucketResult>");
+        for (key, data) in page {
+            body.push_str(&format!("<Contents><Key>{}</Key><Size>{}</Size></Contents>", key.replace('&', "&amp;"), data.len()));
+        }
+        body.push_str(&format!("<IsTruncated>{}</IsTruncated>", truncated));
+        if let (true, Some((last, _))) = (truncated, page.last()) {
+            body.push_str(&format!("<NextContinuationToken>{}</NextContinuationToken>", last.replace('&', "&amp;")));
+        }
+        body.push_str("</ListBucketResult>");
+        HttpResponse::ok(body)
+    }
+}
+
+impl S3Stub {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind the S3 stub");
+        let endpoint = format!("http://{}", listener.local_addr().expect("S3 stub has no address"));
+        let bucket = Arc::new(Mutex::new(S3Bucket::default()));
+
+        let served = bucket.clone();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().map_while(Result::ok) {
+                let Some(request) = read_request(&mut stream) else { continue };
+                let response = served.lock().unwrap().handle(&request);
+                let mut head = format!("HTTP/1.1 {} Stub\r\nContent-Length: {}\r\nConnection: close\r\n", response.status, response.body.len());
+                for (name, value) in &response.headers {
+                    head.push_str(&format!("{}: {}\r\n", name, value));
+                }
+                head.push_str("\r\n");
+                let _ = stream.write_all(head.as_bytes()).and_then(|_| stream.write_all(&response.body));
+            }
+        });
+
+        S3Stub { endpoint, bucket }
+    }
+
+    /// A configuration for this bucket, keeping everything under `prefix`.
+    pub fn config(&self, prefix: &str) -> S3Config {
+        S3Config {
+            endpoint: self.endpoint.clone(),
+            region: S3_REGION.to_string(),
+            bucket: S3_BUCKET.to_string(),
+            prefix: prefix.to_string(),
+            access_key_id: S3_ACCESS_KEY.to_string(),
+            secret_access_key: S3_SECRET_KEY.to_string(),
+            path_style: true,
+            part_size: 5 * 1024 * 1024,
+        }
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.bucket.lock().unwrap().objects.keys().cloned().collect()
+    }
+
+    pub fn object(&self, key: &str) -> Option<Vec<u8>> {
+        self.bucket.lock().unwrap().objects.get(key).cloned()
+    }
+
+    pub fn insert(&self, key: &str, data: &[u8]) {
+        self.bucket.lock().unwrap().objects.insert(key.to_string(), data.to_vec());
+    }
+
+    /// Requests served so far, as `METHOD key?query`.
+    pub fn requests(&self) -> Vec<String> {
+        self.bucket.lock().unwrap().requests.clone()
+    }
+}
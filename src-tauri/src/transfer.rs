@@ -0,0 +1,667 @@
+//! Queue for recursive SFTP uploads and downloads.
+//!
+//! Transfers run one at a time on a worker thread, in the order they were
+//! started. Every file is written under a `.part` name and renamed once it
+//! is complete: a transfer that fails resumes from the partial file the next
+//! time, and a cancelled one deletes it, so no half-written file is ever left
+//! under its final name.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use log::{info, warn};
+use ssh2::{FileStat, OpenFlags, OpenType, RenameFlags, Sftp};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::sftp::{SftpConfig, SftpError};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+/// Attempts per file when the connection drops mid-transfer.
+const MAX_ATTEMPTS: u32 = 3;
+/// Finished transfers kept for `list_sftp_transfers`.
+const HISTORY_LIMIT: usize = 50;
+pub(crate) const PARTIAL_SUFFIX: &str = ".part";
+
+pub const PROGRESS_EVENT: &str = "sftp-transfer-progress";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferDirection {
+    Upload,
+    Download,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransferState {
+    Queued,
+    Running,
+    Completed,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferProgress {
+    pub id: String,
+    pub direction: TransferDirection,
+    pub state: TransferState,
+    pub source: String,
+    pub destination: String,
+    /// Source path of the file being copied.
+    pub current_file: Option<String>,
+    pub files_done: u64,
+    pub files_total: u64,
+    pub bytes_done: u64,
+    pub bytes_total: u64,
+    pub error: Option<String>,
+}
+
+pub type ProgressCallback = Box<dyn Fn(&TransferProgress) + Send>;
+
+struct Transfer {
+    progress: Mutex<TransferProgress>,
+    cancelled: AtomicBool,
+}
+
+struct Job {
+    transfer: Arc<Transfer>,
+    config: SftpConfig,
+    /// Files to copy, when the caller already decided them.
+    plan: Option<Plan>,
+    on_progress: ProgressCallback,
+    done: oneshot::Sender<TransferProgress>,
+}
+
+/// One file to copy, with paths already resolved on both sides.
+pub(crate) struct FileItem {
+    pub(crate) source: String,
+    pub(crate) destination: String,
+    pub(crate) size: u64,
+    /// Unix time the source was last modified, copied onto the destination.
+    pub(crate) modified: Option<u64>,
+}
+
+#[derive(Default)]
+pub(crate) struct Plan {
+    /// Destination directories, parents first.
+    pub(crate) directories: Vec<String>,
+    pub(crate) files: Vec<FileItem>,
+}
+
+lazy_static::lazy_static! {
+    static ref TRANSFERS: Mutex<Vec<Arc<Transfer>>> = Mutex::new(Vec::new());
+    static ref QUEUE: Mutex<Option<mpsc::Sender<Job>>> = Mutex::new(None);
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+fn join_remote(dir: &str, name: &str) -> String {
+    format!("{}/{}", dir.trim_end_matches('/'), name)
+}
+
+pub(crate) fn unix_time(metadata: &fs::Metadata) -> Option<u64> {
+    metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
+fn partial_path(path: &str) -> String {
+    format!("{}{}", path, PARTIAL_SUFFIX)
+}
+
+impl Transfer {
+    fn new(direction: TransferDirection, source: String, destination: String) -> Self {
+        Transfer {
+            progress: Mutex::new(TransferProgress {
+                id: Uuid::new_v4().to_string(),
+                direction,
+                state: TransferState::Queued,
+                source,
+                destination,
+                current_file: None,
+                files_done: 0,
+                files_total: 0,
+                bytes_done: 0,
+                bytes_total: 0,
+                error: None,
+            }),
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    fn snapshot(&self) -> TransferProgress {
+        lock(&self.progress).clone()
+    }
+
+    fn is_finished(&self) -> bool {
+        matches!(
+            lock(&self.progress).state,
+            TransferState::Completed | TransferState::Failed | TransferState::Cancelled
+        )
+    }
+
+    fn check_cancelled(&self) -> Result<(), SftpError> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            Err(SftpError::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Applies progress updates and passes them on, at most every
+/// `PROGRESS_INTERVAL` unless forced.
+struct Reporter<'a> {
+    transfer: &'a Transfer,
+    on_progress: &'a dyn Fn(&TransferProgress),
+    last_report: Option<Instant>,
+}
+
+impl Reporter<'_> {
+    fn update(&mut self, force: bool, apply: impl FnOnce(&mut TransferProgress)) {
+        let snapshot = {
+            let mut progress = lock(&self.transfer.progress);
+            apply(&mut progress);
+            progress.clone()
+        };
+        if force || self.last_report.map_or(true, |at| at.elapsed() >= PROGRESS_INTERVAL) {
+            (self.on_progress)(&snapshot);
+            self.last_report = Some(Instant::now());
+        }
+    }
+}
+
+fn plan_upload(source: &str, destination: &str) -> Result<Plan, SftpError> {
+    fn walk(dir: &Path, remote: &str, plan: &mut Plan) -> Result<(), SftpError> {
+        let local_error = |source| SftpError::Local { path: dir.display().to_string(), source };
+        plan.directories.push(remote.to_string());
+        let mut entries = fs::read_dir(dir).map_err(local_error)?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(local_error)?;
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let metadata = entry.metadata().map_err(local_error)?;
+            let path = entry.path();
+            if metadata.is_dir() {
+                walk(&path, &join_remote(remote, &name), plan)?;
+            } else if metadata.is_file() {
+                plan.files.push(FileItem {
+                    source: path.to_string_lossy().into_owned(),
+                    destination: join_remote(remote, &name),
+                    size: metadata.len(),
+                    modified: unix_time(&metadata),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    let metadata = fs::metadata(source)
+        .map_err(|e| SftpError::Local { path: source.to_string(), source: e })?;
+    let mut plan = Plan::default();
+    if metadata.is_dir() {
+        walk(Path::new(source), destination, &mut plan)?;
+    } else {
+        plan.files.push(FileItem {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            size: metadata.len(),
+            modified: unix_time(&metadata),
+        });
+    }
+    Ok(plan)
+}
+
+fn plan_download(sftp: &Sftp, source: &str, destination: &str) -> Result<Plan, SftpError> {
+    fn walk(sftp: &Sftp, dir: &str, local: &Path, plan: &mut Plan) -> Result<(), SftpError> {
+        plan.directories.push(local.to_string_lossy().into_owned());
+        let mut entries = sftp.readdir(Path::new(dir)).map_err(|e| SftpError::remote(dir, e))?;
+        entries.sort_by(|a, b| a.0.cmp(&b.0));
+
+        for (path, stat) in entries {
+            let Some(name) = path.file_name().map(|n| n.to_string_lossy().into_owned()) else {
+                continue;
+            };
+            if name == "." || name == ".." {
+                continue;
+            }
+            let remote = join_remote(dir, &name);
+            if stat.is_dir() {
+                walk(sftp, &remote, &local.join(&name), plan)?;
+            } else if stat.is_file() {
+                plan.files.push(FileItem {
+                    source: remote,
+                    destination: local.join(&name).to_string_lossy().into_owned(),
+                    size: stat.size.unwrap_or(0),
+                    modified: stat.mtime,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    let stat = sftp.stat(Path::new(source)).map_err(|e| SftpError::remote(source, e))?;
+    let mut plan = Plan::default();
+    if stat.is_dir() {
+        walk(sftp, source, Path::new(destination), &mut plan)?;
+    } else {
+        plan.files.push(FileItem {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            size: stat.size.unwrap_or(0),
+            modified: stat.mtime,
+        });
+    }
+    Ok(plan)
+}
+
+/// Copies `reader` to `writer` from `offset` on, reporting the bytes of the
+/// whole transfer as `bytes_before` plus the position in this file.
+fn copy_chunks(
+    reader: &mut impl Read,
+    writer: &mut impl Write,
+    offset: u64,
+    bytes_before: u64,
+    reporter: &mut Reporter,
+    read_error: impl Fn(io::Error) -> SftpError,
+    write_error: impl Fn(io::Error) -> SftpError,
+) -> Result<(), SftpError> {
+    let mut buffer = vec![0u8; CHUNK_SIZE];
+    let mut position = offset;
+    loop {
+        reporter.transfer.check_cancelled()?;
+        let read = reader.read(&mut buffer).map_err(&read_error)?;
+        if read == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..read]).map_err(&write_error)?;
+        position += read as u64;
+        reporter.update(false, |p| p.bytes_done = bytes_before + position);
+    }
+    writer.flush().map_err(&write_error)
+}
+
+/// Moves a finished upload over its destination, falling back to unlinking
+/// the destination on servers that refuse to overwrite on rename.
+pub(crate) fn replace_remote(sftp: &Sftp, from: &str, to: &str) -> Result<(), SftpError> {
+    let flags = RenameFlags::OVERWRITE | RenameFlags::ATOMIC | RenameFlags::NATIVE;
+    if sftp.rename(Path::new(from), Path::new(to), Some(flags)).is_ok() {
+        return Ok(());
+    }
+    let _ = sftp.unlink(Path::new(to));
+    sftp.rename(Path::new(from), Path::new(to), None).map_err(|e| SftpError::remote(to, e))
+}
+
+fn upload_file(sftp: &Sftp, item: &FileItem, bytes_before: u64, reporter: &mut Reporter) -> Result<(), SftpError> {
+    let partial = partial_path(&item.destination);
+    let offset = match sftp.stat(Path::new(&partial)) {
+        Ok(stat) => stat.size.unwrap_or(0),
+        Err(_) => 0,
+    };
+
+    let local_error = |source| SftpError::Local { path: item.source.clone(), source };
+    let mut local = fs::File::open(&item.source).map_err(local_error)?;
+    let (mut remote, offset) = if offset > 0 && offset <= item.size {
+        let mut remote = sftp.open_mode(Path::new(&partial), OpenFlags::WRITE, 0o644, OpenType::File)
+            .map_err(|e| SftpError::remote(&partial, e))?;
+        remote.seek(SeekFrom::Start(offset))
+            .map_err(|source| SftpError::Transfer { path: partial.clone(), source })?;
+        local.seek(SeekFrom::Start(offset)).map_err(local_error)?;
+        info!("Resuming upload of {} at {} bytes", item.source, offset);
+        (remote, offset)
+    } else {
+        let remote = sftp.create(Path::new(&partial)).map_err(|e| SftpError::remote(&partial, e))?;
+        (remote, 0)
+    };
+
+    let copied = copy_chunks(
+        &mut local,
+        &mut remote,
+        offset,
+        bytes_before,
+        reporter,
+        local_error,
+        |source| SftpError::Transfer { path: item.destination.clone(), source },
+    );
+    drop(remote);
+    if let Err(e) = copied {
+        if matches!(e, SftpError::Cancelled) {
+            let _ = sftp.unlink(Path::new(&partial));
+        }
+        return Err(e);
+    }
+    replace_remote(sftp, &partial, &item.destination)?;
+
+    if let Some(modified) = item.modified {
+        let times = FileStat { size: None, uid: None, gid: None, perm: None, atime: Some(modified), mtime: Some(modified) };
+        if let Err(e) = sftp.setstat(Path::new(&item.destination), times) {
+            warn!("Failed to set the modification time of {}: {}", item.destination, e);
+        }
+    }
+    Ok(())
+}
+
+fn download_file(sftp: &Sftp, item: &FileItem, bytes_before: u64, reporter: &mut Reporter) -> Result<(), SftpError> {
+    let partial = partial_path(&item.destination);
+    let local_error = |source| SftpError::Local { path: partial.clone(), source };
+    let offset = fs::metadata(&partial).map(|m| m.len()).unwrap_or(0);
+
+    let mut remote = sftp.open(Path::new(&item.source)).map_err(|e| SftpError::remote(&item.source, e))?;
+    let (mut local, offset) = if offset > 0 && offset <= item.size {
+        let mut local = fs::OpenOptions::new().write(true).open(&partial).map_err(local_error)?;
+        local.seek(SeekFrom::Start(offset)).map_err(local_error)?;
+        remote.seek(SeekFrom::Start(offset))
+            .map_err(|source| SftpError::Transfer { path: item.source.clone(), source })?;
+        info!("Resuming download of {} at {} bytes", item.source, offset);
+        (local, offset)
+    } else {
+        (fs::File::create(&partial).map_err(local_error)?, 0)
+    };
+
+    let copied = copy_chunks(
+        &mut remote,
+        &mut local,
+        offset,
+        bytes_before,
+        reporter,
+        |source| SftpError::Transfer { path: item.source.clone(), source },
+        local_error,
+    );
+    drop(local);
+    if let Err(e) = copied {
+        if matches!(e, SftpError::Cancelled) {
+            let _ = fs::remove_file(&partial);
+        }
+        return Err(e);
+    }
+    fs::rename(&partial, &item.destination)
+        .map_err(|source| SftpError::Local { path: item.destination.clone(), source })?;
+
+    if let Some(modified) = item.modified {
+        let set = fs::File::options().write(true).open(&item.destination)
+            .and_then(|file| file.set_modified(UNIX_EPOCH + Duration::from_secs(modified)));
+        if let Err(e) = set {
+            warn!("Failed to set the modification time of {}: {}", item.destination, e);
+        }
+    }
+    Ok(())
+}
+
+fn ensure_remote_dir(sftp: &Sftp, path: &str) -> Result<(), SftpError> {
+    match sftp.stat(Path::new(path)) {
+        Ok(stat) if stat.is_dir() => Ok(()),
+        _ => sftp.mkdir(Path::new(path), 0o755).map_err(|e| SftpError::remote(path, e)),
+    }
+}
+
+/// Copies everything `transfer` describes, or the files in `plan` when
+/// given, one file at a time.
+fn execute(
+    config: &SftpConfig,
+    transfer: &Transfer,
+    plan: Option<Plan>,
+    on_progress: &dyn Fn(&TransferProgress),
+) -> Result<(), SftpError> {
+    let (direction, source, destination) = {
+        let progress = lock(&transfer.progress);
+        (progress.direction, progress.source.clone(), progress.destination.clone())
+    };
+    let mut reporter = Reporter { transfer, on_progress, last_report: None };
+
+    let plan = match (plan, direction) {
+        (Some(plan), _) => plan,
+        (None, TransferDirection::Upload) => plan_upload(&source, &config.resolve(&destination))?,
+        (None, TransferDirection::Download) => {
+            let source = config.resolve(&source);
+            config.with_sftp(|sftp| plan_download(sftp, &source, &destination))?
+        },
+    };
+    reporter.update(true, |p| {
+        p.state = TransferState::Running;
+        p.files_total = plan.files.len() as u64;
+        p.bytes_total = plan.files.iter().map(|f| f.size).sum();
+    });
+
+    match direction {
+        TransferDirection::Upload => config.with_sftp(|sftp| {
+            plan.directories.iter().try_for_each(|dir| ensure_remote_dir(sftp, dir))
+        })?,
+        TransferDirection::Download => {
+            for dir in &plan.directories {
+                fs::create_dir_all(dir).map_err(|source| SftpError::Local { path: dir.clone(), source })?;
+            }
+        },
+    }
+
+    let mut bytes_before = 0;
+    for (index, item) in plan.files.iter().enumerate() {
+        transfer.check_cancelled()?;
+        if direction == TransferDirection::Download {
+            if let Some(parent) = Path::new(&item.destination).parent().filter(|p| !p.as_os_str().is_empty()) {
+                fs::create_dir_all(parent)
+                    .map_err(|source| SftpError::Local { path: parent.display().to_string(), source })?;
+            }
+        }
+        reporter.update(true, |p| {
+            p.current_file = Some(item.source.clone());
+            p.files_done = index as u64;
+            p.bytes_done = bytes_before;
+        });
+
+        let mut attempt = 1;
+        loop {
+            let result = config.with_sftp(|sftp| match direction {
+                TransferDirection::Upload => upload_file(sftp, item, bytes_before, &mut reporter),
+                TransferDirection::Download => download_file(sftp, item, bytes_before, &mut reporter),
+            });
+            match result {
+                Err(e) if e.is_connection_lost() && attempt < MAX_ATTEMPTS => {
+                    warn!("Transfer of {} interrupted, resuming: {}", item.source, e);
+                    attempt += 1;
+                },
+                result => break result?,
+            }
+        }
+        bytes_before += item.size;
+    }
+
+    reporter.update(true, |p| {
+        p.state = TransferState::Completed;
+        p.current_file = None;
+        p.files_done = p.files_total;
+        p.bytes_done = p.bytes_total;
+    });
+    Ok(())
+}
+
+/// Runs `transfer` to the end and records how it finished.
+fn finish(
+    config: &SftpConfig,
+    transfer: &Transfer,
+    plan: Option<Plan>,
+    on_progress: &dyn Fn(&TransferProgress),
+) -> TransferProgress {
+    if let Err(e) = execute(config, transfer, plan, on_progress) {
+        let snapshot = {
+            let mut progress = lock(&transfer.progress);
+            progress.state = match e {
+                SftpError::Cancelled => TransferState::Cancelled,
+                _ => TransferState::Failed,
+            };
+            progress.current_file = None;
+            progress.error = Some(e.to_string());
+            progress.clone()
+        };
+        match snapshot.state {
+            TransferState::Cancelled => info!("Transfer {} cancelled", snapshot.id),
+            _ => warn!("Transfer {} failed: {}", snapshot.id, e),
+        }
+        on_progress(&snapshot);
+    }
+    transfer.snapshot()
+}
+
+fn worker(jobs: mpsc::Receiver<Job>) {
+    for job in jobs {
+        let progress = if job.transfer.cancelled.load(Ordering::Relaxed) {
+            let mut progress = lock(&job.transfer.progress);
+            progress.state = TransferState::Cancelled;
+            progress.error = Some(SftpError::Cancelled.to_string());
+            let progress = progress.clone();
+            (job.on_progress)(&progress);
+            progress
+        } else {
+            finish(&job.config, &job.transfer, job.plan, &*job.on_progress)
+        };
+        let _ = job.done.send(progress);
+    }
+}
+
+/// Adds a transfer to the queue. The receiver yields its final progress.
+pub fn enqueue(
+    config: SftpConfig,
+    direction: TransferDirection,
+    source: String,
+    destination: String,
+    on_progress: ProgressCallback,
+) -> Result<(String, oneshot::Receiver<TransferProgress>), String> {
+    queue_job(config, direction, source, destination, None, on_progress)
+}
+
+/// Queues the files of `plan` only; `source` and `destination` label the
+/// transfer.
+pub(crate) fn enqueue_plan(
+    config: SftpConfig,
+    direction: TransferDirection,
+    source: String,
+    destination: String,
+    plan: Plan,
+    on_progress: ProgressCallback,
+) -> Result<(String, oneshot::Receiver<TransferProgress>), String> {
+    queue_job(config, direction, source, destination, Some(plan), on_progress)
+}
+
+fn queue_job(
+    config: SftpConfig,
+    direction: TransferDirection,
+    source: String,
+    destination: String,
+    plan: Option<Plan>,
+    on_progress: ProgressCallback,
+) -> Result<(String, oneshot::Receiver<TransferProgress>), String> {
+    let transfer = Arc::new(Transfer::new(direction, source, destination));
+    let snapshot = transfer.snapshot();
+    {
+        let mut transfers = lock(&TRANSFERS);
+        let finished = transfers.iter().filter(|t| t.is_finished()).count();
+        if finished >= HISTORY_LIMIT {
+            let mut excess = finished + 1 - HISTORY_LIMIT;
+            transfers.retain(|t| {
+                let drop = excess > 0 && t.is_finished();
+                if drop {
+                    excess -= 1;
+                }
+                !drop
+            });
+        }
+        transfers.push(transfer.clone());
+    }
+
+    on_progress(&snapshot);
+    info!("Queued {:?} of {} to {}", direction, snapshot.source, snapshot.destination);
+
+    let (done, receiver) = oneshot::channel();
+    let mut queue = lock(&QUEUE);
+    let sender = queue.get_or_insert_with(|| {
+        let (sender, jobs) = mpsc::channel();
+        thread::spawn(move || worker(jobs));
+        sender
+    });
+    sender.send(Job { transfer, config, plan, on_progress, done })
+        .map_err(|_| "The transfer queue has stopped".to_string())?;
+    Ok((snapshot.id, receiver))
+}
+
+/// Runs a transfer on the calling thread, outside the queue.
+pub fn transfer_now(
+    config: &SftpConfig,
+    direction: TransferDirection,
+    source: &str,
+    destination: &str,
+) -> Result<TransferProgress, SftpError> {
+    let transfer = Transfer::new(direction, source.to_string(), destination.to_string());
+    execute(config, &transfer, None, &|_| {})?;
+    Ok(transfer.snapshot())
+}
+
+/// Waits for a queued transfer and fails unless it completed.
+pub async fn wait(done: oneshot::Receiver<TransferProgress>) -> Result<TransferProgress, String> {
+    let progress = done.await.map_err(|_| "The transfer queue has stopped".to_string())?;
+    match progress.state {
+        TransferState::Completed => Ok(progress),
+        _ => Err(progress.error.unwrap_or_else(|| "Transfer did not complete".to_string())),
+    }
+}
+
+/// Reports progress to the frontend as `sftp-transfer-progress` events.
+pub fn emit_to(app: AppHandle) -> ProgressCallback {
+    Box::new(move |progress| {
+        let _ = app.emit(PROGRESS_EVENT, progress);
+    })
+}
+
+/// Cancels one transfer, or every unfinished one without an ID. Returns how
+/// many were cancelled.
+pub fn cancel(transfer_id: Option<&str>) -> Result<usize, String> {
+    let transfers = lock(&TRANSFERS);
+    let matching: Vec<&Arc<Transfer>> = transfers.iter()
+        .filter(|t| !t.is_finished())
+        .filter(|t| transfer_id.map_or(true, |id| lock(&t.progress).id == id))
+        .collect();
+    if let (Some(id), true) = (transfer_id, matching.is_empty()) {
+        return Err(format!("No active transfer with ID {}", id));
+    }
+    for transfer in &matching {
+        transfer.cancelled.store(true, Ordering::Relaxed);
+    }
+    Ok(matching.len())
+}
+
+pub fn list() -> Vec<TransferProgress> {
+    lock(&TRANSFERS).iter().map(|t| t.snapshot()).collect()
+}
+
+/// Starts a recursive upload or download and returns its ID. Progress
+/// arrives as `sftp-transfer-progress` events.
+#[tauri::command]
+pub fn start_sftp_transfer(
+    app: AppHandle,
+    config: SftpConfig,
+    direction: TransferDirection,
+    source: String,
+    destination: String,
+) -> Result<String, String> {
+    enqueue(config, direction, source, destination, emit_to(app)).map(|(id, _)| id)
+}
+
+#[tauri::command]
+pub fn list_sftp_transfers() -> Result<Vec<TransferProgress>, String> {
+    Ok(list())
+}
+
+/// Cancels the given transfer, or all of them when no ID is passed.
+#[tauri::command]
+pub fn cancel_sftp_transfer(transfer_id: Option<String>) -> Result<usize, String> {
+    cancel(transfer_id.as_deref())
+}
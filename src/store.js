@@ -582,7 +582,7 @@ eula=true`;
           });
           console.log('Files after upload:', afterFiles);
 
-          const filenames = afterFiles.map(f => f.split(/\s+/).pop());
+          const filenames = afterFiles.map(f => f.name);
           console.log('Found filenames:', filenames);
 
           if (!filenames.includes(fileName)) {
@@ -601,13 +601,13 @@ eula=true`;
     }
   },
   
-  async importFromSftp(serverId, config, files) {
+  async importFromSftp(config, remotePath, name) {
     try {
-      console.log(`Importing ${files.length} files from SFTP`);
+      console.log(`Importing server from SFTP: ${remotePath}`);
       const result = await invoke('import_from_sftp', { 
-        server_id: serverId, 
         config, 
-        files 
+        remotePath, 
+        name 
       });
       console.log('SFTP import result:', result);
       return result;
@@ -873,12 +873,15 @@ export const store = reactive({
       
       try {
         await invoke('setup_server', {
-          serverId: currentServerId,
-          serverPath: serverData.path,
-          serverType: serverData.type,
-          version: serverData.version,
-          downloadUrl: serverData.downloadUrl || null,
-          serverName: serverData.name
+          request: {
+            server_id: currentServerId,
+            server_path: serverData.path,
+            server_type: serverData.type,
+            version: serverData.version,
+            download_url: serverData.downloadUrl || null,
+            server_name: serverData.name,
+            node_id: serverData.nodeId || null
+          }
         });
         console.log('Server setup completed successfully');
       } catch (error) {
@@ -897,12 +900,15 @@ export const store = reactive({
           currentServerId = newServerId;
           
           await invoke('setup_server', {
-            serverId: newServerId,
-            serverPath: serverData.path,
-            serverType: serverData.type,
-            version: serverData.version,
-            downloadUrl: serverData.downloadUrl || null,
-            serverName: serverData.name
+            request: {
+              server_id: newServerId,
+              server_path: serverData.path,
+              server_type: serverData.type,
+              version: serverData.version,
+              download_url: serverData.downloadUrl || null,
+              server_name: serverData.name,
+              node_id: serverData.nodeId || null
+            }
           });
           console.log('Server setup completed successfully with new ID');
         } else {
@@ -1742,10 +1748,10 @@ java -Xmx${serverData.memoryAllocation || 4}G -Xms1G ${this.settings.java.useCus
     }
   },
   
-  async importFromSftp(serverId, config, files) {
+  async importFromSftp(config, remotePath, name) {
     try {
-      console.log(`Importing ${files.length} files from SFTP`);
-      const result = await this.tauriAPI.importFromSftp(serverId, config, files);
+      console.log(`Importing server from SFTP: ${remotePath}`);
+      const result = await this.tauriAPI.importFromSftp(config, remotePath, name);
       return result;
     } catch (error) {
       console.error('SFTP import error:', error);
@@ -1775,7 +1781,7 @@ java -Xmx${serverData.memoryAllocation || 4}G -Xms1G ${this.settings.java.useCus
           password: config.password,
           remote_path: '/'
         },
-        path: '/'
+        path: cleanPath
       });
 
       if (!result || (Array.isArray(result) && result.length === 0)) {
@@ -1789,7 +1795,7 @@ java -Xmx${serverData.memoryAllocation || 4}G -Xms1G ${this.settings.java.useCus
             remote_path: '/',
             debug: true
           },
-          path: '/'
+          path: cleanPath
         });
       }
 